@@ -0,0 +1,136 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+use std::fmt;
+use std::str::FromStr;
+
+use reqwest::Url;
+
+use crate::Error;
+
+/// A validated URL of a Snowplow collector
+///
+/// The URL must be an absolute `http` or `https` URL with a host, and no query string or fragment.
+/// It is normalised without a trailing slash, as the collector endpoint path is appended to it.
+///
+/// ## Example
+/// ```
+/// use snowplow_tracker::CollectorUrl;
+///
+/// let collector_url: CollectorUrl = "https://collector.example.com/".parse().unwrap();
+/// assert_eq!(collector_url.as_str(), "https://collector.example.com");
+///
+/// assert!("collector.example.com".parse::<CollectorUrl>().is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CollectorUrl(String);
+
+impl CollectorUrl {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    // Wraps a URL without validating it, for constructors such as `BatchEmitter::new` that can't fail
+    pub(crate) fn unvalidated(collector_url: &str) -> Self {
+        Self(collector_url.to_string())
+    }
+}
+
+impl FromStr for CollectorUrl {
+    type Err = Error;
+
+    fn from_str(collector_url: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| {
+            Error::BuilderError(format!("Invalid collector URL {collector_url:?}: {reason}"))
+        };
+
+        let url = Url::parse(collector_url.trim()).map_err(|e| invalid(&e.to_string()))?;
+
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(invalid("the scheme must be http or https"));
+        }
+        if url.host_str().map_or(true, str::is_empty) {
+            return Err(invalid("a host is required"));
+        }
+        if url.query().is_some() || url.fragment().is_some() {
+            return Err(invalid("a query string or fragment is not allowed"));
+        }
+
+        Ok(Self(url.as_str().trim_end_matches('/').to_string()))
+    }
+}
+
+impl TryFrom<&str> for CollectorUrl {
+    type Error = Error;
+
+    fn try_from(collector_url: &str) -> Result<Self, Self::Error> {
+        collector_url.parse()
+    }
+}
+
+impl AsRef<str> for CollectorUrl {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for CollectorUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_urls_are_normalised() {
+        let cases = [
+            ("http://localhost:9090", "http://localhost:9090"),
+            (
+                "https://collector.example.com/",
+                "https://collector.example.com",
+            ),
+            (
+                "https://example.com/collector/",
+                "https://example.com/collector",
+            ),
+            ("  HTTP://Example.com  ", "http://example.com"),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(CollectorUrl::try_from(input).unwrap().as_str(), expected);
+        }
+    }
+
+    #[test]
+    fn malformed_urls_are_rejected() {
+        let cases = [
+            "",
+            "collector.example.com",
+            "localhost:9090",
+            "ftp://collector.example.com",
+            "http://",
+            "http://localhost:99999",
+            "http://example.com/?key=value",
+            "http://example.com/#fragment",
+        ];
+
+        for input in cases {
+            let err = input.parse::<CollectorUrl>().unwrap_err();
+            assert!(
+                matches!(&err, Error::BuilderError(message) if message.starts_with("Invalid collector URL")),
+                "{input:?} was not rejected: {err:?}"
+            );
+        }
+    }
+}
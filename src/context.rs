@@ -0,0 +1,228 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::payload::SelfDescribingJson;
+
+const WEB_PAGE_SCHEMA: &str = "iglu:com.snowplowanalytics.snowplow/web_page/jsonschema/1-0-0";
+const MOBILE_CONTEXT_SCHEMA: &str =
+    "iglu:com.snowplowanalytics.snowplow/mobile_context/jsonschema/1-0-2";
+
+/// A context entity that can be attached to an event.
+///
+/// Implement this trait on your own types to pass them to [Tracker::track_with_contexts](crate::Tracker::track_with_contexts),
+/// rather than building a [SelfDescribingJson] by hand.
+///
+/// ## Example
+/// ```
+/// use serde::Serialize;
+/// use serde_json::{json, Value};
+/// use snowplow_tracker::Context;
+///
+/// #[derive(Serialize)]
+/// struct WebPage {
+///     keywords: Vec<String>,
+/// }
+///
+/// impl Context for WebPage {
+///     fn schema(&self) -> &str {
+///         "iglu:org.schema/WebPage/jsonschema/1-0-0"
+///     }
+///
+///     fn to_json(&self) -> Value {
+///         json!(self)
+///     }
+/// }
+/// ```
+pub trait Context {
+    /// A valid Iglu schema path for the context entity
+    fn schema(&self) -> &str;
+    /// The data of the context entity, conforming to `schema`
+    fn to_json(&self) -> Value;
+}
+
+impl From<&dyn Context> for SelfDescribingJson {
+    fn from(context: &dyn Context) -> Self {
+        SelfDescribingJson::new(context.schema(), context.to_json())
+    }
+}
+
+impl From<Box<dyn Context>> for SelfDescribingJson {
+    fn from(context: Box<dyn Context>) -> Self {
+        SelfDescribingJson::from(context.as_ref())
+    }
+}
+
+/// Constructors for standard Snowplow context entities, with the correct schema URIs
+///
+/// ## Example
+/// ```
+/// use snowplow_tracker::Contexts;
+/// use uuid::Uuid;
+///
+/// let contexts = vec![
+///     Contexts::web_page(Uuid::new_v4()),
+///     Contexts::mobile("ios", "17.0", "Apple Inc.", "iPhone15,2"),
+/// ];
+/// ```
+pub struct Contexts;
+
+impl Contexts {
+    /// A `web_page` context entity, identifying the page view that an event happened on
+    pub fn web_page(page_view_id: Uuid) -> SelfDescribingJson {
+        SelfDescribingJson::new(WEB_PAGE_SCHEMA, json!({ "id": page_view_id }))
+    }
+
+    /// A `mobile_context` entity, describing the device an event happened on
+    pub fn mobile(
+        os_type: &str,
+        os_version: &str,
+        device_manufacturer: &str,
+        device_model: &str,
+    ) -> SelfDescribingJson {
+        SelfDescribingJson::new(
+            MOBILE_CONTEXT_SCHEMA,
+            json!({
+                "osType": os_type,
+                "osVersion": os_version,
+                "deviceManufacturer": device_manufacturer,
+                "deviceModel": device_model,
+            }),
+        )
+    }
+}
+
+/// A context entity describing the host the tracker is running on: its operating system, hostname and process id
+///
+/// Attach it to every event with [TrackerBuilder::host_context](crate::TrackerBuilder::host_context).
+/// There is no standard schema for it, so `schema` should be one in your own Iglu registry that accepts
+/// an object with a string `os`, a nullable string `hostname` and an integer `pid`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostContext {
+    /// The Iglu schema URI of the context entity
+    pub schema: String,
+    /// The operating system, as in [std::env::consts::OS]
+    pub os: String,
+    /// The hostname, if it could be determined
+    pub hostname: Option<String>,
+    /// The id of the current process
+    pub pid: u32,
+}
+
+impl HostContext {
+    /// Gathers the details of the current host and process, to send with the given `schema`
+    pub fn current(schema: &str) -> Self {
+        Self {
+            schema: schema.to_string(),
+            os: std::env::consts::OS.to_string(),
+            hostname: hostname(),
+            pid: std::process::id(),
+        }
+    }
+}
+
+impl Context for HostContext {
+    fn schema(&self) -> &str {
+        &self.schema
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "os": self.os,
+            "hostname": self.hostname,
+            "pid": self.pid,
+        })
+    }
+}
+
+// The standard library has no way to get the hostname, so the usual environment variables are
+// checked, falling back to `/etc/hostname` on Unix-like systems
+fn hostname() -> Option<String> {
+    std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|hostname| hostname.trim().to_string())
+        .filter(|hostname| !hostname.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Serialize;
+
+    use super::*;
+    use crate::payload::validate_schema_uri;
+
+    #[derive(Serialize)]
+    struct Product {
+        sku: String,
+        price: f64,
+    }
+
+    impl Context for Product {
+        fn schema(&self) -> &str {
+            "iglu:com.acme/product/jsonschema/1-0-0"
+        }
+
+        fn to_json(&self) -> Value {
+            json!(self)
+        }
+    }
+
+    #[test]
+    fn converts_context_to_self_describing_json() {
+        let context: Box<dyn Context> = Box::new(Product {
+            sku: "abc-123".to_string(),
+            price: 9.99,
+        });
+
+        let sdj = SelfDescribingJson::from(context);
+
+        assert_eq!(sdj.schema, "iglu:com.acme/product/jsonschema/1-0-0");
+        assert_eq!(sdj.data, json!({"sku": "abc-123", "price": 9.99}));
+    }
+
+    #[test]
+    fn web_page_context() {
+        let page_view_id = Uuid::new_v4();
+
+        let context = Contexts::web_page(page_view_id);
+
+        assert_eq!(
+            context.schema,
+            "iglu:com.snowplowanalytics.snowplow/web_page/jsonschema/1-0-0"
+        );
+        assert_eq!(context.data, json!({"id": page_view_id.to_string()}));
+        assert!(validate_schema_uri(&context.schema).is_ok());
+    }
+
+    #[test]
+    fn mobile_context() {
+        let context = Contexts::mobile("android", "14", "Google", "Pixel 8");
+
+        assert_eq!(
+            context.schema,
+            "iglu:com.snowplowanalytics.snowplow/mobile_context/jsonschema/1-0-2"
+        );
+        assert_eq!(
+            context.data,
+            json!({
+                "osType": "android",
+                "osVersion": "14",
+                "deviceManufacturer": "Google",
+                "deviceModel": "Pixel 8"
+            })
+        );
+        assert!(validate_schema_uri(&context.schema).is_ok());
+    }
+}
@@ -9,17 +9,36 @@
 // "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
 
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
-use crate::emitter::Emitter;
+use rand::Rng;
+use uuid::Uuid;
+
+use crate::collector_url::CollectorUrl;
+use crate::emitter::{Emitter, QueuePressure};
 use crate::error::Error;
-use crate::event_batch::EventBatch;
+use crate::event_batch::{EventBatch, DEFAULT_FIRST_RETRY_DELAY};
 use crate::event_store::DEFAULT_EVENT_STORE_CAPACITY;
-use crate::event_store::{EventStore, InMemoryEventStore};
+use crate::event_store::{AsyncEventStore, EventStore, InMemoryEventStore};
 use crate::http_client::ReqwestClient;
-use crate::payload::PayloadBuilder;
-use crate::HttpClient;
+use crate::payload::{validate_schema_uri, Payload, PayloadBuilder, SelfDescribingJson};
+use crate::priority::Priority;
+use crate::{HttpClient, RequestContext};
 
+use super::circuit_breaker::CircuitBreaker;
+use super::diagnostics::Diagnostics;
+use super::emitter_event::TrackerIdentity;
+use super::emitter_store::EmitterStore;
+use super::failover::Failover;
+use super::flush_progress::{FlushProgress, FlushProgressCallback};
+use super::heartbeat::heartbeat_batch;
+use super::pending_retries::PendingRetries;
+use super::rate_limiter::RateLimiter;
+use super::store_watermarks::{StoreLevel, StoreWatermarks};
+use super::undelivered::UndeliveredBatches;
 use super::RetryPolicy;
 
 /// An implementation of the [Emitter] trait that sends batched events to the Snowplow Collector.
@@ -28,12 +47,44 @@ pub struct BatchEmitter {
     collector_url: String,
     /// A [HttpClient](crate::HttpClient) implementation to send events to the Snowplow Collector
     http_client: Box<dyn HttpClient + Send + Sync>,
-    /// An [EventStore](crate::EventStore) implementation, used to queue events
-    event_store: Arc<Mutex<dyn EventStore + Send + Sync>>,
+    /// An [EventStore](crate::EventStore) or [AsyncEventStore](crate::AsyncEventStore) implementation, used to queue events
+    event_store: EmitterStore,
     /// The thread running the tokio runtime
     executor_handle: Option<std::thread::JoinHandle<()>>,
     /// The transmitter to send an [EmitterMessage] to the [Emitter] thread
     tx: tokio::sync::mpsc::Sender<EmitterMessage>,
+    /// Pauses sending after repeated failures, if configured
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    /// The randomised delay before the first batch is sent, if startup jitter is configured
+    startup_delay: Option<Duration>,
+    /// The reason the background thread stopped unexpectedly, if it has
+    background_error: Arc<Mutex<Option<String>>>,
+    /// The batches not yet delivered, recorded while closing with a timeout
+    undelivered: Arc<UndeliveredBatches>,
+    /// Notifies a callback as the event store fills and drains, if configured
+    store_watermarks: Option<Arc<StoreWatermarks>>,
+    /// The batches waiting to be retried
+    pending_retries: Arc<PendingRetries>,
+    /// The progress of each batch sent by [flush_with_progress](Emitter::flush_with_progress)
+    flush_progress: Arc<Mutex<HashMap<Uuid, Arc<FlushProgress>>>>,
+    /// Whether the batches of a flush are sent one at a time, in the order they were queued
+    ordered_flush: bool,
+    /// Flushes within this long of the last flush are ignored, if configured
+    flush_coalesce_window: Option<Duration>,
+    /// When the event store was last flushed
+    last_flush: Option<Instant>,
+    /// Context entities added to every event, after any set by the tracker
+    default_contexts: Vec<SelfDescribingJson>,
+    /// The settings to restart the background thread with if it stops unexpectedly, if configured
+    restart_settings: Option<(SendSettings, LoopSettings)>,
+    /// Whether sending is paused, shared with the emitter loop
+    paused: Arc<AtomicBool>,
+    /// How long dropping the emitter waits for the background thread to finish, if limited
+    drop_timeout: Option<Duration>,
+    /// Reports failures to a separate collector, if configured
+    diagnostics: Option<Arc<Diagnostics>>,
+    /// The tracker the emitter was given to, which heartbeats are attributed to, shared with the emitter loop
+    tracker: Arc<Mutex<Option<TrackerIdentity>>>,
 }
 
 /// Possible messages to send to the Emitter, sent via the [Emitter] transmitter
@@ -41,30 +92,112 @@ pub struct BatchEmitter {
 pub enum EmitterMessage {
     /// Sends a batch of events
     Send(EventBatch),
+    /// Sends batches of events one at a time, each once the previous batch has finished sending
+    SendInOrder(Vec<EventBatch>),
+    /// Sends all events currently in the [EventStore]
+    Flush,
+    /// Flushes the event store at the given time, unless a flush is already scheduled sooner
+    FlushAt(tokio::time::Instant),
+    /// Changes the collector URL used for batches sent after this message
+    SetCollectorUrl(String),
+    /// Adds an event to an [AsyncEventStore], sending any batches that fill
+    Add(Box<PayloadBuilder>),
+    /// Sends the events in an [AsyncEventStore]
+    FlushStore(StoreFlush),
+    /// Discards the events in an [AsyncEventStore] without sending them
+    Clear,
     /// Shuts down the [Emitter]
     /// This will also attempt to send all events currently in the [EventStore]
-    Close,
+    ///
+    /// The sender, if provided, is notified once the emitter loop has finished
+    Close(Option<tokio::sync::oneshot::Sender<()>>),
+}
+
+/// Which events an [EmitterMessage::FlushStore] sends
+pub enum StoreFlush {
+    /// Every event, as [flush](Emitter::flush) does
+    All,
+    /// Only full batches, as [flush_full_batches_only](Emitter::flush_full_batches_only) does
+    FullBatchesOnly,
+    /// Every event, reporting progress as [flush_with_progress](Emitter::flush_with_progress) does
+    WithProgress(FlushProgressCallback),
+}
+
+impl std::fmt::Debug for StoreFlush {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StoreFlush::All => write!(f, "All"),
+            StoreFlush::FullBatchesOnly => write!(f, "FullBatchesOnly"),
+            StoreFlush::WithProgress(_) => write!(f, "WithProgress"),
+        }
+    }
 }
 
 /// A builder for the [BatchEmitter] struct
 pub struct BatchEmitterBuilder {
     collector_url: Option<String>,
-    event_store: Arc<Mutex<dyn EventStore + Send + Sync>>,
+    event_store: EmitterStore,
     http_client: Option<Box<dyn HttpClient + Send + Sync>>,
     retry_policy: RetryPolicy,
+    priority_retry_policies: HashMap<Priority, RetryPolicy>,
+    first_retry_delay: (Duration, Duration),
+    non_retryable_codes: Vec<u16>,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    failover: Option<FailoverSettings>,
+    on_store_full: Option<StoreFullCallback>,
+    on_retry: Option<Arc<RetryCallback>>,
+    on_dead_letter: Option<Arc<DeadLetterCallback>>,
+    max_pending_retries: Option<usize>,
+    omit_empty_fields: bool,
+    diagnostics_url: Option<String>,
+    diagnostics_http_client: Option<Box<dyn HttpClient + Send + Sync>>,
+    max_requests_per_second: Option<u32>,
+    sort_by_dtm: bool,
+    loop_settings: LoopSettings,
 }
 
-impl BatchEmitterBuilder {
-    pub fn default() -> Self {
+// The backup collector URLs, failure threshold and probe interval set with `failover`
+type FailoverSettings = (Vec<String>, u32, Duration);
+
+// The watermarks, as fractions of the event store capacity, and the callback set with `on_store_full`
+type StoreFullCallback = (f64, f64, Box<dyn Fn(StoreLevel) + Send + Sync>);
+
+// The callback set with `on_retry`, called with the batch id, retry attempt and delay
+type RetryCallback = dyn Fn(Uuid, u32, Duration) + Send + Sync;
+
+// The callback set with `on_dead_letter`, called with the events that won't be delivered
+type DeadLetterCallback = dyn Fn(&[Payload]) + Send + Sync;
+
+impl Default for BatchEmitterBuilder {
+    fn default() -> Self {
         Self {
             collector_url: None,
-            event_store: Arc::new(Mutex::new(InMemoryEventStore::default())),
+            event_store: EmitterStore::new(InMemoryEventStore::default()),
             http_client: None,
             retry_policy: RetryPolicy::MaxRetries(10),
+            priority_retry_policies: HashMap::new(),
+            first_retry_delay: (DEFAULT_FIRST_RETRY_DELAY, Duration::ZERO),
+            non_retryable_codes: DONT_RETRY_STATUS_CODES.to_vec(),
+            circuit_breaker: None,
+            failover: None,
+            on_store_full: None,
+            on_retry: None,
+            on_dead_letter: None,
+            max_pending_retries: None,
+            omit_empty_fields: false,
+            diagnostics_url: None,
+            diagnostics_http_client: None,
+            max_requests_per_second: None,
+            sort_by_dtm: false,
+            loop_settings: LoopSettings::default(),
         }
     }
+}
 
+impl BatchEmitterBuilder {
     /// Set the URL of your Snowplow [Collector](https://docs.snowplow.io/docs/pipeline-components-and-applications/stream-collector/)
+    ///
+    /// The URL is validated and normalised as a [CollectorUrl] when the emitter is built
     pub fn collector_url(mut self, collector_url: &str) -> Self {
         self.collector_url = Some(collector_url.to_string());
         self
@@ -72,7 +205,18 @@ impl BatchEmitterBuilder {
 
     /// Set the [EventStore] implementation  
     pub fn event_store(mut self, event_store: impl EventStore + Send + Sync + 'static) -> Self {
-        self.event_store = Arc::new(Mutex::new(event_store));
+        self.event_store = EmitterStore::new(event_store);
+        self
+    }
+
+    /// Set an [AsyncEventStore] implementation, for event stores backed by an async database or service
+    ///
+    /// The store is only awaited on the emitter's background runtime, so it should open any connections
+    /// lazily, rather than on the application's runtime. Adding, flushing and clearing events queue a
+    /// message for the emitter loop instead of waiting for the store, so errors from the store, such as
+    /// it being full, are logged rather than returned.
+    pub fn async_event_store(mut self, event_store: impl AsyncEventStore + 'static) -> Self {
+        self.event_store = EmitterStore::new_async(event_store);
         self
     }
 
@@ -88,26 +232,438 @@ impl BatchEmitterBuilder {
         self
     }
 
+    /// Set the retry policy for batches containing events of the given [Priority]
+    ///
+    /// A batch uses the policy of its highest priority event, falling back to the
+    /// [retry_policy](BatchEmitterBuilder::retry_policy) if none is set for that priority.
+    pub fn priority_retry_policy(mut self, priority: Priority, retry_policy: RetryPolicy) -> Self {
+        self.priority_retry_policies.insert(priority, retry_policy);
+        self
+    }
+
+    /// Delay the first retry of a failed batch by `first_delay`, plus or minus a random amount up to `jitter`
+    ///
+    /// Defaults to a fixed delay of 1 second. Jittering the first retry avoids many instances retrying
+    /// at the same time after a shared collector outage. Later retries are always jittered.
+    pub fn first_retry_delay(mut self, first_delay: Duration, jitter: Duration) -> Self {
+        self.first_retry_delay = (first_delay, jitter);
+        self
+    }
+
+    /// Set the HTTP status codes that should not be retried
+    ///
+    /// Defaults to `[400, 401, 403, 410, 422]`
+    pub fn non_retryable_codes(mut self, codes: &[u16]) -> Self {
+        self.non_retryable_codes = codes.to_vec();
+        self
+    }
+
+    /// Pause sending for `cooldown` after `failure_threshold` consecutive batches fail to send
+    ///
+    /// The failure threshold must be greater than zero. While sending is paused, [BatchEmitter::is_healthy](crate::Emitter::is_healthy) returns `false`
+    pub fn circuit_breaker(mut self, failure_threshold: u32, cooldown: Duration) -> Self {
+        self.circuit_breaker = Some(Arc::new(CircuitBreaker::new(failure_threshold, cooldown)));
+        self
+    }
+
+    /// Fail over to the next of `backup_urls` after `failure_threshold` consecutive batches fail to send
+    ///
+    /// While a backup collector is in use, the primary [collector_url](BatchEmitterBuilder::collector_url)
+    /// is probed every `probe_interval` by sending to it again. If the primary is still down, sending fails
+    /// over again once the failure threshold is reached, which must be greater than zero. This requires the [HttpClient] to support
+    /// [set_collector_url](HttpClient::set_collector_url), as [ReqwestClient] does.
+    pub fn failover(
+        mut self,
+        backup_urls: &[&str],
+        failure_threshold: u32,
+        probe_interval: Duration,
+    ) -> Self {
+        let backup_urls = backup_urls.iter().map(|url| url.to_string()).collect();
+        self.failover = Some((backup_urls, failure_threshold, probe_interval));
+        self
+    }
+
+    /// Report batches that the emitter gives up sending as diagnostic events, sent to the collector at `collector_url`
+    ///
+    /// Each diagnostic is a self-describing event using the
+    /// `iglu:com.snowplowanalytics.snowplow/diagnostic_error/jsonschema/1-0-0` schema, sent once without
+    /// retries, in the background with a short timeout so a slow diagnostics collector never holds up the
+    /// emitter. A diagnostic is also sent when the event store first rejects an event because it is full.
+    /// Diagnostics that fail to send are only logged, so they never cause further diagnostics.
+    pub fn diagnostics(mut self, collector_url: &str) -> Self {
+        self.diagnostics_url = Some(collector_url.to_string());
+        self
+    }
+
+    /// Set the [HttpClient] used to send [diagnostics](BatchEmitterBuilder::diagnostics)
+    ///
+    /// The client is pointed at the diagnostics collector URL with [HttpClient::set_collector_url], so
+    /// [build](BatchEmitterBuilder::build) fails if the client doesn't support changing its collector URL.
+    pub fn diagnostics_http_client(
+        mut self,
+        http_client: impl HttpClient + Send + Sync + 'static,
+    ) -> Self {
+        self.diagnostics_http_client = Some(Box::new(http_client));
+        self
+    }
+
+    /// Sort the events in each batch by the time they were created before sending, when `enabled`
+    ///
+    /// The [InMemoryEventStore] keeps events in the order they were added, but other event stores,
+    /// such as ones shared between processes, may not.
+    pub fn sort_by_dtm(mut self, enabled: bool) -> Self {
+        self.sort_by_dtm = enabled;
+        self
+    }
+
+    /// Limit the number of requests sent to the collector to `requests` per second
+    ///
+    /// Batches over the limit, including retries, wait their turn rather than failing, so a burst of
+    /// events doesn't trip the rate limits of a shared collector or proxy. Requests are spaced evenly,
+    /// so at most one is sent every `1 / requests` seconds.
+    pub fn max_requests_per_second(mut self, requests: u32) -> Self {
+        self.max_requests_per_second = Some(requests);
+        self
+    }
+
+    /// Call `callback` when the event store fills to `high_watermark`, and again once it drains below `low_watermark`
+    ///
+    /// The watermarks are fractions of the event store capacity, such as `0.9` and `0.5`. This gives
+    /// producers a chance to throttle before [add](crate::Emitter::add) fails because the store is full.
+    /// [build](BatchEmitterBuilder::build) fails unless both are between 0 and 1, and `low_watermark` is
+    /// below `high_watermark`.
+    /// The callback is called on the thread that adds or flushes events, so it should return quickly.
+    pub fn on_store_full(
+        mut self,
+        high_watermark: f64,
+        low_watermark: f64,
+        callback: impl Fn(StoreLevel) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_store_full = Some((high_watermark, low_watermark, Box::new(callback)));
+        self
+    }
+
+    /// Call `callback` each time a batch is scheduled to be retried, with the batch id, the retry attempt
+    /// (starting from 1) and the delay before the retry is sent
+    ///
+    /// This is useful for monitoring retry rates. The callback is called on the emitter's background thread,
+    /// so it should return quickly.
+    pub fn on_retry(
+        mut self,
+        callback: impl Fn(Uuid, u32, Duration) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_retry = Some(Arc::new(callback));
+        self
+    }
+
+    /// Call `callback` with the events of each batch the emitter gives up on without delivering
+    ///
+    /// This is the emitter's dead-letter hook: it receives batches that run out of retry attempts, are
+    /// rejected with a non-retryable status code, or are dropped by [max_pending_retries](Self::max_pending_retries),
+    /// and events dropped because they [expired](crate::TrackOptions::expire_at) before being sent,
+    /// so the application can persist them elsewhere. The callback is called on the emitter's background
+    /// thread, so it should return quickly.
+    pub fn on_dead_letter(mut self, callback: impl Fn(&[Payload]) + Send + Sync + 'static) -> Self {
+        self.on_dead_letter = Some(Arc::new(callback));
+        self
+    }
+
+    /// Limit the number of batches waiting to be retried at once to `max`
+    ///
+    /// During a long collector outage, particularly with [RetryPolicy::RetryForever], every failed batch
+    /// is held in memory until its next retry. Once `max` batches are waiting, the oldest is dropped
+    /// to make room, as if it had run out of retry attempts, and passed to the [on_dead_letter](Self::on_dead_letter)
+    /// callback if one is set. By default, there is no limit.
+    pub fn max_pending_retries(mut self, max: usize) -> Self {
+        self.max_pending_retries = Some(max);
+        self
+    }
+
+    /// Delay the first send by a random amount of time, up to `max_delay`
+    ///
+    /// This avoids many instances restarting at the same time from sending to the collector in lockstep
+    pub fn startup_jitter(mut self, max_delay: Duration) -> Self {
+        self.loop_settings.startup_jitter = Some(max_delay);
+        self
+    }
+
+    /// Send a heartbeat event every `interval`, to show that the emitter is alive
+    ///
+    /// Each heartbeat is the self-describing `event`, such as one with your own
+    /// `iglu:com.acme/heartbeat/jsonschema/1-0-0` schema. Heartbeats have the namespace and app id of
+    /// the [Tracker](crate::Tracker) the emitter is given to, or an app id of `snowplow-rust-tracker`
+    /// before it is given to one. Each is sent in its own batch, bypassing the event store, and none are
+    /// sent while the emitter is [paused](BatchEmitter::pause). Disabled by default.
+    ///
+    /// [build](BatchEmitterBuilder::build) fails if the event's schema isn't a valid Iglu schema URI.
+    pub fn heartbeat(mut self, interval: Duration, event: SelfDescribingJson) -> Self {
+        self.loop_settings.heartbeat = Some((interval, event));
+        self
+    }
+
+    /// Periodically send all events in the event store, even if there are not enough to fill a batch
+    ///
+    /// This stops events sitting in the event store indefinitely when few events are tracked
+    pub fn flush_interval(mut self, interval: Duration) -> Self {
+        self.loop_settings.flush_interval = Some(interval);
+        self
+    }
+
+    /// Send all events in the event store once the oldest event has waited for `max_latency`,
+    /// even if there are not enough to fill a batch
+    ///
+    /// Events are still sent as soon as a batch fills, so this bounds how long an event waits when few
+    /// events are tracked. The age of the oldest event is checked every tenth of `max_latency`. This requires
+    /// an [EventStore] that tracks when events were added, such as [InMemoryEventStore].
+    pub fn max_latency(mut self, max_latency: Duration) -> Self {
+        self.loop_settings.max_latency = Some(max_latency);
+        self
+    }
+
+    /// Set the number of worker threads used by the emitter's tokio runtime
+    ///
+    /// Defaults to tokio's default, the number of CPU cores. Lowering this avoids many threads
+    /// when several emitters run in the same process, and it must be greater than zero.
+    pub fn worker_threads(mut self, worker_threads: usize) -> Self {
+        self.loop_settings.worker_threads = Some(worker_threads);
+        self
+    }
+
+    /// Set the stack size, in bytes, of the threads of the emitter's tokio runtime
+    ///
+    /// Defaults to tokio's default, currently 2 MiB. Serializing very large or deeply nested context
+    /// entities may need a larger stack.
+    pub fn thread_stack_size(mut self, stack_size: usize) -> Self {
+        self.loop_settings.thread_stack_size = Some(stack_size);
+        self
+    }
+
+    /// Set the maximum number of threads the emitter's tokio runtime uses for blocking work
+    ///
+    /// Defaults to tokio's default, currently 512. Blocking threads are only started when needed, and
+    /// must be greater than zero.
+    pub fn max_blocking_threads(mut self, max_blocking_threads: usize) -> Self {
+        self.loop_settings.max_blocking_threads = Some(max_blocking_threads);
+        self
+    }
+
+    /// Send the batches of each [flush](Emitter::flush) one at a time, so they reach the collector in the order
+    /// their events were queued
+    ///
+    /// By default, batches are sent concurrently, so the final partial batch of a flush can arrive before
+    /// the full batches queued ahead of it. A batch that fails is retried separately, so retries are not ordered.
+    pub fn ordered_flush(mut self, enabled: bool) -> Self {
+        self.loop_settings.ordered_flush = enabled;
+        self
+    }
+
+    /// Coalesce calls to [flush](Emitter::flush) within `window` of the last flush into a single flush
+    /// when the window ends
+    ///
+    /// This stops code that flushes in a tight loop from draining the event store into many tiny batches,
+    /// while events added during the window are still sent once it ends. Closing the emitter always flushes.
+    pub fn flush_coalesce_window(mut self, window: Duration) -> Self {
+        self.loop_settings.flush_coalesce_window = Some(window);
+        self
+    }
+
+    /// Leave out event fields that are empty strings, or empty JSON objects or arrays, when sending events
+    ///
+    /// Optional fields that are not set are always left out. This also removes fields that are set but empty,
+    /// such as a structured event property of `""`, to reduce the size of each request.
+    pub fn omit_empty_fields(mut self, enabled: bool) -> Self {
+        self.omit_empty_fields = enabled;
+        self
+    }
+
+    /// Restart the background thread that sends events if it stops unexpectedly, such as after a panic
+    ///
+    /// By default, once the thread has stopped, [add](Emitter::add) and [flush](Emitter::flush) return
+    /// [Error::EmitterDead], rather than queueing events that will never be sent. Batches that were being
+    /// sent when the thread stopped are lost.
+    pub fn restart_on_failure(mut self, enabled: bool) -> Self {
+        self.loop_settings.restart_on_failure = enabled;
+        self
+    }
+
+    /// Limit how long dropping the emitter waits for its background thread to finish to `timeout`
+    ///
+    /// By default, dropping the emitter waits until the thread has finished, which hangs the dropping thread
+    /// if a send is stuck. With a timeout, a thread still running after `timeout` is logged and left to
+    /// finish on its own, and any events it hasn't sent yet may be lost.
+    pub fn drop_timeout(mut self, timeout: Duration) -> Self {
+        self.loop_settings.drop_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the name of the background thread that sends events, `snowplow-emitter` by default
+    ///
+    /// The name shows up in panic messages, profilers and debuggers, so giving each emitter its own
+    /// name, such as `snowplow-emitter-{namespace}`, tells apart the emitters of several trackers.
+    /// Emitters created by [Snowplow::create_tracker](crate::Snowplow::create_tracker) and
+    /// [TrackerBuilder::from_config](crate::TrackerBuilder::from_config) are named after the tracker's
+    /// namespace this way. The worker threads of the emitter's runtime are named `{name}-worker`.
+    ///
+    /// [build](BatchEmitterBuilder::build) fails if the name contains a null byte.
+    pub fn thread_name(mut self, name: &str) -> Self {
+        self.loop_settings.thread_name = Some(name.to_string());
+        self
+    }
+
+    /// Add `contexts` to every event tracked through the emitter
+    ///
+    /// Unlike contexts set on a [Tracker](crate::Tracker), these apply to events from every tracker using
+    /// the emitter. Trackers add them after the contexts passed when tracking, so they count towards the
+    /// tracker's context size limit and duplicate check.
+    pub fn default_contexts(mut self, contexts: Vec<SelfDescribingJson>) -> Self {
+        self.loop_settings.default_contexts = contexts;
+        self
+    }
+
     /// Build the [BatchEmitter]
     pub fn build(self) -> Result<BatchEmitter, Error> {
         match self.collector_url {
             Some(collector_url) => {
-                let event_store_capacity = match self.event_store.lock() {
-                    Ok(event_store) => event_store.capacity(),
-                    Err(e) => {
-                        return Err(Error::EventStoreError(
-                            format!("Failed to lock event store: {}", e).to_string(),
+                let collector_url: CollectorUrl = collector_url.parse()?;
+                let event_store_capacity = self.event_store.capacity()?;
+
+                // tokio panics when the runtime is built with no worker or blocking threads
+                if self.loop_settings.worker_threads == Some(0) {
+                    return Err(Error::BuilderError(
+                        "Worker threads must be greater than zero".to_string(),
+                    ));
+                }
+                if self.loop_settings.max_blocking_threads == Some(0) {
+                    return Err(Error::BuilderError(
+                        "Max blocking threads must be greater than zero".to_string(),
+                    ));
+                }
+
+                let circuit_breaker_threshold = self
+                    .circuit_breaker
+                    .as_ref()
+                    .map(|circuit_breaker| circuit_breaker.failure_threshold());
+                let failover_threshold = self.failover.as_ref().map(|(_, threshold, _)| *threshold);
+                if circuit_breaker_threshold == Some(0) || failover_threshold == Some(0) {
+                    return Err(Error::BuilderError(
+                        "Failure threshold must be greater than zero".to_string(),
+                    ));
+                }
+
+                if let Some((high_watermark, low_watermark, _)) = &self.on_store_full {
+                    let fractions = 0.0..=1.0;
+                    if !fractions.contains(high_watermark) || !fractions.contains(low_watermark) {
+                        return Err(Error::BuilderError(
+                            "Store watermarks must be between 0 and 1".to_string(),
+                        ));
+                    }
+                    if low_watermark >= high_watermark {
+                        return Err(Error::BuilderError(
+                            "Low store watermark must be below the high watermark".to_string(),
+                        ));
+                    }
+                }
+
+                if let Some((_, event)) = &self.loop_settings.heartbeat {
+                    validate_schema_uri(&event.schema)?;
+                }
+
+                if let Some(thread_name) = &self.loop_settings.thread_name {
+                    if thread_name.contains('\0') {
+                        return Err(Error::BuilderError(
+                            "Thread name must not contain null bytes".to_string(),
+                        ));
+                    }
+                }
+
+                let mut loop_settings = self.loop_settings;
+                loop_settings.store_watermarks =
+                    self.on_store_full
+                        .map(|(high_watermark, low_watermark, callback)| {
+                            Arc::new(StoreWatermarks::new(
+                                event_store_capacity,
+                                high_watermark,
+                                low_watermark,
+                                callback,
+                            ))
+                        });
+
+                let mut http_client = self
+                    .http_client
+                    .unwrap_or(ReqwestClient::new(collector_url.clone()));
+
+                let failover = match self.failover {
+                    Some((backup_urls, failure_threshold, probe_interval)) => {
+                        let backup_urls = backup_urls
+                            .iter()
+                            .map(|url| Ok(url.parse::<CollectorUrl>()?.to_string()))
+                            .collect::<Result<Vec<_>, Error>>()?;
+
+                        // Fail early if the client can't switch between collectors
+                        http_client.set_collector_url(collector_url.as_str())?;
+                        Some(Arc::new(Failover::new(
+                            collector_url.as_str(),
+                            backup_urls,
+                            failure_threshold,
+                            probe_interval,
+                        )))
+                    }
+                    None => None,
+                };
+
+                let rate_limiter = match self.max_requests_per_second {
+                    Some(0) => {
+                        return Err(Error::BuilderError(
+                            "Max requests per second must be greater than zero".to_string(),
                         ))
                     }
+                    Some(requests) => Some(Arc::new(RateLimiter::new(requests))),
+                    None => None,
+                };
+
+                let diagnostics = match self.diagnostics_url {
+                    Some(diagnostics_url) => {
+                        let diagnostics_url: CollectorUrl = diagnostics_url.parse()?;
+                        let client = match self.diagnostics_http_client {
+                            Some(mut client) => {
+                                client.set_collector_url(diagnostics_url.as_str()).map_err(|_| {
+                                    Error::BuilderError(
+                                        "The diagnostics HTTP client must support changing its collector URL"
+                                            .to_string(),
+                                    )
+                                })?;
+                                client
+                            }
+                            None => ReqwestClient::new(diagnostics_url),
+                        };
+                        Some(Arc::new(Diagnostics::new(client)))
+                    }
+                    None => None,
                 };
 
                 Ok(BatchEmitter::create_emitter(
-                    &collector_url,
+                    collector_url.as_str(),
                     event_store_capacity,
                     self.event_store,
-                    self.http_client
-                        .unwrap_or(ReqwestClient::new(&collector_url)),
-                    self.retry_policy,
+                    http_client,
+                    SendSettings {
+                        retry_policy: self.retry_policy,
+                        priority_retry_policies: Arc::new(self.priority_retry_policies),
+                        first_retry_delay: self.first_retry_delay,
+                        non_retryable_codes: Arc::new(self.non_retryable_codes),
+                        circuit_breaker: self.circuit_breaker,
+                        failover,
+                        pending_retries: Arc::new(PendingRetries::new(self.max_pending_retries)),
+                        flush_progress: Arc::new(Mutex::new(HashMap::new())),
+                        on_retry: self.on_retry,
+                        on_dead_letter: self.on_dead_letter,
+                        omit_empty_fields: self.omit_empty_fields,
+                        diagnostics,
+                        rate_limiter,
+                        sort_by_dtm: self.sort_by_dtm,
+                        ..SendSettings::default()
+                    },
+                    loop_settings,
                 ))
             }
             None => Err(Error::EmitterError("Collector URL is required".to_string())),
@@ -115,9 +671,113 @@ impl BatchEmitterBuilder {
     }
 }
 
-// HTTP status codes that should not be retried
+// The name of the background thread, unless configured otherwise
+const DEFAULT_THREAD_NAME: &str = "snowplow-emitter";
+
+// The name of the emitter thread of the tracker with `namespace`
+pub(crate) fn thread_name_for(namespace: &str) -> String {
+    format!("{DEFAULT_THREAD_NAME}-{namespace}")
+}
+
+// HTTP status codes that should not be retried, unless configured otherwise
 const DONT_RETRY_STATUS_CODES: [u16; 5] = [400, 401, 403, 410, 422];
 
+// How the emitter handles a response code from the collector
+#[derive(Debug, PartialEq, Eq)]
+enum ResponseAction {
+    // The collector accepted the batch
+    Success,
+    // The collector redirected the request, and the redirect was not followed by the HttpClient.
+    // Retrying the same URL would be redirected again, so the batch is not retried
+    Redirect,
+    // The batch failed to send and can be retried
+    Retry,
+    // The batch failed to send and should not be retried
+    Fail,
+}
+
+// Settings used when sending a batch, shared between the emitter loop and its send tasks
+#[derive(Clone)]
+struct SendSettings {
+    retry_policy: RetryPolicy,
+    priority_retry_policies: Arc<HashMap<Priority, RetryPolicy>>,
+    // The delay before the first retry of a batch, and the maximum jitter applied to it
+    first_retry_delay: (Duration, Duration),
+    non_retryable_codes: Arc<Vec<u16>>,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    failover: Option<Arc<Failover>>,
+    // The batches not yet delivered, recorded while closing with a timeout
+    undelivered: Arc<UndeliveredBatches>,
+    // The batches that have been re-queued, but not yet sent again
+    pending_retries: Arc<PendingRetries>,
+    // The flush each batch belongs to, for batches sent with progress reporting
+    flush_progress: Arc<Mutex<HashMap<Uuid, Arc<FlushProgress>>>>,
+    on_retry: Option<Arc<RetryCallback>>,
+    on_dead_letter: Option<Arc<DeadLetterCallback>>,
+    omit_empty_fields: bool,
+    diagnostics: Option<Arc<Diagnostics>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    sort_by_dtm: bool,
+    // Batches are held until this instant, set from the startup delay when the emitter loop starts
+    send_after: Option<tokio::time::Instant>,
+    // Set once the emitter loop is closing, so batches stop waiting to be sent
+    closing: tokio::sync::watch::Receiver<bool>,
+}
+
+// Settings for the emitter loop and the tokio runtime it runs on
+#[derive(Default, Clone)]
+struct LoopSettings {
+    startup_jitter: Option<Duration>,
+    flush_interval: Option<Duration>,
+    max_latency: Option<Duration>,
+    worker_threads: Option<usize>,
+    thread_stack_size: Option<usize>,
+    max_blocking_threads: Option<usize>,
+    store_watermarks: Option<Arc<StoreWatermarks>>,
+    ordered_flush: bool,
+    flush_coalesce_window: Option<Duration>,
+    default_contexts: Vec<SelfDescribingJson>,
+    restart_on_failure: bool,
+    paused: Arc<AtomicBool>,
+    drop_timeout: Option<Duration>,
+    thread_name: Option<String>,
+    // The interval and self-describing event of heartbeats
+    heartbeat: Option<(Duration, SelfDescribingJson)>,
+    tracker: Arc<Mutex<Option<TrackerIdentity>>>,
+}
+
+impl SendSettings {
+    // The retry policy for a batch, based on its priority
+    fn retry_policy_for(&self, batch: &EventBatch) -> RetryPolicy {
+        self.priority_retry_policies
+            .get(&batch.priority)
+            .copied()
+            .unwrap_or(self.retry_policy)
+    }
+
+    fn default() -> Self {
+        Self {
+            retry_policy: RetryPolicy::MaxRetries(10),
+            priority_retry_policies: Arc::new(HashMap::new()),
+            first_retry_delay: (DEFAULT_FIRST_RETRY_DELAY, Duration::ZERO),
+            non_retryable_codes: Arc::new(DONT_RETRY_STATUS_CODES.to_vec()),
+            circuit_breaker: None,
+            failover: None,
+            undelivered: Arc::new(UndeliveredBatches::default()),
+            pending_retries: Arc::new(PendingRetries::default()),
+            flush_progress: Arc::new(Mutex::new(HashMap::new())),
+            on_retry: None,
+            on_dead_letter: None,
+            omit_empty_fields: false,
+            diagnostics: None,
+            rate_limiter: None,
+            sort_by_dtm: false,
+            send_after: None,
+            closing: tokio::sync::watch::channel(false).1,
+        }
+    }
+}
+
 /// The batch sent to the Snowplow Collector and the response code
 pub struct SentBatchResponse {
     pub batch: EventBatch,
@@ -132,400 +792,3347 @@ impl BatchEmitter {
     fn create_emitter(
         collector_url: &str,
         event_store_capacity: usize,
-        event_store: Arc<Mutex<dyn EventStore + Send + Sync>>,
+        event_store: EmitterStore,
         http_client: Box<dyn HttpClient + Send + Sync>,
-        retry_policy: RetryPolicy,
+        send_settings: SendSettings,
+        loop_settings: LoopSettings,
     ) -> BatchEmitter {
         let (tx, rx) = tokio::sync::mpsc::channel(event_store_capacity);
+        let startup_delay = loop_settings
+            .startup_jitter
+            .map(|max_delay| rand::thread_rng().gen_range(Duration::ZERO..=max_delay));
         let mut emitter = BatchEmitter {
             collector_url: collector_url.to_string(),
             http_client,
             event_store,
             executor_handle: None,
             tx,
+            circuit_breaker: send_settings.circuit_breaker.clone(),
+            startup_delay,
+            background_error: Arc::new(Mutex::new(None)),
+            undelivered: send_settings.undelivered.clone(),
+            store_watermarks: loop_settings.store_watermarks.clone(),
+            pending_retries: send_settings.pending_retries.clone(),
+            flush_progress: send_settings.flush_progress.clone(),
+            ordered_flush: loop_settings.ordered_flush,
+            flush_coalesce_window: loop_settings.flush_coalesce_window,
+            last_flush: None,
+            default_contexts: loop_settings.default_contexts.clone(),
+            restart_settings: loop_settings
+                .restart_on_failure
+                .then(|| (send_settings.clone(), loop_settings.clone())),
+            paused: loop_settings.paused.clone(),
+            drop_timeout: loop_settings.drop_timeout,
+            diagnostics: send_settings.diagnostics.clone(),
+            tracker: loop_settings.tracker.clone(),
         };
 
+        emitter.spawn_background_thread(rx, send_settings, startup_delay, loop_settings);
+        emitter
+    }
+
+    // Spawns the thread running the tokio runtime, which receives messages from `rx`
+    fn spawn_background_thread(
+        &mut self,
+        rx: tokio::sync::mpsc::Receiver<EmitterMessage>,
+        send_settings: SendSettings,
+        startup_delay: Option<Duration>,
+        loop_settings: LoopSettings,
+    ) {
         // Clone http client to be used in the spawned thread
-        let client = emitter.http_client.clone();
-        let store = emitter.event_store.clone();
+        let client = self.http_client.clone();
+        let store = self.event_store.clone();
+        let background_error = self.background_error.clone();
+
+        // Thread names can't contain null bytes, which a namespace passed to `new_named` might
+        let thread_name = loop_settings
+            .thread_name
+            .clone()
+            .filter(|name| !name.contains('\0'))
+            .unwrap_or_else(|| DEFAULT_THREAD_NAME.to_string());
 
         // Spawn the tokio runtime in a separate thread
-        emitter.executor_handle = Some(std::thread::spawn(move || {
-            BatchEmitter::start_tokio(client, rx, store, retry_policy);
-        }));
+        // Unwrap here as if the thread fails to start, there is nothing we can do
+        let thread = std::thread::Builder::new().name(thread_name);
+        self.executor_handle = Some(
+            thread
+                .spawn(move || {
+                    // A panic is caught and recorded, rather than being propagated when the thread is joined
+                    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                        BatchEmitter::start_tokio(
+                            client,
+                            rx,
+                            store,
+                            send_settings,
+                            startup_delay,
+                            loop_settings,
+                        );
+                    }));
 
-        emitter
+                    if let Err(panic) = result {
+                        let message = panic_message(panic.as_ref());
+                        log::error!("BatchEmitter thread panicked: {message}");
+                        if let Ok(mut background_error) = background_error.lock() {
+                            *background_error = Some(message);
+                        }
+                    }
+                })
+                .unwrap(),
+        );
     }
 
-    /// Create a new [BatchEmitter] with an [InMemoryEventStore]
-    pub fn new(collector_url: &str) -> BatchEmitter {
-        BatchEmitter::create_emitter(
-            collector_url,
-            DEFAULT_EVENT_STORE_CAPACITY,
-            Arc::new(Mutex::new(InMemoryEventStore::default())),
-            ReqwestClient::new(collector_url),
-            RetryPolicy::MaxRetries(10),
-        )
-    }
+    // Checks that the background thread is still running, restarting it if configured to
+    fn ensure_running(&mut self) -> Result<(), Error> {
+        let reason = match self.background_error() {
+            Some(reason) => reason,
+            None => return Ok(()),
+        };
+        let (send_settings, loop_settings) = match &self.restart_settings {
+            Some((send_settings, loop_settings)) => (send_settings.clone(), loop_settings.clone()),
+            None => return Err(Error::EmitterDead(reason)),
+        };
 
-    // Static Methods
+        log::warn!("Restarting BatchEmitter thread, which stopped unexpectedly: {reason}");
+        // The thread has already recorded its panic, so is about to finish
+        if let Some(handle) = self.executor_handle.take() {
+            let _ = handle.join();
+        }
 
-    fn is_successful_response(code: u16) -> bool {
-        code >= 200 && code < 300
+        let capacity = self.event_store.capacity()?;
+        let (tx, rx) = tokio::sync::mpsc::channel(capacity);
+        self.tx = tx;
+        if let Ok(mut background_error) = self.background_error.lock() {
+            *background_error = None;
+        }
+
+        self.spawn_background_thread(rx, send_settings, None, loop_settings);
+        Ok(())
     }
 
-    // True if the code is outside 200-299 and not in DONT_RETRY_STATUS_CODES
-    fn should_retry(code: u16) -> bool {
-        match Self::is_successful_response(code) {
-            true => false,
-            false => !DONT_RETRY_STATUS_CODES.contains(&code),
-        }
+    /// The reason the background thread sending events stopped unexpectedly, if it has
+    ///
+    /// Once the background thread has stopped, no more events are sent to the collector
+    pub fn background_error(&self) -> Option<String> {
+        self.background_error
+            .lock()
+            .ok()
+            .and_then(|background_error| background_error.clone())
     }
 
-    fn retry_batch(
-        mut batch: EventBatch,
-        retry_tx: tokio::sync::mpsc::UnboundedSender<EmitterMessage>,
-    ) {
-        batch.update_for_retry();
+    /// Stop sending events until [resume](BatchEmitter::resume) is called, such as during a collector maintenance window
+    ///
+    /// Events added while paused are kept in the event store, and flushes, including those on the
+    /// [flush_interval](BatchEmitterBuilder::flush_interval), are skipped. Batches already being sent or
+    /// retried carry on. Closing the emitter still sends all events.
+    pub fn pause(&self) {
+        log::info!("Pausing BatchEmitter");
+        self.paused.store(true, Ordering::SeqCst);
+    }
 
-        let batch_id = batch.id;
-        match retry_tx.send(EmitterMessage::Send(batch)) {
-            Ok(_) => log::debug!("Batch {batch_id} re-queued"),
-            Err(e) => {
-                log::warn!("Failed to re-queue batch {batch_id}: {e}")
-            }
-        }
+    /// Resume sending events after [pause](BatchEmitter::pause), sending the full batches buffered while paused
+    ///
+    /// Any remaining events are sent once a batch fills, or on the next flush.
+    pub fn resume(&mut self) -> Result<(), Error> {
+        log::info!("Resuming BatchEmitter");
+        self.paused.store(false, Ordering::SeqCst);
+        self.flush_full_batches_only()
     }
 
-    fn run_cleanup(
-        store: Arc<Mutex<dyn EventStore + Send + Sync>>,
-        batch: EventBatch,
-    ) -> Result<(), Error> {
-        let mut store_guard = match store.lock() {
-            Ok(guard) => guard,
-            Err(e) => {
-                return Err(Error::EmitterError(format!(
-                    "Failed to acquire event store lock: {e}"
-                )))
-            }
-        };
+    /// Whether sending is [paused](BatchEmitter::pause)
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
 
-        match store_guard.cleanup_after_send_attempt(batch.id) {
-            Ok(_) => log::debug!("Cleanup run for batch: {}", batch.id),
-            Err(e) => return Err(Error::EmitterError(format!("Failed to cleanup: {e}"))),
+    /// Send all events in the event store, then shut down the emitter, waiting until sending has finished
+    ///
+    /// Unlike [close](Emitter::close), which only queues the shutdown, this resolves once all
+    /// in-flight batches have been sent, without blocking the async runtime it is awaited on.
+    /// Batches that fail and would be retried are not sent again.
+    pub async fn close_async(&mut self) -> Result<(), Error> {
+        // An async store may have events queued to be added, so is always flushed
+        let has_events = match &self.event_store {
+            EmitterStore::Sync(store) => match store.lock() {
+                Ok(store) => !store.is_empty(),
+                Err(e) => return Err(Error::EmitterError(e.to_string())),
+            },
+            EmitterStore::Async { .. } => true,
         };
+        if has_events {
+            self.flush_event_store()?;
+        }
 
-        Ok(())
+        let (done_tx, done_rx) = tokio::sync::oneshot::channel();
+        self.tx
+            .send(EmitterMessage::Close(Some(done_tx)))
+            .await
+            .map_err(|e| Error::EmitterError(e.to_string()))?;
+
+        done_rx
+            .await
+            .map_err(|_| Error::EmitterError("Emitter thread stopped before closing".to_string()))
     }
 
-    async fn batch_send_task(
-        mut batch: EventBatch,
-        client: Box<dyn HttpClient + Send + Sync>,
-        retry_tx: tokio::sync::mpsc::UnboundedSender<EmitterMessage>,
-        store: Arc<Mutex<dyn EventStore + Send + Sync>>,
-        retry_policy: RetryPolicy,
-    ) {
-        if let Some(delay) = batch.delay {
-            log::debug!("Delaying batch {} for {:?}", batch.id, delay);
-            tokio::time::sleep(delay).await;
+    /// Like [close_async](BatchEmitter::close_async), but stops waiting once `timeout` has elapsed
+    ///
+    /// If any events have not been sent when closing finishes or the timeout elapses, they are returned in
+    /// [Error::UndeliveredEvents] so they can be persisted by the application. This includes batches
+    /// still queued or waiting to be retried. Events still being sent when the timeout elapses may yet
+    /// reach the collector.
+    pub async fn close_with_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
+        // Batches sent before this point are only recorded once they fail, so an emitter that never
+        // closes with a timeout doesn't keep a copy of every batch
+        self.undelivered.start();
+        let result = tokio::time::timeout(timeout, self.close_async()).await;
 
-            if let Err(e) = batch.update_event_stm() {
-                // If the update fails, we just re-send the batch as-is
-                // Not ideal, but it's better than losing events
-                log::warn!(
-                    "Failed to update stm of events in batch {} for retry: {e}",
-                    batch.id
-                )
-            };
-        };
+        let undelivered = self.undelivered.take();
+        if !undelivered.is_empty() {
+            log::warn!("{} events were not delivered on close", undelivered.len());
+            return Err(Error::UndeliveredEvents(undelivered));
+        }
 
-        let batch_length = batch.events.len();
-        match Self::send_batch(batch, client).await {
-            Ok(resp) => {
-                // We got a response from the collector, but need to check if
-                // it was successful
+        match result {
+            Ok(result) => result,
+            Err(_) => Err(Error::EmitterError(format!(
+                "Timed out after {timeout:?} waiting for emitter to close"
+            ))),
+        }
+    }
 
-                match (
-                    Self::should_retry(resp.code),
-                    resp.batch.has_retry(retry_policy),
-                ) {
-                    // An unsuccessful response with retry attempts remaining
-                    (true, true) => Self::retry_batch(resp.batch, retry_tx),
+    /// Create a new [BatchEmitter] with an [InMemoryEventStore]
+    pub fn new(collector_url: &str) -> BatchEmitter {
+        BatchEmitter::create_emitter(
+            collector_url,
+            DEFAULT_EVENT_STORE_CAPACITY,
+            EmitterStore::new(InMemoryEventStore::default()),
+            ReqwestClient::new(CollectorUrl::unvalidated(collector_url)),
+            SendSettings::default(),
+            LoopSettings::default(),
+        )
+    }
 
-                    // An unsuccessful response with no retry attempts remaining
-                    (true, false) => {
-                        log::warn!("Batch {} failed to send, no retry available", resp.batch.id);
-                        match Self::run_cleanup(store, resp.batch) {
-                            Ok(_) => (),
-                            Err(e) => log::error!("{e}"),
+    // Creates an emitter like `new`, whose thread is named after the tracker namespace
+    pub(crate) fn new_named(collector_url: &str, namespace: &str) -> BatchEmitter {
+        BatchEmitter::create_emitter(
+            collector_url,
+            DEFAULT_EVENT_STORE_CAPACITY,
+            EmitterStore::new(InMemoryEventStore::default()),
+            ReqwestClient::new(CollectorUrl::unvalidated(collector_url)),
+            SendSettings::default(),
+            LoopSettings {
+                thread_name: Some(thread_name_for(namespace)),
+                ..LoopSettings::default()
+            },
+        )
+    }
+
+    // Sends all events currently in the event store
+    fn flush_event_store(&mut self) -> Result<(), Error> {
+        log::debug!("Flushing event store");
+
+        let store = match &self.event_store {
+            EmitterStore::Sync(store) => store,
+            EmitterStore::Async { .. } => {
+                return self
+                    .tx
+                    .try_send(EmitterMessage::FlushStore(StoreFlush::All))
+                    .map_err(|e| Error::EmitterError(e.to_string()))
+            }
+        };
+
+        if self.ordered_flush {
+            let batches = Self::take_all_batches(store, self.store_watermarks.as_deref());
+            if batches.is_empty() {
+                log::debug!("Event store is empty, nothing to flush");
+                return Ok(());
+            }
+            for batch in &batches {
+                self.undelivered.add(batch);
+            }
+            return match self.tx.try_send(EmitterMessage::SendInOrder(batches)) {
+                Ok(_) => Ok(()),
+                Err(e) => Err(Error::EmitterError(e.to_string())),
+            };
+        }
+
+        // Get a lock on the event store
+        let mut store_lock = match store.lock() {
+            Ok(store) => store,
+            Err(e) => return Err(Error::EmitterError(e.to_string())),
+        };
+
+        self.send_full_batches(&mut *store_lock)?;
+
+        // Create a batch of the remaining events and send it, if there are any
+        let remaining_events = store_lock.len();
+        if remaining_events > 0 {
+            let final_batch = store_lock.batch_of(remaining_events)?;
+            self.undelivered.add(&final_batch);
+            if let Err(e) = self.tx.try_send(EmitterMessage::Send(final_batch)) {
+                return Err(Error::EmitterError(e.to_string()));
+            };
+        }
+        if let Some(watermarks) = &self.store_watermarks {
+            watermarks.check(store_lock.len());
+        }
+
+        log::debug!("Finished flushing event store");
+
+        Ok(())
+    }
+
+    // Send batches until the event store doesn't have enough events to fill a batch
+    fn send_full_batches(&self, store: &mut (dyn EventStore + Send + Sync)) -> Result<(), Error> {
+        while let Ok(batch) = store.full_batch() {
+            self.undelivered.add(&batch);
+            if let Err(e) = self.tx.try_send(EmitterMessage::Send(batch)) {
+                return Err(Error::EmitterError(e.to_string()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Change the URL of the Snowplow Collector that events are sent to
+    ///
+    /// Batches sent after this call, including retries, use the new URL, while batches already
+    /// being sent complete against the old one. This requires the [HttpClient] to support
+    /// [set_collector_url](HttpClient::set_collector_url), as [ReqwestClient] does.
+    ///
+    /// When [failover](BatchEmitterBuilder::failover) is configured, this replaces the primary collector URL,
+    /// and sending switches back to it from any backup in use.
+    pub fn set_collector_url(&mut self, collector_url: &str) -> Result<(), Error> {
+        let collector_url = collector_url.parse::<CollectorUrl>()?.to_string();
+        self.http_client.set_collector_url(&collector_url)?;
+        if let Err(e) = self
+            .tx
+            .try_send(EmitterMessage::SetCollectorUrl(collector_url.to_string()))
+        {
+            // The emitter loop keeps sending to the old URL, so the client is changed back to match
+            if let Err(e) = self.http_client.set_collector_url(&self.collector_url) {
+                log::error!("Failed to restore collector URL: {e}");
+            }
+            return Err(Error::EmitterError(e.to_string()));
+        }
+
+        log::info!("Collector URL changed to {collector_url}");
+        self.collector_url = collector_url;
+        Ok(())
+    }
+
+    // Adds a payload to the event store, sending a batch if one fills
+    fn add_to_store(&mut self, payload: PayloadBuilder) -> Result<(), Error> {
+        let store = match &self.event_store {
+            EmitterStore::Sync(store) => store,
+            EmitterStore::Async { .. } => {
+                return match self.tx.try_send(EmitterMessage::Add(Box::new(payload))) {
+                    Ok(_) => Ok(()),
+                    Err(tokio::sync::mpsc::error::TrySendError::Full(_)) => Err(Error::QueueFull),
+                    Err(e) => Err(Error::EmitterError(e.to_string())),
+                }
+            }
+        };
+        match store.lock() {
+            Ok(mut store) => {
+                match store.add(payload) {
+                    Ok(_) => log::debug!("Added event to event store"),
+                    Err(e) => {
+                        log::error!("Failed to add event to event store: {e}");
+                        return Err(e);
+                    }
+                }
+                if let Some(watermarks) = &self.store_watermarks {
+                    watermarks.check(store.len());
+                }
+
+                // While paused, events are kept in the event store as batches fill
+                if self.is_paused() {
+                    return Ok(());
+                }
+
+                // If the event store has enough events to fill a batch, send it. We can ignore the error
+                // here, as the only error that can return is the event store being empty, in which case
+                // we don't want to send a batch
+                let batch = match store.full_batch() {
+                    Ok(batch) => batch,
+                    Err(_) => return Ok(()),
+                };
+                match self.tx.try_send(EmitterMessage::Send(batch)) {
+                    Ok(_) => {
+                        if let Some(watermarks) = &self.store_watermarks {
+                            watermarks.check(store.len());
                         }
+                        Ok(())
+                    }
+                    Err(tokio::sync::mpsc::error::TrySendError::Full(EmitterMessage::Send(
+                        batch,
+                    ))) => {
+                        // The batch is put back, so it is sent once the queue has room
+                        Self::return_to_store(&mut *store, batch);
+                        Err(Error::SendQueueFull)
+                    }
+                    Err(e) => Err(Error::EmitterError(e.to_string())),
+                }
+            }
+            Err(e) => Err(Error::EmitterError(e.to_string())),
+        }
+    }
+
+    // Adds the events of a batch that couldn't be queued for sending back to the event store
+    fn return_to_store(store: &mut (dyn EventStore + Send + Sync), batch: EventBatch) {
+        let batch_id = batch.id;
+        if let Err(e) = store.cleanup_after_send_attempt(batch_id) {
+            log::warn!("Failed to clean up batch {batch_id}: {e}");
+        }
+        for event in batch.events {
+            if let Err(e) = store.add(event.into_builder()) {
+                log::error!("Failed to return event to event store: {e}");
+            }
+        }
+    }
+
+    /// The number of batches that failed to send and are waiting to be retried
+    ///
+    /// A batch stops counting as pending once its retry delay has elapsed and it is sent again.
+    pub fn pending_retries(&self) -> usize {
+        self.pending_retries.len()
+    }
+
+    /// The randomised delay applied before the first batch is sent, if [BatchEmitterBuilder::startup_jitter] was set
+    pub fn startup_delay(&self) -> Option<Duration> {
+        self.startup_delay
+    }
+
+    // Static Methods
+
+    fn is_successful_response(code: u16) -> bool {
+        (200..300).contains(&code)
+    }
+
+    fn is_redirect_response(code: u16) -> bool {
+        (300..400).contains(&code)
+    }
+
+    fn response_action(code: u16, non_retryable_codes: &[u16]) -> ResponseAction {
+        if Self::is_successful_response(code) {
+            ResponseAction::Success
+        } else if Self::is_redirect_response(code) {
+            ResponseAction::Redirect
+        } else if non_retryable_codes.contains(&code) {
+            ResponseAction::Fail
+        } else {
+            ResponseAction::Retry
+        }
+    }
+
+    fn retry_batch(
+        mut batch: EventBatch,
+        retry_tx: tokio::sync::mpsc::UnboundedSender<EmitterMessage>,
+        settings: &SendSettings,
+    ) {
+        let (first_delay, first_delay_jitter) = settings.first_retry_delay;
+        batch.update_for_retry_with_jitter(first_delay, first_delay_jitter);
+
+        if let (Some(on_retry), Some(delay)) = (&settings.on_retry, batch.delay) {
+            on_retry(batch.id, batch.retry_attempts, delay);
+        }
+
+        // A batch re-queued while closing won't be sent again
+        settings.undelivered.add(&batch);
+        let batch_id = batch.id;
+        settings.pending_retries.add(batch_id);
+        match retry_tx.send(EmitterMessage::Send(batch)) {
+            Ok(_) => log::debug!("Batch {batch_id} re-queued"),
+            Err(e) => {
+                settings.pending_retries.remove(batch_id);
+                log::warn!("Failed to re-queue batch {batch_id}: {e}")
+            }
+        }
+    }
+
+    // Stops tracking a batch that won't be sent again, and cleans it up in the event store
+    async fn finish_batch(store: EmitterStore, settings: &SendSettings, batch: EventBatch) {
+        settings.undelivered.remove(batch.id);
+
+        let progress = match settings.flush_progress.lock() {
+            Ok(mut flush_progress) => flush_progress.remove(&batch.id),
+            Err(_) => None,
+        };
+        if let Some(progress) = progress {
+            progress.record(batch.events.len());
+        }
+
+        match store.cleanup_after_send_attempt(batch.id).await {
+            Ok(_) => log::debug!("Cleanup run for batch: {}", batch.id),
+            Err(e) => log::error!("Failed to cleanup: {e}"),
+        }
+    }
+
+    // Finishes a batch that failed to send and won't be retried, reporting it to the diagnostics collector
+    async fn give_up_batch(
+        store: EmitterStore,
+        settings: &SendSettings,
+        batch: EventBatch,
+        reason: &str,
+    ) {
+        log::warn!("{reason}");
+        Self::dead_letter(settings, &batch.events);
+        if let Some(diagnostics) = &settings.diagnostics {
+            diagnostics.report(reason);
+        }
+        Self::finish_batch(store, settings, batch).await
+    }
+
+    // Registers the batches of a flush for progress reporting before they are sent, so none can finish unrecorded
+    fn track_flush_progress(
+        flush_progress: &Mutex<HashMap<Uuid, Arc<FlushProgress>>>,
+        batches: &[EventBatch],
+        callback: FlushProgressCallback,
+    ) -> Result<(), Error> {
+        if batches.is_empty() {
+            callback(0, 0);
+            return Ok(());
+        }
+
+        let total = batches.iter().map(|batch| batch.events.len()).sum();
+        let progress = Arc::new(FlushProgress::new(total, callback));
+        match flush_progress.lock() {
+            Ok(mut flush_progress) => {
+                for batch in batches {
+                    flush_progress.insert(batch.id, progress.clone());
+                }
+                Ok(())
+            }
+            Err(e) => Err(Error::EmitterError(e.to_string())),
+        }
+    }
+
+    // Reports a batch that won't be delivered to the dead-letter callback
+    fn dead_letter(settings: &SendSettings, events: &[Payload]) {
+        if let Some(on_dead_letter) = &settings.on_dead_letter {
+            on_dead_letter(events);
+        }
+    }
+
+    // Drops events that expired before they could be sent, returning whether the batch is now empty
+    fn drop_expired_events(batch: &mut EventBatch, settings: &SendSettings) -> bool {
+        let expired = batch.drop_expired(SystemTime::now());
+        if !expired.is_empty() {
+            log::warn!(
+                "Dropped {} expired events from batch {} without sending them",
+                expired.len(),
+                batch.id
+            );
+            Self::dead_letter(settings, &expired);
+
+            // The dropped events are finished, but are no longer counted when the batch finishes
+            let progress = match settings.flush_progress.lock() {
+                Ok(flush_progress) => flush_progress.get(&batch.id).cloned(),
+                Err(_) => None,
+            };
+            if let Some(progress) = progress {
+                progress.record(expired.len());
+            }
+        }
+        batch.is_empty()
+    }
+
+    // `sent_to` is the failover collector URL the batch is sent to, if failover is configured
+    async fn batch_send_task(
+        mut batch: EventBatch,
+        client: Box<dyn HttpClient + Send + Sync>,
+        sent_to: Option<String>,
+        retry_tx: tokio::sync::mpsc::UnboundedSender<EmitterMessage>,
+        store: EmitterStore,
+        settings: SendSettings,
+    ) {
+        // Only retried batches are delayed
+        if let Some(delay) = batch.delay {
+            log::debug!("Delaying batch {} for {:?}", batch.id, delay);
+            if !settings.pending_retries.wait(batch.id, delay).await {
+                Self::dead_letter(&settings, &batch.events);
+                Self::finish_batch(store, &settings, batch).await;
+                return;
+            }
+
+            if let Err(e) = batch.update_event_stm() {
+                // If the update fails, we just re-send the batch as-is
+                // Not ideal, but it's better than losing events
+                log::warn!(
+                    "Failed to update stm of events in batch {} for retry: {e}",
+                    batch.id
+                )
+            };
+        };
+
+        if let Some(rate_limiter) = &settings.rate_limiter {
+            rate_limiter.acquire().await;
+        }
+        if Self::drop_expired_events(&mut batch, &settings) {
+            Self::finish_batch(store, &settings, batch).await;
+            return;
+        }
+        if settings.sort_by_dtm {
+            batch.sort_by_dtm();
+        }
+
+        let batch_length = batch.events.len();
+        let result = Self::send_batch(batch, client, settings.omit_empty_fields).await;
+
+        let non_retryable_codes = settings.non_retryable_codes.as_slice();
+        let sent = matches!(&result, Ok(resp) if Self::is_successful_response(resp.code));
+        if let Some(breaker) = &settings.circuit_breaker {
+            match sent {
+                true => breaker.record_success(),
+                false => breaker.record_failure(),
+            }
+        }
+        if let (Some(failover), Some(sent_to)) = (&settings.failover, &sent_to) {
+            match sent {
+                true => failover.record_success(sent_to),
+                false => failover.record_failure(sent_to),
+            }
+        }
+
+        match result {
+            Ok(resp) => {
+                // We got a response from the collector, but need to check if
+                // it was successful
+
+                match Self::response_action(resp.code, non_retryable_codes) {
+                    // An unsuccessful response with retry attempts remaining
+                    ResponseAction::Retry
+                        if resp.batch.has_retry(settings.retry_policy_for(&resp.batch)) =>
+                    {
+                        Self::retry_batch(resp.batch, retry_tx, &settings)
+                    }
+
+                    // An unsuccessful response with no retry attempts remaining
+                    ResponseAction::Retry => {
+                        let reason =
+                            format!("Batch {} failed to send, no retry available", resp.batch.id);
+                        Self::give_up_batch(store, &settings, resp.batch, &reason).await
+                    }
+
+                    // An unsuccessful response that should not be retried
+                    ResponseAction::Fail => {
+                        let reason = format!(
+                            "Batch {} failed to send with status code {}, not retrying",
+                            resp.batch.id, resp.code
+                        );
+                        Self::give_up_batch(store, &settings, resp.batch, &reason).await
+                    }
+
+                    // A redirect that was not followed
+                    ResponseAction::Redirect => {
+                        let reason = format!(
+                            "Batch {} was redirected with status code {}, not retrying. Check the collector URL, or use a HttpClient that follows redirects",
+                            resp.batch.id,
+                            resp.code
+                        );
+                        Self::give_up_batch(store, &settings, resp.batch, &reason).await
                     }
 
                     // A successful response
-                    (false, _) => {
+                    ResponseAction::Success => {
                         log::info!("Sent batch {} of {batch_length} events", resp.batch.id);
-                        match Self::run_cleanup(store, resp.batch) {
-                            Ok(_) => (),
-                            Err(e) => log::error!("{e}"),
-                        }
+                        Self::finish_batch(store, &settings, resp.batch).await
                     }
                 }
             }
 
             // The request to the collector failed - no response
             Err(failed_batch) => {
-                if failed_batch.has_retry(retry_policy) {
-                    Self::retry_batch(failed_batch, retry_tx)
+                if failed_batch.has_retry(settings.retry_policy_for(&failed_batch)) {
+                    Self::retry_batch(failed_batch, retry_tx, &settings)
                 } else {
-                    log::warn!(
+                    let reason = format!(
                         "Batch {} failed to send, no retry available",
                         failed_batch.id
                     );
-                    match Self::run_cleanup(store, failed_batch) {
-                        Ok(_) => (),
-                        Err(e) => log::error!("{e}"),
-                    }
+                    Self::give_up_batch(store, &settings, failed_batch, &reason).await
+                }
+            }
+        }
+    }
+
+    // Sends an EventBatch to the collector
+    async fn send_batch(
+        batch: EventBatch,
+        http_client: Box<dyn HttpClient + Send + Sync>,
+        omit_empty_fields: bool,
+    ) -> Result<SentBatchResponse, EventBatch> {
+        let context = RequestContext {
+            batch_id: batch.id,
+            attempt: batch.retry_attempts + 1,
+        };
+
+        if log::log_enabled!(log::Level::Debug) {
+            let repeated_bytes = batch.bytes_saved_estimate();
+            if repeated_bytes > 0 {
+                log::debug!(
+                    "Batch {} repeats {repeated_bytes} bytes of context entities",
+                    batch.id
+                );
+            }
+        }
+
+        let payload = match omit_empty_fields {
+            true => batch.as_compact_payload(),
+            false => batch.as_payload(),
+        };
+
+        match http_client.post_with_response(payload, context).await {
+            Ok(response) => {
+                let code = response.status;
+                log::debug!("Batch {} sent with status code {}", batch.id, code);
+                if !Self::is_successful_response(code) && !response.body.is_empty() {
+                    log::debug!(
+                        "Collector response to batch {}: {}",
+                        batch.id,
+                        response.body
+                    );
                 }
+                Ok(SentBatchResponse { batch, code })
+            }
+            Err(e) => {
+                log::warn!("Failed to send batch {}: {e}, re-queueing...", batch.id);
+                Err(batch)
+            }
+        }
+    }
+
+    // Removes all events from the event store as batches, including a final partial batch
+    fn take_all_batches(
+        store: &Mutex<dyn EventStore + Send + Sync>,
+        store_watermarks: Option<&StoreWatermarks>,
+    ) -> Vec<EventBatch> {
+        let mut store_lock = match store.lock() {
+            Ok(store) => store,
+            Err(e) => {
+                log::error!("Failed to acquire event store lock: {e}");
+                return Vec::new();
+            }
+        };
+
+        let batches = EmitterStore::take_sync_batches(&mut *store_lock, false);
+
+        if let Some(watermarks) = store_watermarks {
+            watermarks.check(store_lock.len());
+        }
+
+        batches
+    }
+
+    // Whether the oldest event in the event store has waited for at least `max_latency`
+    async fn max_latency_exceeded(store: &EmitterStore, max_latency: Option<Duration>) -> bool {
+        let oldest_event_age = store.oldest_event_age().await;
+
+        matches!((oldest_event_age, max_latency), (Some(age), Some(max)) if age >= max)
+    }
+
+    // The first tick is delayed by a full interval, as there will be nothing to flush at startup
+    fn flush_timer(interval: Duration) -> tokio::time::Interval {
+        let mut timer = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+        timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        timer
+    }
+
+    // Waits for the next tick of the flush timer, or forever if there is no flush timer
+    async fn flush_tick(flush_timer: &mut Option<tokio::time::Interval>) {
+        match flush_timer {
+            Some(timer) => {
+                timer.tick().await;
             }
+            None => std::future::pending().await,
+        }
+    }
+
+    // Waits until the scheduled flush is due, or forever if no flush is scheduled
+    async fn scheduled_flush(flush_at: Option<tokio::time::Instant>) {
+        match flush_at {
+            Some(flush_at) => tokio::time::sleep_until(flush_at).await,
+            None => std::future::pending().await,
+        }
+    }
+
+    // Waits until a batch can be sent, once the startup delay has passed and any open circuit has cooled down
+    //
+    // This runs in the batch's own task, so the emitter loop keeps handling messages while batches wait,
+    // and stops waiting as soon as the emitter starts closing
+    async fn wait_to_send(batch_id: Uuid, settings: &SendSettings) {
+        let startup_wait = settings
+            .send_after
+            .map(|send_after| send_after.saturating_duration_since(tokio::time::Instant::now()));
+        let cooldown = settings
+            .circuit_breaker
+            .as_ref()
+            .and_then(|breaker| breaker.remaining_cooldown());
+        let wait = match startup_wait.max(cooldown) {
+            Some(wait) if !wait.is_zero() => wait,
+            _ => return,
+        };
+
+        log::debug!("Delaying batch {batch_id} for {wait:?}");
+        let mut closing = settings.closing.clone();
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = async {
+                // An error means the emitter loop has gone, so there is nothing left to wait for
+                while !*closing.borrow() && closing.changed().await.is_ok() {}
+            } => log::debug!("BatchEmitter is closing, sending batch {batch_id} without waiting"),
         }
     }
 
-    // Sends an EventBatch to the collector
-    async fn send_batch(
-        batch: EventBatch,
-        http_client: Box<dyn HttpClient + Send + Sync>,
-    ) -> Result<SentBatchResponse, EventBatch> {
-        match http_client.post(batch.as_payload()).await {
-            Ok(code) => {
-                log::debug!("Batch {} sent with status code {}", batch.id, code);
-                Ok(SentBatchResponse { batch, code })
-            }
-            Err(e) => {
-                log::warn!("Failed to send batch {}: {e}, re-queueing...", batch.id);
-                Err(batch)
-            }
-        }
+    // Spawns a task to send a batch, which waits first if the circuit breaker is open or the startup delay hasn't passed
+    fn dispatch_batch(
+        batch: EventBatch,
+        mut client: Box<dyn HttpClient + Send + Sync>,
+        retry_tx: tokio::sync::mpsc::UnboundedSender<EmitterMessage>,
+        store: EmitterStore,
+        settings: SendSettings,
+    ) -> tokio::task::JoinHandle<()> {
+        settings.undelivered.add(&batch);
+
+        tokio::spawn(async move {
+            Self::wait_to_send(batch.id, &settings).await;
+
+            let sent_to = settings
+                .failover
+                .as_ref()
+                .map(|failover| failover.active_url());
+            if let Some(sent_to) = &sent_to {
+                if let Err(e) = client.set_collector_url(sent_to) {
+                    log::error!("Failed to change collector URL: {e}");
+                }
+            }
+
+            Self::batch_send_task(batch, client, sent_to, retry_tx, store, settings).await
+        })
+    }
+
+    // Spawns a task to send batches one at a time, in order
+    fn dispatch_in_order(
+        batches: Vec<EventBatch>,
+        client: Box<dyn HttpClient + Send + Sync>,
+        retry_tx: tokio::sync::mpsc::UnboundedSender<EmitterMessage>,
+        store: EmitterStore,
+        settings: SendSettings,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            for batch in batches {
+                let batch_id = batch.id;
+                let task = Self::dispatch_batch(
+                    batch,
+                    client.clone(),
+                    retry_tx.clone(),
+                    store.clone(),
+                    settings.clone(),
+                );
+                if let Err(e) = task.await {
+                    log::error!("Failed to send batch {batch_id}: {e}");
+                }
+            }
+        })
+    }
+
+    // Starts a tokio runtime and runs the emitter loop
+    fn start_tokio(
+        mut http_client: Box<dyn HttpClient + Send + Sync>,
+        mut rx: tokio::sync::mpsc::Receiver<EmitterMessage>,
+        event_store: EmitterStore,
+        mut settings: SendSettings,
+        startup_delay: Option<Duration>,
+        loop_settings: LoopSettings,
+    ) {
+        // Create a new runtime to handle the async tasks
+        // Unwrap here as if the runtime fails to start, there is nothing we can do
+        let mut rt_builder = tokio::runtime::Builder::new_multi_thread();
+        rt_builder.enable_all();
+        if let Some(name) = std::thread::current().name() {
+            rt_builder.thread_name(format!("{name}-worker"));
+        }
+        if let Some(worker_threads) = loop_settings.worker_threads {
+            rt_builder.worker_threads(worker_threads);
+        }
+        if let Some(stack_size) = loop_settings.thread_stack_size {
+            rt_builder.thread_stack_size(stack_size);
+        }
+        if let Some(max_blocking_threads) = loop_settings.max_blocking_threads {
+            rt_builder.max_blocking_threads(max_blocking_threads);
+        }
+        let rt = rt_builder.build().unwrap();
+
+        // The main emitter loop
+        // This continuously loops and checks for new batches to send
+        rt.block_on(async {
+            // The currently running tokio tasks
+            let mut tokio_tasks: Vec<_> = Vec::new();
+            let (retry_tx, mut retry_rx) = tokio::sync::mpsc::unbounded_channel();
+            let (closing_tx, closing_rx) = tokio::sync::watch::channel(false);
+            settings.closing = closing_rx;
+            settings.send_after = startup_delay.map(|delay| tokio::time::Instant::now() + delay);
+            if let Some(diagnostics) = &settings.diagnostics {
+                diagnostics.start(tokio::runtime::Handle::current());
+            }
+
+            let mut flush_timer = loop_settings.flush_interval.map(Self::flush_timer);
+            let mut latency_timer = loop_settings.max_latency.map(|max_latency| {
+                Self::flush_timer((max_latency / 10).max(Duration::from_millis(1)))
+            });
+            let mut heartbeat_timer = loop_settings
+                .heartbeat
+                .as_ref()
+                .map(|(interval, _)| Self::flush_timer(*interval));
+            let mut flush_at = None;
+
+            loop {
+                // `rx.recv().await` will not resolve until either a message is received,
+                // or the channel is closed and there are no more messages, in which case we exit the loop
+
+                // select! is used to check both the `retry_rx` channel and the `rx` channel for new messages
+                let message = tokio::select! {
+                    // `biased;` is used to ensure that the `retry_rx` channel is checked first, so retries get priority
+                    biased;
+
+                    retry = retry_rx.recv() => retry,
+                    event = rx.recv() => event,
+                    _ = Self::flush_tick(&mut flush_timer) => Some(EmitterMessage::Flush),
+                    _ = Self::scheduled_flush(flush_at) => {
+                        flush_at = None;
+                        Some(EmitterMessage::Flush)
+                    }
+                    _ = Self::flush_tick(&mut latency_timer) => {
+                        if !Self::max_latency_exceeded(&event_store, loop_settings.max_latency).await {
+                            continue;
+                        }
+                        Some(EmitterMessage::Flush)
+                    }
+                    _ = Self::flush_tick(&mut heartbeat_timer) => {
+                        if loop_settings.paused.load(Ordering::SeqCst) {
+                            continue;
+                        }
+                        let event = match &loop_settings.heartbeat {
+                            Some((_, event)) => event,
+                            None => continue,
+                        };
+                        let tracker = match loop_settings.tracker.lock() {
+                            Ok(tracker) => tracker.clone(),
+                            Err(_) => None,
+                        };
+                        match heartbeat_batch(event, tracker.as_ref()) {
+                            Ok(batch) => Some(EmitterMessage::Send(batch)),
+                            Err(e) => {
+                                log::error!("Failed to create heartbeat event: {e}");
+                                continue;
+                            }
+                        }
+                    }
+                };
+
+                let message = match message {
+                    Some(message) => message,
+                    None => break,
+                };
+
+                match message {
+                    EmitterMessage::Send(batch) => {
+                        // Spawn a new task to send the batch
+                        tokio_tasks.push(Self::dispatch_batch(
+                            batch,
+                            http_client.clone(),
+                            retry_tx.clone(),
+                            event_store.clone(),
+                            settings.clone(),
+                        ));
+                    }
+
+                    EmitterMessage::SendInOrder(batches) => {
+                        tokio_tasks.push(Self::dispatch_in_order(
+                            batches,
+                            http_client.clone(),
+                            retry_tx.clone(),
+                            event_store.clone(),
+                            settings.clone(),
+                        ));
+                    }
+
+                    EmitterMessage::Flush if loop_settings.paused.load(Ordering::SeqCst) => {
+                        log::debug!("BatchEmitter is paused, skipping flush on timer");
+                    }
+
+                    EmitterMessage::Flush => {
+                        for batch in event_store
+                            .take_batches(false, loop_settings.store_watermarks.as_deref())
+                            .await
+                        {
+                            log::debug!("Flushing batch {} on timer", batch.id);
+                            tokio_tasks.push(Self::dispatch_batch(
+                                batch,
+                                http_client.clone(),
+                                retry_tx.clone(),
+                                event_store.clone(),
+                                settings.clone(),
+                            ));
+                        }
+                    }
+
+                    EmitterMessage::Add(payload) => {
+                        let watermarks = loop_settings.store_watermarks.as_deref();
+                        let result = event_store.add(*payload, watermarks).await;
+                        if let (Some(diagnostics), Err(Error::QueueFull)) =
+                            (&settings.diagnostics, &result)
+                        {
+                            diagnostics.report_store_full();
+                        } else if let (Some(diagnostics), Ok(_)) = (&settings.diagnostics, &result) {
+                            diagnostics.store_accepted();
+                        }
+                        if let Err(e) = result {
+                            log::error!("Failed to add event to event store: {e}");
+                        }
+                        // While paused, events are kept in the event store as batches fill
+                        if !loop_settings.paused.load(Ordering::SeqCst) {
+                            for batch in event_store.take_batches(true, watermarks).await {
+                                tokio_tasks.push(Self::dispatch_batch(
+                                    batch,
+                                    http_client.clone(),
+                                    retry_tx.clone(),
+                                    event_store.clone(),
+                                    settings.clone(),
+                                ));
+                            }
+                        }
+                    }
+
+                    EmitterMessage::FlushStore(flush) => {
+                        let full_only = matches!(flush, StoreFlush::FullBatchesOnly);
+                        let batches = event_store
+                            .take_batches(full_only, loop_settings.store_watermarks.as_deref())
+                            .await;
+                        let in_order = loop_settings.ordered_flush && matches!(flush, StoreFlush::All);
+                        if let StoreFlush::WithProgress(callback) = flush {
+                            if let Err(e) =
+                                Self::track_flush_progress(&settings.flush_progress, &batches, callback)
+                            {
+                                log::error!("Failed to track flush progress: {e}");
+                            }
+                        }
+
+                        if in_order && !batches.is_empty() {
+                            tokio_tasks.push(Self::dispatch_in_order(
+                                batches,
+                                http_client.clone(),
+                                retry_tx.clone(),
+                                event_store.clone(),
+                                settings.clone(),
+                            ));
+                        } else {
+                            for batch in batches {
+                                tokio_tasks.push(Self::dispatch_batch(
+                                    batch,
+                                    http_client.clone(),
+                                    retry_tx.clone(),
+                                    event_store.clone(),
+                                    settings.clone(),
+                                ));
+                            }
+                        }
+                    }
+
+                    EmitterMessage::Clear => {
+                        match event_store
+                            .clear(loop_settings.store_watermarks.as_deref())
+                            .await
+                        {
+                            Ok(discarded) => {
+                                log::debug!("Discarded {discarded} events from the event store")
+                            }
+                            Err(e) => log::error!("Failed to clear event store: {e}"),
+                        }
+                    }
+
+                    EmitterMessage::FlushAt(at) => {
+                        flush_at = Some(
+                            flush_at
+                                .map_or(at, |scheduled: tokio::time::Instant| scheduled.min(at)),
+                        );
+                    }
+
+                    // Batches already being sent keep their own copy of the client, with the old URL
+                    EmitterMessage::SetCollectorUrl(collector_url) => {
+                        if let Err(e) = http_client.set_collector_url(&collector_url) {
+                            log::error!("Failed to change collector URL: {e}");
+                        }
+                        if let Some(failover) = &settings.failover {
+                            failover.set_primary_url(&collector_url);
+                        }
+                    }
+
+                    // On break, the emitter and runtime will be dropped
+                    //
+                    // Tokio will cancel any running tasks once the runtime is dropped, meaning any queued or retry batches will be lost,
+                    // so we attempt to send any remaining batches before exiting
+                    EmitterMessage::Close(done) => {
+                        // Batches waiting on the startup delay or an open circuit are sent straight away
+                        let _ = closing_tx.send(true);
+                        let remaining = tokio_tasks.len();
+                        for (i, task) in tokio_tasks.iter_mut().enumerate() {
+                            log::debug!("Waiting for task {}/{remaining} to complete", i + 1);
+                            task.await.unwrap();
+                        }
+                        if let Some(done) = done {
+                            // The caller may have stopped waiting, which is fine
+                            let _ = done.send(());
+                        }
+                        break;
+                    }
+                }
+
+                // Discard any completed tasks in the task list
+                tokio_tasks.retain(|t| !t.is_finished());
+            }
+        });
+    }
+}
+
+// Panics usually carry a `&str` or `String` message
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => match panic.downcast_ref::<String>() {
+            Some(message) => message.clone(),
+            None => "unknown panic".to_string(),
+        },
+    }
+}
+
+impl Drop for BatchEmitter {
+    fn drop(&mut self) {
+        // Get the join handle for the thread running the tokio runtime and wait for it to finish
+        //
+        // It's likely that the thread has already finished once the emitter loop has exited
+        if let Some(handle) = self.executor_handle.take() {
+            if let Some(timeout) = self.drop_timeout {
+                let deadline = Instant::now() + timeout;
+                while !handle.is_finished() && Instant::now() < deadline {
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                if !handle.is_finished() {
+                    // Dropping the handle detaches the thread, which keeps running until it finishes
+                    log::warn!(
+                        "BatchEmitter thread did not finish within {timeout:?}, detaching it"
+                    );
+                    log::debug!("BatchEmitter dropped");
+                    return;
+                }
+            }
+
+            match handle.join() {
+                Ok(_) => log::debug!("BatchEmitter thread joined"),
+                Err(panic) => log::error!(
+                    "BatchEmitter thread panicked: {}",
+                    panic_message(panic.as_ref())
+                ),
+            }
+        }
+        log::debug!("BatchEmitter dropped");
+    }
+}
+
+impl Emitter for BatchEmitter {
+    /// Adds a payload to the event store
+    ///
+    /// This may also trigger sending a payload to the collector if the event store has enough events to fill a batch
+    fn add(&mut self, payload: PayloadBuilder) -> Result<(), Error> {
+        self.ensure_running()?;
+        let result = self.add_to_store(payload);
+        // An async store reports whether it accepted the event from the emitter loop
+        if let Some(diagnostics) = &self.diagnostics {
+            match (&result, &self.event_store) {
+                (Ok(_) | Err(Error::SendQueueFull), EmitterStore::Sync(_)) => {
+                    diagnostics.store_accepted()
+                }
+                (Err(Error::QueueFull), _) => diagnostics.report_store_full(),
+                _ => {}
+            }
+        }
+        result
+    }
+
+    /// Attempt to send all events currently in the event store
+    ///
+    /// If [flush_coalesce_window](BatchEmitterBuilder::flush_coalesce_window) is set, calls within the window
+    /// of the last flush schedule a single flush for when the window ends
+    fn flush(&mut self) -> Result<(), Error> {
+        self.ensure_running()?;
+        if self.is_paused() {
+            log::debug!("BatchEmitter is paused, skipping flush");
+            return Ok(());
+        }
+        if let (Some(window), Some(last_flush)) = (self.flush_coalesce_window, self.last_flush) {
+            let now = Instant::now();
+            // The last flush is in the future while a trailing flush is scheduled
+            if now < last_flush {
+                log::debug!("Flush already scheduled, skipping flush");
+                return Ok(());
+            }
+            if now - last_flush < window {
+                let due = last_flush + window;
+                log::debug!("Flushed {:?} ago, scheduling flush", now - last_flush);
+                self.last_flush = Some(due);
+                return self
+                    .tx
+                    .try_send(EmitterMessage::FlushAt(due.into()))
+                    .map_err(|e| Error::EmitterError(e.to_string()));
+            }
+        }
+
+        self.last_flush = Some(Instant::now());
+        self.flush_event_store()
+    }
+
+    /// Send all full batches in the event store, leaving any remaining events in the store
+    fn flush_full_batches_only(&mut self) -> Result<(), Error> {
+        self.ensure_running()?;
+        if self.is_paused() {
+            log::debug!("BatchEmitter is paused, skipping flush");
+            return Ok(());
+        }
+        log::debug!("Flushing full batches from event store");
+
+        let store = match &self.event_store {
+            EmitterStore::Sync(store) => store,
+            EmitterStore::Async { .. } => {
+                return self
+                    .tx
+                    .try_send(EmitterMessage::FlushStore(StoreFlush::FullBatchesOnly))
+                    .map_err(|e| Error::EmitterError(e.to_string()))
+            }
+        };
+        let mut store_lock = match store.lock() {
+            Ok(store) => store,
+            Err(e) => return Err(Error::EmitterError(e.to_string())),
+        };
+
+        self.send_full_batches(&mut *store_lock)?;
+        if let Some(watermarks) = &self.store_watermarks {
+            watermarks.check(store_lock.len());
+        }
+
+        log::debug!(
+            "Finished flushing full batches, {} events remain in event store",
+            store_lock.len()
+        );
+
+        Ok(())
+    }
+
+    /// Attempt to send all events currently in the event store, reporting progress as each batch finishes
+    ///
+    /// A batch is finished once it has been sent, or has failed with no retries remaining, so the
+    /// callback reaches the total even if some events could not be sent. It is called from the
+    /// emitter's background thread.
+    fn flush_with_progress(&mut self, callback: FlushProgressCallback) -> Result<(), Error> {
+        self.ensure_running()?;
+        // Progress would never be reported while paused
+        if self.is_paused() {
+            return Err(Error::EmitterError("BatchEmitter is paused".to_string()));
+        }
+        log::debug!("Flushing event store with progress");
+
+        let store = match &self.event_store {
+            EmitterStore::Sync(store) => store,
+            EmitterStore::Async { .. } => {
+                return self
+                    .tx
+                    .try_send(EmitterMessage::FlushStore(StoreFlush::WithProgress(
+                        callback,
+                    )))
+                    .map_err(|e| Error::EmitterError(e.to_string()))
+            }
+        };
+        let batches = Self::take_all_batches(store, self.store_watermarks.as_deref());
+        let total: usize = batches.iter().map(|batch| batch.events.len()).sum();
+        Self::track_flush_progress(&self.flush_progress, &batches, callback)?;
+
+        for batch in batches {
+            if let Err(e) = self.tx.try_send(EmitterMessage::Send(batch)) {
+                return Err(Error::EmitterError(e.to_string()));
+            }
+        }
+
+        log::debug!("Queued {total} events to flush");
+
+        Ok(())
+    }
+
+    /// Discard all events in the event store without sending them
+    ///
+    /// Batches already queued to send, or waiting to be retried, are still sent
+    fn clear(&mut self) -> Result<(), Error> {
+        let store = match &self.event_store {
+            EmitterStore::Sync(store) => store,
+            EmitterStore::Async { .. } => {
+                return self
+                    .tx
+                    .try_send(EmitterMessage::Clear)
+                    .map_err(|e| Error::EmitterError(e.to_string()))
+            }
+        };
+        let mut store_lock = match store.lock() {
+            Ok(store) => store,
+            Err(e) => return Err(Error::EmitterError(e.to_string())),
+        };
+
+        let discarded = store_lock.len();
+        store_lock.clear()?;
+        if let Some(watermarks) = &self.store_watermarks {
+            watermarks.check(store_lock.len());
+        }
+
+        log::debug!("Discarded {discarded} events from the event store");
+
+        Ok(())
+    }
+
+    /// Shut down and drop the emitter
+    ///
+    /// This will cancel any running tasks and may result in events being lost
+    fn close(&mut self) -> Result<(), Error> {
+        match self.tx.try_send(EmitterMessage::Close(None)) {
+            Ok(_) => {
+                log::debug!("Closing emitter");
+                Ok(())
+            }
+            Err(e) => Err(Error::EmitterError(e.to_string())),
+        }
+    }
+
+    fn collector_url(&self) -> &str {
+        &self.collector_url
+    }
+
+    fn default_contexts(&self) -> &[SelfDescribingJson] {
+        &self.default_contexts
+    }
+
+    /// Attributes heartbeats to the tracker
+    fn set_tracker(&mut self, namespace: &str, app_id: &str) {
+        if let Ok(mut tracker) = self.tracker.lock() {
+            *tracker = Some(TrackerIdentity {
+                namespace: namespace.to_string(),
+                app_id: app_id.to_string(),
+            });
+        }
+    }
+
+    /// Returns `false` while the circuit breaker is open and sending is paused
+    fn is_healthy(&self) -> bool {
+        match &self.circuit_breaker {
+            Some(breaker) => !breaker.is_open(),
+            None => true,
+        }
+    }
+
+    /// The number of events in the event store, against its capacity
+    ///
+    /// Events in batches that are being sent or waiting to be retried are not counted. With an
+    /// [AsyncEventStore], this is the number of events after the emitter last added or removed events.
+    fn queue_pressure(&self) -> Option<QueuePressure> {
+        Some(QueuePressure {
+            queued: self.event_store.len().ok()?,
+            capacity: self.event_store.capacity().ok()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::SelfDescribingJson;
+
+    // The emitter's event store, for tests using a synchronous store
+    fn sync_store(emitter: &BatchEmitter) -> &Mutex<dyn EventStore + Send + Sync> {
+        match &emitter.event_store {
+            EmitterStore::Sync(store) => store,
+            EmitterStore::Async { .. } => panic!("Expected a synchronous event store"),
+        }
+    }
+
+    // Polls `condition` until it holds, failing the test with `message` if it doesn't within 10 seconds
+    async fn wait_until(condition: impl Fn() -> bool, message: &str) {
+        let timeout = std::time::Instant::now() + Duration::from_secs(10);
+        while !condition() {
+            assert!(std::time::Instant::now() < timeout, "{message}");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    // A HttpClient that always receives a server error from the collector
+    struct FailingHttpClient;
+
+    #[async_trait]
+    impl HttpClient for FailingHttpClient {
+        async fn post(
+            &self,
+            _payload: SelfDescribingJson,
+            _context: RequestContext,
+        ) -> Result<u16, Error> {
+            Ok(500)
+        }
+
+        fn clone(&self) -> Box<dyn HttpClient + Send + Sync> {
+            Box::new(FailingHttpClient)
+        }
+    }
+
+    // A HttpClient that records when each request is made, and the number of events sent
+    struct RecordingHttpClient {
+        sent_at: Arc<Mutex<Vec<(std::time::Instant, usize)>>>,
+    }
+
+    #[async_trait]
+    impl HttpClient for RecordingHttpClient {
+        async fn post(
+            &self,
+            payload: SelfDescribingJson,
+            _context: RequestContext,
+        ) -> Result<u16, Error> {
+            let event_count = payload.data.as_array().map_or(0, |events| events.len());
+            self.sent_at
+                .lock()
+                .unwrap()
+                .push((std::time::Instant::now(), event_count));
+            Ok(200)
+        }
+
+        fn clone(&self) -> Box<dyn HttpClient + Send + Sync> {
+            Box::new(RecordingHttpClient {
+                sent_at: self.sent_at.clone(),
+            })
+        }
+    }
+
+    // A HttpClient that takes longer to send larger batches, recording the size of each batch once sent
+    struct SlowHttpClient {
+        sent: Arc<Mutex<Vec<usize>>>,
+    }
+
+    #[async_trait]
+    impl HttpClient for SlowHttpClient {
+        async fn post(
+            &self,
+            payload: SelfDescribingJson,
+            _context: RequestContext,
+        ) -> Result<u16, Error> {
+            let event_count = payload.data.as_array().map_or(0, |events| events.len());
+            tokio::time::sleep(Duration::from_millis(20 * event_count as u64)).await;
+            self.sent.lock().unwrap().push(event_count);
+            Ok(200)
+        }
+
+        fn clone(&self) -> Box<dyn HttpClient + Send + Sync> {
+            Box::new(SlowHttpClient {
+                sent: self.sent.clone(),
+            })
+        }
+    }
+
+    fn valid_payload() -> PayloadBuilder {
+        crate::Payload::builder()
+            .p("p".to_string())
+            .tv("tv".to_string())
+            .eid(uuid::Uuid::new_v4())
+            .dtm("dtm".to_string())
+            .aid("aid".to_string())
+    }
+
+    #[tokio::test]
+    async fn add_event_to_store() {
+        let mut emitter = BatchEmitter::new("http://localhost:8080");
+        let payload = PayloadBuilder::default();
+
+        emitter.add(payload).unwrap();
+        assert_eq!(sync_store(&emitter).lock().unwrap().len(), 1);
+
+        emitter.close().unwrap();
+    }
+
+    #[tokio::test]
+    async fn send_batch() {
+        let event_store = InMemoryEventStore::new(2, 2);
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(event_store)
+            .build()
+            .unwrap();
+
+        emitter.add(PayloadBuilder::default()).unwrap();
+        assert_eq!(sync_store(&emitter).lock().unwrap().len(), 1);
+
+        // Adding a second event should trigger a batch to be sent
+        emitter.add(PayloadBuilder::default()).unwrap();
+        assert_eq!(sync_store(&emitter).lock().unwrap().len(), 0);
+
+        emitter.close().unwrap();
+    }
+
+    #[test]
+    fn should_retry() {
+        let below_200 = (0..=199).collect::<Vec<_>>();
+        let between_400_and_599 = (400..=599)
+            .filter(|code| !DONT_RETRY_STATUS_CODES.contains(code))
+            .collect::<Vec<_>>();
+
+        let should_retry_codes = [below_200, between_400_and_599].concat();
+
+        for code in 0..=599 {
+            let action = BatchEmitter::response_action(code, &DONT_RETRY_STATUS_CODES);
+            assert_eq!(
+                action == ResponseAction::Retry,
+                should_retry_codes.contains(&code)
+            )
+        }
+    }
+
+    #[tokio::test]
+    async fn circuit_opens_after_consecutive_failures() {
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(10, 1))
+            .http_client(FailingHttpClient)
+            .retry_policy(RetryPolicy::NoRetry)
+            .circuit_breaker(3, Duration::from_secs(60))
+            .build()
+            .unwrap();
+
+        assert!(emitter.is_healthy());
+
+        emitter.add(valid_payload()).unwrap();
+        emitter.add(valid_payload()).unwrap();
+
+        // Two failures are below the threshold, so the circuit should stay closed
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(emitter.is_healthy());
+
+        emitter.add(valid_payload()).unwrap();
+
+        wait_until(|| !emitter.is_healthy(), "Circuit did not open").await;
+
+        emitter.close().unwrap();
+    }
+
+    #[tokio::test]
+    async fn open_circuit_does_not_hold_up_close() {
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(10, 1))
+            .http_client(FailingHttpClient)
+            .retry_policy(RetryPolicy::NoRetry)
+            .circuit_breaker(1, Duration::from_secs(60))
+            .build()
+            .unwrap();
+
+        emitter.add(valid_payload()).unwrap();
+        wait_until(|| !emitter.is_healthy(), "Circuit did not open").await;
+
+        // This batch waits for the cooldown, but the loop keeps handling messages meanwhile
+        emitter.add(valid_payload()).unwrap();
+        let closing_at = std::time::Instant::now();
+        emitter.close().unwrap();
+        drop(emitter);
+
+        assert!(closing_at.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn on_retry_is_called_for_each_retry() {
+        let retries = Arc::new(Mutex::new(Vec::new()));
+        let recorded = retries.clone();
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(10, 1))
+            .http_client(FailingHttpClient)
+            .retry_policy(RetryPolicy::MaxRetries(3))
+            .first_retry_delay(Duration::from_millis(10), Duration::ZERO)
+            .on_retry(move |batch_id, attempt, delay| {
+                recorded.lock().unwrap().push((batch_id, attempt, delay))
+            })
+            .build()
+            .unwrap();
+
+        emitter.add(valid_payload()).unwrap();
+
+        wait_until(
+            || retries.lock().unwrap().len() >= 3,
+            "Batch was not retried",
+        )
+        .await;
+
+        let retries = retries.lock().unwrap();
+        let attempts: Vec<u32> = retries.iter().map(|(_, attempt, _)| *attempt).collect();
+        assert_eq!(attempts, vec![1, 2, 3]);
+        assert!(retries
+            .iter()
+            .all(|(batch_id, _, _)| *batch_id == retries[0].0));
+        assert!(retries.windows(2).all(|pair| pair[0].2 <= pair[1].2));
+
+        emitter.close().unwrap();
+    }
+
+    #[tokio::test]
+    async fn pending_retries_counts_requeued_batches() {
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(10, 1))
+            .http_client(FailingHttpClient)
+            .build()
+            .unwrap();
+
+        assert_eq!(emitter.pending_retries(), 0);
+
+        emitter.add(valid_payload()).unwrap();
+        emitter.add(valid_payload()).unwrap();
+
+        // The first retry is delayed by 1 second, so both batches are still waiting
+        wait_until(
+            || emitter.pending_retries() >= 2,
+            "Batches were not re-queued",
+        )
+        .await;
+        assert_eq!(emitter.pending_retries(), 2);
+
+        emitter.close().unwrap();
+    }
+
+    #[tokio::test]
+    async fn pending_retries_are_capped() {
+        let retries = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counted = retries.clone();
+        let dead_letters = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let dead_lettered = dead_letters.clone();
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(10, 1))
+            .http_client(FailingHttpClient)
+            .retry_policy(RetryPolicy::RetryForever)
+            .max_pending_retries(2)
+            .on_retry(move |_, _, _| {
+                counted.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            })
+            .on_dead_letter(move |events| {
+                dead_lettered.fetch_add(events.len(), std::sync::atomic::Ordering::SeqCst);
+            })
+            .build()
+            .unwrap();
+        emitter.undelivered.start();
+
+        for _ in 0..5 {
+            emitter.add(valid_payload()).unwrap();
+        }
+
+        // The first retry is delayed by 1 second, so every batch fails once before any is retried.
+        // The oldest are dropped, leaving only the capped retries undelivered
+        wait_until(
+            || {
+                assert!(emitter.pending_retries() <= 2);
+                retries.load(std::sync::atomic::Ordering::SeqCst) >= 5
+                    && emitter.undelivered.len() <= 2
+            },
+            "Oldest retries were not dropped",
+        )
+        .await;
+        assert_eq!(emitter.pending_retries(), 2);
+        // The dropped retries are passed to the dead-letter callback
+        wait_until(
+            || dead_letters.load(std::sync::atomic::Ordering::SeqCst) == 3,
+            "Dropped retries were not dead-lettered",
+        )
+        .await;
+
+        emitter.close().unwrap();
+    }
+
+    #[tokio::test]
+    async fn batches_given_up_on_are_dead_lettered() {
+        let dead_letters = Arc::new(Mutex::new(Vec::new()));
+        let dead_lettered = dead_letters.clone();
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(10, 2))
+            .http_client(FailingHttpClient)
+            .retry_policy(RetryPolicy::NoRetry)
+            .on_dead_letter(move |events| {
+                dead_lettered.lock().unwrap().extend_from_slice(events);
+            })
+            .build()
+            .unwrap();
+
+        emitter.add(valid_payload()).unwrap();
+        emitter.add(valid_payload()).unwrap();
+
+        wait_until(
+            || dead_letters.lock().unwrap().len() == 2,
+            "Failed batch was not dead-lettered",
+        )
+        .await;
+        emitter.close().unwrap();
+    }
+
+    #[tokio::test]
+    async fn first_send_waits_for_startup_jitter() {
+        let sent_at = Arc::new(Mutex::new(Vec::new()));
+        let created_at = std::time::Instant::now();
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(10, 1))
+            .http_client(RecordingHttpClient {
+                sent_at: sent_at.clone(),
+            })
+            .startup_jitter(Duration::from_millis(500))
+            .build()
+            .unwrap();
+
+        let startup_delay = emitter.startup_delay().unwrap();
+        assert!(startup_delay <= Duration::from_millis(500));
+
+        emitter.add(valid_payload()).unwrap();
+
+        wait_until(|| !sent_at.lock().unwrap().is_empty(), "Batch was not sent").await;
+
+        let (first_sent_at, _) = sent_at.lock().unwrap()[0];
+        assert!(first_sent_at.duration_since(created_at) >= startup_delay);
+
+        emitter.close().unwrap();
+    }
+
+    #[tokio::test]
+    async fn sends_events_from_async_event_store() {
+        let sent_at = Arc::new(Mutex::new(Vec::new()));
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .async_event_store(InMemoryEventStore::new(10, 2))
+            .http_client(RecordingHttpClient {
+                sent_at: sent_at.clone(),
+            })
+            .build()
+            .unwrap();
+
+        for _ in 0..3 {
+            emitter.add(valid_payload()).unwrap();
+        }
+        emitter.flush().unwrap();
+
+        wait_until(
+            || sent_at.lock().unwrap().len() >= 2,
+            "Batches were not sent",
+        )
+        .await;
+
+        let mut sizes: Vec<usize> = sent_at
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, size)| *size)
+            .collect();
+        sizes.sort();
+        assert_eq!(sizes, vec![1, 2]);
+
+        emitter.close().unwrap();
+    }
+
+    // An async store that waits for a permit before adding each event
+    struct GatedEventStore {
+        store: InMemoryEventStore,
+        gate: Arc<tokio::sync::Semaphore>,
+    }
+
+    #[async_trait]
+    impl AsyncEventStore for GatedEventStore {
+        async fn add(&mut self, payload: PayloadBuilder) -> Result<(), Error> {
+            self.gate.acquire().await.unwrap().forget();
+            EventStore::add(&mut self.store, payload)
+        }
+
+        async fn len(&self) -> usize {
+            EventStore::len(&self.store)
+        }
+
+        fn batch_size(&self) -> usize {
+            EventStore::batch_size(&self.store)
+        }
+
+        fn capacity(&self) -> usize {
+            EventStore::capacity(&self.store)
+        }
+
+        async fn full_batch(&mut self) -> Result<EventBatch, Error> {
+            EventStore::full_batch(&mut self.store)
+        }
+
+        async fn batch_of(&mut self, size: usize) -> Result<EventBatch, Error> {
+            EventStore::batch_of(&mut self.store, size)
+        }
+
+        async fn cleanup_after_send_attempt(&mut self, batch_id: Uuid) -> Result<(), Error> {
+            EventStore::cleanup_after_send_attempt(&mut self.store, batch_id)
+        }
+    }
+
+    #[tokio::test]
+    async fn adding_to_an_async_store_does_not_wait_for_it() {
+        let sent_at = Arc::new(Mutex::new(Vec::new()));
+        let gate = Arc::new(tokio::sync::Semaphore::new(0));
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .async_event_store(GatedEventStore {
+                store: InMemoryEventStore::new(10, 2),
+                gate: gate.clone(),
+            })
+            .http_client(RecordingHttpClient {
+                sent_at: sent_at.clone(),
+            })
+            .build()
+            .unwrap();
+
+        // The store can't add events yet, but the application thread isn't held up by it
+        emitter.add(valid_payload()).unwrap();
+        emitter.add(valid_payload()).unwrap();
+        assert_eq!(emitter.queue_pressure().unwrap().queued, 0);
+
+        gate.add_permits(2);
+        wait_until(
+            || sent_at.lock().unwrap().len() == 1,
+            "Full batch was not sent",
+        )
+        .await;
+        assert_eq!(sent_at.lock().unwrap()[0].1, 2);
+
+        emitter.close().unwrap();
+    }
+
+    // A HttpClient that records the events of each request
+    struct PayloadRecordingHttpClient {
+        events: Arc<Mutex<Vec<serde_json::Value>>>,
+    }
+
+    #[async_trait]
+    impl HttpClient for PayloadRecordingHttpClient {
+        async fn post(
+            &self,
+            payload: SelfDescribingJson,
+            _context: RequestContext,
+        ) -> Result<u16, Error> {
+            if let Some(events) = payload.data.as_array() {
+                self.events.lock().unwrap().extend(events.iter().cloned());
+            }
+            Ok(200)
+        }
+
+        fn clone(&self) -> Box<dyn HttpClient + Send + Sync> {
+            Box::new(PayloadRecordingHttpClient {
+                events: self.events.clone(),
+            })
+        }
+
+        fn set_collector_url(&mut self, _collector_url: &str) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    // Forwards to one emitter, so several trackers can share it
+    #[derive(Clone)]
+    struct SharedTestEmitter {
+        emitter: Arc<Mutex<BatchEmitter>>,
+        // Copied from the emitter, as a reference can't be returned from behind the lock
+        default_contexts: Vec<SelfDescribingJson>,
+    }
+
+    impl Emitter for SharedTestEmitter {
+        fn add(&mut self, payload: PayloadBuilder) -> Result<(), Error> {
+            self.emitter.lock().unwrap().add(payload)
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            self.emitter.lock().unwrap().flush()
+        }
+
+        fn close(&mut self) -> Result<(), Error> {
+            self.emitter.lock().unwrap().close()
+        }
+
+        fn collector_url(&self) -> &str {
+            "http://localhost:8080"
+        }
+
+        fn default_contexts(&self) -> &[SelfDescribingJson] {
+            &self.default_contexts
+        }
+    }
+
+    #[tokio::test]
+    async fn default_contexts_are_added_to_events_from_every_tracker() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(10, 10))
+            .http_client(PayloadRecordingHttpClient {
+                events: events.clone(),
+            })
+            .default_contexts(vec![SelfDescribingJson::new(
+                "iglu:com.acme/deployment/jsonschema/1-0-0",
+                serde_json::json!({"region": "eu-west-1"}),
+            )])
+            .build()
+            .unwrap();
+        let emitter = SharedTestEmitter {
+            default_contexts: emitter.default_contexts().to_vec(),
+            emitter: Arc::new(Mutex::new(emitter)),
+        };
+
+        let mut web = crate::Tracker::new("web", "app_id", emitter.clone(), None);
+        let mut api = crate::Tracker::new("api", "app_id", emitter, None);
+        for tracker in [&mut web, &mut api] {
+            let event = crate::StructuredEvent::builder()
+                .category("shop")
+                .action("add-to-basket")
+                .build()
+                .unwrap();
+            tracker.track(event, None).unwrap();
+        }
+        web.flush().unwrap();
+
+        wait_until(|| events.lock().unwrap().len() >= 2, "Events were not sent").await;
+
+        let events = events.lock().unwrap();
+        let mut namespaces: Vec<&str> = events.iter().map(|e| e["tna"].as_str().unwrap()).collect();
+        namespaces.sort();
+        assert_eq!(namespaces, vec!["api", "web"]);
+        for event in events.iter() {
+            let co: serde_json::Value =
+                serde_json::from_str(event["co"].as_str().unwrap()).unwrap();
+            assert_eq!(co["data"][0]["data"]["region"], "eu-west-1");
+        }
+        drop(events);
+
+        web.close_emitter().unwrap();
+    }
+
+    #[tokio::test]
+    async fn empty_fields_are_omitted_when_enabled() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(10, 1))
+            .http_client(PayloadRecordingHttpClient {
+                events: events.clone(),
+            })
+            .omit_empty_fields(true)
+            .build()
+            .unwrap();
+        let mut tracker = crate::Tracker::new("ns", "app_id", emitter, None);
+
+        let event = crate::StructuredEvent::builder()
+            .category("shop")
+            .action("add-to-basket")
+            .property("")
+            .build()
+            .unwrap();
+        tracker.track(event, None).unwrap();
+
+        wait_until(|| !events.lock().unwrap().is_empty(), "Event was not sent").await;
+
+        let event = events.lock().unwrap()[0].clone();
+        assert!(event.get("se_pr").is_none());
+        assert_eq!(event["se_ca"], "shop");
+
+        tracker.close_emitter().unwrap();
+    }
+
+    #[tokio::test]
+    async fn flushing_an_exact_multiple_of_batch_size_sends_all_events() {
+        let batch_size = 5;
+        let mut event_store = InMemoryEventStore::new(100, batch_size);
+        for _ in 0..2 * batch_size {
+            EventStore::add(&mut event_store, valid_payload()).unwrap();
+        }
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(event_store)
+            .http_client(PayloadRecordingHttpClient {
+                events: events.clone(),
+            })
+            .build()
+            .unwrap();
+
+        // Both full batches are sent, leaving no remainder batch
+        emitter.flush().unwrap();
+
+        wait_until(
+            || events.lock().unwrap().len() >= 2 * batch_size,
+            "Events were not sent",
+        )
+        .await;
+        assert!(sync_store(&emitter).lock().unwrap().is_empty());
+
+        emitter.close_async().await.unwrap();
+        assert_eq!(events.lock().unwrap().len(), 2 * batch_size);
+    }
+
+    #[tokio::test]
+    async fn events_are_sent_in_dtm_order_when_enabled() {
+        let mut event_store = InMemoryEventStore::new(10, 10);
+        for dtm in ["3", "1", "2"] {
+            EventStore::add(&mut event_store, valid_payload().dtm(dtm.to_string())).unwrap();
+        }
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(event_store)
+            .http_client(PayloadRecordingHttpClient {
+                events: events.clone(),
+            })
+            .sort_by_dtm(true)
+            .build()
+            .unwrap();
+
+        emitter.close_async().await.unwrap();
+
+        let dtms: Vec<serde_json::Value> = events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|event| event["dtm"].clone())
+            .collect();
+        assert_eq!(dtms, vec!["1", "2", "3"]);
+    }
+
+    #[tokio::test]
+    async fn expired_events_are_dropped_rather_than_sent() {
+        let mut event_store = InMemoryEventStore::new(10, 10);
+        let expire_at = SystemTime::now() + Duration::from_millis(50);
+        EventStore::add(
+            &mut event_store,
+            valid_payload()
+                .aid("expiring".to_string())
+                .expire_at(expire_at),
+        )
+        .unwrap();
+        EventStore::add(&mut event_store, valid_payload().aid("kept".to_string())).unwrap();
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let dead_lettered = Arc::new(Mutex::new(Vec::new()));
+        let dead_lettered_clone = dead_lettered.clone();
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(event_store)
+            .http_client(PayloadRecordingHttpClient {
+                events: events.clone(),
+            })
+            .on_dead_letter(move |events| {
+                dead_lettered_clone.lock().unwrap().extend(
+                    events
+                        .iter()
+                        .map(|event| serde_json::to_value(event).unwrap()["aid"].clone()),
+                );
+            })
+            .build()
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        emitter.close_async().await.unwrap();
+
+        assert_eq!(*dead_lettered.lock().unwrap(), vec!["expiring"]);
+
+        let aids: Vec<serde_json::Value> = events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|event| event["aid"].clone())
+            .collect();
+        assert_eq!(aids, vec!["kept"]);
+    }
+
+    #[tokio::test]
+    async fn requests_are_rate_limited() {
+        let sent_at = Arc::new(Mutex::new(Vec::new()));
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(20, 1))
+            .http_client(RecordingHttpClient {
+                sent_at: sent_at.clone(),
+            })
+            .max_requests_per_second(2)
+            .build()
+            .unwrap();
+
+        let start = std::time::Instant::now();
+        for _ in 0..10 {
+            emitter.add(valid_payload()).unwrap();
+        }
+
+        wait_until(
+            || sent_at.lock().unwrap().len() >= 10,
+            "Batches were not sent",
+        )
+        .await;
+
+        // The first batch is sent straight away, then one every 500ms
+        let elapsed = sent_at.lock().unwrap()[9].0 - start;
+        assert!(
+            elapsed >= Duration::from_millis(4400) && elapsed < Duration::from_secs(6),
+            "10 batches took {elapsed:?}"
+        );
+
+        emitter.close_async().await.unwrap();
+    }
+
+    #[test]
+    fn zero_requests_per_second_is_rejected() {
+        let result = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .max_requests_per_second(0)
+            .build();
+
+        assert!(matches!(result, Err(Error::BuilderError(_))));
+    }
+
+    #[tokio::test]
+    async fn paused_emitter_buffers_events_until_resumed() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(10, 2))
+            .http_client(PayloadRecordingHttpClient {
+                events: events.clone(),
+            })
+            .flush_interval(Duration::from_millis(20))
+            .build()
+            .unwrap();
+
+        emitter.pause();
+        assert!(emitter.is_paused());
+        for _ in 0..4 {
+            emitter.add(valid_payload()).unwrap();
+        }
+        emitter.flush().unwrap();
+
+        // Neither full batches, flushes nor the flush interval send events while paused
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(events.lock().unwrap().is_empty());
+        assert_eq!(sync_store(&emitter).lock().unwrap().len(), 4);
+
+        emitter.resume().unwrap();
+        assert!(!emitter.is_paused());
+
+        wait_until(|| events.lock().unwrap().len() >= 4, "Events were not sent").await;
+
+        emitter.close_async().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn terminal_send_failures_are_reported_as_diagnostics() {
+        let diagnostics = Arc::new(Mutex::new(Vec::new()));
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(10, 1))
+            .http_client(FailingHttpClient)
+            .retry_policy(RetryPolicy::NoRetry)
+            .diagnostics("http://diagnostics.example.com")
+            .diagnostics_http_client(PayloadRecordingHttpClient {
+                events: diagnostics.clone(),
+            })
+            .build()
+            .unwrap();
+
+        emitter.add(valid_payload()).unwrap();
+
+        wait_until(
+            || !diagnostics.lock().unwrap().is_empty(),
+            "Diagnostic was not sent",
+        )
+        .await;
+        emitter.close_async().await.unwrap();
+
+        let diagnostics = diagnostics.lock().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        let ue_pr: serde_json::Value =
+            serde_json::from_str(diagnostics[0]["ue_pr"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            ue_pr["data"]["schema"],
+            "iglu:com.snowplowanalytics.snowplow/diagnostic_error/jsonschema/1-0-0"
+        );
+        assert!(ue_pr["data"]["data"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("failed to send"));
+    }
+
+    #[tokio::test]
+    async fn a_full_event_store_is_reported_as_a_diagnostic_once() {
+        let diagnostics = Arc::new(Mutex::new(Vec::new()));
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(1, 2))
+            .http_client(FailingHttpClient)
+            .retry_policy(RetryPolicy::NoRetry)
+            .diagnostics("http://diagnostics.example.com")
+            .diagnostics_http_client(PayloadRecordingHttpClient {
+                events: diagnostics.clone(),
+            })
+            .build()
+            .unwrap();
+        emitter.pause();
+
+        emitter.add(valid_payload()).unwrap();
+        assert!(matches!(
+            emitter.add(valid_payload()),
+            Err(Error::QueueFull)
+        ));
+        assert!(matches!(
+            emitter.add(valid_payload()),
+            Err(Error::QueueFull)
+        ));
+
+        wait_until(
+            || !diagnostics.lock().unwrap().is_empty(),
+            "Diagnostic was not sent",
+        )
+        .await;
+        emitter.clear().unwrap();
+        emitter.close_async().await.unwrap();
+
+        let diagnostics = diagnostics.lock().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        let ue_pr: serde_json::Value =
+            serde_json::from_str(diagnostics[0]["ue_pr"].as_str().unwrap()).unwrap();
+        assert!(ue_pr["data"]["data"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("full"));
+    }
+
+    #[tokio::test]
+    async fn diagnostics_are_sent_to_the_diagnostics_url() {
+        let sent_to = Arc::new(Mutex::new(Vec::new()));
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(10, 1))
+            .http_client(FailingHttpClient)
+            .retry_policy(RetryPolicy::NoRetry)
+            .diagnostics("http://diagnostics.example.com")
+            .diagnostics_http_client(UrlRecordingHttpClient {
+                collector_url: "http://somewhere-else".to_string(),
+                down_url: None,
+                sent_to: sent_to.clone(),
+            })
+            .build()
+            .unwrap();
+
+        emitter.add(valid_payload()).unwrap();
+
+        wait_until(
+            || !sent_to.lock().unwrap().is_empty(),
+            "Diagnostic was not sent",
+        )
+        .await;
+        emitter.close_async().await.unwrap();
+
+        assert_eq!(
+            *sent_to.lock().unwrap(),
+            vec!["http://diagnostics.example.com".to_string()]
+        );
+    }
+
+    #[test]
+    fn diagnostics_clients_must_support_changing_the_collector_url() {
+        let result = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .diagnostics("http://diagnostics.example.com")
+            .diagnostics_http_client(FailingHttpClient)
+            .build();
+
+        assert!(matches!(result, Err(Error::BuilderError(_))));
+    }
+
+    // A HttpClient that records the collector URL of each request, failing requests to the down URL
+    struct UrlRecordingHttpClient {
+        collector_url: String,
+        down_url: Option<&'static str>,
+        sent_to: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl HttpClient for UrlRecordingHttpClient {
+        async fn post(
+            &self,
+            _payload: SelfDescribingJson,
+            _context: RequestContext,
+        ) -> Result<u16, Error> {
+            self.sent_to
+                .lock()
+                .unwrap()
+                .push(self.collector_url.clone());
+            match self.down_url {
+                Some(down_url) if down_url == self.collector_url => Ok(503),
+                _ => Ok(200),
+            }
+        }
+
+        fn clone(&self) -> Box<dyn HttpClient + Send + Sync> {
+            Box::new(UrlRecordingHttpClient {
+                collector_url: self.collector_url.clone(),
+                down_url: self.down_url,
+                sent_to: self.sent_to.clone(),
+            })
+        }
+
+        fn set_collector_url(&mut self, collector_url: &str) -> Result<(), Error> {
+            self.collector_url = collector_url.to_string();
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn set_collector_url_changes_where_events_are_sent() {
+        let sent_to = Arc::new(Mutex::new(Vec::new()));
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://old-collector")
+            .event_store(InMemoryEventStore::new(10, 1))
+            .http_client(UrlRecordingHttpClient {
+                collector_url: "http://old-collector".to_string(),
+                down_url: None,
+                sent_to: sent_to.clone(),
+            })
+            .build()
+            .unwrap();
+
+        emitter.add(valid_payload()).unwrap();
+
+        wait_until(|| !sent_to.lock().unwrap().is_empty(), "Event was not sent").await;
+
+        emitter.set_collector_url("http://new-collector").unwrap();
+        assert_eq!(emitter.collector_url(), "http://new-collector");
+        emitter.add(valid_payload()).unwrap();
+
+        wait_until(|| sent_to.lock().unwrap().len() >= 2, "Event was not sent").await;
+
+        assert_eq!(
+            *sent_to.lock().unwrap(),
+            vec!["http://old-collector", "http://new-collector"]
+        );
+
+        emitter.close().unwrap();
+    }
+
+    #[tokio::test]
+    async fn set_collector_url_is_rolled_back_when_the_loop_has_stopped() {
+        let sent_to = Arc::new(Mutex::new(Vec::new()));
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://old-collector")
+            .http_client(UrlRecordingHttpClient {
+                collector_url: "http://old-collector".to_string(),
+                down_url: None,
+                sent_to: sent_to.clone(),
+            })
+            .build()
+            .unwrap();
+        emitter.close().unwrap();
+        wait_until(|| emitter.tx.is_closed(), "Emitter loop did not stop").await;
+
+        assert!(emitter.set_collector_url("http://new-collector").is_err());
+        assert_eq!(emitter.collector_url(), "http://old-collector");
+
+        // The emitter's own client still sends to the old URL
+        emitter
+            .http_client
+            .post(
+                SelfDescribingJson::new(
+                    "iglu:com.acme/event/jsonschema/1-0-0",
+                    serde_json::json!([]),
+                ),
+                RequestContext {
+                    batch_id: Uuid::new_v4(),
+                    attempt: 1,
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(*sent_to.lock().unwrap(), vec!["http://old-collector"]);
+    }
+
+    #[tokio::test]
+    async fn fails_over_to_backup_collector() {
+        let sent_to = Arc::new(Mutex::new(Vec::new()));
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://primary")
+            .event_store(InMemoryEventStore::new(10, 1))
+            .http_client(UrlRecordingHttpClient {
+                collector_url: "http://primary".to_string(),
+                down_url: Some("http://primary"),
+                sent_to: sent_to.clone(),
+            })
+            .failover(&["http://backup"], 1, Duration::from_secs(60))
+            .build()
+            .unwrap();
+
+        emitter.add(valid_payload()).unwrap();
+
+        // The first retry is delayed by 1 second
+        wait_until(
+            || sent_to.lock().unwrap().len() >= 2,
+            "Batch was not retried",
+        )
+        .await;
+
+        assert_eq!(
+            *sent_to.lock().unwrap(),
+            vec!["http://primary", "http://backup"]
+        );
+
+        emitter.close().unwrap();
+    }
+
+    #[tokio::test]
+    async fn set_collector_url_replaces_the_failover_primary() {
+        let sent_to = Arc::new(Mutex::new(Vec::new()));
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://primary")
+            .event_store(InMemoryEventStore::new(10, 1))
+            .http_client(UrlRecordingHttpClient {
+                collector_url: "http://primary".to_string(),
+                down_url: Some("http://primary"),
+                sent_to: sent_to.clone(),
+            })
+            .retry_policy(RetryPolicy::NoRetry)
+            .failover(&["http://backup"], 1, Duration::from_secs(60))
+            .build()
+            .unwrap();
+
+        emitter.add(valid_payload()).unwrap();
+        wait_until(|| !sent_to.lock().unwrap().is_empty(), "Event was not sent").await;
+
+        emitter.set_collector_url("http://new-primary").unwrap();
+        emitter.add(valid_payload()).unwrap();
+        wait_until(|| sent_to.lock().unwrap().len() >= 2, "Event was not sent").await;
+
+        assert_eq!(
+            *sent_to.lock().unwrap(),
+            vec!["http://primary", "http://new-primary"]
+        );
+
+        emitter.close().unwrap();
+    }
+
+    #[test]
+    fn failover_requires_client_support() {
+        let result = BatchEmitter::builder()
+            .collector_url("http://primary")
+            .http_client(FailingHttpClient)
+            .failover(&["http://backup"], 1, Duration::from_secs(60))
+            .build();
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn set_collector_url_requires_client_support() {
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .http_client(FailingHttpClient)
+            .build()
+            .unwrap();
+
+        assert!(emitter.set_collector_url("http://localhost:9090").is_err());
+        assert_eq!(emitter.collector_url(), "http://localhost:8080");
+
+        emitter.close().unwrap();
+    }
+
+    // A HttpClient that receives a 401 for the first request, then succeeds
+    struct UnauthorizedOnceHttpClient {
+        count: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl HttpClient for UnauthorizedOnceHttpClient {
+        async fn post(
+            &self,
+            _payload: SelfDescribingJson,
+            _context: RequestContext,
+        ) -> Result<u16, Error> {
+            match self.count.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                0 => Ok(401),
+                _ => Ok(200),
+            }
+        }
+
+        fn clone(&self) -> Box<dyn HttpClient + Send + Sync> {
+            Box::new(UnauthorizedOnceHttpClient {
+                count: self.count.clone(),
+            })
+        }
+    }
+
+    // A HttpClient that records the context of each request, failing the first request
+    struct ContextRecordingHttpClient {
+        contexts: Arc<Mutex<Vec<RequestContext>>>,
+    }
+
+    #[async_trait]
+    impl HttpClient for ContextRecordingHttpClient {
+        // Unused, as the emitter sends batches with post_with_response
+        async fn post(
+            &self,
+            _payload: SelfDescribingJson,
+            _context: RequestContext,
+        ) -> Result<u16, Error> {
+            Ok(200)
+        }
+
+        async fn post_with_response(
+            &self,
+            _payload: SelfDescribingJson,
+            context: RequestContext,
+        ) -> Result<crate::CollectorResponse, Error> {
+            let mut contexts = self.contexts.lock().unwrap();
+            contexts.push(context);
+            let status = match contexts.len() {
+                1 => 500,
+                _ => 200,
+            };
+            Ok(crate::CollectorResponse {
+                status,
+                body: String::new(),
+            })
+        }
+
+        fn clone(&self) -> Box<dyn HttpClient + Send + Sync> {
+            Box::new(ContextRecordingHttpClient {
+                contexts: self.contexts.clone(),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_keep_the_batch_id_and_count_attempts() {
+        let contexts = Arc::new(Mutex::new(Vec::new()));
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(10, 1))
+            .http_client(ContextRecordingHttpClient {
+                contexts: contexts.clone(),
+            })
+            .build()
+            .unwrap();
+
+        emitter.add(valid_payload()).unwrap();
+
+        // The first retry is delayed by 1 second
+        wait_until(
+            || contexts.lock().unwrap().len() >= 2,
+            "Batch was not retried",
+        )
+        .await;
+        emitter.close().unwrap();
+
+        let contexts = contexts.lock().unwrap();
+        assert_eq!(contexts[0].batch_id, contexts[1].batch_id);
+        assert_eq!(contexts[0].attempt, 1);
+        assert_eq!(contexts[1].attempt, 2);
+    }
+
+    // A HttpClient that panics when cloned by the background thread
+    struct PanicOnCloneHttpClient {
+        clones: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl HttpClient for PanicOnCloneHttpClient {
+        async fn post(
+            &self,
+            _payload: SelfDescribingJson,
+            _context: RequestContext,
+        ) -> Result<u16, Error> {
+            Ok(200)
+        }
+
+        fn clone(&self) -> Box<dyn HttpClient + Send + Sync> {
+            // The first clone is made when the emitter is created, and only the next clone panics
+            if self
+                .clones
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+                == 1
+            {
+                panic!("Failed to clone client");
+            }
+            Box::new(PanicOnCloneHttpClient {
+                clones: self.clones.clone(),
+            })
+        }
+    }
+
+    // A HttpClient that records the names of the threads it is cloned and sends on
+    struct ThreadNameRecordingHttpClient {
+        threads: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl ThreadNameRecordingHttpClient {
+        fn record_thread(&self) {
+            let name = std::thread::current()
+                .name()
+                .unwrap_or("<unnamed>")
+                .to_string();
+            self.threads.lock().unwrap().push(name);
+        }
+    }
+
+    #[async_trait]
+    impl HttpClient for ThreadNameRecordingHttpClient {
+        async fn post(
+            &self,
+            _payload: SelfDescribingJson,
+            _context: RequestContext,
+        ) -> Result<u16, Error> {
+            self.record_thread();
+            Ok(200)
+        }
+
+        fn clone(&self) -> Box<dyn HttpClient + Send + Sync> {
+            self.record_thread();
+            Box::new(ThreadNameRecordingHttpClient {
+                threads: self.threads.clone(),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn background_threads_are_named() {
+        let threads = Arc::new(Mutex::new(Vec::new()));
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(10, 1))
+            .http_client(ThreadNameRecordingHttpClient {
+                threads: threads.clone(),
+            })
+            .thread_name("snowplow-emitter-ns")
+            .build()
+            .unwrap();
+
+        emitter.add(valid_payload()).unwrap();
+        emitter.close_async().await.unwrap();
+
+        // Batches are dispatched from the emitter thread, and sent from the runtime's workers
+        let threads = threads.lock().unwrap();
+        assert!(threads.contains(&"snowplow-emitter-ns".to_string()));
+        assert!(threads.contains(&"snowplow-emitter-ns-worker".to_string()));
+    }
+
+    #[test]
+    fn thread_names_with_null_bytes_are_rejected() {
+        let result = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .thread_name("snowplow\0emitter")
+            .build();
+
+        assert!(matches!(result, Err(Error::BuilderError(_))));
+    }
+
+    #[tokio::test]
+    async fn emitters_are_named_after_the_tracker_namespace() {
+        let mut emitter = BatchEmitter::new_named("http://localhost:8080", "ns");
+        let thread_name = emitter
+            .executor_handle
+            .as_ref()
+            .and_then(|handle| handle.thread().name().map(str::to_string));
+        emitter.close_async().await.unwrap();
+        assert_eq!(thread_name.as_deref(), Some("snowplow-emitter-ns"));
+
+        // A namespace that can't be a thread name falls back to the default name
+        let mut emitter = BatchEmitter::new_named("http://localhost:8080", "n\0s");
+        let thread_name = emitter
+            .executor_handle
+            .as_ref()
+            .and_then(|handle| handle.thread().name().map(str::to_string));
+        emitter.close_async().await.unwrap();
+        assert_eq!(thread_name.as_deref(), Some("snowplow-emitter"));
+    }
+
+    // A HttpClient whose sends block the thread they run on, as a hung synchronous operation would
+    struct BlockingHttpClient;
+
+    #[async_trait]
+    impl HttpClient for BlockingHttpClient {
+        async fn post(
+            &self,
+            _payload: SelfDescribingJson,
+            _context: RequestContext,
+        ) -> Result<u16, Error> {
+            std::thread::sleep(Duration::from_secs(3));
+            Ok(200)
+        }
+
+        fn clone(&self) -> Box<dyn HttpClient + Send + Sync> {
+            Box::new(BlockingHttpClient)
+        }
+    }
+
+    #[test]
+    fn drop_gives_up_on_a_stuck_thread_after_the_timeout() {
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(10, 1))
+            .http_client(BlockingHttpClient)
+            .drop_timeout(Duration::from_millis(200))
+            .build()
+            .unwrap();
+
+        emitter.add(valid_payload()).unwrap();
+        // Closing waits for the stuck send, so the thread doesn't finish
+        emitter.close().unwrap();
+
+        let start = Instant::now();
+        drop(emitter);
+
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn background_panic_is_recorded_not_propagated_on_drop() {
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(10, 1))
+            .http_client(PanicOnCloneHttpClient {
+                clones: Arc::new(std::sync::atomic::AtomicUsize::new(0)),
+            })
+            .build()
+            .unwrap();
+
+        emitter.add(valid_payload()).unwrap();
+
+        wait_until(
+            || emitter.background_error().is_some(),
+            "Thread did not panic",
+        )
+        .await;
+
+        assert_eq!(
+            emitter.background_error().unwrap(),
+            "Failed to clone client"
+        );
+        drop(emitter);
+    }
+
+    #[tokio::test]
+    async fn add_after_background_panic_is_emitter_dead() {
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(10, 1))
+            .http_client(PanicOnCloneHttpClient {
+                clones: Arc::new(std::sync::atomic::AtomicUsize::new(0)),
+            })
+            .build()
+            .unwrap();
+
+        emitter.add(valid_payload()).unwrap();
+
+        wait_until(
+            || emitter.background_error().is_some(),
+            "Thread did not panic",
+        )
+        .await;
+
+        match emitter.add(valid_payload()) {
+            Err(Error::EmitterDead(reason)) => assert_eq!(reason, "Failed to clone client"),
+            other => panic!("Expected the emitter to be dead, got {other:?}"),
+        }
+        assert!(matches!(emitter.flush(), Err(Error::EmitterDead(_))));
+        assert!(sync_store(&emitter).lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn background_thread_is_restarted_when_enabled() {
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(10, 1))
+            .http_client(PanicOnCloneHttpClient {
+                clones: Arc::new(std::sync::atomic::AtomicUsize::new(0)),
+            })
+            .restart_on_failure(true)
+            .build()
+            .unwrap();
+
+        emitter.add(valid_payload()).unwrap();
+
+        wait_until(
+            || emitter.background_error().is_some(),
+            "Thread did not panic",
+        )
+        .await;
+
+        emitter.add(valid_payload()).unwrap();
+        assert!(emitter.background_error().is_none());
+
+        emitter.close_async().await.unwrap();
+    }
+
+    #[test]
+    fn no_content_is_success() {
+        assert_eq!(
+            BatchEmitter::response_action(204, &DONT_RETRY_STATUS_CODES),
+            ResponseAction::Success
+        );
+    }
+
+    #[test]
+    fn unfollowed_redirects_are_not_retried() {
+        for code in [301, 302, 307, 308] {
+            assert_eq!(
+                BatchEmitter::response_action(code, &DONT_RETRY_STATUS_CODES),
+                ResponseAction::Redirect
+            );
+        }
+    }
+
+    #[test]
+    fn should_retry_with_configured_codes() {
+        assert_eq!(
+            BatchEmitter::response_action(401, &DONT_RETRY_STATUS_CODES),
+            ResponseAction::Fail
+        );
+        assert_eq!(
+            BatchEmitter::response_action(401, &[400, 422]),
+            ResponseAction::Retry
+        );
+        assert_eq!(
+            BatchEmitter::response_action(422, &[400, 422]),
+            ResponseAction::Fail
+        );
+    }
+
+    #[test]
+    fn retry_policy_depends_on_batch_priority() {
+        let settings = SendSettings {
+            retry_policy: RetryPolicy::MaxRetries(3),
+            priority_retry_policies: Arc::new(HashMap::from([
+                (Priority::High, RetryPolicy::RetryForever),
+                (Priority::Low, RetryPolicy::NoRetry),
+            ])),
+            ..SendSettings::default()
+        };
+        let batch_of = |priority| {
+            let payload = valid_payload()
+                .priority(priority)
+                .finalise_payload()
+                .unwrap();
+            EventBatch::new(Uuid::new_v4(), vec![payload])
+        };
+
+        assert_eq!(
+            settings.retry_policy_for(&batch_of(Priority::High)),
+            RetryPolicy::RetryForever
+        );
+        assert_eq!(
+            settings.retry_policy_for(&batch_of(Priority::Normal)),
+            RetryPolicy::MaxRetries(3)
+        );
+        assert_eq!(
+            settings.retry_policy_for(&batch_of(Priority::Low)),
+            RetryPolicy::NoRetry
+        );
+    }
+
+    #[tokio::test]
+    async fn retries_configured_retryable_code() {
+        let count = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(10, 1))
+            .http_client(UnauthorizedOnceHttpClient {
+                count: count.clone(),
+            })
+            .non_retryable_codes(&[400, 422])
+            .build()
+            .unwrap();
+
+        emitter.add(valid_payload()).unwrap();
+
+        // The first retry is delayed by 1 second
+        wait_until(
+            || count.load(std::sync::atomic::Ordering::SeqCst) >= 2,
+            "Batch was not retried",
+        )
+        .await;
+
+        emitter.close().unwrap();
+    }
+
+    #[tokio::test]
+    async fn sends_with_a_single_worker_thread() {
+        let sent_at = Arc::new(Mutex::new(Vec::new()));
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(10, 1))
+            .http_client(RecordingHttpClient {
+                sent_at: sent_at.clone(),
+            })
+            .worker_threads(1)
+            .build()
+            .unwrap();
+
+        emitter.add(valid_payload()).unwrap();
+        emitter.add(valid_payload()).unwrap();
+
+        wait_until(
+            || sent_at.lock().unwrap().len() >= 2,
+            "Events were not sent",
+        )
+        .await;
+
+        emitter.close().unwrap();
+    }
+
+    #[tokio::test]
+    async fn sends_with_custom_runtime_threads() {
+        let sent_at = Arc::new(Mutex::new(Vec::new()));
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(10, 1))
+            .http_client(RecordingHttpClient {
+                sent_at: sent_at.clone(),
+            })
+            .thread_stack_size(8 * 1024 * 1024)
+            .max_blocking_threads(4)
+            .build()
+            .unwrap();
+
+        emitter.add(valid_payload()).unwrap();
+        emitter.add(valid_payload()).unwrap();
+        emitter.close_async().await.unwrap();
+
+        assert_eq!(sent_at.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn zero_max_blocking_threads_is_rejected() {
+        let result = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .max_blocking_threads(0)
+            .build();
+
+        assert!(matches!(result, Err(Error::BuilderError(_))));
     }
 
-    // Starts a tokio runtime and runs the emitter loop
-    fn start_tokio(
-        http_client: Box<dyn HttpClient + Send + Sync>,
-        mut rx: tokio::sync::mpsc::Receiver<EmitterMessage>,
-        event_store: Arc<Mutex<dyn EventStore + Send + Sync>>,
-        retry_policy: RetryPolicy,
-    ) {
-        // Create a new runtime to handle the async tasks
-        // Unwrap here as if the runtime fails to start, there is nothing we can do
-        let rt = tokio::runtime::Builder::new_multi_thread()
-            .enable_all()
+    #[test]
+    fn batches_are_kept_in_the_store_when_the_send_queue_is_full() {
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(10, 2))
             .build()
             .unwrap();
 
-        // The main emitter loop
-        // This continuously loops and checks for new batches to send
-        rt.block_on(async {
-            // The currently running tokio tasks
-            let mut tokio_tasks: Vec<_> = Vec::new();
-            let (retry_tx, mut retry_rx) = tokio::sync::mpsc::unbounded_channel();
-
-            loop {
-                // `rx.recv().await` will not resolve until either a message is received,
-                // or the channel is closed and there are no more messages, in which case we exit the loop
+        // Replace the send queue with a full one that the emitter loop doesn't read
+        let (tx, _rx) = tokio::sync::mpsc::channel(1);
+        tx.try_send(EmitterMessage::SetCollectorUrl(
+            "http://localhost:8080".to_string(),
+        ))
+        .unwrap();
+        let loop_tx = std::mem::replace(&mut emitter.tx, tx);
 
-                // select! is used to check both the `retry_rx` channel and the `rx` channel for new messages
-                let message = match tokio::select! {
-                    // `biased;` is used to ensure that the `retry_rx` channel is checked first, so retries get priority
-                    biased;
+        emitter.add(valid_payload()).unwrap();
+        assert!(matches!(
+            emitter.add(valid_payload()),
+            Err(Error::SendQueueFull)
+        ));
+        assert_eq!(sync_store(&emitter).lock().unwrap().len(), 2);
 
-                    retry = retry_rx.recv() => retry,
-                    event = rx.recv() => event,
-                } {
-                    Some(message) => message,
-                    None => break,
-                };
+        emitter.tx = loop_tx;
+        emitter.close().unwrap();
+    }
 
-                match message {
-                    EmitterMessage::Send(batch) => {
-                        // Clone to move into the task
-                        let client = http_client.clone();
-                        let retry_transmitter = retry_tx.clone();
-                        let store = event_store.clone();
+    #[test]
+    fn zero_worker_threads_is_rejected() {
+        let result = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .worker_threads(0)
+            .build();
 
-                        // Spawn a new task to send the batch
-                        tokio_tasks.push(tokio::spawn(async move {
-                            Self::batch_send_task(
-                                batch,
-                                client,
-                                retry_transmitter,
-                                store,
-                                retry_policy,
-                            )
-                            .await
-                        }));
-                    }
+        assert!(matches!(result, Err(Error::BuilderError(_))));
+    }
 
-                    // On break, the emitter and runtime will be dropped
-                    //
-                    // Tokio will cancel any running tasks once the runtime is dropped, meaning any queued or retry batches will be lost,
-                    // so we attempt to send any remaining batches before exiting
-                    EmitterMessage::Close => {
-                        let remaining = tokio_tasks.len();
-                        for (i, task) in tokio_tasks.iter_mut().enumerate() {
-                            log::debug!("Waiting for task {}/{remaining} to complete", i + 1);
-                            task.await.unwrap();
-                        }
-                        break;
-                    }
-                }
+    #[test]
+    fn zero_failure_thresholds_are_rejected() {
+        let result = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .circuit_breaker(0, Duration::from_secs(1))
+            .build();
+        assert!(matches!(result, Err(Error::BuilderError(_))));
 
-                // Discard any completed tasks in the task list
-                tokio_tasks.retain(|t| !t.is_finished());
-            }
-        });
+        let result = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .failover(&["http://localhost:8081"], 0, Duration::from_secs(1))
+            .build();
+        assert!(matches!(result, Err(Error::BuilderError(_))));
     }
-}
 
-impl Drop for BatchEmitter {
-    fn drop(&mut self) {
-        // Get the join handle for the thread running the tokio runtime and wait for it to finish
-        //
-        // It's likely that the thread has already finished once the emitter loop has exited
-        if let Some(handle) = self.executor_handle.take() {
-            handle.join().unwrap();
-            log::debug!("BatchEmitter thread joined");
+    #[test]
+    fn invalid_store_watermarks_are_rejected() {
+        for (high_watermark, low_watermark) in [(1.5, 0.5), (0.9, -0.1), (0.5, 0.5), (0.5, 0.9)] {
+            let result = BatchEmitter::builder()
+                .collector_url("http://localhost:8080")
+                .on_store_full(high_watermark, low_watermark, |_| {})
+                .build();
+
+            assert!(
+                matches!(result, Err(Error::BuilderError(_))),
+                "Watermarks {high_watermark} and {low_watermark} were accepted"
+            );
         }
-        log::debug!("BatchEmitter dropped");
     }
-}
 
-impl Emitter for BatchEmitter {
-    /// Adds a payload to the event store
-    ///
-    /// This may also trigger sending a payload to the collector if the event store has enough events to fill a batch
-    fn add(&mut self, payload: PayloadBuilder) -> Result<(), Error> {
-        let batch = match self.event_store.lock() {
-            Ok(mut store) => {
-                match store.add(payload) {
-                    Ok(_) => log::debug!("Added event to event store"),
-                    Err(e) => {
-                        log::error!("Failed to add event to event store: {e}");
-                        return Err(e);
-                    }
-                }
-                // If the event store has enough events to fill a batch, return the batch
-                store.full_batch()
-            }
-            Err(e) => return Err(Error::EmitterError(e.to_string())),
-        };
+    #[tokio::test]
+    async fn close_async_waits_for_events_to_send() {
+        let sent_at = Arc::new(Mutex::new(Vec::new()));
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(10, 2))
+            .http_client(RecordingHttpClient {
+                sent_at: sent_at.clone(),
+            })
+            .build()
+            .unwrap();
 
-        // We can ignore the error here, as the only error that can return is the event store being empty,
-        // in which case we don't want to send a batch
-        if let Ok(batch) = batch {
-            return match self.tx.try_send(EmitterMessage::Send(batch)) {
-                Ok(_) => Ok(()),
-                Err(e) => Err(Error::EmitterError(e.to_string())),
-            };
+        for _ in 0..3 {
+            emitter.add(valid_payload()).unwrap();
         }
 
-        Ok(())
+        emitter.close_async().await.unwrap();
+
+        let event_counts: Vec<usize> = sent_at.lock().unwrap().iter().map(|s| s.1).collect();
+        assert_eq!(event_counts.iter().sum::<usize>(), 3);
     }
 
-    /// Attempt to send all events currently in the event store
-    fn flush(&mut self) -> Result<(), Error> {
-        log::debug!("Flushing event store");
+    #[tokio::test]
+    async fn close_with_timeout_returns_undelivered_events() {
+        // Nothing is listening on this port, so every request fails to connect
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:1")
+            .event_store(InMemoryEventStore::new(10, 2))
+            .build()
+            .unwrap();
 
-        // Get a lock on the event store
-        let mut store_lock = match self.event_store.lock() {
-            Ok(store) => store,
-            Err(e) => return Err(Error::EmitterError(e.to_string())),
-        };
+        for _ in 0..3 {
+            emitter.add(valid_payload()).unwrap();
+        }
 
-        // Send batches until the event store doesn't have enough events to fill a batch
-        while let Ok(batch) = store_lock.full_batch() {
-            if let Err(e) = self.tx.try_send(EmitterMessage::Send(batch)) {
-                return Err(Error::EmitterError(e.to_string()));
-            }
+        match emitter.close_with_timeout(Duration::from_millis(500)).await {
+            Err(Error::UndeliveredEvents(events)) => assert_eq!(events.len(), 3),
+            other => panic!("Expected undelivered events, got {other:?}"),
         }
+    }
 
-        // Create a batch of the remaining events and send it
-        let remaining_events = store_lock.len();
-        let final_batch = store_lock.batch_of(remaining_events)?;
-        if let Err(e) = self.tx.try_send(EmitterMessage::Send(final_batch)) {
-            return Err(Error::EmitterError(e.to_string()));
-        };
+    #[tokio::test]
+    async fn on_store_full_fires_at_watermarks() {
+        let levels = Arc::new(Mutex::new(Vec::new()));
+        let recorded = levels.clone();
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(10, 10))
+            .http_client(RecordingHttpClient {
+                sent_at: Arc::new(Mutex::new(Vec::new())),
+            })
+            .on_store_full(0.9, 0.5, move |level| recorded.lock().unwrap().push(level))
+            .build()
+            .unwrap();
 
-        log::debug!("Finished flushing event store");
+        for _ in 0..9 {
+            emitter.add(valid_payload()).unwrap();
+        }
+        assert_eq!(
+            *levels.lock().unwrap(),
+            vec![StoreLevel::AboveHighWatermark]
+        );
 
-        Ok(())
+        // The tenth event fills a batch, draining the store
+        emitter.add(valid_payload()).unwrap();
+        assert_eq!(
+            *levels.lock().unwrap(),
+            vec![
+                StoreLevel::AboveHighWatermark,
+                StoreLevel::BelowLowWatermark
+            ]
+        );
+
+        emitter.close().unwrap();
     }
 
-    /// Shut down and drop the emitter
-    ///
-    /// This will cancel any running tasks and may result in events being lost
-    fn close(&mut self) -> Result<(), Error> {
-        match self.tx.try_send(EmitterMessage::Close) {
-            Ok(_) => {
-                log::debug!("Closing emitter");
-                Ok(())
+    #[tokio::test]
+    async fn flush_full_batches_only_leaves_remainder() {
+        let sent_at = Arc::new(Mutex::new(Vec::new()));
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(200, 50))
+            .http_client(RecordingHttpClient {
+                sent_at: sent_at.clone(),
+            })
+            .build()
+            .unwrap();
+
+        // Add directly to the store, as `Emitter::add` sends full batches itself
+        {
+            let mut store = sync_store(&emitter).lock().unwrap();
+            for _ in 0..125 {
+                store.add(valid_payload()).unwrap();
             }
-            Err(e) => Err(Error::EmitterError(e.to_string())),
         }
+
+        emitter.flush_full_batches_only().unwrap();
+        assert_eq!(sync_store(&emitter).lock().unwrap().len(), 25);
+
+        wait_until(
+            || sent_at.lock().unwrap().len() >= 2,
+            "Batches were not sent",
+        )
+        .await;
+
+        let sent_events: usize = sent_at.lock().unwrap().iter().map(|(_, n)| n).sum();
+        assert_eq!(sent_events, 100);
+
+        emitter.close().unwrap();
     }
 
-    fn collector_url(&self) -> &str {
-        &self.collector_url
+    #[tokio::test]
+    async fn clear_discards_queued_events() {
+        let sent_at = Arc::new(Mutex::new(Vec::new()));
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(100, 50))
+            .http_client(RecordingHttpClient {
+                sent_at: sent_at.clone(),
+            })
+            .build()
+            .unwrap();
+
+        for _ in 0..3 {
+            emitter.add(valid_payload()).unwrap();
+        }
+        emitter.clear().unwrap();
+
+        assert_eq!(sync_store(&emitter).lock().unwrap().len(), 0);
+        emitter.close_async().await.unwrap();
+        assert!(sent_at.lock().unwrap().is_empty());
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
+    #[tokio::test]
+    async fn ordered_flush_sends_batches_in_order() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(100, 10))
+            .http_client(SlowHttpClient { sent: sent.clone() })
+            .ordered_flush(true)
+            .build()
+            .unwrap();
+
+        // Add directly to the store, as `Emitter::add` sends full batches itself
+        {
+            let mut store = sync_store(&emitter).lock().unwrap();
+            for _ in 0..25 {
+                store.add(valid_payload()).unwrap();
+            }
+        }
+
+        // Without ordering, the smaller final batch would be sent first
+        emitter.flush().unwrap();
+        emitter.close_async().await.unwrap();
+
+        assert_eq!(*sent.lock().unwrap(), vec![10, 10, 5]);
+    }
 
     #[tokio::test]
-    async fn add_event_to_store() {
-        let mut emitter = BatchEmitter::new("http://localhost:8080");
-        let payload = PayloadBuilder::default();
+    async fn rapid_flushes_are_coalesced() {
+        let sent_at = Arc::new(Mutex::new(Vec::new()));
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(100, 50))
+            .http_client(RecordingHttpClient {
+                sent_at: sent_at.clone(),
+            })
+            .flush_coalesce_window(Duration::from_millis(300))
+            .build()
+            .unwrap();
 
-        emitter.add(payload).unwrap();
-        assert_eq!(emitter.event_store.lock().unwrap().len(), 1);
+        let first_flush = std::time::Instant::now();
+        for _ in 0..5 {
+            emitter.add(valid_payload()).unwrap();
+            emitter.flush().unwrap();
+        }
+
+        // Only the first flush drains the event store, the rest are sent when the window ends
+        assert_eq!(sync_store(&emitter).lock().unwrap().len(), 4);
+
+        wait_until(
+            || sent_at.lock().unwrap().len() >= 2,
+            "Trailing flush was not sent",
+        )
+        .await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let sent_at = sent_at.lock().unwrap();
+        let event_counts: Vec<usize> = sent_at.iter().map(|(_, count)| *count).collect();
+        assert_eq!(event_counts, vec![1, 4]);
+        assert!(sent_at[1].0 - first_flush >= Duration::from_millis(300));
 
         emitter.close().unwrap();
     }
 
     #[tokio::test]
-    async fn send_batch() {
-        let event_store = InMemoryEventStore::new(2, 2);
+    async fn sends_event_once_max_latency_elapses() {
+        let sent_at = Arc::new(Mutex::new(Vec::new()));
         let mut emitter = BatchEmitter::builder()
             .collector_url("http://localhost:8080")
-            .event_store(event_store)
+            .event_store(InMemoryEventStore::new(100, 50))
+            .http_client(RecordingHttpClient {
+                sent_at: sent_at.clone(),
+            })
+            .max_latency(Duration::from_millis(200))
             .build()
             .unwrap();
 
-        emitter.add(PayloadBuilder::default()).unwrap();
-        assert_eq!(emitter.event_store.lock().unwrap().len(), 1);
+        let tracked_at = std::time::Instant::now();
+        emitter.add(valid_payload()).unwrap();
 
-        // Adding a second event should trigger a batch to be sent
-        emitter.add(PayloadBuilder::default()).unwrap();
-        assert_eq!(emitter.event_store.lock().unwrap().len(), 0);
+        wait_until(|| !sent_at.lock().unwrap().is_empty(), "Event was not sent").await;
+
+        let (sent_at, event_count) = sent_at.lock().unwrap()[0];
+        assert_eq!(event_count, 1);
+        assert!(sent_at.duration_since(tracked_at) >= Duration::from_millis(200));
 
         emitter.close().unwrap();
     }
 
-    #[test]
-    fn should_retry() {
-        let below_200 = (0..=199).collect::<Vec<_>>();
-        let between_300_and_599 = (300..=599)
-            .into_iter()
-            .filter(|code| !DONT_RETRY_STATUS_CODES.contains(code))
-            .collect::<Vec<_>>();
+    #[tokio::test]
+    async fn sends_heartbeats_at_the_configured_interval() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(10, 10))
+            .http_client(PayloadRecordingHttpClient {
+                events: events.clone(),
+            })
+            .heartbeat(
+                Duration::from_millis(50),
+                SelfDescribingJson::new(
+                    "iglu:com.acme/heartbeat/jsonschema/1-0-0",
+                    serde_json::json!({}),
+                ),
+            )
+            .build()
+            .unwrap();
+        let mut tracker = crate::Tracker::new("ns", "app_id", emitter, None);
 
-        let should_retry_codes = [below_200, between_300_and_599].concat();
+        wait_until(
+            || events.lock().unwrap().len() >= 2,
+            "Heartbeats were not sent",
+        )
+        .await;
+        tracker.close_emitter().unwrap();
 
-        for code in 0..=599 {
-            assert_eq!(
-                BatchEmitter::should_retry(code),
-                should_retry_codes.contains(&code)
+        for event in events.lock().unwrap().iter() {
+            assert_eq!(event["e"], "ue");
+            assert_eq!(event["aid"], "app_id");
+            assert_eq!(event["tna"], "ns");
+            assert!(event["ue_pr"]
+                .as_str()
+                .unwrap()
+                .contains("iglu:com.acme/heartbeat/jsonschema/1-0-0"));
+        }
+    }
+
+    #[test]
+    fn heartbeats_must_have_a_valid_schema() {
+        let result = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .heartbeat(
+                Duration::from_secs(60),
+                SelfDescribingJson::new("com.acme/heartbeat", serde_json::json!({})),
             )
+            .build();
+
+        assert!(matches!(result, Err(Error::BuilderError(_))));
+    }
+
+    #[tokio::test]
+    async fn flushes_on_interval() {
+        let sent_at = Arc::new(Mutex::new(Vec::new()));
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(100, 50))
+            .http_client(RecordingHttpClient {
+                sent_at: sent_at.clone(),
+            })
+            .flush_interval(Duration::from_millis(200))
+            .build()
+            .unwrap();
+
+        emitter.add(valid_payload()).unwrap();
+
+        wait_until(
+            || !sent_at.lock().unwrap().is_empty(),
+            "Event was not flushed",
+        )
+        .await;
+
+        assert_eq!(sent_at.lock().unwrap()[0].1, 1);
+        assert_eq!(sync_store(&emitter).lock().unwrap().len(), 0);
+
+        emitter.close().unwrap();
+    }
+
+    #[tokio::test]
+    async fn flush_with_progress_reports_each_batch() {
+        let sent_at = Arc::new(Mutex::new(Vec::new()));
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:8080")
+            .event_store(InMemoryEventStore::new(1000, 50))
+            .http_client(RecordingHttpClient {
+                sent_at: sent_at.clone(),
+            })
+            .build()
+            .unwrap();
+
+        // Add directly to the store, as `Emitter::add` sends full batches itself
+        {
+            let mut store = sync_store(&emitter).lock().unwrap();
+            for _ in 0..500 {
+                store.add(valid_payload()).unwrap();
+            }
         }
+
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let recorded = reported.clone();
+        emitter
+            .flush_with_progress(Box::new(move |finished, total| {
+                recorded.lock().unwrap().push((finished, total))
+            }))
+            .unwrap();
+
+        wait_until(
+            || reported.lock().unwrap().len() >= 10,
+            "Flush did not finish",
+        )
+        .await;
+
+        let reported = reported.lock().unwrap();
+        let finished: Vec<usize> = reported.iter().map(|(finished, _)| *finished).collect();
+        assert_eq!(finished, (1..=10).map(|i| i * 50).collect::<Vec<_>>());
+        assert!(reported.iter().all(|(_, total)| *total == 500));
+
+        emitter.close().unwrap();
     }
 }
@@ -0,0 +1,125 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Tracks consecutive failed batches, and pauses sending once too many have failed in a row.
+///
+/// The circuit opens after `failure_threshold` consecutive failures, and stays open for `cooldown`.
+/// Once the cooldown has elapsed, sending resumes; a further failure re-opens the circuit,
+/// while a success closes it.
+#[derive(Debug)]
+pub(crate) struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    consecutive_failures: AtomicU32,
+    open_until: Mutex<Option<Instant>>,
+}
+
+impl CircuitBreaker {
+    pub(crate) fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            failure_threshold,
+            cooldown,
+            consecutive_failures: AtomicU32::new(0),
+            open_until: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn failure_threshold(&self) -> u32 {
+        self.failure_threshold
+    }
+
+    /// Records a successful send, closing the circuit
+    pub(crate) fn record_success(&self) {
+        self.consecutive_failures.store(0, Ordering::SeqCst);
+        if let Ok(mut open_until) = self.open_until.lock() {
+            *open_until = None;
+        }
+    }
+
+    /// Records a failed send, opening the circuit if the failure threshold has been reached
+    pub(crate) fn record_failure(&self) {
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::SeqCst) + 1;
+        if failures >= self.failure_threshold {
+            if let Ok(mut open_until) = self.open_until.lock() {
+                log::warn!(
+                    "{failures} consecutive batches failed, pausing sending for {:?}",
+                    self.cooldown
+                );
+                *open_until = Some(Instant::now() + self.cooldown);
+            }
+        }
+    }
+
+    /// The time remaining until the circuit closes, or `None` if the circuit is closed
+    pub(crate) fn remaining_cooldown(&self) -> Option<Duration> {
+        let open_until = match self.open_until.lock() {
+            Ok(open_until) => *open_until,
+            Err(_) => return None,
+        };
+
+        open_until
+            .map(|until| until.saturating_duration_since(Instant::now()))
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    /// Whether the circuit is currently open, and sending is paused
+    pub(crate) fn is_open(&self) -> bool {
+        self.remaining_cooldown().is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opens_after_failure_threshold() {
+        let breaker = CircuitBreaker::new(3, Duration::from_secs(60));
+
+        breaker.record_failure();
+        breaker.record_failure();
+        assert!(!breaker.is_open());
+
+        breaker.record_failure();
+        assert!(breaker.is_open());
+    }
+
+    #[test]
+    fn success_resets_failures() {
+        let breaker = CircuitBreaker::new(2, Duration::from_secs(60));
+
+        breaker.record_failure();
+        breaker.record_success();
+        breaker.record_failure();
+        assert!(!breaker.is_open());
+
+        breaker.record_failure();
+        assert!(breaker.is_open());
+
+        breaker.record_success();
+        assert!(!breaker.is_open());
+    }
+
+    #[test]
+    fn closes_after_cooldown() {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(50));
+
+        breaker.record_failure();
+        assert!(breaker.is_open());
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(!breaker.is_open());
+    }
+}
@@ -0,0 +1,147 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde_json::json;
+use uuid::Uuid;
+
+use super::emitter_event::emitter_event;
+use crate::event_batch::EventBatch;
+use crate::payload::{Payload, SelfDescribingJson};
+use crate::{Error, HttpClient, RequestContext};
+
+const DIAGNOSTIC_SCHEMA: &str =
+    "iglu:com.snowplowanalytics.snowplow/diagnostic_error/jsonschema/1-0-0";
+
+// How long a diagnostic event can take to send before it is abandoned
+const REPORT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Reports failures the emitter gives up on as `diagnostic_error` events, sent to a separate collector.
+///
+/// Each report is sent once in its own task on the emitter's runtime, bypassing the event store and
+/// retries, and is abandoned if it takes longer than a few seconds. Reports made before the emitter
+/// loop starts are held until it does. A report that fails to send is only logged, so reporting can
+/// never lead to further reports.
+pub(crate) struct Diagnostics {
+    client: Box<dyn HttpClient + Send + Sync>,
+    state: Mutex<ReportState>,
+    // Set once the event store being full has been reported, until it accepts an event again
+    store_full_reported: AtomicBool,
+}
+
+#[derive(Default)]
+struct ReportState {
+    // The runtime of the emitter loop, set each time the loop starts
+    runtime: Option<tokio::runtime::Handle>,
+    // Events reported before the loop started
+    pending: Vec<Payload>,
+}
+
+impl Diagnostics {
+    pub(crate) fn new(client: Box<dyn HttpClient + Send + Sync>) -> Self {
+        Self {
+            client,
+            state: Mutex::new(ReportState::default()),
+            store_full_reported: AtomicBool::new(false),
+        }
+    }
+
+    /// Sends reports on `runtime`, which the emitter loop runs on, including any made before it started
+    pub(crate) fn start(&self, runtime: tokio::runtime::Handle) {
+        if let Ok(mut state) = self.state.lock() {
+            for event in state.pending.drain(..) {
+                self.send(&runtime, event);
+            }
+            state.runtime = Some(runtime);
+        }
+    }
+
+    /// Sends a diagnostic event with `message` to the diagnostics collector, without waiting for it to be sent
+    pub(crate) fn report(&self, message: &str) {
+        let event = match Self::diagnostic_event(message) {
+            Ok(event) => event,
+            Err(e) => {
+                log::error!("Failed to create diagnostic event: {e}");
+                return;
+            }
+        };
+        match self.state.lock() {
+            Ok(mut state) => match &state.runtime {
+                Some(runtime) => self.send(runtime, event),
+                None => state.pending.push(event),
+            },
+            Err(e) => log::error!("Failed to lock diagnostics: {e}"),
+        }
+    }
+
+    fn send(&self, runtime: &tokio::runtime::Handle, event: Payload) {
+        let batch = EventBatch::new(Uuid::new_v4(), vec![event]);
+        let context = RequestContext {
+            batch_id: batch.id,
+            attempt: 1,
+        };
+        let client = self.client.clone();
+        runtime.spawn(async move {
+            let post = client.post(batch.as_payload(), context);
+            match tokio::time::timeout(REPORT_TIMEOUT, post).await {
+                Ok(Ok(code)) if (200..300).contains(&code) => log::debug!("Sent diagnostic event"),
+                Ok(Ok(code)) => log::warn!("Failed to send diagnostic event, status code {code}"),
+                Ok(Err(e)) => log::warn!("Failed to send diagnostic event: {e}"),
+                Err(_) => log::warn!("Timed out sending diagnostic event"),
+            }
+        });
+    }
+
+    /// Reports that the event store is full, once until it accepts an event again
+    pub(crate) fn report_store_full(&self) {
+        if !self.store_full_reported.swap(true, Ordering::SeqCst) {
+            self.report("Event store is full, so new events are being dropped");
+        }
+    }
+
+    /// Records that the event store has accepted an event, so it being full is reported again
+    pub(crate) fn store_accepted(&self) {
+        self.store_full_reported.store(false, Ordering::SeqCst);
+    }
+
+    fn diagnostic_event(message: &str) -> Result<Payload, Error> {
+        emitter_event(
+            SelfDescribingJson::new(
+                DIAGNOSTIC_SCHEMA,
+                json!({ "message": message, "className": "BatchEmitter" }),
+            ),
+            None,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::emitter::emitter_event::EMITTER_APP_ID;
+
+    #[test]
+    fn diagnostic_events_are_self_describing() {
+        let event = Diagnostics::diagnostic_event("Batch failed to send").unwrap();
+
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["e"], "ue");
+        assert_eq!(json["aid"], EMITTER_APP_ID);
+        let ue_pr: Value = serde_json::from_str(json["ue_pr"].as_str().unwrap()).unwrap();
+        assert_eq!(ue_pr["data"]["schema"], DIAGNOSTIC_SCHEMA);
+        assert_eq!(ue_pr["data"]["data"]["message"], "Batch failed to send");
+    }
+}
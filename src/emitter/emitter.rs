@@ -9,7 +9,8 @@
 // "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
 
-use crate::payload::PayloadBuilder;
+use crate::emitter::FlushProgressCallback;
+use crate::payload::{PayloadBuilder, SelfDescribingJson};
 use crate::Error;
 
 /// An Emitter is responsible for handling events in an [EventStore](crate::EventStore),
@@ -21,8 +22,80 @@ pub trait Emitter {
     fn add(&mut self, payload: PayloadBuilder) -> Result<(), Error>;
     /// Try to send all events in the Emitter's queue
     fn flush(&mut self) -> Result<(), Error>;
+    /// Try to send only full batches of events in the Emitter's queue, leaving any remainder queued
+    ///
+    /// Emitters that don't send events in batches return an error by default
+    fn flush_full_batches_only(&mut self) -> Result<(), Error> {
+        Err(Error::EmitterError(
+            "This Emitter does not support flushing only full batches".to_string(),
+        ))
+    }
+    /// Try to send all events in the Emitter's queue, calling `callback` with the number of events
+    /// finished so far and the total as each batch finishes sending
+    ///
+    /// Emitters that don't send events in batches return an error by default
+    fn flush_with_progress(&mut self, _callback: FlushProgressCallback) -> Result<(), Error> {
+        Err(Error::EmitterError(
+            "This Emitter does not support flushing with progress".to_string(),
+        ))
+    }
+    /// Discard all events in the Emitter's queue without sending them
+    ///
+    /// Events already being sent are not affected. Emitters without a queue return an error by default
+    fn clear(&mut self) -> Result<(), Error> {
+        Err(Error::EmitterError(
+            "This Emitter does not support clearing its queue".to_string(),
+        ))
+    }
     /// Safely shuts down the Emitter.
     fn close(&mut self) -> Result<(), Error>;
     /// The provided URL of the Snowplow collector
     fn collector_url(&self) -> &str;
+    /// Context entities that trackers add to every event they track through the Emitter
+    ///
+    /// Emitters without default contexts return none by default
+    fn default_contexts(&self) -> &[SelfDescribingJson] {
+        &[]
+    }
+    /// Called with the namespace and app id of each [Tracker](crate::Tracker) the Emitter is given to
+    ///
+    /// Emitters that send events of their own, such as heartbeats, attribute them to the tracker they
+    /// were last given to. Does nothing by default
+    fn set_tracker(&mut self, _namespace: &str, _app_id: &str) {}
+    /// Whether the Emitter is currently able to send events
+    ///
+    /// Returns `false` while an Emitter has paused sending, e.g. after repeated failures
+    fn is_healthy(&self) -> bool {
+        true
+    }
+    /// How full the Emitter's queue is
+    ///
+    /// Emitters without a queue of limited capacity return `None` by default
+    fn queue_pressure(&self) -> Option<QueuePressure> {
+        None
+    }
+}
+
+/// How full an [Emitter]'s queue is, so producers can reduce their tracking rate as it fills
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueuePressure {
+    /// The number of events waiting in the queue
+    pub queued: usize,
+    /// The maximum number of events the queue can hold
+    pub capacity: usize,
+}
+
+impl QueuePressure {
+    /// The fraction of the queue's capacity in use, from `0.0` when empty to `1.0` when full
+    pub fn fraction(&self) -> f64 {
+        match self.capacity {
+            0 => 1.0,
+            capacity => (self.queued as f64 / capacity as f64).min(1.0),
+        }
+    }
+
+    /// The percentage of the queue's capacity in use, rounded down
+    pub fn percent(&self) -> u8 {
+        (self.fraction() * 100.0) as u8
+    }
 }
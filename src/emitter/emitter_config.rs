@@ -0,0 +1,144 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::batch_emitter::BatchEmitterBuilder;
+use super::{BatchEmitter, RetryPolicy};
+use crate::event_store::{InMemoryEventStore, DEFAULT_BATCH_SIZE, DEFAULT_EVENT_STORE_CAPACITY};
+
+/// Serializable configuration for a [BatchEmitter]
+///
+/// This allows the emitter to be configured from a config file rather than in code.
+/// Fields that are not set use the same defaults as [BatchEmitter::builder].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EmitterConfig {
+    /// The URL of your Snowplow Collector
+    pub collector_url: String,
+    /// The number of events sent in each batch
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    /// The maximum number of events held in the event store
+    #[serde(default = "default_capacity")]
+    pub capacity: usize,
+    /// The retry policy for failed requests
+    #[serde(default = "default_retry_policy")]
+    pub retry_policy: RetryPolicy,
+    /// HTTP status codes that should not be retried
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub non_retryable_codes: Option<Vec<u16>>,
+    /// The maximum size of a batch, in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_batch_bytes: Option<usize>,
+    /// How often to send all stored events, in milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flush_interval_ms: Option<u64>,
+    /// The maximum random delay before the first send, in milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub startup_jitter_ms: Option<u64>,
+}
+
+fn default_batch_size() -> usize {
+    DEFAULT_BATCH_SIZE
+}
+
+fn default_capacity() -> usize {
+    DEFAULT_EVENT_STORE_CAPACITY
+}
+
+fn default_retry_policy() -> RetryPolicy {
+    RetryPolicy::MaxRetries(10)
+}
+
+impl EmitterConfig {
+    /// Create a config for the given collector URL, with all other settings at their defaults
+    pub fn new(collector_url: &str) -> Self {
+        Self {
+            collector_url: collector_url.to_string(),
+            batch_size: default_batch_size(),
+            capacity: default_capacity(),
+            retry_policy: default_retry_policy(),
+            non_retryable_codes: None,
+            max_batch_bytes: None,
+            flush_interval_ms: None,
+            startup_jitter_ms: None,
+        }
+    }
+}
+
+impl From<EmitterConfig> for BatchEmitterBuilder {
+    fn from(config: EmitterConfig) -> Self {
+        let mut event_store = InMemoryEventStore::new(config.capacity, config.batch_size);
+        if let Some(max_batch_bytes) = config.max_batch_bytes {
+            event_store = event_store.with_max_batch_bytes(max_batch_bytes);
+        }
+
+        let mut builder = BatchEmitter::builder()
+            .collector_url(&config.collector_url)
+            .event_store(event_store)
+            .retry_policy(config.retry_policy);
+
+        if let Some(codes) = config.non_retryable_codes {
+            builder = builder.non_retryable_codes(&codes);
+        }
+        if let Some(interval) = config.flush_interval_ms {
+            builder = builder.flush_interval(Duration::from_millis(interval));
+        }
+        if let Some(jitter) = config.startup_jitter_ms {
+            builder = builder.startup_jitter(Duration::from_millis(jitter));
+        }
+
+        builder
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Emitter;
+
+    #[test]
+    fn deserialize_and_build_emitter() {
+        let config: EmitterConfig = serde_json::from_str(
+            r#"{
+                "collector_url": "http://localhost:9090",
+                "batch_size": 5,
+                "retry_policy": { "MaxRetries": 3 },
+                "non_retryable_codes": [400],
+                "flush_interval_ms": 1000
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.batch_size, 5);
+        assert_eq!(config.capacity, DEFAULT_EVENT_STORE_CAPACITY);
+        assert_eq!(config.retry_policy, RetryPolicy::MaxRetries(3));
+
+        let mut emitter = BatchEmitterBuilder::from(config).build().unwrap();
+        assert_eq!(emitter.collector_url(), "http://localhost:9090");
+        emitter.close().unwrap();
+    }
+
+    #[test]
+    fn config_round_trips() {
+        let mut config = EmitterConfig::new("http://localhost:9090");
+        config.retry_policy = RetryPolicy::RetryForever;
+
+        let json = serde_json::to_string(&config).unwrap();
+
+        assert_eq!(
+            serde_json::from_str::<EmitterConfig>(&json).unwrap(),
+            config
+        );
+    }
+}
@@ -0,0 +1,81 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use uuid::Uuid;
+
+use crate::payload::{EventType, Payload, SelfDescribingEventData, SelfDescribingJson};
+use crate::Error;
+
+// The app id of events sent by an emitter that hasn't been given to a tracker
+pub(crate) const EMITTER_APP_ID: &str = "snowplow-rust-tracker";
+
+/// The namespace and app id of the [Tracker](crate::Tracker) an emitter was given to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TrackerIdentity {
+    pub(crate) namespace: String,
+    pub(crate) app_id: String,
+}
+
+/// Creates a self-describing event sent by the emitter itself, rather than tracked by the app
+///
+/// The event is attributed to `tracker` if the emitter has one, or has an app id of
+/// `snowplow-rust-tracker` and no namespace otherwise.
+pub(crate) fn emitter_event(
+    data: SelfDescribingJson,
+    tracker: Option<&TrackerIdentity>,
+) -> Result<Payload, Error> {
+    let since_the_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| Error::BuilderError(format!("Failed to get current time: {e}")))?;
+
+    let mut builder = Payload::builder()
+        .p("srv".to_string())
+        .tv(format!("rust-{}", env!("CARGO_PKG_VERSION")))
+        .eid(Uuid::new_v4())
+        .dtm(since_the_epoch.as_millis().to_string())
+        .e(EventType::SelfDescribingEvent)
+        .ue_pr(SelfDescribingEventData::new(data));
+
+    builder = match tracker {
+        Some(tracker) => builder
+            .aid(tracker.app_id.clone())
+            .tna(tracker.namespace.clone()),
+        None => builder.aid(EMITTER_APP_ID.to_string()),
+    };
+    builder.finalise_payload()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn emitter_events_are_attributed_to_the_tracker() {
+        let data = || SelfDescribingJson::new("iglu:com.acme/ping/jsonschema/1-0-0", json!({}));
+        let tracker = TrackerIdentity {
+            namespace: "ns".to_string(),
+            app_id: "app_id".to_string(),
+        };
+
+        let json = serde_json::to_value(emitter_event(data(), Some(&tracker)).unwrap()).unwrap();
+        assert_eq!(json["aid"], "app_id");
+        assert_eq!(json["tna"], "ns");
+        assert_eq!(json["e"], "ue");
+
+        let json = serde_json::to_value(emitter_event(data(), None).unwrap()).unwrap();
+        assert_eq!(json["aid"], EMITTER_APP_ID);
+        assert!(json.get("tna").is_none());
+    }
+}
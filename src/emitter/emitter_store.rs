@@ -0,0 +1,222 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use super::store_watermarks::StoreWatermarks;
+use crate::error::Error;
+use crate::event_batch::EventBatch;
+use crate::event_store::{AsyncEventStore, EventStore};
+use crate::payload::PayloadBuilder;
+
+/// The event store a [BatchEmitter](super::BatchEmitter) sends events from.
+///
+/// A synchronous [EventStore] is locked and used directly by whichever thread calls the emitter.
+/// An [AsyncEventStore] is only used by the emitter loop and its send tasks, where it is awaited,
+/// so application threads pass it events as messages rather than blocking on it.
+#[derive(Clone)]
+pub(crate) enum EmitterStore {
+    Sync(Arc<Mutex<dyn EventStore + Send + Sync>>),
+    Async {
+        store: Arc<tokio::sync::Mutex<dyn AsyncEventStore>>,
+        capacity: usize,
+        // The number of events in the store after the last operation on it
+        queued: Arc<AtomicUsize>,
+    },
+}
+
+impl EmitterStore {
+    pub(crate) fn new(store: impl EventStore + Send + Sync + 'static) -> Self {
+        EmitterStore::Sync(Arc::new(Mutex::new(store)))
+    }
+
+    pub(crate) fn new_async(store: impl AsyncEventStore + 'static) -> Self {
+        EmitterStore::Async {
+            capacity: store.capacity(),
+            store: Arc::new(tokio::sync::Mutex::new(store)),
+            queued: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub(crate) fn capacity(&self) -> Result<usize, Error> {
+        match self {
+            EmitterStore::Sync(store) => store
+                .lock()
+                .map(|store| store.capacity())
+                .map_err(|e| Error::EventStoreError(format!("Failed to lock event store: {e}"))),
+            EmitterStore::Async { capacity, .. } => Ok(*capacity),
+        }
+    }
+
+    /// The number of events in the store
+    ///
+    /// An async store can't be read without awaiting it, so this is the number of events it held
+    /// after the emitter last added or removed events.
+    pub(crate) fn len(&self) -> Result<usize, Error> {
+        match self {
+            EmitterStore::Sync(store) => store
+                .lock()
+                .map(|store| store.len())
+                .map_err(|e| Error::EventStoreError(format!("Failed to lock event store: {e}"))),
+            EmitterStore::Async { queued, .. } => Ok(queued.load(Ordering::SeqCst)),
+        }
+    }
+
+    /// Adds an event, checking the store's level against the watermarks
+    pub(crate) async fn add(
+        &self,
+        payload: PayloadBuilder,
+        watermarks: Option<&StoreWatermarks>,
+    ) -> Result<(), Error> {
+        let (result, len) = match self {
+            EmitterStore::Sync(store) => {
+                let mut store = Self::lock(store)?;
+                (store.add(payload), store.len())
+            }
+            EmitterStore::Async { store, queued, .. } => {
+                let mut store = store.lock().await;
+                let result = store.add(payload).await;
+                let len = store.len().await;
+                queued.store(len, Ordering::SeqCst);
+                (result, len)
+            }
+        };
+
+        if let Some(watermarks) = watermarks {
+            watermarks.check(len);
+        }
+        result
+    }
+
+    /// Removes the events in the store as batches, including a final partial batch unless `full_only` is set
+    pub(crate) async fn take_batches(
+        &self,
+        full_only: bool,
+        watermarks: Option<&StoreWatermarks>,
+    ) -> Vec<EventBatch> {
+        let (batches, len) = match self {
+            EmitterStore::Sync(store) => match Self::lock(store) {
+                Ok(mut store) => (Self::take_sync_batches(&mut *store, full_only), store.len()),
+                Err(e) => {
+                    log::error!("{e}");
+                    return Vec::new();
+                }
+            },
+            EmitterStore::Async { store, queued, .. } => {
+                let mut store = store.lock().await;
+                let mut batches = Vec::new();
+                while let Ok(batch) = store.full_batch().await {
+                    batches.push(batch);
+                }
+
+                let remaining_events = store.len().await;
+                if !full_only && remaining_events > 0 {
+                    match store.batch_of(remaining_events).await {
+                        Ok(batch) => batches.push(batch),
+                        Err(e) => log::error!("Failed to create batch of remaining events: {e}"),
+                    }
+                }
+
+                let len = store.len().await;
+                queued.store(len, Ordering::SeqCst);
+                (batches, len)
+            }
+        };
+
+        if let Some(watermarks) = watermarks {
+            watermarks.check(len);
+        }
+        batches
+    }
+
+    /// Removes the events in a synchronous store as batches, including a final partial batch unless `full_only` is set
+    pub(crate) fn take_sync_batches(
+        store: &mut (dyn EventStore + Send + Sync),
+        full_only: bool,
+    ) -> Vec<EventBatch> {
+        let mut batches = Vec::new();
+        while let Ok(batch) = store.full_batch() {
+            batches.push(batch);
+        }
+
+        let remaining_events = store.len();
+        if !full_only && remaining_events > 0 {
+            match store.batch_of(remaining_events) {
+                Ok(batch) => batches.push(batch),
+                Err(e) => log::error!("Failed to create batch of remaining events: {e}"),
+            }
+        }
+
+        batches
+    }
+
+    /// Discards all events in the store, returning how many were discarded
+    pub(crate) async fn clear(&self, watermarks: Option<&StoreWatermarks>) -> Result<usize, Error> {
+        let (discarded, result, len) = match self {
+            EmitterStore::Sync(store) => {
+                let mut store = Self::lock(store)?;
+                let discarded = store.len();
+                (discarded, store.clear(), store.len())
+            }
+            EmitterStore::Async { store, queued, .. } => {
+                let mut store = store.lock().await;
+                let discarded = store.len().await;
+                let result = store.clear().await;
+                let len = store.len().await;
+                queued.store(len, Ordering::SeqCst);
+                (discarded, result, len)
+            }
+        };
+
+        if let Some(watermarks) = watermarks {
+            watermarks.check(len);
+        }
+        result.map(|_| discarded)
+    }
+
+    pub(crate) async fn oldest_event_age(&self) -> Option<Duration> {
+        match self {
+            EmitterStore::Sync(store) => match Self::lock(store) {
+                Ok(store) => store.oldest_event_age(),
+                Err(e) => {
+                    log::error!("{e}");
+                    None
+                }
+            },
+            EmitterStore::Async { store, .. } => store.lock().await.oldest_event_age().await,
+        }
+    }
+
+    pub(crate) async fn cleanup_after_send_attempt(&self, batch_id: Uuid) -> Result<(), Error> {
+        match self {
+            EmitterStore::Sync(store) => Self::lock(store)?.cleanup_after_send_attempt(batch_id),
+            EmitterStore::Async { store, .. } => {
+                store
+                    .lock()
+                    .await
+                    .cleanup_after_send_attempt(batch_id)
+                    .await
+            }
+        }
+    }
+
+    fn lock(
+        store: &Mutex<dyn EventStore + Send + Sync>,
+    ) -> Result<std::sync::MutexGuard<'_, dyn EventStore + Send + Sync + 'static>, Error> {
+        store
+            .lock()
+            .map_err(|e| Error::EventStoreError(format!("Failed to lock event store: {e}")))
+    }
+}
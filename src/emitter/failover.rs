@@ -0,0 +1,215 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Switches between a primary collector URL and its backups, when sending keeps failing.
+///
+/// After `failure_threshold` consecutive failures, sending fails over to the next URL in turn.
+/// While a backup is in use, the primary is probed every `probe_interval` by switching back to it;
+/// if it is still down, sending fails over again once the failure threshold is reached.
+///
+/// Results are recorded against the URL the batch was sent to, so batches still in flight after
+/// failing over, or after the primary URL changes, don't count towards the URL now in use.
+#[derive(Debug)]
+pub(crate) struct Failover {
+    failure_threshold: u32,
+    probe_interval: Duration,
+    state: Mutex<FailoverState>,
+}
+
+#[derive(Debug)]
+struct FailoverState {
+    // The primary URL, followed by the backups
+    collector_urls: Vec<String>,
+    // The index of the URL currently in use
+    active: usize,
+    consecutive_failures: u32,
+    // When sending last failed over away from the primary
+    failed_over_at: Option<Instant>,
+}
+
+impl Failover {
+    pub(crate) fn new(
+        primary_url: &str,
+        backup_urls: Vec<String>,
+        failure_threshold: u32,
+        probe_interval: Duration,
+    ) -> Self {
+        let mut collector_urls = vec![primary_url.to_string()];
+        collector_urls.extend(backup_urls);
+
+        Self {
+            failure_threshold,
+            probe_interval,
+            state: Mutex::new(FailoverState {
+                collector_urls,
+                active: 0,
+                consecutive_failures: 0,
+                failed_over_at: None,
+            }),
+        }
+    }
+
+    /// Records a successful send to `collector_url`, resetting the failure count
+    pub(crate) fn record_success(&self, collector_url: &str) {
+        if let Ok(mut state) = self.state.lock() {
+            if state.collector_urls[state.active] == collector_url {
+                state.consecutive_failures = 0;
+            }
+        }
+    }
+
+    /// Records a failed send to `collector_url`, failing over to the next URL if the failure threshold has been reached
+    pub(crate) fn record_failure(&self, collector_url: &str) {
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return,
+        };
+        if state.collector_urls[state.active] != collector_url {
+            return;
+        }
+
+        state.consecutive_failures += 1;
+        if state.consecutive_failures < self.failure_threshold {
+            return;
+        }
+
+        let failed_url = state.active;
+        state.active = (state.active + 1) % state.collector_urls.len();
+        state.consecutive_failures = 0;
+        state.failed_over_at = match state.active {
+            0 => None,
+            _ => Some(Instant::now()),
+        };
+
+        log::warn!(
+            "{} consecutive batches failed to send to {}, failing over to {}",
+            self.failure_threshold,
+            state.collector_urls[failed_url],
+            state.collector_urls[state.active]
+        );
+    }
+
+    /// Replaces the primary URL, switching back to it from any backup in use
+    pub(crate) fn set_primary_url(&self, collector_url: &str) {
+        if let Ok(mut state) = self.state.lock() {
+            state.collector_urls[0] = collector_url.to_string();
+            state.active = 0;
+            state.consecutive_failures = 0;
+            state.failed_over_at = None;
+        }
+    }
+
+    /// The collector URL to send the next batch to
+    ///
+    /// Once the probe interval has elapsed since failing over, this switches back to the primary URL
+    pub(crate) fn active_url(&self) -> String {
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(e) => return e.into_inner().collector_urls[0].clone(),
+        };
+
+        let probe_due = state
+            .failed_over_at
+            .is_some_and(|failed_over_at| failed_over_at.elapsed() >= self.probe_interval);
+        if state.active != 0 && probe_due {
+            log::info!(
+                "Probing primary collector {} after failing over",
+                state.collector_urls[0]
+            );
+            state.active = 0;
+            state.consecutive_failures = 0;
+            state.failed_over_at = None;
+        }
+
+        state.collector_urls[state.active].clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failover(failure_threshold: u32, probe_interval: Duration) -> Failover {
+        Failover::new(
+            "http://primary",
+            vec!["http://backup-1".to_string(), "http://backup-2".to_string()],
+            failure_threshold,
+            probe_interval,
+        )
+    }
+
+    #[test]
+    fn fails_over_after_failure_threshold() {
+        let failover = failover(2, Duration::from_secs(60));
+
+        failover.record_failure("http://primary");
+        assert_eq!(failover.active_url(), "http://primary");
+
+        failover.record_failure("http://primary");
+        assert_eq!(failover.active_url(), "http://backup-1");
+
+        failover.record_failure("http://backup-1");
+        failover.record_failure("http://backup-1");
+        assert_eq!(failover.active_url(), "http://backup-2");
+    }
+
+    #[test]
+    fn failures_of_other_urls_are_ignored() {
+        let failover = failover(1, Duration::from_secs(60));
+
+        failover.record_failure("http://primary");
+        assert_eq!(failover.active_url(), "http://backup-1");
+
+        // A batch still in flight to the primary fails after failing over
+        failover.record_failure("http://primary");
+        assert_eq!(failover.active_url(), "http://backup-1");
+    }
+
+    #[test]
+    fn set_primary_url_switches_back_to_the_new_primary() {
+        let failover = failover(1, Duration::from_secs(60));
+
+        failover.record_failure("http://primary");
+        failover.set_primary_url("http://new-primary");
+        assert_eq!(failover.active_url(), "http://new-primary");
+
+        // Batches sent to the old primary don't count against the new one
+        failover.record_failure("http://primary");
+        assert_eq!(failover.active_url(), "http://new-primary");
+
+        failover.record_failure("http://new-primary");
+        assert_eq!(failover.active_url(), "http://backup-1");
+    }
+
+    #[test]
+    fn success_resets_failures() {
+        let failover = failover(2, Duration::from_secs(60));
+
+        failover.record_failure("http://primary");
+        failover.record_success("http://primary");
+        failover.record_failure("http://primary");
+        assert_eq!(failover.active_url(), "http://primary");
+    }
+
+    #[test]
+    fn probes_primary_after_interval() {
+        let failover = failover(1, Duration::from_millis(50));
+
+        failover.record_failure("http://primary");
+        assert_eq!(failover.active_url(), "http://backup-1");
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(failover.active_url(), "http://primary");
+    }
+}
@@ -0,0 +1,69 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+use std::sync::Mutex;
+
+/// The callback passed to [Emitter::flush_with_progress](crate::Emitter::flush_with_progress),
+/// called with the number of events finished so far and the total number of events being flushed
+pub type FlushProgressCallback = Box<dyn Fn(usize, usize) + Send + Sync>;
+
+/// Tracks how many of the events in a flush have finished sending, reporting each step to a callback.
+///
+/// Batches finish in any order, as they are sent concurrently, but the count passed to the
+/// callback always increases.
+pub(crate) struct FlushProgress {
+    total: usize,
+    // Held while calling the callback, so concurrent batches report in order
+    finished: Mutex<usize>,
+    callback: FlushProgressCallback,
+}
+
+impl FlushProgress {
+    pub(crate) fn new(total: usize, callback: FlushProgressCallback) -> Self {
+        Self {
+            total,
+            finished: Mutex::new(0),
+            callback,
+        }
+    }
+
+    /// Records that a batch of `events` has finished, whether or not it was sent successfully
+    pub(crate) fn record(&self, events: usize) {
+        if let Ok(mut finished) = self.finished.lock() {
+            *finished += events;
+            log::debug!("Flushed {}/{} events", *finished, self.total);
+            (self.callback)(*finished, self.total);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn reports_running_total() {
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let recorded = reported.clone();
+        let progress = FlushProgress::new(
+            5,
+            Box::new(move |finished, total| recorded.lock().unwrap().push((finished, total))),
+        );
+
+        progress.record(2);
+        progress.record(2);
+        progress.record(1);
+
+        assert_eq!(*reported.lock().unwrap(), vec![(2, 5), (4, 5), (5, 5)]);
+    }
+}
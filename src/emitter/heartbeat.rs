@@ -0,0 +1,58 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+use uuid::Uuid;
+
+use super::emitter_event::{emitter_event, TrackerIdentity};
+use crate::event_batch::EventBatch;
+use crate::payload::SelfDescribingJson;
+use crate::Error;
+
+/// A batch holding a single heartbeat `event`, sent periodically to show the emitter is alive
+///
+/// Heartbeats bypass the event store, so they are never counted against its capacity.
+pub(crate) fn heartbeat_batch(
+    event: &SelfDescribingJson,
+    tracker: Option<&TrackerIdentity>,
+) -> Result<EventBatch, Error> {
+    let event = emitter_event(event.clone(), tracker)?;
+    Ok(EventBatch::new(Uuid::new_v4(), vec![event]))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::EventKind;
+
+    const HEARTBEAT_SCHEMA: &str = "iglu:com.acme/heartbeat/jsonschema/1-0-0";
+
+    #[test]
+    fn heartbeats_are_single_self_describing_events() {
+        let tracker = TrackerIdentity {
+            namespace: "ns".to_string(),
+            app_id: "app_id".to_string(),
+        };
+        let event = SelfDescribingJson::new(HEARTBEAT_SCHEMA, json!({}));
+        let batch = heartbeat_batch(&event, Some(&tracker)).unwrap();
+
+        assert_eq!(batch.len(), 1);
+        assert_eq!(
+            batch.events[0].event_kind(),
+            Some(EventKind::SelfDescribing)
+        );
+        let json = serde_json::to_value(&batch.events[0]).unwrap();
+        assert!(json["ue_pr"].as_str().unwrap().contains(HEARTBEAT_SCHEMA));
+        assert_eq!(json["aid"], "app_id");
+        assert_eq!(json["tna"], "ns");
+    }
+}
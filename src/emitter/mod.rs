@@ -10,9 +10,28 @@
 // See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
 
 mod batch_emitter;
+mod circuit_breaker;
+mod diagnostics;
+#[allow(clippy::module_inception)]
 mod emitter;
+mod emitter_config;
+mod emitter_event;
+mod emitter_store;
+mod failover;
+mod flush_progress;
+mod heartbeat;
+mod pending_retries;
+mod rate_limiter;
 mod retry_policy;
+mod store_watermarks;
+mod sync_emitter;
+mod undelivered;
 
-pub use batch_emitter::BatchEmitter;
-pub use emitter::Emitter;
+pub(crate) use batch_emitter::thread_name_for;
+pub use batch_emitter::{BatchEmitter, BatchEmitterBuilder};
+pub use emitter::{Emitter, QueuePressure};
+pub use emitter_config::EmitterConfig;
+pub use flush_progress::FlushProgressCallback;
 pub use retry_policy::RetryPolicy;
+pub use store_watermarks::StoreLevel;
+pub use sync_emitter::SyncEmitter;
@@ -0,0 +1,139 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+/// Tracks the batches waiting to be retried, oldest first.
+///
+/// When a maximum is set, adding a retry beyond it evicts the oldest pending retry. The evicted
+/// batch's task is woken early, so it can give up on the batch instead of holding it until its
+/// retry delay has elapsed.
+#[derive(Debug, Default)]
+pub(crate) struct PendingRetries {
+    max: Option<usize>,
+    retries: Mutex<VecDeque<(Uuid, Arc<Notify>)>>,
+}
+
+impl PendingRetries {
+    pub(crate) fn new(max: Option<usize>) -> Self {
+        Self {
+            max,
+            retries: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Records that a batch is waiting to be retried, evicting the oldest pending retries if over the maximum
+    pub(crate) fn add(&self, batch_id: Uuid) {
+        if let Ok(mut retries) = self.retries.lock() {
+            retries.push_back((batch_id, Arc::new(Notify::new())));
+
+            while self.max.is_some_and(|max| retries.len() > max) {
+                if let Some((evicted_id, evicted)) = retries.pop_front() {
+                    log::warn!(
+                        "Too many batches waiting to be retried, dropping batch {evicted_id}"
+                    );
+                    // Stores a permit if the task isn't waiting yet, so it still wakes
+                    evicted.notify_one();
+                }
+            }
+        }
+    }
+
+    /// Waits until `delay` has elapsed, or the batch is evicted
+    ///
+    /// Returns `false` if the batch was evicted and should not be retried.
+    pub(crate) async fn wait(&self, batch_id: Uuid, delay: std::time::Duration) -> bool {
+        let evicted = match self.notifier(batch_id) {
+            Some(notifier) => {
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => false,
+                    _ = notifier.notified() => true,
+                }
+            }
+            None => true,
+        };
+
+        // An evicted batch has already been removed
+        !evicted && self.remove(batch_id)
+    }
+
+    /// Stops tracking a batch, returning `false` if it had already been evicted
+    pub(crate) fn remove(&self, batch_id: Uuid) -> bool {
+        match self.retries.lock() {
+            Ok(mut retries) => match retries.iter().position(|(id, _)| *id == batch_id) {
+                Some(index) => retries.remove(index).is_some(),
+                None => false,
+            },
+            Err(_) => false,
+        }
+    }
+
+    /// The number of batches waiting to be retried
+    pub(crate) fn len(&self) -> usize {
+        self.retries
+            .lock()
+            .map(|retries| retries.len())
+            .unwrap_or(0)
+    }
+
+    fn notifier(&self, batch_id: Uuid) -> Option<Arc<Notify>> {
+        let retries = self.retries.lock().ok()?;
+        retries
+            .iter()
+            .find(|(id, _)| *id == batch_id)
+            .map(|(_, notifier)| notifier.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn evicts_oldest_over_max() {
+        let pending = PendingRetries::new(Some(2));
+        let ids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+
+        ids.iter().for_each(|id| pending.add(*id));
+
+        assert_eq!(pending.len(), 2);
+        assert!(!pending.remove(ids[0]));
+        assert!(pending.remove(ids[1]));
+        assert!(pending.remove(ids[2]));
+    }
+
+    // With the clock paused, the retry delay elapses instantly if the eviction isn't noticed
+    #[tokio::test(start_paused = true)]
+    async fn evicted_batch_stops_waiting() {
+        let pending = PendingRetries::new(Some(1));
+        let first = Uuid::new_v4();
+        pending.add(first);
+        pending.add(Uuid::new_v4());
+
+        assert!(!pending.wait(first, Duration::from_secs(60)).await);
+    }
+
+    #[tokio::test]
+    async fn batch_is_retried_after_delay() {
+        let pending = PendingRetries::new(None);
+        let batch_id = Uuid::new_v4();
+        pending.add(batch_id);
+
+        assert!(pending.wait(batch_id, Duration::from_millis(10)).await);
+        assert_eq!(pending.len(), 0);
+    }
+}
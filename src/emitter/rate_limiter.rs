@@ -0,0 +1,89 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Limits the rate of requests to the collector, making requests over the rate wait for their turn.
+///
+/// This is a token bucket holding a single token, refilled every `interval`, so requests are evenly
+/// spaced rather than sent in bursts.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    interval: Duration,
+    // The earliest time the next request can be sent
+    next_slot: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(requests_per_second: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / requests_per_second.max(1),
+            next_slot: Mutex::new(None),
+        }
+    }
+
+    /// Waits until a request can be sent without exceeding the rate
+    pub(crate) async fn acquire(&self) {
+        let wait = self.reserve(Instant::now());
+        if !wait.is_zero() {
+            log::debug!("Rate limit reached, waiting {wait:?} to send");
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    // Reserves the next free slot, returning how long to wait for it
+    fn reserve(&self, now: Instant) -> Duration {
+        // A poisoned lock still holds a valid slot, so we can carry on using it
+        let mut next_slot = self
+            .next_slot
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let slot = next_slot.map_or(now, |next_slot| next_slot.max(now));
+        *next_slot = Some(slot + self.interval);
+        slot - now
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_are_spaced_by_the_interval() {
+        let limiter = RateLimiter::new(2);
+        let now = Instant::now();
+
+        let waits: Vec<Duration> = (0..3).map(|_| limiter.reserve(now)).collect();
+
+        assert_eq!(
+            waits,
+            vec![
+                Duration::ZERO,
+                Duration::from_millis(500),
+                Duration::from_secs(1)
+            ]
+        );
+    }
+
+    #[test]
+    fn unused_slots_are_not_saved_up() {
+        let limiter = RateLimiter::new(2);
+        let now = Instant::now();
+
+        limiter.reserve(now);
+        let later = now + Duration::from_secs(5);
+
+        assert_eq!(limiter.reserve(later), Duration::ZERO);
+        assert_eq!(limiter.reserve(later), Duration::from_millis(500));
+    }
+}
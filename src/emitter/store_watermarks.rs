@@ -0,0 +1,118 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// How full the event store is, passed to the callback set with
+/// [BatchEmitterBuilder::on_store_full](crate::BatchEmitterBuilder::on_store_full)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreLevel {
+    /// The event store has filled to the high watermark, so producers should back off
+    AboveHighWatermark,
+    /// The event store has drained below the low watermark, so producers can resume
+    BelowLowWatermark,
+}
+
+/// Notifies a callback when the event store fills past the high watermark, and again once it
+/// drains below the low watermark.
+///
+/// The callback only fires when the level changes, so it isn't called for every event added
+/// while the store is full.
+pub(crate) struct StoreWatermarks {
+    high: usize,
+    low: usize,
+    above_high: AtomicBool,
+    callback: Box<dyn Fn(StoreLevel) + Send + Sync>,
+}
+
+impl StoreWatermarks {
+    /// The watermarks are fractions of the event store `capacity`
+    pub(crate) fn new(
+        capacity: usize,
+        high_watermark: f64,
+        low_watermark: f64,
+        callback: Box<dyn Fn(StoreLevel) + Send + Sync>,
+    ) -> Self {
+        Self {
+            high: (capacity as f64 * high_watermark).ceil() as usize,
+            low: (capacity as f64 * low_watermark).ceil() as usize,
+            above_high: AtomicBool::new(false),
+            callback,
+        }
+    }
+
+    /// Checks the number of events in the event store against the watermarks
+    pub(crate) fn check(&self, store_len: usize) {
+        if store_len >= self.high {
+            if !self.above_high.swap(true, Ordering::SeqCst) {
+                log::warn!("Event store has reached {store_len} events");
+                (self.callback)(StoreLevel::AboveHighWatermark);
+            }
+        } else if store_len < self.low && self.above_high.swap(false, Ordering::SeqCst) {
+            log::info!("Event store has drained to {store_len} events");
+            (self.callback)(StoreLevel::BelowLowWatermark);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    fn recording_watermarks() -> (StoreWatermarks, Arc<Mutex<Vec<StoreLevel>>>) {
+        let levels = Arc::new(Mutex::new(Vec::new()));
+        let recorded = levels.clone();
+        let watermarks = StoreWatermarks::new(
+            10,
+            0.9,
+            0.5,
+            Box::new(move |level| recorded.lock().unwrap().push(level)),
+        );
+        (watermarks, levels)
+    }
+
+    #[test]
+    fn fires_once_above_high_watermark() {
+        let (watermarks, levels) = recording_watermarks();
+
+        for len in 1..=10 {
+            watermarks.check(len);
+        }
+
+        assert_eq!(
+            *levels.lock().unwrap(),
+            vec![StoreLevel::AboveHighWatermark]
+        );
+    }
+
+    #[test]
+    fn fires_again_below_low_watermark() {
+        let (watermarks, levels) = recording_watermarks();
+
+        // Draining without first reaching the high watermark doesn't fire
+        watermarks.check(2);
+        watermarks.check(9);
+        // Between the watermarks, the store is still considered full
+        watermarks.check(6);
+        watermarks.check(4);
+        watermarks.check(3);
+
+        assert_eq!(
+            *levels.lock().unwrap(),
+            vec![
+                StoreLevel::AboveHighWatermark,
+                StoreLevel::BelowLowWatermark
+            ]
+        );
+    }
+}
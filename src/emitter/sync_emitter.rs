@@ -0,0 +1,208 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+use reqwest::blocking::Client;
+use reqwest::header::CONTENT_TYPE;
+
+use crate::emitter::{Emitter, QueuePressure};
+use crate::error::Error;
+use crate::event_batch::EventBatch;
+use crate::event_store::{EventStore, InMemoryEventStore};
+use crate::http_client::{request_error_kind, POST_CONTENT_TYPE, POST_PATH};
+use crate::payload::PayloadBuilder;
+
+/// An implementation of the [Emitter] trait that sends batches of events on the calling thread.
+///
+/// Unlike the [BatchEmitter](crate::BatchEmitter), there is no background thread or async runtime:
+/// [add](Emitter::add) sends a batch as soon as one is full, and [flush](Emitter::flush) sends all
+/// stored events before returning. Failed batches are not retried, and the error is returned to the caller.
+///
+/// This suits short-lived programs such as CLI tools, where simplicity matters more than throughput.
+/// The blocking HTTP client must not be used from within an async runtime.
+pub struct SyncEmitter {
+    collector_url: String,
+    client: Client,
+    event_store: Box<dyn EventStore + Send + Sync>,
+}
+
+impl SyncEmitter {
+    /// Create a [SyncEmitter] with the default [InMemoryEventStore]
+    pub fn new(collector_url: &str) -> SyncEmitter {
+        SyncEmitter::with_event_store(collector_url, InMemoryEventStore::default())
+    }
+
+    /// Create a [SyncEmitter] using the given [EventStore] implementation
+    pub fn with_event_store(
+        collector_url: &str,
+        event_store: impl EventStore + Send + Sync + 'static,
+    ) -> SyncEmitter {
+        SyncEmitter {
+            collector_url: collector_url.to_string(),
+            client: Client::new(),
+            event_store: Box::new(event_store),
+        }
+    }
+
+    fn send_batch(&mut self, mut batch: EventBatch) -> Result<(), Error> {
+        batch.update_event_stm()?;
+
+        let body = serde_json::to_vec(&batch.as_payload())
+            .map_err(|e| Error::EmitterError(format!("Failed to serialize payload: {e}")))?;
+
+        let result = self
+            .client
+            .post(format!("{}/{}", self.collector_url, POST_PATH))
+            .header(CONTENT_TYPE, POST_CONTENT_TYPE)
+            .body(body)
+            .send();
+
+        self.event_store.cleanup_after_send_attempt(batch.id)?;
+
+        match result {
+            Ok(resp) if resp.status().is_success() => Ok(()),
+            Ok(resp) => Err(Error::EmitterError(format!(
+                "Batch {} failed to send, with status code {}",
+                batch.id,
+                resp.status().as_u16()
+            ))),
+            Err(e) => Err(Error::RequestError(
+                request_error_kind(&e),
+                format!("POST request failed: {e}"),
+            )),
+        }
+    }
+}
+
+impl Emitter for SyncEmitter {
+    fn add(&mut self, payload: PayloadBuilder) -> Result<(), Error> {
+        self.event_store.add(payload)?;
+
+        if self.event_store.len() >= self.event_store.batch_size() {
+            let batch = self.event_store.full_batch()?;
+            self.send_batch(batch)?;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        while !self.event_store.is_empty() {
+            let size = self.event_store.len().min(self.event_store.batch_size());
+            let batch = self.event_store.batch_of(size)?;
+            self.send_batch(batch)?;
+        }
+
+        Ok(())
+    }
+
+    fn flush_full_batches_only(&mut self) -> Result<(), Error> {
+        while self.event_store.len() >= self.event_store.batch_size() {
+            let batch = self.event_store.full_batch()?;
+            self.send_batch(batch)?;
+        }
+
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<(), Error> {
+        self.event_store.clear()
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        self.flush()
+    }
+
+    fn collector_url(&self) -> &str {
+        &self.collector_url
+    }
+
+    fn queue_pressure(&self) -> Option<QueuePressure> {
+        Some(QueuePressure {
+            queued: self.event_store.len(),
+            capacity: self.event_store.capacity(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::test_utils::{test_server, ReceivedRequest};
+    use crate::{Payload, RequestErrorKind};
+
+    // Starts a collector that responds to `requests` requests with a 200
+    fn collector_server(requests: usize) -> (String, Arc<Mutex<Vec<ReceivedRequest>>>) {
+        let ok = "HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+        test_server(vec![ok.to_string(); requests])
+    }
+
+    // The number of events in each request the collector has received
+    fn event_counts(received: &Mutex<Vec<ReceivedRequest>>) -> Vec<usize> {
+        received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|request| {
+                let payload: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+                payload["data"].as_array().unwrap().len()
+            })
+            .collect()
+    }
+
+    fn valid_payload() -> PayloadBuilder {
+        Payload::builder()
+            .p("pc".to_string())
+            .tv("rust-test".to_string())
+            .eid(Uuid::new_v4())
+            .dtm("1".to_string())
+            .aid("app_id".to_string())
+    }
+
+    #[test]
+    fn sends_full_batches_on_add_and_remainder_on_flush() {
+        let (collector_url, received) = collector_server(2);
+        let mut emitter =
+            SyncEmitter::with_event_store(&collector_url, InMemoryEventStore::new(10, 3));
+
+        for _ in 0..4 {
+            emitter.add(valid_payload()).unwrap();
+        }
+        assert_eq!(event_counts(&received), vec![3]);
+
+        emitter.flush().unwrap();
+        assert_eq!(event_counts(&received), vec![3, 1]);
+    }
+
+    #[test]
+    fn returns_request_errors_to_the_caller() {
+        // Bind and drop a listener, so nothing is listening on the port
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut emitter = SyncEmitter::new(&format!("http://127.0.0.1:{port}"));
+
+        emitter.add(valid_payload()).unwrap();
+
+        match emitter.flush() {
+            Err(Error::RequestError(kind, _)) => {
+                assert_eq!(kind, RequestErrorKind::ConnectionRefused)
+            }
+            other => panic!("Expected a request error, got {other:?}"),
+        }
+    }
+}
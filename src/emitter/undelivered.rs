@@ -0,0 +1,123 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use uuid::Uuid;
+
+use crate::event_batch::EventBatch;
+use crate::payload::Payload;
+
+/// Tracks the batches that haven't been delivered while a close with a timeout is in progress.
+///
+/// Batches are only copied once collecting has started, so emitters that never close with a
+/// timeout don't hold a second copy of every batch being sent.
+#[derive(Debug, Default)]
+pub(crate) struct UndeliveredBatches {
+    collecting: AtomicBool,
+    batches: Mutex<HashMap<Uuid, Vec<Payload>>>,
+}
+
+impl UndeliveredBatches {
+    /// Starts recording the batches that are sent or re-queued
+    pub(crate) fn start(&self) {
+        self.collecting.store(true, Ordering::SeqCst);
+    }
+
+    /// Records a batch that is about to be sent or re-queued, if collecting has started
+    pub(crate) fn add(&self, batch: &EventBatch) {
+        if !self.collecting.load(Ordering::SeqCst) {
+            return;
+        }
+        if let Ok(mut batches) = self.batches.lock() {
+            batches.insert(batch.id, batch.events.clone());
+        }
+    }
+
+    /// Stops tracking a batch that was delivered, or given up on
+    pub(crate) fn remove(&self, batch_id: Uuid) {
+        if !self.collecting.load(Ordering::SeqCst) {
+            return;
+        }
+        if let Ok(mut batches) = self.batches.lock() {
+            batches.remove(&batch_id);
+        }
+    }
+
+    /// The number of undelivered batches recorded
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.batches
+            .lock()
+            .map(|batches| batches.len())
+            .unwrap_or(0)
+    }
+
+    /// Stops collecting, returning the events of every batch that is still undelivered
+    pub(crate) fn take(&self) -> Vec<Payload> {
+        self.collecting.store(false, Ordering::SeqCst);
+        match self.batches.lock() {
+            Ok(mut batches) => batches.drain().flat_map(|(_, events)| events).collect(),
+            Err(e) => {
+                log::error!("Failed to acquire undelivered batches lock: {e}");
+                Vec::new()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batch(event_count: usize) -> EventBatch {
+        let events = (0..event_count)
+            .map(|_| {
+                Payload::builder()
+                    .p("srv".to_string())
+                    .tv("tv".to_string())
+                    .eid(Uuid::new_v4())
+                    .dtm("1".to_string())
+                    .aid("aid".to_string())
+                    .finalise_payload()
+                    .unwrap()
+            })
+            .collect();
+        EventBatch::new(Uuid::new_v4(), events)
+    }
+
+    #[test]
+    fn batches_are_only_recorded_while_collecting() {
+        let undelivered = UndeliveredBatches::default();
+
+        undelivered.add(&batch(2));
+        undelivered.start();
+        let delivered = batch(1);
+        undelivered.add(&delivered);
+        undelivered.add(&batch(3));
+        undelivered.remove(delivered.id);
+
+        assert_eq!(undelivered.take().len(), 3);
+    }
+
+    #[test]
+    fn take_stops_collecting() {
+        let undelivered = UndeliveredBatches::default();
+        undelivered.start();
+        undelivered.add(&batch(1));
+
+        assert_eq!(undelivered.take().len(), 1);
+        undelivered.add(&batch(1));
+        assert!(undelivered.take().is_empty());
+    }
+}
@@ -11,6 +11,8 @@
 
 use std::fmt::{Display, Formatter, Result};
 
+use crate::payload::Payload;
+
 /// The errors that can occur when using the Snowplow Tracker
 #[derive(Debug)]
 #[non_exhaustive]
@@ -21,6 +23,46 @@ pub enum Error {
     EmitterError(String),
     /// An error occurred in the event store
     EventStoreError(String),
+    /// A request to the collector failed without receiving a response
+    RequestError(RequestErrorKind, String),
+    /// The emitter was closed before these events could be sent to the collector
+    UndeliveredEvents(Vec<Payload>),
+    /// The event store, or the queue of events waiting to be added to it, is full, so the event was not added
+    ///
+    /// This replaces the `EventStoreError("Event store is full")` returned by earlier versions, so code
+    /// matching on that error should match this variant instead.
+    QueueFull,
+    /// The event was added, but the queue of batches waiting to be sent is full
+    ///
+    /// The batch the event completed was put back into the event store, and is sent with a later batch or flush.
+    SendQueueFull,
+    /// The emitter's background thread stopped unexpectedly, so events can no longer be sent
+    EmitterDead(String),
+}
+
+/// The reason a request to the collector failed without receiving a response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RequestErrorKind {
+    /// The collector host name could not be resolved
+    Dns,
+    /// The collector refused the connection
+    ConnectionRefused,
+    /// The request timed out
+    Timeout,
+    /// Any other failure, such as a TLS or protocol error
+    Other,
+}
+
+impl Display for RequestErrorKind {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            RequestErrorKind::Dns => write!(f, "DNS failure"),
+            RequestErrorKind::ConnectionRefused => write!(f, "Connection refused"),
+            RequestErrorKind::Timeout => write!(f, "Timeout"),
+            RequestErrorKind::Other => write!(f, "Request failed"),
+        }
+    }
 }
 
 impl Display for Error {
@@ -29,6 +71,13 @@ impl Display for Error {
             Error::BuilderError(builder_err) => write!(f, "{}", builder_err),
             Error::EmitterError(emitter_err) => write!(f, "{}", emitter_err),
             Error::EventStoreError(event_store_err) => write!(f, "{}", event_store_err),
+            Error::RequestError(kind, request_err) => write!(f, "{kind}: {request_err}"),
+            Error::UndeliveredEvents(events) => {
+                write!(f, "{} events were not delivered", events.len())
+            }
+            Error::QueueFull => write!(f, "Event store is full"),
+            Error::SendQueueFull => write!(f, "Send queue is full"),
+            Error::EmitterDead(reason) => write!(f, "Emitter thread stopped: {reason}"),
         }
     }
 }
@@ -15,9 +15,16 @@ use serde_json::{json, Value};
 use uuid::Uuid;
 
 use crate::error::Error;
-use crate::payload::{EventType, PayloadBuilder, SelfDescribingEventData, SelfDescribingJson};
+use crate::payload::{
+    validate_schema_uri, EventType, PayloadBuilder, SelfDescribingEventData, SelfDescribingJson,
+};
 use crate::subject::Subject;
 
+pub(crate) const SCREEN_VIEW_SCHEMA: &str =
+    "iglu:com.snowplowanalytics.mobile/screen_view/jsonschema/1-0-0";
+pub(crate) const TIMING_SCHEMA: &str =
+    "iglu:com.snowplowanalytics.snowplow/timing/jsonschema/1-0-0";
+
 /// Trait implemented by event types that enables the event to add itself to a PayloadBuilder.
 pub trait PayloadAddable {
     fn add_to_payload(self, payload_builder: PayloadBuilder) -> PayloadBuilder;
@@ -52,6 +59,19 @@ impl SelfDescribingEvent {
     pub fn builder() -> SelfDescribingEventBuilder {
         SelfDescribingEventBuilder::default()
     }
+
+    /// Creates an event from a schema and data known only at runtime, such as from a catalog of events
+    ///
+    /// Returns an [Error::BuilderError] if `schema` is not a valid Iglu URI, of the format
+    /// `iglu:{vendor}/{name}/{format}/{version}`.
+    pub fn from_value(schema: String, data: Value) -> Result<Self, Error> {
+        validate_schema_uri(&schema)?;
+        Ok(Self {
+            schema,
+            data,
+            subject: None,
+        })
+    }
 }
 
 impl PayloadAddable for SelfDescribingEvent {
@@ -75,12 +95,17 @@ impl PayloadAddable for SelfDescribingEvent {
 #[builder(build_fn(error = "Error"))]
 pub struct StructuredEvent {
     /// Name you for the group of objects you want to track e.g. "media", "ecomm".
+    ///
+    /// The builder accepts anything that converts into a `String`, so a fixed taxonomy can be
+    /// modelled as your own enum by implementing `From<YourEnum> for String`.
     #[serde(rename(serialize = "se_ca"))]
     pub category: String,
 
     /// Defines the type of user interaction for the web object.
     ///
     /// E.g., "play-video", "add-to-basket".
+    ///
+    /// Like the category, the builder accepts your own enum implementing `From<YourEnum> for String`.
     #[serde(rename(serialize = "se_ac"))]
     pub action: String,
 
@@ -115,6 +140,17 @@ pub struct StructuredEvent {
     pub subject: Option<Subject>,
 }
 
+// Serializer to convert the f64 to the JSON `String` type
+// expected by the collector, rather than the default JSON `Number`
+//
+// `Display` for f64 never uses scientific notation, and prints whole numbers without a fraction
+fn f64_to_string<S>(num: &f64, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&num.to_string())
+}
+
 // Serializer to convert the optional f64 to the JSON `String` type
 // expected by the collector, rather than the default JSON `Number`
 fn optional_f64_to_string<S>(num: &Option<f64>, serializer: S) -> Result<S::Ok, S::Error>
@@ -122,7 +158,7 @@ where
     S: Serializer,
 {
     if let Some(num) = num {
-        serializer.serialize_str(&num.to_string())
+        f64_to_string(num, serializer)
     } else {
         serializer.serialize_none()
     }
@@ -134,6 +170,47 @@ impl StructuredEvent {
     }
 }
 
+/// Maximum lengths, in characters, for the string fields of a [StructuredEvent]
+///
+/// Fields without a limit are not checked. Used with [StructuredEventBuilder::build_with_length_limits]
+/// to catch values that would be truncated by downstream column length limits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StructuredEventLengthLimits {
+    pub category: Option<usize>,
+    pub action: Option<usize>,
+    pub property: Option<usize>,
+    pub label: Option<usize>,
+}
+
+impl StructuredEventBuilder {
+    /// Build the [StructuredEvent], returning an error if any field is longer than its limit
+    pub fn build_with_length_limits(
+        &self,
+        limits: &StructuredEventLengthLimits,
+    ) -> Result<StructuredEvent, Error> {
+        let event = self.build()?;
+
+        let fields = [
+            ("category", Some(&event.category), limits.category),
+            ("action", Some(&event.action), limits.action),
+            ("property", event.property.as_ref(), limits.property),
+            ("label", event.label.as_ref(), limits.label),
+        ];
+        for (name, value, limit) in fields {
+            if let (Some(value), Some(limit)) = (value, limit) {
+                let length = value.chars().count();
+                if length > limit {
+                    return Err(Error::BuilderError(format!(
+                        "Field `{name}` is {length} characters long, exceeding the limit of {limit}"
+                    )));
+                }
+            }
+        }
+
+        Ok(event)
+    }
+}
+
 impl PayloadAddable for StructuredEvent {
     fn add_to_payload(self, payload_builder: PayloadBuilder) -> PayloadBuilder {
         payload_builder
@@ -146,6 +223,91 @@ impl PayloadAddable for StructuredEvent {
     }
 }
 
+/// Legacy event to track an ecommerce transaction.
+///
+/// The order ID and total value are required, all other fields are optional.
+/// Monetary amounts are sent as strings, with zero amounts sent as `"0"` rather than omitted.
+#[derive(Serialize, Deserialize, Builder, Debug, Clone)]
+#[builder(setter(into, strip_option))]
+#[builder(build_fn(error = "Error"))]
+pub struct EcommerceTransactionEvent {
+    /// The ID of the order.
+    #[serde(rename(serialize = "tr_id"))]
+    pub id: String,
+
+    /// The total value of the order.
+    #[serde(rename(serialize = "tr_tt"))]
+    #[serde(serialize_with = "f64_to_string")]
+    pub total: f64,
+
+    /// The affiliation of the order, e.g. the store the order was placed from.
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename(serialize = "tr_af"))]
+    pub affiliation: Option<String>,
+
+    /// The amount of tax on the order.
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename(serialize = "tr_tx"))]
+    #[serde(serialize_with = "optional_f64_to_string")]
+    pub tax: Option<f64>,
+
+    /// The shipping cost of the order.
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename(serialize = "tr_sh"))]
+    #[serde(serialize_with = "optional_f64_to_string")]
+    pub shipping: Option<f64>,
+
+    /// The delivery city of the order.
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename(serialize = "tr_ci"))]
+    pub city: Option<String>,
+
+    /// The delivery state of the order.
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename(serialize = "tr_st"))]
+    pub state: Option<String>,
+
+    /// The delivery country of the order.
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename(serialize = "tr_co"))]
+    pub country: Option<String>,
+
+    /// The ISO 4217 currency code the order amounts are in, e.g. "GBP".
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename(serialize = "tr_cu"))]
+    pub currency: Option<String>,
+
+    /// The [Subject] of the event.
+    #[builder(default)]
+    #[serde(skip_serializing)]
+    pub subject: Option<Subject>,
+}
+
+impl EcommerceTransactionEvent {
+    pub fn builder() -> EcommerceTransactionEventBuilder {
+        EcommerceTransactionEventBuilder::default()
+    }
+}
+
+impl PayloadAddable for EcommerceTransactionEvent {
+    fn add_to_payload(self, payload_builder: PayloadBuilder) -> PayloadBuilder {
+        payload_builder
+            .e(EventType::EcommerceTransaction)
+            .ecommerce_transaction(self)
+    }
+
+    fn subject(&self) -> &Option<Subject> {
+        &self.subject
+    }
+}
+
 /// Event to track user viewing a screen within the application.
 ///
 /// It is a self-describing event with the schema "iglu:com.snowplowanalytics.snowplow/screen_view/jsonschema/1-0-0"
@@ -201,7 +363,7 @@ impl ScreenViewEvent {
 impl PayloadAddable for ScreenViewEvent {
     fn add_to_payload(self, payload_builder: PayloadBuilder) -> PayloadBuilder {
         let event = SelfDescribingEvent {
-            schema: "iglu:com.snowplowanalytics.mobile/screen_view/jsonschema/1-0-0".to_string(),
+            schema: SCREEN_VIEW_SCHEMA.to_string(),
             data: json!(self),
             subject: self.subject,
         };
@@ -219,7 +381,7 @@ impl PayloadAddable for ScreenViewEvent {
 /// It is a self-describing event with the schema "iglu:com.snowplowanalytics.snowplow/timing/jsonschema/1-0-0"
 #[derive(Serialize, Deserialize, Builder, Default)]
 #[builder(setter(into, strip_option), default)]
-#[builder(build_fn(error = "Error"))]
+#[builder(build_fn(validate = "Self::validate", error = "Error"))]
 pub struct TimingEvent {
     /// The category of the timed event
     pub category: String,
@@ -228,6 +390,8 @@ pub struct TimingEvent {
     pub variable: String,
 
     /// The number of milliseconds in elapsed time
+    ///
+    /// Elapsed time can't be negative, so the builder rejects negative timings.
     pub timing: i64,
 
     /// An optional description
@@ -246,10 +410,21 @@ impl TimingEvent {
     }
 }
 
+impl TimingEventBuilder {
+    fn validate(&self) -> Result<(), Error> {
+        match self.timing {
+            Some(timing) if timing < 0 => Err(Error::BuilderError(format!(
+                "Timing must not be negative, got {timing}"
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
 impl PayloadAddable for TimingEvent {
     fn add_to_payload(self, payload_builder: PayloadBuilder) -> PayloadBuilder {
         let event = SelfDescribingEvent {
-            schema: "iglu:com.snowplowanalytics.snowplow/timing/jsonschema/1-0-0".to_string(),
+            schema: TIMING_SCHEMA.to_string(),
             data: json!(self),
             subject: self.subject,
         };
@@ -280,6 +455,74 @@ mod tests {
         assert_eq!("test_action", event.action);
     }
 
+    #[test]
+    fn category_and_action_accept_user_enums() {
+        enum Category {
+            Shop,
+        }
+
+        impl From<Category> for String {
+            fn from(category: Category) -> String {
+                match category {
+                    Category::Shop => "shop".to_string(),
+                }
+            }
+        }
+
+        enum Action {
+            AddToBasket,
+        }
+
+        impl From<Action> for String {
+            fn from(action: Action) -> String {
+                match action {
+                    Action::AddToBasket => "add-to-basket".to_string(),
+                }
+            }
+        }
+
+        let event = StructuredEvent::builder()
+            .category(Category::Shop)
+            .action(Action::AddToBasket)
+            .build()
+            .unwrap();
+        assert_eq!(event.category, "shop");
+        assert_eq!(event.action, "add-to-basket");
+
+        let event = StructuredEvent::builder()
+            .category("shop")
+            .action(String::from("add-to-basket"))
+            .build()
+            .unwrap();
+        assert_eq!(event.category, "shop");
+        assert_eq!(event.action, "add-to-basket");
+    }
+
+    #[test]
+    fn over_long_category_fails_length_validation() {
+        let limits = StructuredEventLengthLimits {
+            category: Some(10),
+            ..StructuredEventLengthLimits::default()
+        };
+
+        let err = StructuredEvent::builder()
+            .category("a".repeat(11))
+            .action("test_action")
+            .build_with_length_limits(&limits)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Field `category` is 11 characters long, exceeding the limit of 10"
+        );
+
+        let event = StructuredEvent::builder()
+            .category("a".repeat(10))
+            .action("a".repeat(100))
+            .build_with_length_limits(&limits)
+            .unwrap();
+        assert_eq!(event.category.len(), 10);
+    }
+
     #[test]
     fn builds_payload_for_self_describing_event() {
         let event = SelfDescribingEvent::builder()
@@ -334,6 +577,92 @@ mod tests {
         assert_eq!(event.value.unwrap(), 2_f64);
     }
 
+    #[test]
+    fn whole_number_values_are_serialized_as_integers() {
+        let se_va = |value: f64| {
+            let event = StructuredEvent::builder()
+                .category("shop")
+                .action("add-to-basket")
+                .value(value)
+                .build()
+                .unwrap();
+            serde_json::to_value(event).unwrap()["se_va"].clone()
+        };
+
+        assert_eq!(se_va(1_000_000_000_000.0), "1000000000000");
+        assert_eq!(se_va(1e20), "100000000000000000000");
+        assert_eq!(se_va(2.5), "2.5");
+    }
+
+    #[test]
+    fn ecommerce_transaction_requires_id_and_total() {
+        let err = EcommerceTransactionEvent::builder()
+            .total(10.0)
+            .build()
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Field not initialized: id");
+
+        let err = EcommerceTransactionEvent::builder()
+            .id("order-1")
+            .build()
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Field not initialized: total");
+
+        let event = EcommerceTransactionEvent::builder()
+            .id("order-1")
+            .total(10.0)
+            .build()
+            .unwrap();
+        assert_eq!(
+            json!(event),
+            json!({
+                "tr_id": "order-1",
+                "tr_tt": "10",
+            })
+        );
+    }
+
+    #[test]
+    fn ecommerce_transaction_serializes_zero_amounts() {
+        let event = EcommerceTransactionEvent::builder()
+            .id("order-1")
+            .total(0.0)
+            .tax(0.0)
+            .shipping(0.0)
+            .currency("GBP")
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            json!(event),
+            json!({
+                "tr_id": "order-1",
+                "tr_tt": "0",
+                "tr_tx": "0",
+                "tr_sh": "0",
+                "tr_cu": "GBP",
+            })
+        );
+    }
+
+    #[test]
+    fn builds_payload_for_ecommerce_transaction() {
+        let event = EcommerceTransactionEvent::builder()
+            .id("order-1")
+            .total(25.5)
+            .affiliation("web")
+            .build()
+            .unwrap();
+
+        let payload = event.add_to_payload(payload_builder()).build().unwrap();
+        let serialized = json!(payload);
+
+        assert_eq!(serialized["e"], "tr");
+        assert_eq!(serialized["tr_id"], "order-1");
+        assert_eq!(serialized["tr_tt"], "25.5");
+        assert_eq!(serialized["tr_af"], "web");
+    }
+
     #[test]
     fn builds_payload_for_screen_view() {
         let event = ScreenViewEvent::builder()
@@ -389,6 +718,35 @@ mod tests {
         assert_eq!(data.data, expected.data);
     }
 
+    #[test]
+    fn self_describing_event_from_value_validates_schema() {
+        let event = SelfDescribingEvent::from_value(
+            "iglu:com.acme/purchase/jsonschema/1-0-0".to_string(),
+            json!({"sku": "abc"}),
+        )
+        .unwrap();
+        assert_eq!(event.data, json!({"sku": "abc"}));
+        assert!(event.subject.is_none());
+
+        let result = SelfDescribingEvent::from_value("com.acme/purchase".to_string(), json!({}));
+        assert!(matches!(result, Err(Error::BuilderError(_))));
+    }
+
+    #[test]
+    fn timing_event_rejects_negative_timings() {
+        let timing_event = |timing: i64| {
+            TimingEvent::builder()
+                .category("fetch_resource")
+                .variable("map_loaded")
+                .timing(timing)
+                .build()
+        };
+
+        assert!(matches!(timing_event(-1), Err(Error::BuilderError(_))));
+        assert_eq!(timing_event(0).unwrap().timing, 0);
+        assert_eq!(timing_event(1423).unwrap().timing, 1423);
+    }
+
     fn payload_builder() -> PayloadBuilder {
         Payload::builder()
             .p("platform".to_string())
@@ -9,17 +9,21 @@
 // "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
 
+use std::collections::HashMap;
 use std::time::{Duration, SystemTime, SystemTimeError, UNIX_EPOCH};
 
 use rand::Rng;
-use serde_json::json;
+use serde_json::{json, Value};
 use uuid::Uuid;
 
-use crate::{emitter::RetryPolicy, payload::Payload, Error, SelfDescribingJson};
+use crate::{emitter::RetryPolicy, payload::Payload, Error, Priority, SelfDescribingJson};
 
 const PAYLOAD_DATA_SCHEMA: &str =
     "iglu:com.snowplowanalytics.snowplow/payload_data/jsonschema/1-0-4";
 
+// The delay before the first retry of a batch, unless configured otherwise
+pub(crate) const DEFAULT_FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+
 /// A batch of events to be sent to the collector.
 #[derive(Debug)]
 pub struct EventBatch {
@@ -27,15 +31,24 @@ pub struct EventBatch {
     pub events: Vec<Payload>,
     pub delay: Option<Duration>,
     pub retry_attempts: u32,
+    /// The highest [Priority] of the events in the batch
+    pub priority: Priority,
 }
 
 impl EventBatch {
     pub fn new(id: Uuid, events: Vec<Payload>) -> Self {
+        let priority = events
+            .iter()
+            .map(|event| event.priority)
+            .max()
+            .unwrap_or_default();
+
         Self {
             id,
             events,
             delay: None,
             retry_attempts: 0,
+            priority,
         }
     }
 
@@ -47,6 +60,75 @@ impl EventBatch {
         }
     }
 
+    /// Creates a sendable payload from the batch, like [as_payload](EventBatch::as_payload), omitting
+    /// event fields that are empty strings, or empty JSON objects or arrays
+    pub fn as_compact_payload(&self) -> SelfDescribingJson {
+        let mut payload = self.as_payload();
+        if let Some(events) = payload.data.as_array_mut() {
+            for fields in events.iter_mut().filter_map(Value::as_object_mut) {
+                fields.retain(|_, value| !is_empty_value(value));
+            }
+        }
+        payload
+    }
+
+    /// Sorts the events in the batch by the time they were created, their `dtm`
+    ///
+    /// The sort is stable, so events created in the same millisecond keep their order. Events whose
+    /// `dtm` isn't a timestamp are moved to the end.
+    pub fn sort_by_dtm(&mut self) {
+        self.events
+            .sort_by_key(|event| event.dtm.parse::<u64>().unwrap_or(u64::MAX));
+    }
+
+    /// Removes events whose [expire_at](crate::PayloadBuilder::expire_at) is before `now`, returning
+    /// the removed events
+    pub fn drop_expired(&mut self, now: SystemTime) -> Vec<Payload> {
+        let (kept, expired) = std::mem::take(&mut self.events)
+            .into_iter()
+            .partition(|event| event.expire_at.map_or(true, |expire_at| expire_at > now));
+        self.events = kept;
+        expired
+    }
+
+    /// The number of events in the batch.
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Whether the batch contains no events.
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// The size in bytes of the batch once serialized for sending, as returned by [EventBatch::as_payload].
+    pub fn byte_size(&self) -> usize {
+        // Serializing a `SelfDescribingJson` with `Value` data cannot fail
+        serde_json::to_vec(&self.as_payload())
+            .map(|bytes| bytes.len())
+            .unwrap_or_default()
+    }
+
+    /// An estimate of the bytes taken up by context entities repeated across events in the batch.
+    ///
+    /// The tracker protocol requires each event to carry its own context entities, so repetition can't be
+    /// removed when sending. A large estimate suggests moving the repeated data to an enrichment instead.
+    pub fn bytes_saved_estimate(&self) -> usize {
+        let mut entity_counts: HashMap<String, usize> = HashMap::new();
+        for context in self.events.iter().filter_map(|event| event.co.as_ref()) {
+            for entity in &context.data {
+                if let Ok(entity) = serde_json::to_string(entity) {
+                    *entity_counts.entry(entity).or_default() += 1;
+                }
+            }
+        }
+
+        entity_counts
+            .iter()
+            .map(|(entity, count)| entity.len() * (count - 1))
+            .sum()
+    }
+
     /// Whether the batch has any retries remaining.
     pub fn has_retry(&self, retry_policy: RetryPolicy) -> bool {
         match retry_policy {
@@ -62,7 +144,7 @@ impl EventBatch {
             SystemTime::now()
                 .duration_since(UNIX_EPOCH)
                 .map_err(|e: SystemTimeError| {
-                    Error::BuilderError(format!("Failed to get current time: {}", e.to_string()))
+                    Error::BuilderError(format!("Failed to get current time: {e}"))
                 })?;
 
         for event in self.events.iter_mut() {
@@ -73,7 +155,22 @@ impl EventBatch {
     }
 
     /// Updates the delay until another sending attempt is made.
+    ///
+    /// The first retry is delayed by 1 second.
     pub fn update_for_retry(&mut self) {
+        self.update_for_retry_with_jitter(DEFAULT_FIRST_RETRY_DELAY, Duration::ZERO)
+    }
+
+    /// Updates the delay until another sending attempt is made, with the first retry delayed by
+    /// `first_delay`, plus or minus a random amount up to `first_delay_jitter`.
+    ///
+    /// Jittering the first retry stops many batches that failed at the same time from all being
+    /// retried at the same time.
+    pub fn update_for_retry_with_jitter(
+        &mut self,
+        first_delay: Duration,
+        first_delay_jitter: Duration,
+    ) {
         let max_event_delay_time = Duration::from_secs(600_000);
 
         self.retry_attempts += 1;
@@ -85,20 +182,39 @@ impl EventBatch {
 
                 Some(delay.mul_f32(delay_mul).min(max_event_delay_time))
             }
-            None => Some(Duration::from_secs(1)),
+            None if first_delay_jitter.is_zero() => Some(first_delay),
+            None => {
+                let min_delay = first_delay.saturating_sub(first_delay_jitter);
+                let max_delay = first_delay + first_delay_jitter;
+                Some(rand::thread_rng().gen_range(min_delay..=max_delay))
+            }
         }
     }
 }
 
+// Values that carry no information, so can be left out of a payload
+fn is_empty_value(value: &Value) -> bool {
+    match value {
+        Value::Null => true,
+        Value::String(value) => value.is_empty(),
+        Value::Array(values) => values.is_empty(),
+        Value::Object(fields) => fields.is_empty(),
+        Value::Bool(_) | Value::Number(_) => false,
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::time::Duration;
+    use std::time::{Duration, SystemTime};
 
     use uuid::Uuid;
 
+    use serde_json::json;
+
     use crate::emitter::RetryPolicy;
-    use crate::PayloadBuilder;
+    use crate::payload::ContextData;
     use crate::{event_batch::EventBatch, payload::Payload};
+    use crate::{PayloadBuilder, SelfDescribingJson};
 
     fn create_payloads(n: usize) -> Vec<PayloadBuilder> {
         (0..n)
@@ -114,6 +230,145 @@ mod tests {
             .collect()
     }
 
+    #[test]
+    fn expired_events_are_dropped() {
+        let now = SystemTime::now();
+        let mut payloads = create_payloads(3).into_iter();
+        let events = vec![
+            payloads.next().unwrap().build().unwrap(),
+            payloads
+                .next()
+                .unwrap()
+                .expire_at(now - Duration::from_secs(1))
+                .build()
+                .unwrap(),
+            payloads
+                .next()
+                .unwrap()
+                .expire_at(now + Duration::from_secs(60))
+                .build()
+                .unwrap(),
+        ];
+        let ids_before = events.iter().map(|event| event.eid).collect::<Vec<_>>();
+        let kept = [events[0].eid, events[2].eid];
+        let mut batch = EventBatch::new(Uuid::new_v4(), events);
+
+        let expired = batch.drop_expired(now);
+        assert_eq!(
+            expired.iter().map(|event| event.eid).collect::<Vec<_>>(),
+            [ids_before[1]]
+        );
+        let ids = batch
+            .events
+            .iter()
+            .map(|event| event.eid)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, kept);
+    }
+
+    #[test]
+    fn events_are_sorted_by_dtm() {
+        let events = ["3", "1", "2", "1"]
+            .iter()
+            .map(|dtm| {
+                create_payloads(1)
+                    .remove(0)
+                    .dtm(dtm.to_string())
+                    .build()
+                    .unwrap()
+            })
+            .collect::<Vec<Payload>>();
+        let ids = events.iter().map(|event| event.eid).collect::<Vec<_>>();
+        let mut batch = EventBatch::new(Uuid::new_v4(), events);
+
+        batch.sort_by_dtm();
+
+        let dtms = batch.events.iter().map(|event| event.dtm.as_str());
+        assert_eq!(dtms.collect::<Vec<_>>(), vec!["1", "1", "2", "3"]);
+        // Events with the same dtm keep their order
+        assert_eq!(batch.events[0].eid, ids[1]);
+        assert_eq!(batch.events[1].eid, ids[3]);
+    }
+
+    #[test]
+    fn batch_len() {
+        let batch = EventBatch::new(
+            Uuid::new_v4(),
+            create_payloads(5)
+                .drain(..)
+                .map(|p| p.finalise_payload().unwrap())
+                .collect(),
+        );
+
+        assert_eq!(batch.len(), batch.events.len());
+        assert_eq!(batch.len(), 5);
+        assert!(!batch.is_empty());
+        assert!(EventBatch::new(Uuid::new_v4(), vec![]).is_empty());
+    }
+
+    #[test]
+    fn batch_byte_size() {
+        let create_batch = |n| {
+            EventBatch::new(
+                Uuid::new_v4(),
+                create_payloads(n)
+                    .drain(..)
+                    .map(|p| p.finalise_payload().unwrap())
+                    .collect(),
+            )
+        };
+
+        let small_batch = create_batch(1);
+        let large_batch = create_batch(5);
+
+        assert_eq!(
+            small_batch.byte_size(),
+            serde_json::to_vec(&small_batch.as_payload()).unwrap().len()
+        );
+        assert!(large_batch.byte_size() > small_batch.byte_size());
+    }
+
+    #[test]
+    fn bytes_saved_estimate_counts_repeated_contexts() {
+        let app_context = SelfDescribingJson::new(
+            "iglu:com.acme/app/jsonschema/1-0-0",
+            json!({"version": "1.2.3"}),
+        );
+        let app_context_bytes = serde_json::to_string(&app_context).unwrap().len();
+        let events = create_payloads(4)
+            .into_iter()
+            .enumerate()
+            .map(|(i, payload)| {
+                let user_context = SelfDescribingJson::new(
+                    "iglu:com.acme/user/jsonschema/1-0-0",
+                    json!({"id": i}),
+                );
+                payload
+                    .co(ContextData::new(vec![app_context.clone(), user_context]))
+                    .finalise_payload()
+                    .unwrap()
+            })
+            .collect();
+
+        let batch = EventBatch::new(Uuid::new_v4(), events);
+
+        // The app context is repeated in 3 events, while each user context is unique
+        assert_eq!(batch.bytes_saved_estimate(), 3 * app_context_bytes);
+    }
+
+    #[test]
+    fn bytes_saved_estimate_without_contexts() {
+        let batch = EventBatch::new(
+            Uuid::new_v4(),
+            create_payloads(3)
+                .drain(..)
+                .map(|p| p.finalise_payload().unwrap())
+                .collect(),
+        );
+
+        assert_eq!(batch.bytes_saved_estimate(), 0);
+    }
+
     #[test]
     fn update_event_stm() {
         let now = std::time::SystemTime::now()
@@ -155,6 +410,41 @@ mod tests {
         assert!(batch.delay.unwrap() > Duration::from_secs(0));
     }
 
+    #[test]
+    fn first_retry_delay_is_jittered() {
+        let first_delays: Vec<Duration> = (0..10)
+            .map(|_| {
+                let mut batch = EventBatch::new(
+                    Uuid::new_v4(),
+                    create_payloads(1)
+                        .drain(..)
+                        .map(|p| p.finalise_payload().unwrap())
+                        .collect(),
+                );
+                batch.update_for_retry_with_jitter(
+                    Duration::from_secs(1),
+                    Duration::from_millis(500),
+                );
+                batch.delay.unwrap()
+            })
+            .collect();
+
+        for delay in &first_delays {
+            assert!(*delay >= Duration::from_millis(500));
+            assert!(*delay <= Duration::from_millis(1500));
+        }
+        assert!(first_delays.iter().any(|delay| *delay != first_delays[0]));
+    }
+
+    #[test]
+    fn first_retry_delay_without_jitter() {
+        let mut batch = EventBatch::new(Uuid::new_v4(), vec![]);
+
+        batch.update_for_retry();
+
+        assert_eq!(batch.delay, Some(Duration::from_secs(1)));
+    }
+
     #[test]
     fn no_retry_policy() {
         let batch = EventBatch::new(
@@ -168,6 +458,28 @@ mod tests {
         assert!(!batch.has_retry(RetryPolicy::NoRetry));
     }
 
+    #[test]
+    fn compact_payload_omits_empty_fields() {
+        let event = crate::StructuredEvent::builder()
+            .category("shop")
+            .action("add-to-basket")
+            .property("")
+            .build()
+            .unwrap();
+        let payload = create_payloads(1)
+            .remove(0)
+            .structured_event(event)
+            .finalise_payload()
+            .unwrap();
+        let batch = EventBatch::new(Uuid::new_v4(), vec![payload]);
+
+        assert_eq!(batch.as_payload().data[0]["se_pr"], "");
+
+        let compact = batch.as_compact_payload();
+        assert!(compact.data[0].get("se_pr").is_none());
+        assert_eq!(compact.data[0]["se_ca"], "shop");
+    }
+
     #[test]
     fn limited_retry_policy() {
         let mut batch = EventBatch::new(
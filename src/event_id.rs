@@ -0,0 +1,140 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rand::Rng;
+use uuid::Uuid;
+
+/// The version of UUID used for event IDs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EventIdVersion {
+    /// Random UUIDs (the default)
+    #[default]
+    V4,
+    /// Time-ordered UUIDs, which sort by the time the event was tracked
+    V7,
+}
+
+impl EventIdVersion {
+    /// Generates a new event ID of this version
+    pub(crate) fn generate(&self) -> Uuid {
+        match self {
+            EventIdVersion::V4 => Uuid::new_v4(),
+            EventIdVersion::V7 => new_v7(),
+        }
+    }
+}
+
+/// Generates the IDs of tracked events
+///
+/// Set on a tracker with [id_provider](crate::TrackerBuilder::id_provider), such as to give events
+/// predictable IDs in tests. [EventIdVersion] implements this, and [EventIdVersion::V4] is the default.
+pub trait IdProvider: Send + Sync {
+    /// Returns the ID for the next tracked event
+    fn next_id(&self) -> Uuid;
+}
+
+impl IdProvider for EventIdVersion {
+    fn next_id(&self) -> Uuid {
+        self.generate()
+    }
+}
+
+// The last timestamp and counter used to generate a v7 UUID, shared across trackers
+// so that ids generated within the same process are always ordered
+static LAST_V7_TIMESTAMP: Mutex<(u64, u16)> = Mutex::new((0, 0));
+
+// The maximum value of the 12 bit counter in a v7 UUID
+const MAX_V7_COUNTER: u16 = 0x0FFF;
+
+// Generates a v7 UUID, as specified in RFC 9562: a 48 bit Unix timestamp in milliseconds,
+// followed by a 12 bit counter and 62 random bits.
+//
+// The version of the `uuid` crate in use does not support v7 without unstable flags,
+// so it is built by hand here.
+fn new_v7() -> Uuid {
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since_the_epoch| since_the_epoch.as_millis() as u64)
+        .unwrap_or_default();
+
+    let (timestamp, counter) = {
+        // A poisoned lock still holds a valid timestamp, so we can carry on using it
+        let mut last = LAST_V7_TIMESTAMP
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        *last = if now_ms > last.0 {
+            (now_ms, 0)
+        } else if last.1 < MAX_V7_COUNTER {
+            // Within the same millisecond (or if the clock moved backwards), increment the counter
+            (last.0, last.1 + 1)
+        } else {
+            // The counter is exhausted, so borrow from the next millisecond
+            (last.0 + 1, 0)
+        };
+        *last
+    };
+
+    let mut bytes = [0_u8; 16];
+    rand::thread_rng().fill(&mut bytes[8..]);
+    bytes[0..6].copy_from_slice(&timestamp.to_be_bytes()[2..8]);
+    bytes[6] = 0x70 | ((counter >> 8) as u8 & 0x0F);
+    bytes[7] = counter as u8;
+    // Set the RFC 4122 variant
+    bytes[8] = (bytes[8] & 0x3F) | 0x80;
+
+    Uuid::from_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generates_v4_by_default() {
+        let id = EventIdVersion::default().generate();
+        assert_eq!(id.get_version_num(), 4);
+    }
+
+    #[test]
+    fn v7_ids_are_time_ordered() {
+        let ids: Vec<Uuid> = (0..10_000).map(|_| EventIdVersion::V7.generate()).collect();
+
+        for id in &ids {
+            assert_eq!(id.get_version_num(), 7);
+            assert_eq!(id.get_variant(), uuid::Variant::RFC4122);
+        }
+
+        for pair in ids.windows(2) {
+            assert!(pair[0] < pair[1]);
+        }
+    }
+
+    #[test]
+    fn v7_ids_contain_timestamp() {
+        let before = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let id = EventIdVersion::V7.generate();
+
+        let mut timestamp_bytes = [0_u8; 8];
+        timestamp_bytes[2..8].copy_from_slice(&id.as_bytes()[0..6]);
+        let timestamp = u64::from_be_bytes(timestamp_bytes);
+
+        // Other tests may have borrowed from future milliseconds, so allow some leeway
+        assert!(timestamp >= before);
+        assert!(timestamp < before + 60_000);
+    }
+}
@@ -0,0 +1,139 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use crate::error::Error;
+use crate::event_batch::EventBatch;
+use crate::event_store::EventStore;
+use crate::payload::PayloadBuilder;
+
+/// An [EventStore] with async methods, for stores backed by an async database or service.
+///
+/// Use it on a [BatchEmitter](crate::BatchEmitter) with
+/// [async_event_store](crate::BatchEmitterBuilder::async_event_store). Every [EventStore] is also an
+/// AsyncEventStore, so the two can be used interchangeably.
+#[async_trait]
+pub trait AsyncEventStore: Send + Sync {
+    /// Add a [PayloadBuilder] to the AsyncEventStore
+    async fn add(&mut self, payload: PayloadBuilder) -> Result<(), Error>;
+    /// The number of events currently in the AsyncEventStore
+    async fn len(&self) -> usize;
+    /// Whether the AsyncEventStore currently holds no events
+    async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+    /// The set size of the batches that will be sent to the collector
+    fn batch_size(&self) -> usize;
+    /// The maximum number of events that can be stored in the AsyncEventStore
+    fn capacity(&self) -> usize;
+    /// Removes and returns a batch of events from the event store
+    /// The batch size is determined by the `batch_size` field
+    async fn full_batch(&mut self) -> Result<EventBatch, Error>;
+    /// Removes and returns the provided number of events from the AsyncEventStore as an [EventBatch]
+    async fn batch_of(&mut self, size: usize) -> Result<EventBatch, Error>;
+    /// How long the oldest event in the AsyncEventStore has been waiting to be sent
+    ///
+    /// AsyncEventStores that don't track when events were added return `None`
+    async fn oldest_event_age(&self) -> Option<Duration> {
+        None
+    }
+    /// Discards all events in the AsyncEventStore without sending them
+    async fn clear(&mut self) -> Result<(), Error> {
+        while !self.is_empty().await {
+            let size = self.len().await.min(self.batch_size());
+            self.batch_of(size).await?;
+        }
+        Ok(())
+    }
+    // A method to be called after attempts to send are finished, either successfully or unsuccessfully
+    async fn cleanup_after_send_attempt(&mut self, batch_id: Uuid) -> Result<(), Error>;
+}
+
+#[async_trait]
+impl<T: EventStore + Send + Sync> AsyncEventStore for T {
+    async fn add(&mut self, payload: PayloadBuilder) -> Result<(), Error> {
+        EventStore::add(self, payload)
+    }
+
+    async fn len(&self) -> usize {
+        EventStore::len(self)
+    }
+
+    async fn is_empty(&self) -> bool {
+        EventStore::is_empty(self)
+    }
+
+    fn batch_size(&self) -> usize {
+        EventStore::batch_size(self)
+    }
+
+    fn capacity(&self) -> usize {
+        EventStore::capacity(self)
+    }
+
+    async fn full_batch(&mut self) -> Result<EventBatch, Error> {
+        EventStore::full_batch(self)
+    }
+
+    async fn batch_of(&mut self, size: usize) -> Result<EventBatch, Error> {
+        EventStore::batch_of(self, size)
+    }
+
+    async fn oldest_event_age(&self) -> Option<Duration> {
+        EventStore::oldest_event_age(self)
+    }
+
+    async fn clear(&mut self) -> Result<(), Error> {
+        EventStore::clear(self)
+    }
+
+    async fn cleanup_after_send_attempt(&mut self, batch_id: Uuid) -> Result<(), Error> {
+        EventStore::cleanup_after_send_attempt(self, batch_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::payload::Payload;
+    use crate::InMemoryEventStore;
+
+    fn valid_payload() -> PayloadBuilder {
+        Payload::builder()
+            .p("p".to_string())
+            .tv("tv".to_string())
+            .eid(Uuid::new_v4())
+            .dtm("dtm".to_string())
+            .aid("aid".to_string())
+    }
+
+    #[tokio::test]
+    async fn sync_stores_are_async_stores() {
+        let mut store = InMemoryEventStore::new(10, 2);
+
+        AsyncEventStore::add(&mut store, valid_payload())
+            .await
+            .unwrap();
+        AsyncEventStore::add(&mut store, valid_payload())
+            .await
+            .unwrap();
+
+        assert_eq!(AsyncEventStore::len(&store).await, 2);
+        assert_eq!(
+            AsyncEventStore::full_batch(&mut store).await.unwrap().len(),
+            2
+        );
+    }
+}
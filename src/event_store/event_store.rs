@@ -9,6 +9,8 @@
 // "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
 
+use std::time::Duration;
+
 use uuid::Uuid;
 
 use crate::error::Error;
@@ -23,6 +25,10 @@ pub trait EventStore {
     fn add(&mut self, payload: PayloadBuilder) -> Result<(), Error>;
     /// The number of events currently in the EventStore
     fn len(&self) -> usize;
+    /// Whether the EventStore currently holds no events
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
     /// The set size of the batches that will be sent to the collector
     fn batch_size(&self) -> usize;
     /// The maximum number of events that can be stored in the EventStore
@@ -32,6 +38,19 @@ pub trait EventStore {
     fn full_batch(&mut self) -> Result<EventBatch, Error>;
     /// Removes and returns the provided number of events from the EventStore as an [EventBatch]
     fn batch_of(&mut self, size: usize) -> Result<EventBatch, Error>;
+    /// How long the oldest event in the EventStore has been waiting to be sent
+    ///
+    /// EventStores that don't track when events were added return `None`
+    fn oldest_event_age(&self) -> Option<Duration> {
+        None
+    }
+    /// Discards all events in the EventStore without sending them
+    fn clear(&mut self) -> Result<(), Error> {
+        while !self.is_empty() {
+            self.batch_of(self.len().min(self.batch_size()))?;
+        }
+        Ok(())
+    }
     // A method to be called after attempts to send are finished, either successfully or unsuccessfully
     fn cleanup_after_send_attempt(&mut self, batch_id: Uuid) -> Result<(), Error>;
 }
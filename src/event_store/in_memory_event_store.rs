@@ -9,6 +9,8 @@
 // "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
 
+use std::time::{Duration, Instant};
+
 use uuid::Uuid;
 
 use crate::event_batch::EventBatch;
@@ -18,11 +20,16 @@ use crate::Error;
 
 // This is pub(crate) as it is used in BatchEmitter
 pub(crate) const DEFAULT_EVENT_STORE_CAPACITY: usize = 10_000;
-const DEFAULT_BATCH_SIZE: usize = 50;
+pub(crate) const DEFAULT_BATCH_SIZE: usize = 50;
 
 struct InMemoryEventStoreQueue {
     queue: Vec<PayloadBuilder>,
-    capacity: usize,
+    // The serialized size of each event in `queue`, only tracked when a batch byte limit is set
+    sizes: Vec<usize>,
+    // When each event in `queue` was added
+    enqueued_at: Vec<Instant>,
+    // The queue grows beyond its initial capacity, up to `max_capacity`
+    max_capacity: usize,
 }
 
 // A slightly extended Vec to store maximum capacity,
@@ -32,25 +39,75 @@ impl InMemoryEventStoreQueue {
         InMemoryEventStoreQueue {
             // `with_capacity` allocates `capacity` elements, to avoid later reallocation
             queue: Vec::with_capacity(capacity),
-            capacity,
+            sizes: Vec::new(),
+            enqueued_at: Vec::with_capacity(capacity),
+            max_capacity: capacity,
         }
     }
 
-    /// Add a payload to the queue
-    /// Returns an error if the queue is full
-    fn push(&mut self, payload: PayloadBuilder) -> Result<(), Error> {
-        if self.queue.len() == self.queue.capacity() {
-            return Err(Error::EventStoreError("Event store is full".to_string()));
+    /// Add a payload to the queue, behind any events of the same or higher priority
+    /// Returns the position of the payload in the queue, or an error if the queue is full
+    fn push(&mut self, payload: PayloadBuilder) -> Result<usize, Error> {
+        if self.queue.len() >= self.max_capacity {
+            return Err(Error::QueueFull);
         }
-        self.queue.push(payload);
+
+        let priority = payload.priority.unwrap_or_default();
+        let position = self
+            .queue
+            .partition_point(|queued| queued.priority.unwrap_or_default() >= priority);
+        self.queue.insert(position, payload);
+        self.enqueued_at.insert(position, Instant::now());
+        Ok(position)
+    }
+
+    /// Add a payload to the queue, along with its serialized size
+    fn push_with_size(&mut self, payload: PayloadBuilder) -> Result<(), Error> {
+        let size = serde_json::to_vec(&payload.clone().finalise_payload()?)
+            .map_err(|e| Error::EventStoreError(format!("Failed to serialize event: {e}")))?
+            .len();
+
+        let position = self.push(payload)?;
+        self.sizes.insert(position, size);
         Ok(())
     }
+
+    /// The number of events at the front of the queue whose total serialized size is within `max_bytes`
+    ///
+    /// This is always at least 1 for a non-empty queue, so an event larger than `max_bytes` is still sent
+    fn events_within_bytes(&self, max_bytes: usize) -> usize {
+        let mut total_bytes = 0;
+        let mut count = 0;
+        for size in &self.sizes {
+            // Account for the comma separating events in the serialized batch
+            total_bytes += size + 1;
+            if total_bytes > max_bytes && count > 0 {
+                break;
+            }
+            count += 1;
+        }
+        count
+    }
+}
+
+/// How the id of an [EventBatch] is chosen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BatchIdStrategy {
+    /// A new random UUID, unrelated to the events in the batch (the default)
+    #[default]
+    Random,
+    /// The event id of the first event in the batch
+    FirstEventId,
 }
 
 /// An implementation of the [EventStore] trait, that queues events in a Vec
+///
+/// Events are batched in order of [Priority](crate::Priority), then in the order they were added.
 pub struct InMemoryEventStore {
     event_queue: InMemoryEventStoreQueue,
     batch_size: usize,
+    max_batch_bytes: Option<usize>,
+    batch_id_strategy: BatchIdStrategy,
 }
 
 /// Provides an instance of [InMemoryEventStore], with the default batch size of 50, and a queue capacity of 10,000
@@ -59,6 +116,8 @@ impl Default for InMemoryEventStore {
         Self {
             event_queue: InMemoryEventStoreQueue::new(DEFAULT_EVENT_STORE_CAPACITY),
             batch_size: DEFAULT_BATCH_SIZE,
+            max_batch_bytes: None,
+            batch_id_strategy: BatchIdStrategy::Random,
         }
     }
 }
@@ -68,9 +127,38 @@ impl InMemoryEventStore {
         Self {
             event_queue: InMemoryEventStoreQueue::new(queue_capacity),
             batch_size,
+            max_batch_bytes: None,
+            batch_id_strategy: BatchIdStrategy::Random,
         }
     }
 
+    /// Also create a batch once the serialized size of the queued events reaches `max_batch_bytes`,
+    /// even if there are fewer than `batch_size` events
+    ///
+    /// A single event larger than `max_batch_bytes` is sent in a batch on its own.
+    pub fn with_max_batch_bytes(mut self, max_batch_bytes: usize) -> Self {
+        self.max_batch_bytes = Some(max_batch_bytes);
+        self
+    }
+
+    /// Allow the queue to grow beyond its initial capacity, up to `max_capacity` events, before
+    /// rejecting new events
+    ///
+    /// Only the initial capacity is allocated up front, so transient spikes can be absorbed without
+    /// allocating for the worst case. A `max_capacity` below the initial capacity is ignored.
+    pub fn with_max_capacity(mut self, max_capacity: usize) -> Self {
+        self.event_queue.max_capacity = max_capacity.max(self.event_queue.queue.capacity());
+        self
+    }
+
+    /// Set how batch ids are chosen
+    ///
+    /// Defaults to [BatchIdStrategy::Random], so batch ids don't collide with event ids
+    pub fn with_batch_id_strategy(mut self, batch_id_strategy: BatchIdStrategy) -> Self {
+        self.batch_id_strategy = batch_id_strategy;
+        self
+    }
+
     fn event_batch(&mut self, size: usize) -> Result<EventBatch, Error> {
         if self.event_queue.queue.is_empty() {
             return Err(Error::EventStoreError("Event store is empty".to_string()));
@@ -89,20 +177,30 @@ impl InMemoryEventStore {
             .drain(0..size)
             .map(|e| e.finalise_payload())
             .collect::<Result<Vec<Payload>, Error>>()?;
+        let tracked_sizes = size.min(self.event_queue.sizes.len());
+        self.event_queue.sizes.drain(0..tracked_sizes);
+        self.event_queue.enqueued_at.drain(0..size);
 
-        // Take the first event's `eid` and use it for the batch id
         let first_event_id = match events_to_send.first() {
-            Some(payload) => payload.eid.clone(),
+            Some(payload) => payload.eid,
             None => return Err(Error::EventStoreError("No events to send".to_string())),
         };
 
-        Ok(EventBatch::new(first_event_id, events_to_send))
+        let batch_id = match self.batch_id_strategy {
+            BatchIdStrategy::Random => Uuid::new_v4(),
+            BatchIdStrategy::FirstEventId => first_event_id,
+        };
+
+        Ok(EventBatch::new(batch_id, events_to_send))
     }
 }
 
 impl EventStore for InMemoryEventStore {
     fn add(&mut self, event: PayloadBuilder) -> Result<(), Error> {
-        self.event_queue.push(event)
+        match self.max_batch_bytes {
+            Some(_) => self.event_queue.push_with_size(event),
+            None => self.event_queue.push(event).map(|_| ()),
+        }
     }
 
     fn len(&self) -> usize {
@@ -110,11 +208,26 @@ impl EventStore for InMemoryEventStore {
     }
 
     fn capacity(&self) -> usize {
-        self.event_queue.capacity
+        self.event_queue.max_capacity
     }
 
     fn full_batch(&mut self) -> Result<EventBatch, Error> {
-        if self.event_queue.queue.len() < self.batch_size {
+        let queue_len = self.event_queue.queue.len();
+
+        // When a byte limit is set, a batch is full once the queued events exceed the limit
+        if let Some(max_bytes) = self.max_batch_bytes {
+            let within_limit = self.event_queue.events_within_bytes(max_bytes);
+            let oversized_first_event = self
+                .event_queue
+                .sizes
+                .first()
+                .is_some_and(|size| size + 1 > max_bytes);
+            if within_limit < queue_len.min(self.batch_size) || oversized_first_event {
+                return self.event_batch(within_limit);
+            }
+        }
+
+        if queue_len < self.batch_size {
             return Err(Error::EventStoreError(
                 "Failed to get batch: Not enough events in the event store for a full batch"
                     .to_string(),
@@ -136,15 +249,32 @@ impl EventStore for InMemoryEventStore {
         self.batch_size
     }
 
+    // Higher priority events are queued ahead of older events, so the oldest event may not be first
+    fn oldest_event_age(&self) -> Option<Duration> {
+        self.event_queue
+            .enqueued_at
+            .iter()
+            .min()
+            .map(|enqueued_at| enqueued_at.elapsed())
+    }
+
+    fn clear(&mut self) -> Result<(), Error> {
+        self.event_queue.queue.clear();
+        self.event_queue.sizes.clear();
+        self.event_queue.enqueued_at.clear();
+        Ok(())
+    }
+
     // InMemoryEventStore doesn't need to do anything to clean up after a send attempt
-    fn cleanup_after_send_attempt(&mut self, batch_id: Uuid) -> Result<(), Error> {
-        Ok(drop(batch_id))
+    fn cleanup_after_send_attempt(&mut self, _batch_id: Uuid) -> Result<(), Error> {
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::Priority;
 
     fn create_payloads(n: usize) -> Vec<PayloadBuilder> {
         (0..n)
@@ -165,7 +295,7 @@ mod test {
         let mut event_store = InMemoryEventStore::default();
         let mut payloads = create_payloads(1);
         let payload = payloads.drain(..1).next().unwrap();
-        let expected_eid = payload.eid.clone();
+        let expected_eid = payload.eid;
 
         event_store.add(payload).unwrap();
 
@@ -195,6 +325,67 @@ mod test {
         assert_eq!(event_store.len(), 4);
     }
 
+    #[test]
+    fn grows_up_to_max_capacity() {
+        let mut event_store = InMemoryEventStore::new(2, 2).with_max_capacity(4);
+        let mut payloads = create_payloads(5).into_iter();
+
+        for payload in payloads.by_ref().take(4) {
+            event_store.add(payload).unwrap();
+        }
+        assert_eq!(event_store.len(), 4);
+        assert_eq!(event_store.capacity(), 4);
+
+        assert!(event_store.add(payloads.next().unwrap()).is_err());
+        assert_eq!(event_store.len(), 4);
+    }
+
+    #[test]
+    fn rejects_events_at_capacity_by_default() {
+        let mut event_store = InMemoryEventStore::new(2, 2);
+        let mut payloads = create_payloads(3).into_iter();
+
+        event_store.add(payloads.next().unwrap()).unwrap();
+        event_store.add(payloads.next().unwrap()).unwrap();
+
+        assert!(event_store.add(payloads.next().unwrap()).is_err());
+    }
+
+    #[test]
+    fn clear_discards_all_events() {
+        let mut event_store = InMemoryEventStore::new(10, 2).with_max_batch_bytes(100_000);
+        for payload in create_payloads(5) {
+            event_store.add(payload).unwrap();
+        }
+
+        event_store.clear().unwrap();
+
+        assert_eq!(event_store.len(), 0);
+        assert!(event_store.full_batch().is_err());
+    }
+
+    #[test]
+    fn tracks_age_of_oldest_event() {
+        let mut event_store = InMemoryEventStore::new(10, 2);
+        assert!(event_store.oldest_event_age().is_none());
+
+        let mut payloads = create_payloads(3).into_iter();
+        event_store.add(payloads.next().unwrap()).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        event_store
+            .add(payloads.next().unwrap().priority(Priority::High))
+            .unwrap();
+        event_store.add(payloads.next().unwrap()).unwrap();
+        assert!(event_store.oldest_event_age().unwrap() >= Duration::from_millis(50));
+
+        // The high priority event is batched first, leaving the oldest event queued
+        event_store.batch_of(1).unwrap();
+        assert!(event_store.oldest_event_age().unwrap() >= Duration::from_millis(50));
+
+        event_store.batch_of(2).unwrap();
+        assert!(event_store.oldest_event_age().is_none());
+    }
+
     #[test]
     fn get_batch() {
         let mut event_store = InMemoryEventStore::new(4, 2);
@@ -209,6 +400,116 @@ mod test {
         assert_eq!(event_store.len(), 2);
     }
 
+    #[test]
+    fn byte_limit_triggers_batch_before_batch_size() {
+        let mut event_store = InMemoryEventStore::new(100, 50).with_max_batch_bytes(2_500);
+        let large_payloads = (0..3).map(|_| {
+            Payload::builder()
+                .p("p".to_string())
+                .tv("tv".to_string())
+                .eid(uuid::Uuid::new_v4())
+                .dtm("dtm".to_string())
+                .aid("a".repeat(1_000))
+        });
+
+        for payload in large_payloads {
+            event_store.add(payload).unwrap();
+        }
+
+        // Three ~1KB events exceed the limit, so a batch of the two that fit is created
+        let batch = event_store.full_batch().unwrap();
+        assert_eq!(batch.events.len(), 2);
+        assert_eq!(event_store.len(), 1);
+
+        // The remaining event is within the limit, and below the batch size
+        assert!(event_store.full_batch().is_err());
+    }
+
+    #[test]
+    fn byte_limit_not_reached_waits_for_batch_size() {
+        let mut event_store = InMemoryEventStore::new(10, 2).with_max_batch_bytes(100_000);
+
+        event_store.add(create_payloads(1).remove(0)).unwrap();
+        assert!(event_store.full_batch().is_err());
+
+        event_store.add(create_payloads(1).remove(0)).unwrap();
+        assert_eq!(event_store.full_batch().unwrap().events.len(), 2);
+    }
+
+    #[test]
+    fn oversized_event_is_sent_alone() {
+        let mut event_store = InMemoryEventStore::new(10, 5).with_max_batch_bytes(10);
+
+        for payload in create_payloads(2) {
+            event_store.add(payload).unwrap();
+        }
+
+        assert_eq!(event_store.full_batch().unwrap().events.len(), 1);
+        assert_eq!(event_store.len(), 1);
+        assert_eq!(event_store.full_batch().unwrap().events.len(), 1);
+        assert!(event_store.is_empty());
+    }
+
+    #[test]
+    fn batch_id_differs_from_event_ids() {
+        let mut event_store = InMemoryEventStore::new(4, 4);
+        for payload in create_payloads(4) {
+            event_store.add(payload).unwrap();
+        }
+
+        let batch = event_store.full_batch().unwrap();
+
+        assert!(batch.events.iter().all(|event| event.eid != batch.id));
+    }
+
+    #[test]
+    fn first_event_id_batch_id_strategy() {
+        let mut event_store =
+            InMemoryEventStore::new(2, 2).with_batch_id_strategy(BatchIdStrategy::FirstEventId);
+        for payload in create_payloads(2) {
+            event_store.add(payload).unwrap();
+        }
+
+        let batch = event_store.full_batch().unwrap();
+
+        assert_eq!(batch.id, batch.events[0].eid);
+    }
+
+    #[test]
+    fn batches_higher_priority_events_first() {
+        let mut event_store = InMemoryEventStore::new(10, 2);
+        let priorities = [
+            Priority::Low,
+            Priority::Normal,
+            Priority::High,
+            Priority::Low,
+            Priority::High,
+        ];
+        let payloads: Vec<_> = create_payloads(5)
+            .into_iter()
+            .zip(priorities)
+            .map(|(payload, priority)| payload.priority(priority))
+            .collect();
+        let eids: Vec<_> = payloads.iter().map(|p| p.eid.unwrap()).collect();
+
+        for payload in payloads {
+            event_store.add(payload).unwrap();
+        }
+
+        let first_batch = event_store.full_batch().unwrap();
+        assert_eq!(first_batch.priority, Priority::High);
+        let first_eids: Vec<_> = first_batch.events.iter().map(|e| e.eid).collect();
+        assert_eq!(first_eids, vec![eids[2], eids[4]]);
+
+        let second_batch = event_store.full_batch().unwrap();
+        let second_eids: Vec<_> = second_batch.events.iter().map(|e| e.eid).collect();
+        assert_eq!(second_eids, vec![eids[1], eids[0]]);
+
+        let last_batch = event_store.batch_of(1).unwrap();
+        assert_eq!(last_batch.priority, Priority::Low);
+        assert_eq!(last_batch.events[0].eid, eids[3]);
+    }
+
     #[test]
     fn get_batch_without_enough_events_in_queue() {
         let mut event_store = InMemoryEventStore::new(2, 2);
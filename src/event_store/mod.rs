@@ -9,9 +9,20 @@
 // "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
 
+mod async_event_store;
+#[allow(clippy::module_inception)]
 mod event_store;
 mod in_memory_event_store;
+#[cfg(feature = "redis")]
+mod redis_connection;
+#[cfg(feature = "redis")]
+mod redis_event_store;
+mod ring_buffer_event_store;
 
+pub use async_event_store::AsyncEventStore;
 pub use event_store::EventStore;
-pub use in_memory_event_store::InMemoryEventStore;
-pub(crate) use in_memory_event_store::DEFAULT_EVENT_STORE_CAPACITY;
+pub use in_memory_event_store::{BatchIdStrategy, InMemoryEventStore};
+pub(crate) use in_memory_event_store::{DEFAULT_BATCH_SIZE, DEFAULT_EVENT_STORE_CAPACITY};
+#[cfg(feature = "redis")]
+pub use redis_event_store::RedisEventStore;
+pub use ring_buffer_event_store::RingBufferEventStore;
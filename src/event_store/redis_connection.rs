@@ -0,0 +1,320 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+use std::future::Future;
+use std::pin::Pin;
+
+use reqwest::Url;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use crate::Error;
+
+const DEFAULT_REDIS_PORT: u16 = 6379;
+
+// The largest bulk string Redis will send, so a corrupt length can't cause a huge allocation
+const MAX_BULK_LENGTH: i64 = 512 * 1024 * 1024;
+
+// Arrays are read element by element, so only this many are allocated up front
+const MAX_ARRAY_PREALLOCATION: usize = 1024;
+
+/// A value in a reply from Redis
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum RedisValue {
+    Status(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<RedisValue>>),
+}
+
+impl RedisValue {
+    pub(crate) fn into_integer(self) -> Result<i64, Error> {
+        match self {
+            RedisValue::Integer(value) => Ok(value),
+            other => Err(unexpected_reply(&other)),
+        }
+    }
+
+    pub(crate) fn into_array(self) -> Result<Vec<RedisValue>, Error> {
+        match self {
+            RedisValue::Array(values) => Ok(values.unwrap_or_default()),
+            other => Err(unexpected_reply(&other)),
+        }
+    }
+
+    pub(crate) fn into_bytes(self) -> Result<Option<Vec<u8>>, Error> {
+        match self {
+            RedisValue::Bulk(value) => Ok(value),
+            other => Err(unexpected_reply(&other)),
+        }
+    }
+}
+
+/// Where to connect to Redis, parsed from a `redis://[:password@]host[:port][/db]` URL
+#[derive(Debug, Clone)]
+pub(crate) struct RedisConfig {
+    address: String,
+    password: Option<String>,
+    db: Option<u32>,
+}
+
+impl RedisConfig {
+    pub(crate) fn parse(redis_url: &str) -> Result<Self, Error> {
+        let invalid = |reason: &str| {
+            Error::EventStoreError(format!("Invalid Redis URL {redis_url:?}: {reason}"))
+        };
+
+        let url = Url::parse(redis_url).map_err(|e| invalid(&e.to_string()))?;
+        if url.scheme() != "redis" {
+            return Err(invalid("the scheme must be redis"));
+        }
+        let host = url
+            .host_str()
+            .filter(|host| !host.is_empty())
+            .ok_or_else(|| invalid("a host is required"))?;
+
+        let db = match url.path().trim_start_matches('/') {
+            "" => None,
+            db => Some(
+                db.parse()
+                    .map_err(|_| invalid("the path must be a database number"))?,
+            ),
+        };
+
+        Ok(Self {
+            address: format!("{host}:{}", url.port().unwrap_or(DEFAULT_REDIS_PORT)),
+            password: url.password().map(str::to_string),
+            db,
+        })
+    }
+}
+
+/// A connection to Redis, speaking just enough of the [RESP](https://redis.io/docs/reference/protocol-spec/)
+/// protocol to send commands and read their replies
+pub(crate) struct RedisConnection<S> {
+    stream: BufReader<S>,
+}
+
+impl RedisConnection<TcpStream> {
+    pub(crate) async fn connect(config: &RedisConfig) -> Result<Self, Error> {
+        let stream = TcpStream::connect(&config.address).await.map_err(|e| {
+            Error::EventStoreError(format!(
+                "Failed to connect to Redis at {}: {e}",
+                config.address
+            ))
+        })?;
+        let mut connection = Self::new(stream);
+
+        if let Some(password) = &config.password {
+            connection.command(&[b"AUTH", password.as_bytes()]).await?;
+        }
+        if let Some(db) = config.db {
+            connection
+                .command(&[b"SELECT", db.to_string().as_bytes()])
+                .await?;
+        }
+
+        Ok(connection)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> RedisConnection<S> {
+    pub(crate) fn new(stream: S) -> Self {
+        Self {
+            stream: BufReader::new(stream),
+        }
+    }
+
+    /// Sends a command and waits for its reply, returning an error if Redis replies with one
+    pub(crate) async fn command(&mut self, args: &[&[u8]]) -> Result<RedisValue, Error> {
+        self.send(args).await?;
+        self.read_value().await
+    }
+
+    async fn send(&mut self, args: &[&[u8]]) -> Result<(), Error> {
+        let mut request = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            request.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+            request.extend_from_slice(arg);
+            request.extend_from_slice(b"\r\n");
+        }
+
+        self.stream
+            .get_mut()
+            .write_all(&request)
+            .await
+            .map_err(io_error)
+    }
+
+    // Arrays contain nested values, so reading is boxed to allow recursion
+    fn read_value(
+        &mut self,
+    ) -> Pin<Box<dyn Future<Output = Result<RedisValue, Error>> + Send + '_>> {
+        Box::pin(async move {
+            let line = self.read_line().await?;
+            let unexpected =
+                || Error::EventStoreError(format!("Unexpected reply from Redis: {line}"));
+            // The type is a single ASCII character, so anything else can't be split after it
+            let (kind, rest) = match line.is_char_boundary(1) {
+                true => line.split_at(1),
+                false => return Err(unexpected()),
+            };
+
+            match kind {
+                "+" => Ok(RedisValue::Status(rest.to_string())),
+                "-" => Err(Error::EventStoreError(format!("Redis error: {rest}"))),
+                ":" => Ok(RedisValue::Integer(parse_integer(rest)?)),
+                "$" => match parse_integer(rest)? {
+                    -1 => Ok(RedisValue::Bulk(None)),
+                    length @ 0..=MAX_BULK_LENGTH => {
+                        let length = length as usize;
+                        // The value is followed by a CRLF
+                        let mut value = vec![0; length + 2];
+                        self.stream.read_exact(&mut value).await.map_err(io_error)?;
+                        if &value[length..] != b"\r\n" {
+                            return Err(unexpected());
+                        }
+                        value.truncate(length);
+                        Ok(RedisValue::Bulk(Some(value)))
+                    }
+                    _ => Err(unexpected()),
+                },
+                "*" => match parse_integer(rest)? {
+                    -1 => Ok(RedisValue::Array(None)),
+                    length @ 0.. => {
+                        let mut values =
+                            Vec::with_capacity((length as usize).min(MAX_ARRAY_PREALLOCATION));
+                        for _ in 0..length {
+                            values.push(self.read_value().await?);
+                        }
+                        Ok(RedisValue::Array(Some(values)))
+                    }
+                    _ => Err(unexpected()),
+                },
+                _ => Err(unexpected()),
+            }
+        })
+    }
+
+    async fn read_line(&mut self) -> Result<String, Error> {
+        let mut line = String::new();
+        self.stream.read_line(&mut line).await.map_err(io_error)?;
+
+        match line.strip_suffix("\r\n") {
+            Some(line) if !line.is_empty() => Ok(line.to_string()),
+            _ => Err(Error::EventStoreError(
+                "Redis closed the connection".to_string(),
+            )),
+        }
+    }
+}
+
+fn parse_integer(value: &str) -> Result<i64, Error> {
+    value
+        .parse()
+        .map_err(|_| Error::EventStoreError(format!("Unexpected reply from Redis: {value}")))
+}
+
+fn io_error(e: std::io::Error) -> Error {
+    Error::EventStoreError(format!("Redis connection failed: {e}"))
+}
+
+fn unexpected_reply(value: &RedisValue) -> Error {
+    Error::EventStoreError(format!("Unexpected reply from Redis: {value:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn sends_commands_and_reads_replies() {
+        let (client, mut server) = duplex(1024);
+        let mut connection = RedisConnection::new(client);
+
+        server
+            .write_all(b"*3\r\n$5\r\nfirst\r\n$-1\r\n:2\r\n")
+            .await
+            .unwrap();
+        let reply = connection
+            .command(&[b"LRANGE", b"events", b"0", b"1"])
+            .await
+            .unwrap();
+
+        let expected = b"*4\r\n$6\r\nLRANGE\r\n$6\r\nevents\r\n$1\r\n0\r\n$1\r\n1\r\n";
+        let mut request = vec![0; expected.len()];
+        server.read_exact(&mut request).await.unwrap();
+        assert_eq!(request, expected);
+        assert_eq!(
+            reply,
+            RedisValue::Array(Some(vec![
+                RedisValue::Bulk(Some(b"first".to_vec())),
+                RedisValue::Bulk(None),
+                RedisValue::Integer(2),
+            ]))
+        );
+    }
+
+    #[tokio::test]
+    async fn error_replies_are_errors() {
+        let (client, mut server) = duplex(1024);
+        let mut connection = RedisConnection::new(client);
+
+        server
+            .write_all(b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n")
+            .await
+            .unwrap();
+        let result = connection.command(&[b"LLEN", b"events"]).await;
+
+        assert!(
+            matches!(result, Err(Error::EventStoreError(message)) if message.contains("WRONGTYPE"))
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_lengths_are_errors() {
+        for reply in [
+            b"$-2\r\n".as_slice(),
+            b"*-5\r\n",
+            b"$9999999999\r\n",
+            b"$3\r\nabcdef\r\n",
+            b"\xc3\xa9\r\n",
+        ] {
+            let (client, mut server) = duplex(1024);
+            let mut connection = RedisConnection::new(client);
+
+            server.write_all(reply).await.unwrap();
+            let result = connection.command(&[b"LLEN", b"events"]).await;
+
+            assert!(
+                matches!(result, Err(Error::EventStoreError(_))),
+                "Expected an error for {reply:?}, got {result:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn parses_redis_urls() {
+        let config = RedisConfig::parse("redis://:secret@localhost:6380/2").unwrap();
+        assert_eq!(config.address, "localhost:6380");
+        assert_eq!(config.password.as_deref(), Some("secret"));
+        assert_eq!(config.db, Some(2));
+
+        let config = RedisConfig::parse("redis://redis.example.com").unwrap();
+        assert_eq!(config.address, "redis.example.com:6379");
+        assert_eq!(config.db, None);
+
+        assert!(RedisConfig::parse("http://localhost:6379").is_err());
+        assert!(RedisConfig::parse("redis://localhost/events").is_err());
+    }
+}
@@ -0,0 +1,313 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use serde_json::Value;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use super::redis_connection::{RedisConfig, RedisConnection, RedisValue};
+use crate::event_batch::EventBatch;
+use crate::event_store::AsyncEventStore;
+use crate::payload::{Payload, PayloadBuilder};
+use crate::Error;
+
+// How long a batch can be in flight before it is assumed lost and its events are re-queued, unless configured otherwise
+const DEFAULT_IN_FLIGHT_TIMEOUT: Duration = Duration::from_secs(600);
+
+// Pushes an event unless the list is full, returning 0 if it is
+const ADD_SCRIPT: &str = r#"
+if redis.call('LLEN', KEYS[1]) >= tonumber(ARGV[1]) then
+    return 0
+end
+return redis.call('RPUSH', KEYS[1], ARGV[2])
+"#;
+
+// Moves up to ARGV[1] events from the front of the list into the in-flight hash, as one batch
+// under the id ARGV[2], stamped with the time it was taken, ARGV[3]
+const TAKE_BATCH_SCRIPT: &str = r#"
+local events = redis.call('LRANGE', KEYS[1], 0, tonumber(ARGV[1]) - 1)
+if #events > 0 then
+    redis.call('LTRIM', KEYS[1], #events, -1)
+    redis.call('HSET', KEYS[2], ARGV[2], cjson.encode({taken_at = tonumber(ARGV[3]), events = events}))
+end
+return events
+"#;
+
+// Moves the events of batches taken at or before ARGV[1] back to the front of the list, in their
+// original order, returning the number of events re-queued
+const RECOVER_SCRIPT: &str = r#"
+local recovered = 0
+local entries = redis.call('HGETALL', KEYS[2])
+for i = 1, #entries, 2 do
+    local batch = cjson.decode(entries[i + 1])
+    if batch.taken_at <= tonumber(ARGV[1]) then
+        for j = #batch.events, 1, -1 do
+            redis.call('LPUSH', KEYS[1], batch.events[j])
+        end
+        redis.call('HDEL', KEYS[2], entries[i])
+        recovered = recovered + #batch.events
+    end
+end
+return recovered
+"#;
+
+/// An implementation of the [AsyncEventStore] trait, that queues events in a Redis list
+///
+/// Several instances of a service can share the same Redis list, so events added by one instance
+/// can be sent by any of them, and events survive an instance restarting. Batches are moved atomically
+/// from the front of the list into the `<key>:in_flight` hash, and kept there until
+/// [cleanup_after_send_attempt](AsyncEventStore::cleanup_after_send_attempt) is called. If an instance
+/// stops before then, any store sharing the list moves the batch's events back to the front of the list
+/// once it has been in flight for longer than the [in_flight_timeout](RedisEventStore::in_flight_timeout).
+///
+/// Events are serialized with [PayloadBuilder::to_json], which keeps their [Priority](crate::Priority) and expiry time,
+/// but batches are taken in the order events were added rather than by priority.
+/// Use it on a [BatchEmitter](crate::BatchEmitter) with
+/// [async_event_store](crate::BatchEmitterBuilder::async_event_store).
+///
+/// ## Example
+/// ```no_run
+/// use snowplow_tracker::{BatchEmitter, RedisEventStore};
+///
+/// let event_store = RedisEventStore::new("redis://localhost:6379", "snowplow:events", 10_000, 50).unwrap();
+/// let emitter = BatchEmitter::builder()
+///     .collector_url("https://collector.example.com")
+///     .async_event_store(event_store)
+///     .build()
+///     .unwrap();
+/// ```
+pub struct RedisEventStore {
+    config: RedisConfig,
+    key: String,
+    in_flight_key: String,
+    capacity: usize,
+    batch_size: usize,
+    in_flight_timeout: Duration,
+    // When stale in-flight batches were last re-queued
+    last_recovery: Option<Instant>,
+    // Connected on first use, and again after a failed command
+    connection: Mutex<Option<RedisConnection<TcpStream>>>,
+}
+
+impl RedisEventStore {
+    /// Creates a store using the list at `key`
+    ///
+    /// `redis_url` has the form `redis://[:password@]host[:port][/db]`. The connection is opened when the
+    /// store is first used.
+    pub fn new(
+        redis_url: &str,
+        key: &str,
+        capacity: usize,
+        batch_size: usize,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            config: RedisConfig::parse(redis_url)?,
+            key: key.to_string(),
+            in_flight_key: format!("{key}:in_flight"),
+            capacity,
+            batch_size,
+            in_flight_timeout: DEFAULT_IN_FLIGHT_TIMEOUT,
+            last_recovery: None,
+            connection: Mutex::new(None),
+        })
+    }
+
+    /// How long a batch can be in flight before its events are assumed lost and re-queued, 10 minutes by default
+    ///
+    /// This should be longer than a batch can take to send, including its retries, or its events may be sent twice.
+    pub fn in_flight_timeout(mut self, timeout: Duration) -> Self {
+        self.in_flight_timeout = timeout;
+        self
+    }
+
+    // Runs a Lua script, which Redis applies atomically
+    async fn eval(&self, script: &str, args: &[&[u8]]) -> Result<RedisValue, Error> {
+        let mut command: Vec<&[u8]> = vec![
+            b"EVAL",
+            script.as_bytes(),
+            b"2",
+            self.key.as_bytes(),
+            self.in_flight_key.as_bytes(),
+        ];
+        command.extend_from_slice(args);
+        self.command(&command).await
+    }
+
+    // Re-queues the events of batches left in flight by an instance that stopped, at most once per timeout
+    async fn recover_in_flight(&mut self) -> Result<(), Error> {
+        if self
+            .last_recovery
+            .is_some_and(|last| last.elapsed() < self.in_flight_timeout)
+        {
+            return Ok(());
+        }
+
+        let stale_before = unix_seconds(
+            SystemTime::now()
+                .checked_sub(self.in_flight_timeout)
+                .unwrap_or(UNIX_EPOCH),
+        )
+        .to_string();
+        let recovered = self
+            .eval(RECOVER_SCRIPT, &[stale_before.as_bytes()])
+            .await?
+            .into_integer()?;
+        if recovered > 0 {
+            log::warn!("Re-queued {recovered} events from batches that were never cleaned up");
+        }
+
+        self.last_recovery = Some(Instant::now());
+        Ok(())
+    }
+
+    async fn command(&self, args: &[&[u8]]) -> Result<RedisValue, Error> {
+        let mut connection = self.connection.lock().await;
+        let result = match connection.as_mut() {
+            Some(connection) => connection.command(args).await,
+            None => {
+                let mut connected = RedisConnection::connect(&self.config).await?;
+                let result = connected.command(args).await;
+                *connection = Some(connected);
+                result
+            }
+        };
+
+        Self::reset_on_error(&mut connection, result)
+    }
+
+    // A failed command may leave part of its reply unread, so the connection is not reused
+    fn reset_on_error<T>(
+        connection: &mut Option<RedisConnection<TcpStream>>,
+        result: Result<T, Error>,
+    ) -> Result<T, Error> {
+        if result.is_err() {
+            *connection = None;
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl AsyncEventStore for RedisEventStore {
+    async fn add(&mut self, payload: PayloadBuilder) -> Result<(), Error> {
+        let event = payload.to_json()?.to_string();
+        let capacity = self.capacity.to_string();
+        let pushed = self
+            .eval(ADD_SCRIPT, &[capacity.as_bytes(), event.as_bytes()])
+            .await?
+            .into_integer()?;
+
+        match pushed {
+            0 => Err(Error::QueueFull),
+            _ => Ok(()),
+        }
+    }
+
+    async fn len(&self) -> usize {
+        match self.command(&[b"LLEN", self.key.as_bytes()]).await {
+            Ok(len) => len.into_integer().unwrap_or(0) as usize,
+            Err(e) => {
+                log::error!("Failed to get event store length: {e}");
+                0
+            }
+        }
+    }
+
+    fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    async fn full_batch(&mut self) -> Result<EventBatch, Error> {
+        if self.len().await < self.batch_size {
+            return Err(Error::EventStoreError(
+                "Failed to get batch: Not enough events in the event store for a full batch"
+                    .to_string(),
+            ));
+        }
+        self.batch_of(self.batch_size).await
+    }
+
+    // Other instances may take events concurrently, so the batch can be smaller than `size`
+    async fn batch_of(&mut self, size: usize) -> Result<EventBatch, Error> {
+        if size == 0 {
+            return Err(Error::EventStoreError(
+                "Requested batch size must be greater than zero".to_string(),
+            ));
+        }
+
+        if let Err(e) = self.recover_in_flight().await {
+            log::error!("Failed to re-queue in-flight events: {e}");
+        }
+
+        let batch_id = Uuid::new_v4();
+        let size = size.to_string();
+        let taken_at = unix_seconds(SystemTime::now()).to_string();
+        let entries = self
+            .eval(
+                TAKE_BATCH_SCRIPT,
+                &[
+                    size.as_bytes(),
+                    batch_id.to_string().as_bytes(),
+                    taken_at.as_bytes(),
+                ],
+            )
+            .await?
+            .into_array()?;
+        if entries.is_empty() {
+            return Err(Error::EventStoreError("Event store is empty".to_string()));
+        }
+
+        let events = entries
+            .into_iter()
+            .map(|entry| {
+                let entry = entry.into_bytes()?.unwrap_or_default();
+                serde_json::from_slice::<Value>(&entry)
+                    .map_err(|e| Error::EventStoreError(format!("Invalid event in Redis: {e}")))
+            })
+            .collect::<Result<Vec<Value>, Error>>()?;
+
+        let payloads = events
+            .into_iter()
+            .map(|event| PayloadBuilder::from_json(event)?.finalise_payload())
+            .collect::<Result<Vec<Payload>, Error>>()?;
+
+        Ok(EventBatch::new(batch_id, payloads))
+    }
+
+    async fn clear(&mut self) -> Result<(), Error> {
+        self.command(&[b"DEL", self.key.as_bytes()]).await?;
+        Ok(())
+    }
+
+    async fn cleanup_after_send_attempt(&mut self, batch_id: Uuid) -> Result<(), Error> {
+        self.command(&[
+            b"HDEL",
+            self.in_flight_key.as_bytes(),
+            batch_id.to_string().as_bytes(),
+        ])
+        .await?;
+        Ok(())
+    }
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,211 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+use std::collections::VecDeque;
+
+use uuid::Uuid;
+
+use crate::event_batch::EventBatch;
+use crate::event_store::{EventStore, DEFAULT_BATCH_SIZE, DEFAULT_EVENT_STORE_CAPACITY};
+use crate::payload::{Payload, PayloadBuilder};
+use crate::Error;
+
+/// An implementation of the [EventStore] trait, that queues events in a fixed-size ring buffer
+///
+/// Unlike [InMemoryEventStore](crate::InMemoryEventStore), which rejects new events when full,
+/// this overwrites the oldest event in the store. Use it when losing old events under load is
+/// preferable to losing new ones.
+pub struct RingBufferEventStore {
+    ring: VecDeque<PayloadBuilder>,
+    capacity: usize,
+    batch_size: usize,
+    evicted_count: usize,
+}
+
+/// Provides an instance of [RingBufferEventStore], with the default batch size of 50, and a capacity of 10,000
+impl Default for RingBufferEventStore {
+    fn default() -> Self {
+        Self::new(DEFAULT_EVENT_STORE_CAPACITY, DEFAULT_BATCH_SIZE)
+    }
+}
+
+impl RingBufferEventStore {
+    /// Create a store that holds up to `capacity` events, sent in batches of `batch_size`
+    ///
+    /// Once `capacity` events are queued, each new event overwrites the oldest one.
+    pub fn new(capacity: usize, batch_size: usize) -> Self {
+        Self {
+            // `with_capacity` allocates `capacity` elements, to avoid later reallocation
+            ring: VecDeque::with_capacity(capacity),
+            capacity,
+            batch_size,
+            evicted_count: 0,
+        }
+    }
+
+    /// The number of events that have been overwritten because the store was full
+    pub fn evicted_count(&self) -> usize {
+        self.evicted_count
+    }
+
+    fn event_batch(&mut self, size: usize) -> Result<EventBatch, Error> {
+        if self.ring.is_empty() {
+            return Err(Error::EventStoreError("Event store is empty".to_string()));
+        }
+
+        let events_to_send: Vec<Payload> = self
+            .ring
+            .drain(0..size)
+            .map(|e| e.finalise_payload())
+            .collect::<Result<Vec<Payload>, Error>>()?;
+
+        Ok(EventBatch::new(Uuid::new_v4(), events_to_send))
+    }
+}
+
+impl EventStore for RingBufferEventStore {
+    fn add(&mut self, payload: PayloadBuilder) -> Result<(), Error> {
+        if self.capacity == 0 {
+            return Err(Error::EventStoreError(
+                "Event store has no capacity".to_string(),
+            ));
+        }
+
+        if self.ring.len() == self.capacity {
+            self.ring.pop_front();
+            self.evicted_count += 1;
+            log::debug!("Event store is full, oldest event evicted");
+        }
+        self.ring.push_back(payload);
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn full_batch(&mut self) -> Result<EventBatch, Error> {
+        if self.ring.len() < self.batch_size {
+            return Err(Error::EventStoreError(
+                "Failed to get batch: Not enough events in the event store for a full batch"
+                    .to_string(),
+            ));
+        }
+        self.event_batch(self.batch_size)
+    }
+
+    fn batch_of(&mut self, size: usize) -> Result<EventBatch, Error> {
+        if size == 0 {
+            return Err(Error::EventStoreError(
+                "Requested batch size must be greater than zero".to_string(),
+            ));
+        }
+        if size > self.ring.len() {
+            return Err(Error::EventStoreError(
+                "Requested batch size is greater than queue length".to_string(),
+            ));
+        }
+        self.event_batch(size)
+    }
+
+    fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    fn clear(&mut self) -> Result<(), Error> {
+        self.ring.clear();
+        Ok(())
+    }
+
+    // RingBufferEventStore doesn't need to do anything to clean up after a send attempt
+    fn cleanup_after_send_attempt(&mut self, _batch_id: Uuid) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn create_payloads(n: usize) -> Vec<PayloadBuilder> {
+        (0..n)
+            .map(|_| {
+                Payload::builder()
+                    .p("p".to_string())
+                    .tv("tv".to_string())
+                    .eid(uuid::Uuid::new_v4())
+                    .dtm("dtm".to_string())
+                    .stm("stm".to_string())
+                    .aid("aid".to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn overwrites_oldest_events_at_capacity() {
+        let mut event_store = RingBufferEventStore::new(3, 3);
+        let payloads = create_payloads(5);
+        let expected_eids: Vec<_> = payloads[2..].iter().map(|p| p.eid).collect();
+
+        for payload in payloads {
+            event_store.add(payload).unwrap();
+        }
+
+        assert_eq!(event_store.len(), 3);
+        let batch = event_store.full_batch().unwrap();
+        let eids: Vec<_> = batch.events.iter().map(|e| Some(e.eid)).collect();
+        assert_eq!(eids, expected_eids);
+    }
+
+    #[test]
+    fn evicted_count_increments_when_full() {
+        let mut event_store = RingBufferEventStore::new(2, 2);
+        let mut payloads = create_payloads(4).into_iter();
+
+        event_store.add(payloads.next().unwrap()).unwrap();
+        event_store.add(payloads.next().unwrap()).unwrap();
+        assert_eq!(event_store.evicted_count(), 0);
+
+        event_store.add(payloads.next().unwrap()).unwrap();
+        assert_eq!(event_store.evicted_count(), 1);
+
+        event_store.add(payloads.next().unwrap()).unwrap();
+        assert_eq!(event_store.evicted_count(), 2);
+        assert_eq!(event_store.len(), 2);
+    }
+
+    #[test]
+    fn batch_of_remaining_events() {
+        let mut event_store = RingBufferEventStore::new(10, 5);
+        for payload in create_payloads(3) {
+            event_store.add(payload).unwrap();
+        }
+
+        assert!(event_store.full_batch().is_err());
+        assert_eq!(event_store.batch_of(3).unwrap().events.len(), 3);
+        assert!(event_store.is_empty());
+    }
+
+    #[test]
+    fn batch_of_zero_is_an_error() {
+        let mut event_store = RingBufferEventStore::new(10, 5);
+        for payload in create_payloads(3) {
+            event_store.add(payload).unwrap();
+        }
+
+        assert!(event_store.batch_of(0).is_err());
+        assert_eq!(event_store.len(), 3);
+    }
+}
@@ -0,0 +1,106 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+use serde_json::Value;
+
+use crate::{Error, SelfDescribingJson};
+
+/// How a batch of events is encoded for the collector
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BatchFormat {
+    /// The whole batch as a single `payload_data` JSON body, as expected by the collector's POST endpoint
+    #[default]
+    Json,
+    /// Each event as its own form-encoded string of fields, as expected by the collector's GET endpoint
+    ///
+    /// Fields that aren't strings, such as numbers, are written as JSON.
+    Form,
+}
+
+impl BatchFormat {
+    /// Encodes the `payload_data` batch `payload`, returning one body for [Json](BatchFormat::Json),
+    /// or one per event for [Form](BatchFormat::Form)
+    pub fn encode(&self, payload: &SelfDescribingJson) -> Result<Vec<String>, Error> {
+        match self {
+            BatchFormat::Json => serde_json::to_string(payload)
+                .map(|body| vec![body])
+                .map_err(|e| Error::EmitterError(format!("Failed to serialize payload: {e}"))),
+            BatchFormat::Form => {
+                let events = payload.data.as_array().ok_or_else(|| {
+                    Error::EmitterError("Payload data is not an array of events".to_string())
+                })?;
+                events.iter().map(form_encode).collect()
+            }
+        }
+    }
+}
+
+fn form_encode(event: &Value) -> Result<String, Error> {
+    let fields = event
+        .as_object()
+        .ok_or_else(|| Error::EmitterError(format!("Event is not a JSON object: {event}")))?;
+
+    let mut encoded = form_urlencoded::Serializer::new(String::new());
+    for (key, value) in fields {
+        match value {
+            Value::Null => continue,
+            Value::String(value) => encoded.append_pair(key, value),
+            value => encoded.append_pair(key, &value.to_string()),
+        };
+    }
+    Ok(encoded.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn structured_event_batch() -> SelfDescribingJson {
+        SelfDescribingJson::new(
+            "iglu:com.snowplowanalytics.snowplow/payload_data/jsonschema/1-0-4",
+            json!([{
+                "e": "se",
+                "eid": "8e26f7b7-6a0c-4a07-a3d7-0a6d5f7d4c3e",
+                "se_ca": "shop",
+                "se_ac": "add to basket",
+                "se_va": 2,
+                "co": "{\"schema\":\"iglu:com.acme/cart/jsonschema/1-0-0\",\"data\":[]}",
+            }]),
+        )
+    }
+
+    #[test]
+    fn json_encodes_the_whole_batch() {
+        let payload = structured_event_batch();
+
+        let bodies = BatchFormat::Json.encode(&payload).unwrap();
+
+        assert_eq!(bodies.len(), 1);
+        let body: Value = serde_json::from_str(&bodies[0]).unwrap();
+        assert_eq!(body, serde_json::to_value(&payload).unwrap());
+    }
+
+    #[test]
+    fn form_encodes_each_event() {
+        let bodies = BatchFormat::Form.encode(&structured_event_batch()).unwrap();
+
+        assert_eq!(
+            bodies,
+            vec![
+                "co=%7B%22schema%22%3A%22iglu%3Acom.acme%2Fcart%2Fjsonschema%2F1-0-0%22%2C%22data%22%3A%5B%5D%7D\
+                &e=se&eid=8e26f7b7-6a0c-4a07-a3d7-0a6d5f7d4c3e&se_ac=add+to+basket&se_ca=shop&se_va=2"
+            ]
+        );
+    }
+}
@@ -0,0 +1,271 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+
+use crate::{Error, HttpClient, RequestContext, RequestErrorKind, SelfDescribingJson};
+
+/// A [HttpClient] implementation that writes batches to a file, rather than sending them to a collector.
+///
+/// Each batch is appended to the file as a line of JSON, the same body a collector would receive,
+/// and is treated as sent with a `200` status code. This suits air-gapped environments, where events
+/// are collected to files and shipped out-of-band. A batch that fails to write is retried like a
+/// failed request. Files are written on tokio's blocking thread pool, so writing doesn't hold up the
+/// emitter's runtime.
+///
+/// With [rotate_at](FileHttpClient::rotate_at), a full file is renamed to `<path>.<timestamp>`, and a
+/// new file is started at `path`, so rotated files can be shipped while events are still written.
+///
+/// ## Example
+/// ```no_run
+/// use snowplow_tracker::{BatchEmitter, FileHttpClient};
+///
+/// let emitter = BatchEmitter::builder()
+///     .collector_url("http://localhost:9090")
+///     .http_client(FileHttpClient::new("events.jsonl").rotate_at(10_000_000))
+///     .build()
+///     .unwrap();
+/// ```
+pub struct FileHttpClient {
+    path: PathBuf,
+    max_file_bytes: Option<u64>,
+    // Shared between clones, so batches sent at the same time don't interleave or rotate twice
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl FileHttpClient {
+    /// Creates a client writing batches to the file at `path`, which is created if it doesn't exist
+    pub fn new(path: impl AsRef<Path>) -> FileHttpClient {
+        FileHttpClient {
+            path: path.as_ref().to_path_buf(),
+            max_file_bytes: None,
+            write_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Rotate the file once writing a batch would take it over `max_bytes`
+    ///
+    /// A batch is never split across files, so a batch larger than `max_bytes` is written to a file of its own.
+    pub fn rotate_at(mut self, max_bytes: u64) -> Self {
+        self.max_file_bytes = Some(max_bytes);
+        self
+    }
+
+    // A client writing to the same file as this one, sharing its lock
+    fn shared(&self) -> FileHttpClient {
+        FileHttpClient {
+            path: self.path.clone(),
+            max_file_bytes: self.max_file_bytes,
+            write_lock: self.write_lock.clone(),
+        }
+    }
+
+    // Blocks on the file system, so is called from a blocking task
+
+    fn write_line(&self, line: &[u8]) -> std::io::Result<()> {
+        // A poisoned lock guards no data, so we can carry on using it
+        let _guard = self
+            .write_lock
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        if let Some(max_bytes) = self.max_file_bytes {
+            let file_bytes = match std::fs::metadata(&self.path) {
+                Ok(metadata) => metadata.len(),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+                Err(e) => return Err(e),
+            };
+            if file_bytes > 0 && file_bytes + line.len() as u64 > max_bytes {
+                self.rotate()?;
+            }
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(line)?;
+        file.flush()
+    }
+
+    // Renames the current file to `<path>.<timestamp>`, adding a counter if that name is taken
+    fn rotate(&self) -> std::io::Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since_the_epoch| since_the_epoch.as_millis())
+            .unwrap_or_default();
+
+        let mut rotated = self.rotated_path(&timestamp.to_string());
+        let mut counter = 1;
+        while rotated.exists() {
+            rotated = self.rotated_path(&format!("{timestamp}-{counter}"));
+            counter += 1;
+        }
+
+        log::debug!("Rotating {} to {}", self.path.display(), rotated.display());
+        std::fs::rename(&self.path, rotated)
+    }
+
+    fn rotated_path(&self, suffix: &str) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{suffix}"));
+        path.into()
+    }
+}
+
+#[async_trait]
+impl HttpClient for FileHttpClient {
+    async fn post(
+        &self,
+        payload: SelfDescribingJson,
+        context: RequestContext,
+    ) -> Result<u16, Error> {
+        let mut line = serde_json::to_vec(&payload)
+            .map_err(|e| Error::EmitterError(format!("Failed to serialize payload: {e}")))?;
+        line.push(b'\n');
+
+        let writer = self.shared();
+        let result = tokio::task::spawn_blocking(move || writer.write_line(&line))
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+            .and_then(|result| result);
+        result.map_err(|e| {
+            Error::RequestError(
+                RequestErrorKind::Other,
+                format!(
+                    "Failed to write batch {} to {}: {e}",
+                    context.batch_id,
+                    self.path.display()
+                ),
+            )
+        })?;
+
+        Ok(200)
+    }
+
+    fn clone(&self) -> Box<dyn HttpClient + Send + Sync> {
+        Box::new(self.shared())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{BatchEmitter, InMemoryEventStore, StructuredEvent, Tracker};
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!("snowplow-batches-{}.jsonl", Uuid::new_v4()))
+    }
+
+    fn context() -> RequestContext {
+        RequestContext {
+            batch_id: Uuid::new_v4(),
+            attempt: 1,
+        }
+    }
+
+    #[test]
+    fn tracked_events_are_written_to_the_file() {
+        let path = temp_path();
+        let emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:9090")
+            .event_store(InMemoryEventStore::new(10, 2))
+            .http_client(FileHttpClient::new(&path))
+            .build()
+            .unwrap();
+        let mut tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(emitter)
+            .build()
+            .unwrap();
+
+        for action in ["first", "second"] {
+            let event = StructuredEvent::builder()
+                .category("category")
+                .action(action)
+                .build()
+                .unwrap();
+            tracker.track(event, None).unwrap();
+        }
+        tracker.close_emitter().unwrap();
+        // Dropping the tracker waits for the batch to be written
+        drop(tracker);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 1);
+        let batch: Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(
+            batch["schema"],
+            "iglu:com.snowplowanalytics.snowplow/payload_data/jsonschema/1-0-4"
+        );
+        let actions: Vec<&Value> = batch["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|event| &event["se_ac"])
+            .collect();
+        assert_eq!(actions, vec!["first", "second"]);
+    }
+
+    #[tokio::test]
+    async fn full_files_are_rotated() {
+        let path = temp_path();
+        let client = FileHttpClient::new(&path).rotate_at(100);
+        let batch = |n| SelfDescribingJson::new("iglu:com.acme/batch/jsonschema/1-0-0", json!(n));
+
+        for n in 0..3 {
+            assert_eq!(client.post(batch(n), context()).await.unwrap(), 200);
+        }
+
+        let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+        let mut rotated: Vec<PathBuf> = std::fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|entry| {
+                let name = entry.file_name().unwrap().to_string_lossy();
+                name.starts_with(&format!("{file_name}."))
+            })
+            .collect();
+        rotated.sort();
+        let current = std::fs::read_to_string(&path).unwrap();
+        let rotated_contents: Vec<String> = rotated
+            .iter()
+            .map(|rotated| std::fs::read_to_string(rotated).unwrap())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        for rotated in &rotated {
+            std::fs::remove_file(rotated).unwrap();
+        }
+
+        // Each line is 59 bytes, so only one fits in each file
+        assert_eq!(rotated_contents.len(), 2);
+        assert!(rotated_contents
+            .iter()
+            .all(|contents| contents.lines().count() == 1));
+        assert_eq!(
+            current,
+            "{\"schema\":\"iglu:com.acme/batch/jsonschema/1-0-0\",\"data\":2}\n"
+        );
+    }
+}
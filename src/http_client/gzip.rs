@@ -0,0 +1,325 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+//! A minimal gzip encoder for request bodies.
+//!
+//! Bodies are compressed as a single DEFLATE block using the fixed Huffman codes (RFC 1951 3.2.6)
+//! and a hash-chain LZ77 match finder. This doesn't reach the ratio of a full DEFLATE implementation,
+//! but the repetitive JSON of an event batch still compresses well.
+
+const WINDOW_SIZE: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Compress `data` into a gzip stream
+pub(crate) fn gzip(data: &[u8]) -> Vec<u8> {
+    // Header: magic, DEFLATE method, no flags, no mtime, no extra flags, unknown OS
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+
+    let mut writer = BitWriter::new(out);
+    // A single, final block using the fixed Huffman codes
+    writer.write_bits(1, 1);
+    writer.write_bits(1, 2);
+    deflate_fixed(data, &mut writer);
+    write_literal_length(&mut writer, 256);
+    out = writer.finish();
+
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+fn deflate_fixed(data: &[u8], writer: &mut BitWriter) {
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; data.len()];
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let (length, distance) = longest_match(data, pos, &head, &prev);
+
+        if length >= MIN_MATCH {
+            write_match(writer, length, distance);
+        } else {
+            write_literal_length(writer, data[pos] as u16);
+        }
+
+        // Every position covered is added to the hash chains, so later matches can refer back to it
+        for p in pos..pos + length.max(1) {
+            if p + MIN_MATCH <= data.len() {
+                let hash = hash(&data[p..]);
+                prev[p] = head[hash];
+                head[hash] = p;
+            }
+        }
+        pos += length.max(1);
+    }
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let value = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+    (value.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+fn longest_match(data: &[u8], pos: usize, head: &[usize], prev: &[usize]) -> (usize, usize) {
+    if pos + MIN_MATCH > data.len() {
+        return (0, 0);
+    }
+
+    let max_length = MAX_MATCH.min(data.len() - pos);
+    let mut best = (0, 0);
+    let mut candidate = head[hash(&data[pos..])];
+    let mut chain = 0;
+
+    while candidate != usize::MAX && pos - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+        let length = data[candidate..]
+            .iter()
+            .zip(&data[pos..pos + max_length])
+            .take_while(|(a, b)| a == b)
+            .count();
+        if length > best.0 {
+            best = (length, pos - candidate);
+            if length == max_length {
+                break;
+            }
+        }
+        candidate = prev[candidate];
+        chain += 1;
+    }
+
+    best
+}
+
+fn write_match(writer: &mut BitWriter, length: usize, distance: usize) {
+    let code = LENGTH_BASE
+        .iter()
+        .rposition(|&base| base as usize <= length)
+        .unwrap();
+    write_literal_length(writer, 257 + code as u16);
+    writer.write_bits(
+        (length - LENGTH_BASE[code] as usize) as u32,
+        LENGTH_EXTRA[code],
+    );
+
+    let code = DISTANCE_BASE
+        .iter()
+        .rposition(|&base| base as usize <= distance)
+        .unwrap();
+    writer.write_huffman(code as u32, 5);
+    writer.write_bits(
+        (distance - DISTANCE_BASE[code] as usize) as u32,
+        DISTANCE_EXTRA[code],
+    );
+}
+
+fn write_literal_length(writer: &mut BitWriter, value: u16) {
+    let value = value as u32;
+    match value {
+        0..=143 => writer.write_huffman(0x30 + value, 8),
+        144..=255 => writer.write_huffman(0x190 + value - 144, 9),
+        256..=279 => writer.write_huffman(value - 256, 7),
+        _ => writer.write_huffman(0xc0 + value - 280, 8),
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb88320 & mask);
+        }
+    }
+    !crc
+}
+
+// Writes bits least-significant first, as DEFLATE requires
+struct BitWriter {
+    out: Vec<u8>,
+    buffer: u32,
+    count: u8,
+}
+
+impl BitWriter {
+    fn new(out: Vec<u8>) -> Self {
+        Self {
+            out,
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    fn write_bits(&mut self, value: u32, bits: u8) {
+        for i in 0..bits {
+            self.buffer |= ((value >> i) & 1) << self.count;
+            self.count += 1;
+            if self.count == 8 {
+                self.out.push(self.buffer as u8);
+                self.buffer = 0;
+                self.count = 0;
+            }
+        }
+    }
+
+    // Huffman codes are packed most-significant bit first
+    fn write_huffman(&mut self, code: u32, bits: u8) {
+        let reversed = code.reverse_bits() >> (32 - bits as u32);
+        self.write_bits(reversed, bits);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.buffer as u8);
+        }
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Decodes a gzip stream containing fixed Huffman blocks, to check the encoder round-trips
+    fn gunzip(data: &[u8]) -> Vec<u8> {
+        assert_eq!(&data[..3], &[0x1f, 0x8b, 8]);
+        let body = &data[10..data.len() - 8];
+        let mut bit = 0;
+        let mut read = |bits: usize| -> u32 {
+            let mut value = 0;
+            for i in 0..bits {
+                value |= (((body[bit / 8] >> (bit % 8)) & 1) as u32) << i;
+                bit += 1;
+            }
+            value
+        };
+        // Reads a Huffman code of `bits` bits, most-significant bit first
+        fn code(read: &mut impl FnMut(usize) -> u32, bits: usize) -> u32 {
+            (0..bits).fold(0, |acc, _| acc << 1 | read(1))
+        }
+
+        assert_eq!(read(1), 1);
+        assert_eq!(read(2), 1);
+
+        let mut out: Vec<u8> = Vec::new();
+        loop {
+            let mut value = code(&mut read, 7);
+            let symbol = if value <= 0x17 {
+                value + 256
+            } else {
+                value = value << 1 | read(1);
+                if (0x30..=0xbf).contains(&value) {
+                    value - 0x30
+                } else if (0xc0..=0xc7).contains(&value) {
+                    value - 0xc0 + 280
+                } else {
+                    (value << 1 | read(1)) - 0x190 + 144
+                }
+            };
+
+            match symbol {
+                0..=255 => out.push(symbol as u8),
+                256 => break,
+                _ => {
+                    let index = (symbol - 257) as usize;
+                    let length =
+                        LENGTH_BASE[index] as usize + read(LENGTH_EXTRA[index] as usize) as usize;
+                    let index = code(&mut read, 5) as usize;
+                    let distance = DISTANCE_BASE[index] as usize
+                        + read(DISTANCE_EXTRA[index] as usize) as usize;
+                    for _ in 0..length {
+                        out.push(out[out.len() - distance]);
+                    }
+                }
+            }
+        }
+
+        let trailer = &data[data.len() - 8..];
+        assert_eq!(trailer[..4], crc32(&out).to_le_bytes());
+        assert_eq!(trailer[4..], (out.len() as u32).to_le_bytes());
+        out
+    }
+
+    #[test]
+    fn matches_known_gzip_output() {
+        let input =
+            br#"[{"e":"pv","url":"https://example.com"},{"e":"pv","url":"https://example.com/a"}]"#;
+
+        // Checked with `gzip -d`, which decompresses it to `input`
+        let expected: [u8; 66] = [
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x8b, 0xae, 0x56, 0x4a,
+            0x55, 0xb2, 0x52, 0x2a, 0x28, 0x53, 0xd2, 0x51, 0x2a, 0x2d, 0xca, 0x01, 0x32, 0x33,
+            0x4a, 0x4a, 0x0a, 0x8a, 0xad, 0xf4, 0xf5, 0x53, 0x2b, 0x12, 0x73, 0x0b, 0x72, 0x52,
+            0xf5, 0x92, 0xf3, 0x73, 0x95, 0x6a, 0x75, 0x88, 0x51, 0xa6, 0x9f, 0xa8, 0x54, 0x1b,
+            0x0b, 0x00, 0x38, 0xd8, 0x20, 0x46, 0x51, 0x00, 0x00, 0x00,
+        ];
+
+        assert_eq!(gzip(input), expected);
+    }
+
+    #[test]
+    fn decodes_gzip_output_of_zlib() {
+        // `hello hello hello`, compressed by zlib as a fixed Huffman block, so the decoder used by
+        // the round trip tests is checked against another encoder
+        let compressed = [
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xcb, 0x48, 0xcd, 0xc9,
+            0xc9, 0x57, 0xc8, 0x40, 0x90, 0x00, 0x80, 0x88, 0xf9, 0xe5, 0x11, 0x00, 0x00, 0x00,
+        ];
+
+        assert_eq!(gunzip(&compressed), b"hello hello hello");
+    }
+
+    #[test]
+    fn crc32_matches_known_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    }
+
+    #[test]
+    fn round_trips() {
+        let inputs: Vec<Vec<u8>> = vec![
+            vec![],
+            b"a".to_vec(),
+            (0..=255).collect(),
+            "{\"e\":\"se\",\"se_ca\":\"shop\"}".repeat(500).into_bytes(),
+            (0..100_000u32).map(|i| (i * 7919 % 251) as u8).collect(),
+        ];
+
+        for input in inputs {
+            assert_eq!(gunzip(&gzip(&input)), input);
+        }
+    }
+
+    #[test]
+    fn compresses_repetitive_json() {
+        let input = "{\"e\":\"se\",\"se_ca\":\"shop\",\"se_ac\":\"add-to-basket\"}"
+            .repeat(100)
+            .into_bytes();
+
+        assert!(gzip(&input).len() < input.len() / 10);
+    }
+}
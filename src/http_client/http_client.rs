@@ -10,19 +10,101 @@
 // See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
 
 use async_trait::async_trait;
+use uuid::Uuid;
 
 use crate::payload::SelfDescribingJson;
 use crate::Error;
 
+/// Details of the batch being sent in a POST request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestContext {
+    /// The id of the batch, which stays the same across retries so can be used as an idempotency key
+    pub batch_id: Uuid,
+    /// Which attempt at sending the batch this is, starting at 1
+    pub attempt: u32,
+}
+
+/// The response from the collector to a POST request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollectorResponse {
+    /// The HTTP status code
+    pub status: u16,
+    /// The response body, useful for diagnosing why events were rejected
+    pub body: String,
+}
+
 /// A HttpClient is responsible for sending events to the collector.
 ///
 /// This is an async trait, using the [async_trait crate](https://crates.io/crates/async-trait).
 ///
 /// Implement this trait to use your own HttpClient implementation on an [Emitter](crate::Emitter).
+///
+/// [post](HttpClient::post) returns the HTTP status code of the collector's response,
+/// which the [BatchEmitter](crate::BatchEmitter) uses to decide whether to retry a batch.
+///
+/// ## Example
+/// ```
+/// use async_trait::async_trait;
+/// use snowplow_tracker::{BatchEmitter, Error, HttpClient, RequestContext, SelfDescribingJson};
+///
+/// struct AcceptAllClient;
+///
+/// #[async_trait]
+/// impl HttpClient for AcceptAllClient {
+///     async fn post(
+///         &self,
+///         _payload: SelfDescribingJson,
+///         _context: RequestContext,
+///     ) -> Result<u16, Error> {
+///         Ok(200)
+///     }
+///
+///     fn clone(&self) -> Box<dyn HttpClient + Send + Sync> {
+///         Box::new(AcceptAllClient)
+///     }
+/// }
+///
+/// let mut emitter = BatchEmitter::builder()
+///     .collector_url("http://localhost:9090")
+///     .http_client(AcceptAllClient)
+///     .build()
+///     .unwrap();
+/// # use snowplow_tracker::Emitter;
+/// # emitter.close().unwrap();
+/// ```
 #[async_trait]
 pub trait HttpClient {
     /// Send a [SelfDescribingJson] to the collector via POST
-    async fn post(&self, payload: SelfDescribingJson) -> Result<u16, Error>;
+    ///
+    /// The [RequestContext] identifies the batch being sent, e.g. for setting request headers
+    async fn post(
+        &self,
+        payload: SelfDescribingJson,
+        context: RequestContext,
+    ) -> Result<u16, Error>;
+    /// Send a [SelfDescribingJson] to the collector via POST, returning the status code and response body
+    ///
+    /// By default this calls [post](HttpClient::post) and returns an empty body
+    async fn post_with_response(
+        &self,
+        payload: SelfDescribingJson,
+        context: RequestContext,
+    ) -> Result<CollectorResponse, Error> {
+        let status = self.post(payload, context).await?;
+        Ok(CollectorResponse {
+            status,
+            body: String::new(),
+        })
+    }
     /// Duplicate the HttpClient
     fn clone(&self) -> Box<dyn HttpClient + Send + Sync>;
+    /// Change the collector URL used by later requests
+    ///
+    /// Used by [BatchEmitter::set_collector_url](crate::BatchEmitter::set_collector_url).
+    /// By default this returns an error, as the collector URL is not known to the trait.
+    fn set_collector_url(&mut self, _collector_url: &str) -> Result<(), Error> {
+        Err(Error::EmitterError(
+            "This HttpClient does not support changing the collector URL".to_string(),
+        ))
+    }
 }
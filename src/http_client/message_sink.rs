@@ -0,0 +1,160 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::{Error, HttpClient, RequestContext, SelfDescribingJson};
+
+/// A message bus, such as a Kafka topic or Pub/Sub topic, that batches of events can be published to
+///
+/// This is an async trait, using the [async_trait crate](https://crates.io/crates/async-trait).
+/// Implement it with the client library for your message bus, and use it on a
+/// [BatchEmitter](crate::BatchEmitter) with a [MessageSinkHttpClient].
+#[async_trait]
+pub trait MessageSink: Send + Sync {
+    /// Publish `message`, the serialized batch, with `key`, the id of the batch
+    ///
+    /// The key stays the same when a batch is retried, so it can be used to partition or deduplicate
+    /// batches. Return an error to have the batch retried.
+    async fn publish(&self, key: &str, message: Vec<u8>) -> Result<(), Error>;
+}
+
+/// A [HttpClient] implementation that publishes batches to a [MessageSink], rather than sending them to a collector.
+///
+/// Each batch is published as the JSON body a collector would receive, and is treated as sent with
+/// a `200` status code once published. This lets the tracker feed a pipeline that ingests from a
+/// message bus, keeping the emitter's event store and retries.
+///
+/// ## Example
+/// ```
+/// use async_trait::async_trait;
+/// use snowplow_tracker::{BatchEmitter, Error, MessageSink, MessageSinkHttpClient};
+///
+/// struct LoggingSink;
+///
+/// #[async_trait]
+/// impl MessageSink for LoggingSink {
+///     async fn publish(&self, key: &str, message: Vec<u8>) -> Result<(), Error> {
+///         println!("{key}: {}", String::from_utf8_lossy(&message));
+///         Ok(())
+///     }
+/// }
+///
+/// let mut emitter = BatchEmitter::builder()
+///     .collector_url("http://localhost:9090")
+///     .http_client(MessageSinkHttpClient::new(LoggingSink))
+///     .build()
+///     .unwrap();
+/// # use snowplow_tracker::Emitter;
+/// # emitter.close().unwrap();
+/// ```
+pub struct MessageSinkHttpClient {
+    sink: Arc<dyn MessageSink>,
+}
+
+impl MessageSinkHttpClient {
+    /// Creates a client publishing batches to `sink`
+    pub fn new(sink: impl MessageSink + 'static) -> Self {
+        Self {
+            sink: Arc::new(sink),
+        }
+    }
+}
+
+#[async_trait]
+impl HttpClient for MessageSinkHttpClient {
+    async fn post(
+        &self,
+        payload: SelfDescribingJson,
+        context: RequestContext,
+    ) -> Result<u16, Error> {
+        let message = serde_json::to_vec(&payload)
+            .map_err(|e| Error::EmitterError(format!("Failed to serialize payload: {e}")))?;
+
+        self.sink
+            .publish(&context.batch_id.to_string(), message)
+            .await?;
+        Ok(200)
+    }
+
+    fn clone(&self) -> Box<dyn HttpClient + Send + Sync> {
+        Box::new(MessageSinkHttpClient {
+            sink: self.sink.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{BatchEmitter, Emitter, InMemoryEventStore, Payload};
+
+    // The key and body of each published message
+    type Messages = Arc<Mutex<Vec<(String, Vec<u8>)>>>;
+
+    // A sink that keeps the messages published to it
+    #[derive(Default)]
+    struct InMemorySink {
+        messages: Messages,
+    }
+
+    #[async_trait]
+    impl MessageSink for InMemorySink {
+        async fn publish(&self, key: &str, message: Vec<u8>) -> Result<(), Error> {
+            self.messages
+                .lock()
+                .unwrap()
+                .push((key.to_string(), message));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn batches_are_published_to_the_sink() {
+        let sink = InMemorySink::default();
+        let messages = sink.messages.clone();
+        let mut emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:9090")
+            .event_store(InMemoryEventStore::new(10, 2))
+            .http_client(MessageSinkHttpClient::new(sink))
+            .build()
+            .unwrap();
+
+        for aid in ["first", "second"] {
+            let payload = Payload::builder()
+                .p("srv".to_string())
+                .tv("tv".to_string())
+                .eid(uuid::Uuid::new_v4())
+                .dtm("1".to_string())
+                .aid(aid.to_string());
+            emitter.add(payload).unwrap();
+        }
+        emitter.close_async().await.unwrap();
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 1);
+        let (key, message) = &messages[0];
+        assert!(uuid::Uuid::parse_str(key).is_ok());
+        let batch: Value = serde_json::from_slice(message).unwrap();
+        assert_eq!(
+            batch["schema"],
+            "iglu:com.snowplowanalytics.snowplow/payload_data/jsonschema/1-0-4"
+        );
+        assert_eq!(batch["data"][0]["aid"], "first");
+        assert_eq!(batch["data"][1]["aid"], "second");
+    }
+}
@@ -9,8 +9,19 @@
 // "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
 
+mod batch_format;
+mod file_http_client;
+mod gzip;
+#[allow(clippy::module_inception)]
 mod http_client;
+#[cfg(feature = "message-sink")]
+mod message_sink;
 mod reqwest_client;
 
-pub use http_client::HttpClient;
+pub use batch_format::BatchFormat;
+pub use file_http_client::FileHttpClient;
+pub use http_client::{CollectorResponse, HttpClient, RequestContext};
+#[cfg(feature = "message-sink")]
+pub use message_sink::{MessageSink, MessageSinkHttpClient};
 pub use reqwest_client::ReqwestClient;
+pub(crate) use reqwest_client::{request_error_kind, POST_CONTENT_TYPE, POST_PATH};
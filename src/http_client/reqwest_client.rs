@@ -9,43 +9,755 @@
 // "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
 
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+
 use async_trait::async_trait;
-use reqwest::Client;
+use reqwest::header::{CONTENT_ENCODING, CONTENT_TYPE};
+use reqwest::redirect::Policy;
+use reqwest::{Client, RequestBuilder};
+use uuid::Uuid;
 
-use crate::{Error, HttpClient, SelfDescribingJson};
+use super::gzip::gzip;
+use crate::{
+    BatchFormat, CollectorResponse, CollectorUrl, Error, HttpClient, RequestContext,
+    RequestErrorKind, SelfDescribingJson,
+};
 
-const POST_PATH: &str = "com.snowplowanalytics.snowplow/tp2";
+pub(crate) const POST_PATH: &str = "com.snowplowanalytics.snowplow/tp2";
+// The collector's GET endpoint, which takes a single form-encoded event
+const GET_PATH: &str = "i";
+// The content type expected by the collector's tp2 endpoint
+pub(crate) const POST_CONTENT_TYPE: &str = "application/json; charset=utf-8";
+// Lets collectors and proxies deduplicate retried batches
+const IDEMPOTENCY_KEY: &str = "Idempotency-Key";
 
 /// A [HttpClient] implementation useing the reqwest crate to send events to the collector.
 pub struct ReqwestClient {
     pub client: reqwest::Client,
     pub collector_url: String,
+    // Request bodies of at least this many bytes are gzipped, smaller bodies are sent uncompressed
+    gzip_min_bytes: Option<usize>,
+    // Query parameters added to every request, such as an API key required by a managed collector
+    query_params: Vec<(String, String)>,
+    // The `Content-Type` header of every POST request
+    content_type: String,
+    // Whether request and response bodies are logged at debug level
+    log_bodies: bool,
+    // How batches are encoded
+    batch_format: BatchFormat,
+    // The events already sent from batches that failed part way through, shared between clones
+    partial_batches: Arc<PartialBatches>,
 }
 
 impl ReqwestClient {
-    pub fn new(collector_url: &str) -> Box<ReqwestClient> {
+    /// Creates a client that sends events to `collector_url`, such as `"https://collector.example.com".parse()?`
+    pub fn new(collector_url: CollectorUrl) -> Box<ReqwestClient> {
         Box::new(ReqwestClient {
             client: Client::new(),
             collector_url: collector_url.to_string(),
+            gzip_min_bytes: None,
+            query_params: Vec::new(),
+            content_type: POST_CONTENT_TYPE.to_string(),
+            log_bodies: false,
+            batch_format: BatchFormat::default(),
+            partial_batches: Arc::default(),
         })
     }
+
+    /// Creates a client with explicit redirect handling.
+    ///
+    /// When `follow_redirects` is `false`, redirect responses from the collector are returned to the
+    /// [Emitter](crate::Emitter) as-is, rather than being followed.
+    pub fn with_redirect_policy(
+        collector_url: CollectorUrl,
+        follow_redirects: bool,
+    ) -> Result<Box<ReqwestClient>, Error> {
+        let policy = match follow_redirects {
+            true => Policy::default(),
+            false => Policy::none(),
+        };
+
+        let client = Client::builder()
+            .redirect(policy)
+            .build()
+            .map_err(|e| Error::EmitterError(format!("Failed to build HTTP client: {e}")))?;
+
+        Ok(Box::new(ReqwestClient {
+            client,
+            collector_url: collector_url.to_string(),
+            gzip_min_bytes: None,
+            query_params: Vec::new(),
+            content_type: POST_CONTENT_TYPE.to_string(),
+            log_bodies: false,
+            batch_format: BatchFormat::default(),
+            partial_batches: Arc::default(),
+        }))
+    }
+
+    /// Gzip request bodies of at least `min_bytes`, sending smaller bodies uncompressed
+    ///
+    /// Compressing small batches costs more CPU than it saves in bandwidth, so only larger bodies are compressed.
+    /// GET requests sent with [BatchFormat::Form] have no body, so are never compressed.
+    pub fn gzip_min_bytes(mut self, min_bytes: usize) -> Self {
+        self.gzip_min_bytes = Some(min_bytes);
+        self
+    }
+
+    /// Add the query parameters `params` to every request sent to the collector
+    ///
+    /// Some managed collectors require a parameter such as an API key on the tp2 endpoint
+    pub fn query_params(mut self, params: &[(&str, &str)]) -> Self {
+        self.query_params = params
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        self
+    }
+
+    /// Set the `Content-Type` header of requests
+    ///
+    /// By default, requests are sent with `application/json; charset=utf-8`, the `application/json` type
+    /// the collector's tp2 endpoint expects with its charset stated explicitly. The body is always JSON.
+    /// Some proxies and relays expect `text/plain`, as sent by the JavaScript tracker's beacon requests
+    /// to avoid CORS preflight requests.
+    ///
+    /// GET requests sent with [BatchFormat::Form] have no body, so no `Content-Type` is sent.
+    pub fn content_type(mut self, content_type: &str) -> Self {
+        self.content_type = content_type.to_string();
+        self
+    }
+
+    /// Log the JSON body of each request, and the body of the collector's response, at debug level
+    ///
+    /// This helps to diagnose events that fail validation in the pipeline. It is off by default, as
+    /// events may hold personal data that shouldn't be written to logs. Bodies are logged in full,
+    /// before any gzip compression.
+    pub fn log_bodies(mut self, enabled: bool) -> Self {
+        self.log_bodies = enabled;
+        self
+    }
+
+    /// Set how batches are encoded, which is [BatchFormat::Json] by default
+    ///
+    /// With [BatchFormat::Form], each event in a batch is sent as its own GET request to the collector's
+    /// `/i` endpoint, for collectors or proxies that only accept GET requests. A batch is sent until an
+    /// event fails, and a retry of the batch only sends the events that weren't sent before. GET requests
+    /// have no body, so [gzip_min_bytes](Self::gzip_min_bytes) and [content_type](Self::content_type)
+    /// don't apply to them.
+    pub fn batch_format(mut self, batch_format: BatchFormat) -> Self {
+        self.batch_format = batch_format;
+        self
+    }
+
+    // Builds a GET request for each event in the batch that isn't in `sent`, with the event's fields
+    // as query parameters, returning each with the event's id
+    fn get_requests(
+        &self,
+        payload: &SelfDescribingJson,
+        context: &RequestContext,
+        sent: &HashSet<String>,
+    ) -> Result<Vec<(Option<String>, RequestBuilder)>, Error> {
+        let collector_url = format!("{}/{}", self.collector_url, GET_PATH);
+        let extra_params = form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(&self.query_params)
+            .finish();
+
+        let events = BatchFormat::Form.encode(payload)?;
+        let event_ids = payload.data.as_array().into_iter().flatten().map(|event| {
+            event
+                .get("eid")
+                .and_then(|eid| eid.as_str())
+                .map(str::to_string)
+        });
+        let requests = event_ids
+            .zip(events)
+            .filter(|(eid, _)| eid.as_ref().map_or(true, |eid| !sent.contains(eid)))
+            .map(|(eid, event)| {
+                if self.log_bodies {
+                    log::debug!(
+                        "Sending event of batch {} to {collector_url}: {event}",
+                        context.batch_id
+                    );
+                }
+                let query = match extra_params.is_empty() {
+                    true => event,
+                    false => format!("{event}&{extra_params}"),
+                };
+                let request = self
+                    .client
+                    .get(format!("{collector_url}?{query}"))
+                    .header(IDEMPOTENCY_KEY, context.batch_id.to_string());
+                (eid, request)
+            })
+            .collect();
+        Ok(requests)
+    }
+
+    async fn send(
+        &self,
+        payload: &SelfDescribingJson,
+        context: &RequestContext,
+    ) -> Result<reqwest::Response, Error> {
+        match self.batch_format {
+            BatchFormat::Json => self
+                .post_request(payload, context)?
+                .send()
+                .await
+                .map_err(|e| {
+                    Error::RequestError(request_error_kind(&e), format!("POST request failed: {e}"))
+                }),
+            BatchFormat::Form => self.send_gets(payload, context).await,
+        }
+    }
+
+    // Sends a GET request for each event of a batch in turn, stopping at the first unsuccessful response,
+    // and returns the last response received. The events sent before a failure are remembered, so
+    // they are skipped when the batch is retried.
+    async fn send_gets(
+        &self,
+        payload: &SelfDescribingJson,
+        context: &RequestContext,
+    ) -> Result<reqwest::Response, Error> {
+        let mut sent = self.partial_batches.take(context.batch_id);
+        let requests = self.get_requests(payload, context, &sent)?;
+
+        let mut last_response = None;
+        for (eid, request) in requests {
+            let resp = match request.send().await {
+                Ok(resp) => resp,
+                Err(e) => {
+                    self.partial_batches.record(context.batch_id, sent);
+                    return Err(Error::RequestError(
+                        request_error_kind(&e),
+                        format!("GET request failed: {e}"),
+                    ));
+                }
+            };
+            let succeeded = resp.status().is_success();
+            last_response = Some(resp);
+            if !succeeded {
+                self.partial_batches.record(context.batch_id, sent);
+                break;
+            }
+            if let Some(eid) = eid {
+                sent.insert(eid);
+            }
+        }
+
+        last_response.ok_or_else(|| Error::EmitterError("Batch has no events to send".to_string()))
+    }
+
+    // Builds the POST request with explicit headers, rather than relying on reqwest's `.json()`,
+    // so the headers stay correct when the body is encoded differently.
+    //
+    // `Content-Encoding` is only set when the body is gzipped
+    fn post_request(
+        &self,
+        payload: &SelfDescribingJson,
+        context: &RequestContext,
+    ) -> Result<RequestBuilder, Error> {
+        let collector_url = format!("{}/{}", self.collector_url, POST_PATH);
+        let body = serde_json::to_vec(payload)
+            .map_err(|e| Error::EmitterError(format!("Failed to serialize payload: {e}")))?;
+
+        let request = self
+            .client
+            .post(&collector_url)
+            .query(&self.query_params)
+            .header(CONTENT_TYPE, &self.content_type)
+            .header(IDEMPOTENCY_KEY, context.batch_id.to_string());
+
+        if self.log_bodies {
+            log::debug!(
+                "Sending batch {} to {collector_url}: {}",
+                context.batch_id,
+                String::from_utf8_lossy(&body)
+            );
+        }
+
+        match self.gzip_min_bytes {
+            Some(min_bytes) if body.len() >= min_bytes => {
+                Ok(request.header(CONTENT_ENCODING, "gzip").body(gzip(&body)))
+            }
+            _ => Ok(request.body(body)),
+        }
+    }
+}
+
+// The number of partly sent batches whose sent events are remembered, beyond which the oldest are
+// forgotten, as batches the emitter gives up on are never sent again
+const MAX_PARTIAL_BATCHES: usize = 1000;
+
+// The ids of the events already sent from batches that failed part way through being sent as GET requests
+#[derive(Default)]
+struct PartialBatches {
+    batches: Mutex<VecDeque<(Uuid, HashSet<String>)>>,
+}
+
+impl PartialBatches {
+    // Removes and returns the events already sent from the batch
+    fn take(&self, batch_id: Uuid) -> HashSet<String> {
+        let mut batches = self.lock();
+        match batches.iter().position(|(id, _)| *id == batch_id) {
+            Some(index) => batches
+                .remove(index)
+                .map(|(_, sent)| sent)
+                .unwrap_or_default(),
+            None => HashSet::new(),
+        }
+    }
+
+    fn record(&self, batch_id: Uuid, sent: HashSet<String>) {
+        if sent.is_empty() {
+            return;
+        }
+        let mut batches = self.lock();
+        if batches.len() >= MAX_PARTIAL_BATCHES {
+            batches.pop_front();
+        }
+        batches.push_back((batch_id, sent));
+    }
+
+    // A poisoned lock only loses track of sent events, so we can carry on using it
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<(Uuid, HashSet<String>)>> {
+        self.batches
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// Works out why a request failed, by inspecting the chain of underlying errors
+pub(crate) fn request_error_kind(error: &reqwest::Error) -> RequestErrorKind {
+    if error.is_timeout() {
+        return RequestErrorKind::Timeout;
+    }
+
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(error);
+    while let Some(err) = source {
+        if let Some(io_err) = err.downcast_ref::<std::io::Error>() {
+            return match io_err.kind() {
+                std::io::ErrorKind::ConnectionRefused => RequestErrorKind::ConnectionRefused,
+                std::io::ErrorKind::TimedOut => RequestErrorKind::Timeout,
+                // A failed connection reports the OS error of its socket, while the resolver reports
+                // a failed lookup without one
+                _ if error.is_connect() && io_err.raw_os_error().is_none() => RequestErrorKind::Dns,
+                _ => RequestErrorKind::Other,
+            };
+        }
+
+        source = err.source();
+    }
+
+    RequestErrorKind::Other
 }
 
 #[async_trait]
 impl HttpClient for ReqwestClient {
-    async fn post(&self, payload: SelfDescribingJson) -> Result<u16, Error> {
-        let collector_url = format!("{}/{}", self.collector_url, POST_PATH);
+    async fn post(
+        &self,
+        payload: SelfDescribingJson,
+        context: RequestContext,
+    ) -> Result<u16, Error> {
+        let resp = self.send(&payload, &context).await?;
+        if !self.log_bodies {
+            return Ok(resp.status().as_u16());
+        }
+
+        let status = resp.status().as_u16();
+        // The status is what matters, so failing to read the body isn't an error
+        let body = resp.text().await.unwrap_or_else(|e| e.to_string());
+        log::debug!(
+            "Collector responded to batch {} with status {status}: {body}",
+            context.batch_id
+        );
+        Ok(status)
+    }
 
-        match self.client.post(&collector_url).json(&payload).send().await {
-            Ok(resp) => Ok(resp.status().as_u16()),
-            Err(e) => Err(Error::EmitterError(format!("POST request failed: {e}"))),
+    async fn post_with_response(
+        &self,
+        payload: SelfDescribingJson,
+        context: RequestContext,
+    ) -> Result<CollectorResponse, Error> {
+        let resp = self.send(&payload, &context).await?;
+        let status = resp.status().as_u16();
+        let body = resp.text().await.map_err(|e| {
+            Error::RequestError(
+                request_error_kind(&e),
+                format!("Failed to read response: {e}"),
+            )
+        })?;
+        if self.log_bodies {
+            log::debug!(
+                "Collector responded to batch {} with status {status}: {body}",
+                context.batch_id
+            );
         }
+
+        Ok(CollectorResponse { status, body })
     }
 
     fn clone(&self) -> Box<dyn HttpClient + Send + Sync> {
         Box::new(ReqwestClient {
             client: self.client.clone(),
             collector_url: self.collector_url.clone(),
+            gzip_min_bytes: self.gzip_min_bytes,
+            query_params: self.query_params.clone(),
+            content_type: self.content_type.clone(),
+            log_bodies: self.log_bodies,
+            batch_format: self.batch_format,
+            partial_batches: self.partial_batches.clone(),
         })
     }
+
+    fn set_collector_url(&mut self, collector_url: &str) -> Result<(), Error> {
+        self.collector_url = collector_url.to_string();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_utils::test_server;
+
+    // Starts a server that responds to a single request with the given status code, redirecting to an unreachable port
+    fn redirecting_server(code: u16) -> CollectorUrl {
+        test_server(vec![format!(
+            "HTTP/1.1 {code} Redirect\r\nLocation: http://127.0.0.1:1/\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        )])
+        .0
+        .parse()
+        .unwrap()
+    }
+
+    fn collector_url() -> CollectorUrl {
+        "http://localhost:9090".parse().unwrap()
+    }
+
+    fn context() -> RequestContext {
+        RequestContext {
+            batch_id: uuid::Uuid::new_v4(),
+            attempt: 1,
+        }
+    }
+
+    fn empty_payload() -> SelfDescribingJson {
+        SelfDescribingJson::new(
+            "iglu:com.snowplowanalytics.snowplow/payload_data/jsonschema/1-0-4",
+            json!([]),
+        )
+    }
+
+    #[tokio::test]
+    async fn returns_unfollowed_redirects() {
+        for code in [301, 308] {
+            let client =
+                ReqwestClient::with_redirect_policy(redirecting_server(code), false).unwrap();
+
+            assert_eq!(client.post(empty_payload(), context()).await.unwrap(), code);
+        }
+    }
+
+    #[tokio::test]
+    async fn dns_failure_error_kind() {
+        // The `.invalid` TLD is reserved, and guaranteed never to resolve
+        let client = ReqwestClient::new("http://collector.invalid".parse().unwrap());
+
+        match client.post(empty_payload(), context()).await {
+            Err(Error::RequestError(kind, _)) => assert_eq!(kind, RequestErrorKind::Dns),
+            other => panic!("Expected a DNS failure, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn connection_refused_error_kind() {
+        // Bind and drop a listener to find a port with nothing listening on it
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let client = ReqwestClient::new(format!("http://{addr}").parse().unwrap());
+
+        match client.post(empty_payload(), context()).await {
+            Err(Error::RequestError(kind, _)) => {
+                assert_eq!(kind, RequestErrorKind::ConnectionRefused)
+            }
+            other => panic!("Expected connection refused, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn follows_redirects() {
+        let client = ReqwestClient::with_redirect_policy(redirecting_server(308), true).unwrap();
+
+        // The redirect points to an unreachable port, so following it fails the request
+        assert!(client.post(empty_payload(), context()).await.is_err());
+    }
+
+    #[test]
+    fn post_request_sets_collector_headers() {
+        let client = ReqwestClient::new(collector_url());
+        let payload = empty_payload();
+
+        let request = client
+            .post_request(&payload, &context())
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            request.url().as_str(),
+            "http://localhost:9090/com.snowplowanalytics.snowplow/tp2"
+        );
+        assert_eq!(
+            request.headers().get(CONTENT_TYPE).unwrap(),
+            "application/json; charset=utf-8"
+        );
+        assert!(request.headers().get(CONTENT_ENCODING).is_none());
+        assert_eq!(
+            request.body().unwrap().as_bytes().unwrap(),
+            serde_json::to_vec(&payload).unwrap()
+        );
+    }
+
+    #[test]
+    fn post_request_uses_configured_content_type() {
+        let client = ReqwestClient::new(collector_url()).content_type("text/plain");
+        let payload = empty_payload();
+
+        let request = client
+            .post_request(&payload, &context())
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert_eq!(request.headers().get(CONTENT_TYPE).unwrap(), "text/plain");
+        assert_eq!(
+            request.body().unwrap().as_bytes().unwrap(),
+            serde_json::to_vec(&payload).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn post_returns_status_code() {
+        let (url, _) = test_server(vec![
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+        ]);
+        let client = ReqwestClient::new(url.parse().unwrap());
+
+        assert_eq!(client.post(empty_payload(), context()).await.unwrap(), 200);
+    }
+
+    #[tokio::test]
+    async fn bodies_are_logged_when_enabled() {
+        let (url, _) = test_server(vec![
+            "HTTP/1.1 400 Bad Request\r\nContent-Length: 19\r\nConnection: close\r\n\r\nunknown field: xyz1".to_string(),
+            "HTTP/1.1 200 OK\r\nContent-Length: 9\r\nConnection: close\r\n\r\nquiet-ok2".to_string(),
+        ]);
+        let logged_payload = SelfDescribingJson::new(
+            "iglu:com.snowplowanalytics.snowplow/payload_data/jsonschema/1-0-4",
+            json!([{"aid": "logged-body-app"}]),
+        );
+        let unlogged_payload = SelfDescribingJson::new(
+            "iglu:com.snowplowanalytics.snowplow/payload_data/jsonschema/1-0-4",
+            json!([{"aid": "unlogged-body-app"}]),
+        );
+
+        // Install the logger before anything is logged
+        crate::test_utils::captured_logs();
+
+        let client = ReqwestClient::new(url.parse().unwrap()).log_bodies(true);
+        assert_eq!(client.post(logged_payload, context()).await.unwrap(), 400);
+        let client = ReqwestClient::new(url.parse().unwrap());
+        assert_eq!(client.post(unlogged_payload, context()).await.unwrap(), 200);
+
+        let logs = crate::test_utils::captured_logs();
+        assert!(logs
+            .iter()
+            .any(|line| line.starts_with("DEBUG Sending batch")
+                && line.contains(r#"{"aid":"logged-body-app"}"#)));
+        assert!(logs
+            .iter()
+            .any(|line| line.contains("with status 400: unknown field: xyz1")));
+        assert!(!logs.iter().any(|line| line.contains("unlogged-body-app")));
+        assert!(!logs.iter().any(|line| line.contains("quiet-ok2")));
+    }
+
+    #[tokio::test]
+    async fn form_format_sends_each_event_as_a_get_request() {
+        let ok = "HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string();
+        let (url, received) = test_server(vec![ok.clone(), ok]);
+        let client = ReqwestClient::new(url.parse().unwrap())
+            .query_params(&[("api_key", "abc")])
+            .batch_format(BatchFormat::Form);
+        let payload = SelfDescribingJson::new(
+            "iglu:com.snowplowanalytics.snowplow/payload_data/jsonschema/1-0-4",
+            json!([{"e": "se", "se_ca": "shop"}, {"e": "se", "se_ca": "cart"}]),
+        );
+
+        assert_eq!(client.post(payload, context()).await.unwrap(), 200);
+
+        let request_lines: Vec<String> = received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|request| request.head.lines().next().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            request_lines,
+            vec![
+                "GET /i?e=se&se_ca=shop&api_key=abc HTTP/1.1",
+                "GET /i?e=se&se_ca=cart&api_key=abc HTTP/1.1"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn retried_form_batches_only_send_unsent_events() {
+        let ok = "HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string();
+        let error =
+            "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                .to_string();
+        let (url, received) = test_server(vec![ok.clone(), error, ok.clone(), ok]);
+        let client = ReqwestClient::new(url.parse().unwrap()).batch_format(BatchFormat::Form);
+        let payload = SelfDescribingJson::new(
+            "iglu:com.snowplowanalytics.snowplow/payload_data/jsonschema/1-0-4",
+            json!([{"eid": "a", "e": "pv"}, {"eid": "b", "e": "pv"}, {"eid": "c", "e": "pv"}]),
+        );
+        let batch_id = uuid::Uuid::new_v4();
+
+        let first = RequestContext {
+            batch_id,
+            attempt: 1,
+        };
+        assert_eq!(client.post(payload.clone(), first).await.unwrap(), 500);
+        // Retries may be sent by a clone of the client
+        let retry = RequestContext {
+            batch_id,
+            attempt: 2,
+        };
+        let cloned = HttpClient::clone(&client);
+        assert_eq!(cloned.post(payload, retry).await.unwrap(), 200);
+
+        let request_lines: Vec<String> = received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|request| request.head.lines().next().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            request_lines,
+            vec![
+                "GET /i?e=pv&eid=a HTTP/1.1",
+                "GET /i?e=pv&eid=b HTTP/1.1",
+                "GET /i?e=pv&eid=b HTTP/1.1",
+                "GET /i?e=pv&eid=c HTTP/1.1"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn query_params_are_sent_by_clones() {
+        let (url, received) = test_server(vec![
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+        ]);
+        let client = ReqwestClient::new(url.parse().unwrap()).query_params(&[("key", "abc 123")]);
+
+        let cloned = HttpClient::clone(&client);
+        assert_eq!(cloned.post(empty_payload(), context()).await.unwrap(), 200);
+
+        let request_line = received.lock().unwrap()[0]
+            .head
+            .lines()
+            .next()
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            request_line,
+            "POST /com.snowplowanalytics.snowplow/tp2?key=abc+123 HTTP/1.1"
+        );
+    }
+
+    #[tokio::test]
+    async fn post_with_response_returns_status_and_body() {
+        let body = "Invalid payload";
+        let (url, _) = test_server(vec![format!(
+            "HTTP/1.1 400 Bad Request\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )]);
+        let client = ReqwestClient::new(url.parse().unwrap());
+
+        let response = client
+            .post_with_response(empty_payload(), context())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            response,
+            CollectorResponse {
+                status: 400,
+                body: body.to_string()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn batch_id_is_sent_as_idempotency_key() {
+        let ok = "HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+        let (url, received) = test_server(vec![ok.to_string(), ok.to_string()]);
+        let client = ReqwestClient::new(url.parse().unwrap());
+        let batch_id = uuid::Uuid::new_v4();
+
+        for attempt in 1..=2 {
+            client
+                .post_with_response(empty_payload(), RequestContext { batch_id, attempt })
+                .await
+                .unwrap();
+        }
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        for request in received.iter() {
+            assert!(request
+                .head
+                .to_lowercase()
+                .contains(&format!("idempotency-key: {batch_id}")));
+        }
+    }
+
+    #[test]
+    fn only_bodies_over_gzip_threshold_are_compressed() {
+        let client = ReqwestClient::new(collector_url()).gzip_min_bytes(1000);
+
+        let small_payload = empty_payload();
+        let request = client
+            .post_request(&small_payload, &context())
+            .unwrap()
+            .build()
+            .unwrap();
+        assert!(request.headers().get(CONTENT_ENCODING).is_none());
+        assert_eq!(
+            request.body().unwrap().as_bytes().unwrap(),
+            serde_json::to_vec(&small_payload).unwrap()
+        );
+
+        let large_payload = SelfDescribingJson::new(
+            "iglu:com.snowplowanalytics.snowplow/payload_data/jsonschema/1-0-4",
+            json!(vec![json!({"e": "se", "se_ca": "shop"}); 100]),
+        );
+        let request = client
+            .post_request(&large_payload, &context())
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(request.headers().get(CONTENT_ENCODING).unwrap(), "gzip");
+        let body = request.body().unwrap().as_bytes().unwrap();
+        assert_eq!(&body[..2], &[0x1f, 0x8b]);
+        assert!(body.len() < serde_json::to_vec(&large_payload).unwrap().len());
+    }
 }
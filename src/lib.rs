@@ -59,23 +59,56 @@
 //! }
 //! ```
 
+mod collector_url;
+mod context;
 mod emitter;
 mod error;
 mod event;
 mod event_batch;
+mod event_id;
 mod event_store;
 mod http_client;
+#[cfg(feature = "micro")]
+mod micro_client;
 mod payload;
+mod priority;
+mod schema_validation;
 mod snowplow;
 mod subject;
+#[cfg(test)]
+mod test_utils;
+mod track_options;
 mod tracker;
 
-pub use emitter::{BatchEmitter, Emitter, RetryPolicy};
-pub use error::Error;
-pub use event::{ScreenViewEvent, SelfDescribingEvent, StructuredEvent, TimingEvent};
-pub use event_store::{EventStore, InMemoryEventStore};
-pub use http_client::{HttpClient, ReqwestClient};
-pub use payload::{Payload, PayloadBuilder, SelfDescribingJson};
+pub use collector_url::CollectorUrl;
+pub use context::{Context, Contexts, HostContext};
+pub use emitter::{
+    BatchEmitter, BatchEmitterBuilder, Emitter, EmitterConfig, FlushProgressCallback,
+    QueuePressure, RetryPolicy, StoreLevel, SyncEmitter,
+};
+pub use error::{Error, RequestErrorKind};
+pub use event::{
+    EcommerceTransactionEvent, ScreenViewEvent, SelfDescribingEvent, StructuredEvent,
+    StructuredEventLengthLimits, TimingEvent,
+};
+pub use event_id::{EventIdVersion, IdProvider};
+#[cfg(feature = "redis")]
+pub use event_store::RedisEventStore;
+pub use event_store::{
+    AsyncEventStore, BatchIdStrategy, EventStore, InMemoryEventStore, RingBufferEventStore,
+};
+pub use http_client::{
+    BatchFormat, CollectorResponse, FileHttpClient, HttpClient, RequestContext, ReqwestClient,
+};
+#[cfg(feature = "message-sink")]
+pub use http_client::{MessageSink, MessageSinkHttpClient};
+#[cfg(feature = "micro")]
+pub use micro_client::{MicroClient, MicroEvents};
+pub use payload::{EventKind, Payload, PayloadBuilder, SelfDescribingJson};
+pub use priority::Priority;
 pub use snowplow::Snowplow;
-pub use subject::Subject;
-pub use tracker::Tracker;
+pub use subject::{Subject, SubjectBuilder};
+pub use track_options::TrackOptions;
+pub use tracker::{
+    ContextLimitAction, DuplicateContextAction, Tracker, TrackerBuilder, TrackerSettings,
+};
@@ -0,0 +1,152 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use crate::http_client::request_error_kind;
+use crate::{Error, RequestErrorKind};
+
+/// A client for the REST API of [Snowplow Micro](https://docs.snowplow.io/docs/testing-debugging/snowplow-micro/),
+/// for checking which events reached the collector in integration tests
+///
+/// ## Example
+/// ```no_run
+/// # async fn run() -> Result<(), snowplow_tracker::Error> {
+/// use std::time::Duration;
+/// use snowplow_tracker::MicroClient;
+///
+/// let micro = MicroClient::new("http://localhost:9090");
+/// micro.wait_for_good(1, Duration::from_secs(10)).await?;
+///
+/// assert_eq!(micro.bad_count().await?, 0);
+/// # Ok(())
+/// # }
+/// ```
+pub struct MicroClient {
+    client: reqwest::Client,
+    micro_url: String,
+}
+
+/// The events that Micro has received
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MicroEvents {
+    /// Events that passed validation
+    Good,
+    /// Events that failed validation
+    Bad,
+}
+
+impl MicroEvents {
+    fn path(&self) -> &str {
+        match self {
+            MicroEvents::Good => "good",
+            MicroEvents::Bad => "bad",
+        }
+    }
+}
+
+impl MicroClient {
+    /// Creates a client for the Micro instance at `micro_url`, such as `http://localhost:9090`
+    pub fn new(micro_url: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            micro_url: micro_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// The number of good events Micro has received
+    pub async fn good_count(&self) -> Result<usize, Error> {
+        self.count("good").await
+    }
+
+    /// The number of bad events Micro has received
+    pub async fn bad_count(&self) -> Result<usize, Error> {
+        self.count("bad").await
+    }
+
+    /// The good or bad events Micro has received, as returned by its `/micro/good` or `/micro/bad` endpoint
+    pub async fn events(&self, events: MicroEvents) -> Result<Vec<Value>, Error> {
+        match self.get(events.path()).await? {
+            Value::Array(events) => Ok(events),
+            other => Err(Error::RequestError(
+                RequestErrorKind::Other,
+                format!("Unexpected response from Micro: {other}"),
+            )),
+        }
+    }
+
+    /// Waits until Micro has received at least `count` good events, returning an error after `timeout`
+    pub async fn wait_for_good(&self, count: usize, timeout: Duration) -> Result<(), Error> {
+        self.wait_for(MicroEvents::Good, count, timeout).await
+    }
+
+    /// Waits until Micro has received at least `count` good or bad events, returning an error after `timeout`
+    pub async fn wait_for(
+        &self,
+        events: MicroEvents,
+        count: usize,
+        timeout: Duration,
+    ) -> Result<(), Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.events(events).await?.len() >= count {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::RequestError(
+                    RequestErrorKind::Timeout,
+                    format!("Timed out waiting for {count} {} events", events.path()),
+                ));
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Discards all events Micro has received
+    pub async fn reset(&self) -> Result<(), Error> {
+        self.get("reset").await.map(|_| ())
+    }
+
+    // Micro's `/micro/all` endpoint returns the total, good and bad event counts
+    async fn count(&self, key: &str) -> Result<usize, Error> {
+        let counts = self.get("all").await?;
+        counts[key]
+            .as_u64()
+            .map(|count| count as usize)
+            .ok_or_else(|| {
+                Error::RequestError(
+                    RequestErrorKind::Other,
+                    format!("Unexpected response from Micro: {counts}"),
+                )
+            })
+    }
+
+    async fn get(&self, endpoint: &str) -> Result<Value, Error> {
+        let request_error = |e: reqwest::Error| {
+            Error::RequestError(
+                request_error_kind(&e),
+                format!("Request to Micro failed: {e}"),
+            )
+        };
+
+        self.client
+            .get(format!("{}/micro/{endpoint}", self.micro_url))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(request_error)?
+            .json()
+            .await
+            .map_err(request_error)
+    }
+}
@@ -9,24 +9,65 @@
 // "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
 
-use std::time::{SystemTime, SystemTimeError, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, SystemTimeError, UNIX_EPOCH};
 
 use derive_builder::Builder;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use serde_json::Value;
+use serde_json::{Map, Value};
 use uuid::Uuid;
 
+use crate::event::{SCREEN_VIEW_SCHEMA, TIMING_SCHEMA};
+use crate::EcommerceTransactionEvent;
 use crate::Error;
+use crate::Priority;
 use crate::StructuredEvent;
 use crate::Subject;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum EventType {
-    #[serde(rename(serialize = "se"))]
+    #[serde(rename = "se")]
     StructuredEvent,
-    #[serde(rename(serialize = "ue"))]
+    #[serde(rename = "ue")]
     SelfDescribingEvent,
+    #[serde(rename = "tr")]
+    EcommerceTransaction,
+}
+
+/// The kind of event a [Payload] holds, for logging or routing events by type
+///
+/// Screen view and timing events are sent as self-describing events, and are recognised by their schema.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum EventKind {
+    /// A [ScreenViewEvent](crate::ScreenViewEvent)
+    ScreenView,
+    /// A [TimingEvent](crate::TimingEvent)
+    Timing,
+    /// A [StructuredEvent]
+    Structured,
+    /// A [SelfDescribingEvent](crate::SelfDescribingEvent) with any other schema
+    SelfDescribing,
+    /// An [EcommerceTransactionEvent]
+    EcommerceTransaction,
+}
+
+impl EventKind {
+    // Any version of the screen view and timing schemas is recognised
+    fn from_schema(schema: &str) -> EventKind {
+        let without_version = |schema: &str| {
+            schema
+                .rsplit_once('/')
+                .map_or(schema, |(name, _)| name)
+                .to_string()
+        };
+
+        match without_version(schema) {
+            name if name == without_version(SCREEN_VIEW_SCHEMA) => EventKind::ScreenView,
+            name if name == without_version(TIMING_SCHEMA) => EventKind::Timing,
+            _ => EventKind::SelfDescribing,
+        }
+    }
 }
 
 #[derive(Builder, Serialize, Deserialize, Default, Clone, Debug)]
@@ -34,7 +75,7 @@ pub enum EventType {
 #[builder(pattern = "owned")]
 #[builder(setter(strip_option))]
 #[builder(build_fn(error = "Error"))]
-#[builder(derive(Clone))]
+#[builder(derive(Clone, Debug))]
 /// The final payload that is sent to the collector
 ///
 /// For more information, see the [Snowplow Tracker Protocol](https://docs.snowplow.io/docs/collecting-data/collecting-from-own-applications/snowplow-tracker-protocol)
@@ -42,20 +83,25 @@ pub struct Payload {
     p: String,
     tv: String,
     pub(crate) eid: Uuid,
-    dtm: String,
+    pub(crate) dtm: String,
     pub(crate) stm: String,
 
     #[builder(default)]
     e: Option<EventType>,
     aid: String,
 
+    /// The namespace of the tracker that sent the event
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tna: Option<String>,
+
     #[builder(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) ue_pr: Option<SelfDescribingEventData>,
 
     #[builder(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
-    co: Option<ContextData>,
+    pub(crate) co: Option<ContextData>,
 
     // Structured Event
     #[builder(default)]
@@ -63,17 +109,118 @@ pub struct Payload {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) structured_event: Option<StructuredEvent>,
 
-    // Subject
+    // Ecommerce Transaction Event
+    #[builder(default)]
+    #[serde(flatten)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) ecommerce_transaction: Option<EcommerceTransactionEvent>,
+
+    /// The [Subject] of the event
+    ///
+    /// The subject fields are flattened into top-level payload keys (`uid`, `tz`, `lang`, `ip`, `ua`,
+    /// `duid`, `tnuid` and `sid`), with unset fields omitted. The [Tracker](crate::Tracker) sets this
+    /// to the event subject merged with the tracker subject, so event-level fields take priority.
     #[builder(default)]
     #[serde(flatten)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) subject: Option<Subject>,
+
+    /// The [Priority] of the event, used to order events in the event store
+    ///
+    /// This is not sent to the collector, but is kept by [PayloadBuilder::to_json].
+    #[builder(default)]
+    #[serde(skip)]
+    pub(crate) priority: Priority,
+
+    /// The time after which the event is dropped rather than sent
+    ///
+    /// This is not sent to the collector, but is kept by [PayloadBuilder::to_json].
+    #[builder(default)]
+    #[serde(skip)]
+    pub(crate) expire_at: Option<SystemTime>,
+
+    /// Fields not modelled by the crate, set with [PayloadBuilder::raw] or kept from a payload
+    /// restored with [PayloadBuilder::from_json], sent as they are
+    #[builder(default)]
+    #[serde(flatten)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extra_fields: Option<Map<String, Value>>,
 }
 
 impl Payload {
     pub fn builder() -> PayloadBuilder {
         PayloadBuilder::default()
     }
+
+    /// The kind of event the payload holds, or `None` if the payload has no event type
+    pub fn event_kind(&self) -> Option<EventKind> {
+        match self.e.as_ref()? {
+            EventType::StructuredEvent => Some(EventKind::Structured),
+            EventType::EcommerceTransaction => Some(EventKind::EcommerceTransaction),
+            EventType::SelfDescribingEvent => Some(
+                self.self_describing_schema()
+                    .map_or(EventKind::SelfDescribing, |schema| {
+                        EventKind::from_schema(&schema)
+                    }),
+            ),
+        }
+    }
+
+    // A field set with `PayloadBuilder::raw` would be serialized twice if the crate also sets it
+    fn check_extra_fields(&self) -> Result<(), Error> {
+        let extra_fields = match &self.extra_fields {
+            Some(extra_fields) => extra_fields,
+            None => return Ok(()),
+        };
+        let known_fields = serde_json::to_value(Payload {
+            extra_fields: None,
+            ..self.clone()
+        })
+        .map_err(|e| Error::BuilderError(format!("Failed to serialize payload: {e}")))?;
+
+        match extra_fields
+            .keys()
+            .find(|key| known_fields.get(key.as_str()).is_some())
+        {
+            Some(key) => Err(Error::BuilderError(format!(
+                "Raw field {key} is already set by the tracker"
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    // Turns the payload back into a builder, so it can be added to an event store again
+    pub(crate) fn into_builder(self) -> PayloadBuilder {
+        PayloadBuilder {
+            p: Some(self.p),
+            tv: Some(self.tv),
+            eid: Some(self.eid),
+            dtm: Some(self.dtm),
+            stm: Some(self.stm),
+            e: Some(self.e),
+            aid: Some(self.aid),
+            tna: Some(self.tna),
+            ue_pr: Some(self.ue_pr),
+            co: Some(self.co),
+            structured_event: Some(self.structured_event),
+            ecommerce_transaction: Some(self.ecommerce_transaction),
+            subject: Some(self.subject),
+            priority: Some(self.priority),
+            expire_at: Some(self.expire_at),
+            extra_fields: Some(self.extra_fields),
+        }
+    }
+
+    // Payloads restored with `PayloadBuilder::from_json` keep `ue_pr` in its serialized form
+    fn self_describing_schema(&self) -> Option<String> {
+        if let Some(ue_pr) = &self.ue_pr {
+            return Some(ue_pr.data.schema.clone());
+        }
+
+        let ue_pr = self.extra_fields.as_ref()?.get("ue_pr")?.as_str()?;
+        let ue_pr: Value = serde_json::from_str(ue_pr).ok()?;
+        ue_pr["data"]["schema"].as_str().map(str::to_string)
+    }
 }
 
 impl PayloadBuilder {
@@ -82,26 +229,146 @@ impl PayloadBuilder {
             SystemTime::now()
                 .duration_since(UNIX_EPOCH)
                 .map_err(|e: SystemTimeError| {
-                    Error::BuilderError(format!("Failed to get current time: {}", e.to_string()))
+                    Error::BuilderError(format!("Failed to get current time: {e}"))
                 })?;
 
-        self.stm(since_the_epoch.as_millis().to_string()).build()
+        let payload = self.stm(since_the_epoch.as_millis().to_string()).build()?;
+        payload.check_extra_fields()?;
+        Ok(payload)
+    }
+
+    /// Sets a top-level payload field that the crate doesn't model, such as one added in a newer version
+    /// of the [Snowplow Tracker Protocol](https://docs.snowplow.io/docs/collecting-data/collecting-from-own-applications/snowplow-tracker-protocol)
+    ///
+    /// The field is serialized alongside the known fields. It can't replace a field the crate already sets,
+    /// such as `aid`, and [finalise_payload](PayloadBuilder::finalise_payload) returns an error if it does.
+    pub fn raw(mut self, key: &str, value: String) -> Self {
+        self.extra_fields
+            .get_or_insert(None)
+            .get_or_insert_with(Map::new)
+            .insert(key.to_string(), Value::String(value));
+        self
+    }
+
+    /// Serializes the payload, so it can be persisted and later restored with [PayloadBuilder::from_json]
+    ///
+    /// A [Priority] other than [Priority::Normal] is kept in a `priority` field, and an
+    /// [expire_at](PayloadBuilder::expire_at) time in an `expire_at` field, in milliseconds since the epoch.
+    pub fn to_json(&self) -> Result<Value, Error> {
+        let payload = self.clone().finalise_payload()?;
+        let priority = payload.priority;
+        let expire_at = payload
+            .expire_at
+            .map(|expire_at| {
+                expire_at.duration_since(UNIX_EPOCH).map_err(|e| {
+                    Error::BuilderError(format!("Payload expire_at is before the epoch: {e}"))
+                })
+            })
+            .transpose()?;
+        let mut json = serde_json::to_value(payload)
+            .map_err(|e| Error::BuilderError(format!("Failed to serialize payload: {e}")))?;
+
+        if let Value::Object(fields) = &mut json {
+            if priority != Priority::Normal {
+                fields.insert(PRIORITY_FIELD.to_string(), json!(priority));
+            }
+            if let Some(expire_at) = expire_at {
+                fields.insert(
+                    EXPIRE_AT_FIELD.to_string(),
+                    json!(expire_at.as_millis() as u64),
+                );
+            }
+        }
+        Ok(json)
+    }
+
+    /// Restores a payload from its serialized form, such as a [Payload] persisted with `serde_json`
+    ///
+    /// The event id and `dtm` are preserved, while `stm` is set again when the payload is sent.
+    /// Event-specific fields are kept as they were serialized.
+    pub fn from_json(payload: Value) -> Result<Self, Error> {
+        let mut fields = match payload {
+            Value::Object(fields) => fields,
+            other => {
+                return Err(Error::BuilderError(format!(
+                    "Expected a payload object, found {other}"
+                )))
+            }
+        };
+
+        let mut required = |key: &str| match fields.remove(key) {
+            Some(Value::String(value)) => Ok(value),
+            _ => Err(Error::BuilderError(format!(
+                "Payload field {key} is missing or not a string"
+            ))),
+        };
+
+        let eid = required("eid")?;
+        let mut builder = Self::default()
+            .p(required("p")?)
+            .tv(required("tv")?)
+            .eid(Uuid::parse_str(&eid).map_err(|e| {
+                Error::BuilderError(format!("Payload field eid is not a valid UUID: {e}"))
+            })?)
+            .dtm(required("dtm")?)
+            .aid(required("aid")?);
+
+        if let Ok(tna) = required("tna") {
+            builder = builder.tna(tna);
+        }
+        if let Some(e) = fields.remove("e").filter(|e| !e.is_null()) {
+            let event_type = serde_json::from_value(e)
+                .map_err(|e| Error::BuilderError(format!("Payload field e is invalid: {e}")))?;
+            builder = builder.e(event_type);
+        }
+        if let Some(priority) = fields.remove(PRIORITY_FIELD) {
+            let priority = serde_json::from_value(priority).map_err(|e| {
+                Error::BuilderError(format!("Payload field {PRIORITY_FIELD} is invalid: {e}"))
+            })?;
+            builder = builder.priority(priority);
+        }
+        if let Some(expire_at) = fields.remove(EXPIRE_AT_FIELD) {
+            let millis = expire_at.as_u64().ok_or_else(|| {
+                Error::BuilderError(format!(
+                    "Payload field {EXPIRE_AT_FIELD} is not a number of milliseconds"
+                ))
+            })?;
+            builder = builder.expire_at(UNIX_EPOCH + Duration::from_millis(millis));
+        }
+        fields.remove("stm");
+
+        if !fields.is_empty() {
+            builder = builder.extra_fields(fields);
+        }
+
+        Ok(builder)
     }
 }
 
+// The field `PayloadBuilder::to_json` keeps the priority of an event in
+const PRIORITY_FIELD: &str = "priority";
+// The field `PayloadBuilder::to_json` keeps the expiry time of an event in
+const EXPIRE_AT_FIELD: &str = "expire_at";
+
 #[derive(Deserialize, Clone, Debug)]
 pub struct SelfDescribingEventData {
     pub schema: String,
     pub data: SelfDescribingJson,
 }
 
+pub(crate) const DEFAULT_UNSTRUCT_EVENT_SCHEMA: &str =
+    "iglu:com.snowplowanalytics.snowplow/unstruct_event/jsonschema/1-0-0";
+
 impl SelfDescribingEventData {
     pub fn new(data: SelfDescribingJson) -> SelfDescribingEventData {
+        SelfDescribingEventData::with_schema(DEFAULT_UNSTRUCT_EVENT_SCHEMA, data)
+    }
+
+    /// Create event data wrapped in a custom schema, rather than the default `unstruct_event/jsonschema/1-0-0`
+    pub fn with_schema(schema: &str, data: SelfDescribingJson) -> SelfDescribingEventData {
         SelfDescribingEventData {
-            schema: String::from(
-                "iglu:com.snowplowanalytics.snowplow/unstruct_event/jsonschema/1-0-0",
-            ),
-            data: data,
+            schema: schema.to_string(),
+            data,
         }
     }
 }
@@ -124,7 +391,9 @@ impl Serialize for SelfDescribingEventData {
 }
 
 /// Self-describing JSON to be used mainly when creating context entities.
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Builder, Clone, Debug)]
+#[builder(setter(into))]
+#[builder(build_fn(validate = "Self::validate", error = "Error"))]
 pub struct SelfDescribingJson {
     /// A valid Iglu schema path.
     ///
@@ -138,24 +407,128 @@ pub struct SelfDescribingJson {
 }
 
 impl SelfDescribingJson {
+    /// Creates a SelfDescribingJson without validating the schema.
+    ///
+    /// Use [SelfDescribingJson::builder] to have the schema URI checked.
     pub fn new(schema: &str, data: Value) -> SelfDescribingJson {
         SelfDescribingJson {
             schema: schema.to_string(),
-            data: data,
+            data,
+        }
+    }
+
+    /// Creates a SelfDescribingJson from data serialized as a JSON string, such as one received from another system
+    ///
+    /// Returns an [Error::BuilderError] if `json` is not valid JSON, or `schema` is not a valid Iglu URI.
+    pub fn from_str(schema: &str, json: &str) -> Result<SelfDescribingJson, Error> {
+        validate_schema_uri(schema)?;
+        let data = serde_json::from_str(json)
+            .map_err(|e| Error::BuilderError(format!("Invalid JSON data for {schema}: {e}")))?;
+
+        Ok(SelfDescribingJson::new(schema, data))
+    }
+
+    /// Creates a builder which validates the schema URI on `build`.
+    ///
+    /// ## Example
+    /// ```
+    /// use snowplow_tracker::SelfDescribingJson;
+    /// use serde_json::json;
+    ///
+    /// let context = SelfDescribingJson::builder()
+    ///     .schema("iglu:org.schema/WebPage/jsonschema/1-0-0")
+    ///     .data(json!({"keywords": ["tester"]}))
+    ///     .build();
+    /// assert!(context.is_ok());
+    ///
+    /// let context = SelfDescribingJson::builder()
+    ///     .schema("org.schema/WebPage/1-0-0")
+    ///     .data(json!({"keywords": ["tester"]}))
+    ///     .build();
+    /// assert!(context.is_err());
+    /// ```
+    pub fn builder() -> SelfDescribingJsonBuilder {
+        SelfDescribingJsonBuilder::default()
+    }
+}
+
+impl SelfDescribingJsonBuilder {
+    fn validate(&self) -> Result<(), Error> {
+        match &self.schema {
+            Some(schema) => validate_schema_uri(schema),
+            // Missing fields are reported by the generated `build`
+            None => Ok(()),
         }
     }
 }
 
+/// Checks that a schema is a valid Iglu URI, of the format `iglu:{vendor}/{name}/{format}/{version}`,
+/// where version is a SchemaVer string such as `1-0-0`.
+pub(crate) fn validate_schema_uri(schema: &str) -> Result<(), Error> {
+    let invalid = |reason: &str| {
+        Err(Error::BuilderError(format!(
+            "Invalid schema URI \"{schema}\": {reason}"
+        )))
+    };
+
+    let path = match schema.strip_prefix("iglu:") {
+        Some(path) => path,
+        None => return invalid("must start with \"iglu:\""),
+    };
+
+    let parts: Vec<&str> = path.split('/').collect();
+    let (vendor, name, format, version) = match parts[..] {
+        [vendor, name, format, version] => (vendor, name, format, version),
+        _ => return invalid("must be of the format iglu:{vendor}/{name}/{format}/{version}"),
+    };
+
+    let is_identifier = |part: &str, extra: &[char]| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || extra.contains(&c))
+    };
+
+    if !is_identifier(vendor, &['.']) {
+        return invalid("vendor is empty or contains invalid characters");
+    }
+    if !is_identifier(name, &[]) {
+        return invalid("name is empty or contains invalid characters");
+    }
+    if !is_identifier(format, &[]) {
+        return invalid("format is empty or contains invalid characters");
+    }
+
+    let version_parts: Vec<&str> = version.split('-').collect();
+    let is_schema_ver = version_parts.len() == 3
+        && version_parts
+            .iter()
+            .all(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()));
+    if !is_schema_ver {
+        return invalid("version must be of the format {model}-{revision}-{addition}");
+    }
+
+    Ok(())
+}
+
 #[derive(Deserialize, Clone, Debug)]
 pub struct ContextData {
     pub schema: String,
     pub data: Vec<SelfDescribingJson>,
 }
 
+pub(crate) const DEFAULT_CONTEXTS_SCHEMA: &str =
+    "iglu:com.snowplowanalytics.snowplow/contexts/jsonschema/1-0-1";
+
 impl ContextData {
     pub fn new(data: Vec<SelfDescribingJson>) -> ContextData {
+        ContextData::with_schema(DEFAULT_CONTEXTS_SCHEMA, data)
+    }
+
+    /// Create context data wrapped in a custom contexts schema, rather than the default `contexts/jsonschema/1-0-1`
+    pub fn with_schema(schema: &str, data: Vec<SelfDescribingJson>) -> ContextData {
         ContextData {
-            schema: String::from("iglu:com.snowplowanalytics.snowplow/contexts/jsonschema/1-0-1"),
+            schema: schema.to_string(),
             data,
         }
     }
@@ -177,3 +550,295 @@ impl Serialize for ContextData {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restored_payloads_keep_their_event_kind() {
+        let payload = Payload::builder()
+            .p("pc".to_string())
+            .tv("tv".to_string())
+            .eid(Uuid::new_v4())
+            .dtm("1".to_string())
+            .aid("aid".to_string())
+            .e(EventType::SelfDescribingEvent)
+            .ue_pr(SelfDescribingEventData::new(SelfDescribingJson::new(
+                "iglu:com.snowplowanalytics.snowplow/timing/jsonschema/1-0-1",
+                json!({"category": "load", "variable": "map", "timing": 100}),
+            )));
+        assert_eq!(
+            payload.clone().finalise_payload().unwrap().event_kind(),
+            Some(EventKind::Timing)
+        );
+
+        let restored = PayloadBuilder::from_json(payload.to_json().unwrap()).unwrap();
+        assert_eq!(
+            restored.finalise_payload().unwrap().event_kind(),
+            Some(EventKind::Timing)
+        );
+    }
+
+    #[test]
+    fn builds_self_describing_json_with_valid_schema() {
+        let sdj = SelfDescribingJson::builder()
+            .schema("iglu:com.snowplowanalytics.snowplow/link_click/jsonschema/1-0-1")
+            .data(json!({"targetUrl": "http://example.com"}))
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            sdj.schema,
+            "iglu:com.snowplowanalytics.snowplow/link_click/jsonschema/1-0-1"
+        );
+        assert_eq!(sdj.data, json!({"targetUrl": "http://example.com"}));
+    }
+
+    #[test]
+    fn self_describing_json_builder_rejects_invalid_schemas() {
+        let invalid_schemas = [
+            "",
+            "com.acme/event/jsonschema/1-0-0",
+            "iglu:com.acme/event/jsonschema",
+            "iglu:com.acme/event/jsonschema/1-0-0/extra",
+            "iglu:/event/jsonschema/1-0-0",
+            "iglu:com.acme//jsonschema/1-0-0",
+            "iglu:com.acme/ev ent/jsonschema/1-0-0",
+            "iglu:com.acme/event/jsonschema/1-0",
+            "iglu:com.acme/event/jsonschema/1-0-x",
+            "iglu:com.acme/event/jsonschema/1.0.0",
+        ];
+
+        for schema in invalid_schemas {
+            let result = SelfDescribingJson::builder()
+                .schema(schema)
+                .data(json!({}))
+                .build();
+            assert!(result.is_err(), "{schema} should be invalid");
+        }
+    }
+
+    #[test]
+    fn self_describing_json_builder_requires_data() {
+        let err = SelfDescribingJson::builder()
+            .schema("iglu:com.acme/event/jsonschema/1-0-0")
+            .build()
+            .unwrap_err();
+
+        assert_eq!(err.to_string(), "Field not initialized: data");
+    }
+
+    fn payload_builder() -> PayloadBuilder {
+        Payload::builder()
+            .p("pc".to_string())
+            .tv("rust-test".to_string())
+            .eid(Uuid::new_v4())
+            .dtm("1".to_string())
+            .aid("app_id".to_string())
+    }
+
+    #[test]
+    fn subject_fields_are_flattened_into_payload_keys() {
+        let domain_user_id = Uuid::new_v4();
+        let network_user_id = Uuid::new_v4();
+        let session_user_id = Uuid::new_v4();
+        let event_subject = Subject::builder()
+            .user_id("event_user")
+            .timezone("Europe/London")
+            .language("en")
+            .ip_address("0.0.0.0")
+            .build()
+            .unwrap();
+        let tracker_subject = Subject::builder()
+            .user_id("tracker_user")
+            .language("fr")
+            .user_agent("Mozilla/Firefox")
+            .domain_user_id(domain_user_id)
+            .network_user_id(network_user_id)
+            .session_user_id(session_user_id)
+            .build()
+            .unwrap();
+
+        let payload = payload_builder()
+            .subject(event_subject.merge(tracker_subject))
+            .finalise_payload()
+            .unwrap();
+        let json = serde_json::to_value(payload).unwrap();
+
+        assert_eq!(json["uid"], "event_user");
+        assert_eq!(json["tz"], "Europe/London");
+        assert_eq!(json["lang"], "en");
+        assert_eq!(json["ip"], "0.0.0.0");
+        assert_eq!(json["ua"], "Mozilla/Firefox");
+        assert_eq!(json["duid"], domain_user_id.to_string());
+        assert_eq!(json["tnuid"], network_user_id.to_string());
+        assert_eq!(json["sid"], session_user_id.to_string());
+        assert!(json.get("subject").is_none());
+    }
+
+    #[test]
+    fn unset_subject_fields_are_omitted() {
+        let subject = Subject::builder().user_id("user").build().unwrap();
+
+        let payload = payload_builder()
+            .subject(subject)
+            .finalise_payload()
+            .unwrap();
+        let json = serde_json::to_value(payload).unwrap();
+
+        assert_eq!(json["uid"], "user");
+        for key in ["tz", "lang", "ip", "ua", "duid", "tnuid", "sid"] {
+            assert!(json.get(key).is_none(), "{key} should be omitted");
+        }
+    }
+
+    #[test]
+    fn tracker_namespace_is_serialized_as_tna() {
+        let payload = payload_builder()
+            .tna("ns".to_string())
+            .finalise_payload()
+            .unwrap();
+        let json = serde_json::to_value(payload).unwrap();
+
+        assert_eq!(json["tna"], "ns");
+        assert!(
+            serde_json::to_value(payload_builder().finalise_payload().unwrap())
+                .unwrap()
+                .get("tna")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn payload_is_restored_from_json() {
+        let subject = Subject::builder().user_id("user").build().unwrap();
+        let payload = payload_builder()
+            .e(EventType::StructuredEvent)
+            .tna("ns".to_string())
+            .subject(subject)
+            .co(ContextData::new(vec![SelfDescribingJson::new(
+                "iglu:com.acme/context/jsonschema/1-0-0",
+                json!({"a": 1}),
+            )]))
+            .finalise_payload()
+            .unwrap();
+        let json = serde_json::to_value(&payload).unwrap();
+
+        let restored = PayloadBuilder::from_json(json.clone())
+            .unwrap()
+            .finalise_payload()
+            .unwrap();
+        let mut restored_json = serde_json::to_value(&restored).unwrap();
+
+        assert_eq!(restored.eid, payload.eid);
+        restored_json["stm"] = json["stm"].clone();
+        assert_eq!(restored_json, json);
+    }
+
+    #[test]
+    fn self_describing_json_is_parsed_from_a_string() {
+        let sdj = SelfDescribingJson::from_str(
+            "iglu:com.acme/context/jsonschema/1-0-0",
+            r#"{"id": 1, "tags": ["a", "b"]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(sdj.schema, "iglu:com.acme/context/jsonschema/1-0-0");
+        assert_eq!(sdj.data, json!({"id": 1, "tags": ["a", "b"]}));
+    }
+
+    #[test]
+    fn malformed_json_strings_are_rejected() {
+        for json in [r#"{"id": 1"#, "", "not json"] {
+            let result =
+                SelfDescribingJson::from_str("iglu:com.acme/context/jsonschema/1-0-0", json);
+            assert!(
+                matches!(&result, Err(Error::BuilderError(message)) if message.starts_with("Invalid JSON data")),
+                "{json:?} was not rejected: {result:?}"
+            );
+        }
+
+        let result = SelfDescribingJson::from_str("com.acme/context", "{}");
+        assert!(matches!(result, Err(Error::BuilderError(_))));
+    }
+
+    #[test]
+    fn raw_fields_are_serialized_with_known_fields() {
+        let payload = payload_builder()
+            .e(EventType::StructuredEvent)
+            .raw("xyz", "custom".to_string())
+            .raw("abc", "other".to_string())
+            .finalise_payload()
+            .unwrap();
+
+        let json = serde_json::to_value(&payload).unwrap();
+        assert_eq!(json["xyz"], "custom");
+        assert_eq!(json["abc"], "other");
+        assert_eq!(json["e"], "se");
+    }
+
+    #[test]
+    fn raw_fields_cannot_replace_known_fields() {
+        let result = payload_builder()
+            .raw("aid", "other_app_id".to_string())
+            .finalise_payload();
+
+        assert!(matches!(result, Err(Error::BuilderError(_))));
+    }
+
+    #[test]
+    fn payload_builder_round_trips_through_json() {
+        let builder = payload_builder()
+            .e(EventType::SelfDescribingEvent)
+            .tna("ns".to_string());
+
+        let restored = PayloadBuilder::from_json(builder.to_json().unwrap()).unwrap();
+
+        assert_eq!(restored.eid, builder.eid);
+        assert_eq!(restored.tna, builder.tna);
+        assert!(matches!(
+            restored.e,
+            Some(Some(EventType::SelfDescribingEvent))
+        ));
+    }
+
+    #[test]
+    fn priority_round_trips_through_json() {
+        let builder = payload_builder().priority(Priority::High);
+
+        let json = builder.to_json().unwrap();
+        assert_eq!(json["priority"], "high");
+
+        let restored = PayloadBuilder::from_json(json).unwrap();
+        assert_eq!(restored.priority, Some(Priority::High));
+        assert!(restored.extra_fields.is_none());
+
+        let normal = payload_builder().to_json().unwrap();
+        assert!(normal.get("priority").is_none());
+    }
+
+    #[test]
+    fn expire_at_round_trips_through_json() {
+        let expire_at = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        let builder = payload_builder().expire_at(expire_at);
+
+        let json = builder.to_json().unwrap();
+        assert_eq!(json["expire_at"], 1_700_000_000_123u64);
+
+        let restored = PayloadBuilder::from_json(json).unwrap();
+        assert_eq!(restored.expire_at, Some(Some(expire_at)));
+        assert!(restored.extra_fields.is_none());
+
+        let unexpiring = payload_builder().to_json().unwrap();
+        assert!(unexpiring.get("expire_at").is_none());
+    }
+
+    #[test]
+    fn payload_without_required_fields_is_not_restored() {
+        let result = PayloadBuilder::from_json(json!({"p": "pc", "tv": "rust-test"}));
+
+        assert!(matches!(result, Err(Error::BuilderError(message)) if message.contains("eid")));
+        assert!(PayloadBuilder::from_json(json!("payload")).is_err());
+    }
+}
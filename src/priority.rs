@@ -0,0 +1,31 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+use serde::{Deserialize, Serialize};
+
+/// How important an event is, set when the event is tracked with [TrackOptions::priority](crate::TrackOptions::priority)
+///
+/// The [InMemoryEventStore](crate::InMemoryEventStore) batches higher priority events first, and a
+/// [BatchEmitter](crate::BatchEmitter) can apply a different retry policy to each priority with
+/// [BatchEmitterBuilder::priority_retry_policy](crate::BatchEmitterBuilder::priority_retry_policy).
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    /// Events that can wait until more important events have been sent, such as screen views
+    Low,
+    /// The priority of events tracked with [Tracker::track](crate::Tracker::track) (the default)
+    #[default]
+    Normal,
+    /// Events that should be sent first, such as purchases and errors
+    High,
+}
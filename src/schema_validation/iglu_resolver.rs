@@ -0,0 +1,179 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use super::json_schema;
+use crate::error::Error;
+use crate::payload::validate_schema_uri;
+
+// How long to wait for the registry, as schemas are fetched while an event is being tracked
+const FETCH_TIMEOUT: Duration = Duration::from_secs(2);
+
+// How long a schema that failed to fetch is rejected before it is fetched again
+const FAILED_FETCH_TTL: Duration = Duration::from_secs(60);
+
+/// Fetches JSON Schemas from an Iglu registry, caching each schema after it is first fetched
+///
+/// Schemas that fail to fetch are also cached for a minute, so a registry that is down or slow
+/// doesn't hold up every event tracked with the schema.
+pub(crate) struct IgluResolver {
+    registry_url: String,
+    timeout: Duration,
+    cache: Mutex<HashMap<String, Value>>,
+    // The error of each schema that failed to fetch, and when it was fetched
+    failed: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl IgluResolver {
+    /// Schemas are fetched from `{registry_url}/schemas/{vendor}/{name}/{format}/{version}`,
+    /// as for a static registry such as Iglu Central, or `{iglu_server_url}/api` for an Iglu Server
+    pub(crate) fn new(registry_url: &str) -> Self {
+        Self {
+            registry_url: registry_url.trim_end_matches('/').to_string(),
+            timeout: FETCH_TIMEOUT,
+            cache: Mutex::new(HashMap::new()),
+            failed: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The JSON Schema for an Iglu schema URI
+    pub(crate) fn schema(&self, schema_uri: &str) -> Result<Value, Error> {
+        validate_schema_uri(schema_uri)?;
+
+        if let Some(schema) = self
+            .cache
+            .lock()
+            .ok()
+            .and_then(|c| c.get(schema_uri).cloned())
+        {
+            return Ok(schema);
+        }
+
+        if let Ok(failed) = self.failed.lock() {
+            if let Some((error, fetched_at)) = failed.get(schema_uri) {
+                if fetched_at.elapsed() < FAILED_FETCH_TTL {
+                    return Err(Error::BuilderError(error.clone()));
+                }
+            }
+        }
+
+        match self.fetch(schema_uri) {
+            Ok(schema) => {
+                if let Ok(mut cache) = self.cache.lock() {
+                    cache.insert(schema_uri.to_string(), schema.clone());
+                }
+                if let Ok(mut failed) = self.failed.lock() {
+                    failed.remove(schema_uri);
+                }
+                Ok(schema)
+            }
+            Err(error) => {
+                if let Ok(mut failed) = self.failed.lock() {
+                    failed.insert(schema_uri.to_string(), (error.clone(), Instant::now()));
+                }
+                Err(Error::BuilderError(error))
+            }
+        }
+    }
+
+    fn fetch(&self, schema_uri: &str) -> Result<Value, String> {
+        let path = schema_uri.trim_start_matches("iglu:");
+        let url = format!("{}/schemas/{path}", self.registry_url);
+        log::debug!("Fetching schema {schema_uri} from {url}");
+
+        // The blocking client can't be used from within an async runtime, so the request is
+        // made on its own thread, in case the tracker is used from async code
+        let timeout = self.timeout;
+        let response = std::thread::spawn(move || {
+            reqwest::blocking::Client::builder()
+                .timeout(timeout)
+                .build()?
+                .get(url)
+                .send()
+                .and_then(|response| response.error_for_status())
+                .and_then(|response| response.json::<Value>())
+        })
+        .join()
+        .map_err(|_| format!("Failed to fetch schema {schema_uri}"))?;
+
+        let schema = response.map_err(|e| format!("Failed to fetch schema {schema_uri}: {e}"))?;
+        json_schema::check_supported(&schema)
+            .map_err(|reason| format!("Schema {schema_uri} can't be validated: {reason}"))?;
+        Ok(schema)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_utils::schema_registry;
+
+    #[test]
+    fn failed_fetches_are_cached() {
+        let (registry_url, requests) = schema_registry(vec![(
+            "com.acme/purchase/jsonschema/1-0-0",
+            json!({"type": "object"}),
+        )]);
+        let resolver = IgluResolver::new(&registry_url);
+
+        assert!(resolver
+            .schema("iglu:com.acme/unknown/jsonschema/1-0-0")
+            .is_err());
+        assert!(resolver
+            .schema("iglu:com.acme/unknown/jsonschema/1-0-0")
+            .is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        assert!(resolver
+            .schema("iglu:com.acme/purchase/jsonschema/1-0-0")
+            .is_ok());
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn schemas_with_unsupported_keywords_are_rejected() {
+        let (registry_url, _) = schema_registry(vec![(
+            "com.acme/purchase/jsonschema/1-0-0",
+            json!({"properties": {"id": {"type": "string", "format": "uuid"}}}),
+        )]);
+        let resolver = IgluResolver::new(&registry_url);
+
+        let error = resolver
+            .schema("iglu:com.acme/purchase/jsonschema/1-0-0")
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("unsupported keyword format at $.properties.id"));
+    }
+
+    #[test]
+    fn slow_registries_time_out() {
+        // A registry that accepts connections, but never responds
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut resolver = IgluResolver::new(&format!("http://{}", listener.local_addr().unwrap()));
+        resolver.timeout = Duration::from_millis(100);
+
+        let started = Instant::now();
+        assert!(resolver
+            .schema("iglu:com.acme/purchase/jsonschema/1-0-0")
+            .is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
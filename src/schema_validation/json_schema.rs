@@ -0,0 +1,311 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+use serde_json::Value;
+
+// The keywords that are checked by `validate`
+const SUPPORTED_KEYWORDS: [&str; 12] = [
+    "type",
+    "enum",
+    "properties",
+    "required",
+    "additionalProperties",
+    "items",
+    "minLength",
+    "maxLength",
+    "minimum",
+    "maximum",
+    "minItems",
+    "maxItems",
+];
+
+// Keywords that only describe the schema, so don't affect validation
+const ANNOTATION_KEYWORDS: [&str; 8] = [
+    "$schema",
+    "$id",
+    "$comment",
+    "self",
+    "title",
+    "description",
+    "default",
+    "examples",
+];
+
+// Checks that a JSON Schema only uses the keywords `validate` supports, returning the first unsupported keyword
+//
+// Schemas using other keywords, such as `pattern`, `format`, `$ref` or `oneOf`, would be only partly
+// checked, letting through data that fails validation in the pipeline, so they are rejected instead.
+pub(crate) fn check_supported(schema: &Value) -> Result<(), String> {
+    check_supported_at(schema, "$")
+}
+
+fn check_supported_at(schema: &Value, path: &str) -> Result<(), String> {
+    let schema = match schema {
+        Value::Object(schema) => schema,
+        _ => return Ok(()),
+    };
+
+    for (keyword, value) in schema {
+        if ANNOTATION_KEYWORDS.contains(&keyword.as_str()) {
+            continue;
+        }
+        if !SUPPORTED_KEYWORDS.contains(&keyword.as_str()) {
+            return Err(format!("unsupported keyword {keyword} at {path}"));
+        }
+
+        match (keyword.as_str(), value) {
+            ("properties", Value::Object(properties)) => {
+                for (field, property_schema) in properties {
+                    check_supported_at(property_schema, &format!("{path}.properties.{field}"))?;
+                }
+            }
+            ("additionalProperties", _) => {
+                check_supported_at(value, &format!("{path}.additionalProperties"))?
+            }
+            // Only a single schema for every item is supported, not a schema for each position
+            ("items", Value::Array(_)) => {
+                return Err(format!("unsupported array of items at {path}"));
+            }
+            ("items", _) => check_supported_at(value, &format!("{path}.items"))?,
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+// Validates an instance against a JSON Schema, returning the reason it is invalid if it doesn't match
+//
+// Only the keywords in `SUPPORTED_KEYWORDS` are checked, so schemas should be checked with
+// `check_supported` first.
+pub(crate) fn validate(schema: &Value, instance: &Value) -> Result<(), String> {
+    validate_at(schema, instance, "$")
+}
+
+fn validate_at(schema: &Value, instance: &Value, path: &str) -> Result<(), String> {
+    // `true` and `{}` accept anything, while `false` accepts nothing
+    let schema = match schema {
+        Value::Bool(true) => return Ok(()),
+        Value::Bool(false) => return Err(format!("{path} is not allowed")),
+        Value::Object(schema) => schema,
+        _ => return Ok(()),
+    };
+
+    if let Some(expected) = schema.get("type") {
+        let matches_type = match expected {
+            Value::String(expected) => is_type(instance, expected),
+            Value::Array(expected) => expected
+                .iter()
+                .filter_map(Value::as_str)
+                .any(|expected| is_type(instance, expected)),
+            _ => true,
+        };
+        if !matches_type {
+            return Err(format!("{path} should be of type {expected}"));
+        }
+    }
+
+    if let Some(Value::Array(allowed)) = schema.get("enum") {
+        if !allowed.contains(instance) {
+            return Err(format!(
+                "{path} should be one of {}",
+                Value::Array(allowed.clone())
+            ));
+        }
+    }
+
+    match instance {
+        Value::Object(object) => {
+            if let Some(Value::Array(required)) = schema.get("required") {
+                for field in required.iter().filter_map(Value::as_str) {
+                    if !object.contains_key(field) {
+                        return Err(format!("{path}.{field} is required"));
+                    }
+                }
+            }
+
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for (field, value) in object {
+                let field_path = format!("{path}.{field}");
+                match properties.and_then(|properties| properties.get(field)) {
+                    Some(property_schema) => validate_at(property_schema, value, &field_path)?,
+                    None => {
+                        if let Some(additional) = schema.get("additionalProperties") {
+                            validate_at(additional, value, &field_path)?;
+                        }
+                    }
+                }
+            }
+        }
+
+        Value::Array(items) => {
+            check_bound(schema, "minItems", items.len() as f64, path, |n, min| {
+                n >= min
+            })?;
+            check_bound(schema, "maxItems", items.len() as f64, path, |n, max| {
+                n <= max
+            })?;
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    validate_at(item_schema, item, &format!("{path}[{i}]"))?;
+                }
+            }
+        }
+
+        Value::String(string) => {
+            let length = string.chars().count() as f64;
+            check_bound(schema, "minLength", length, path, |n, min| n >= min)?;
+            check_bound(schema, "maxLength", length, path, |n, max| n <= max)?;
+        }
+
+        Value::Number(number) => {
+            if let Some(number) = number.as_f64() {
+                check_bound(schema, "minimum", number, path, |n, min| n >= min)?;
+                check_bound(schema, "maximum", number, path, |n, max| n <= max)?;
+            }
+        }
+
+        _ => (),
+    }
+
+    Ok(())
+}
+
+fn is_type(instance: &Value, expected: &str) -> bool {
+    match expected {
+        "null" => instance.is_null(),
+        "boolean" => instance.is_boolean(),
+        "object" => instance.is_object(),
+        "array" => instance.is_array(),
+        "string" => instance.is_string(),
+        "number" => instance.is_number(),
+        "integer" => {
+            instance.is_i64()
+                || instance.is_u64()
+                || instance.as_f64().is_some_and(|n| n.fract() == 0.0)
+        }
+        // Unknown types are not checked
+        _ => true,
+    }
+}
+
+// Checks a numeric keyword, such as `minimum`, against a value taken from the instance
+fn check_bound(
+    schema: &serde_json::Map<String, Value>,
+    keyword: &str,
+    value: f64,
+    path: &str,
+    within_bound: impl Fn(f64, f64) -> bool,
+) -> Result<(), String> {
+    match schema.get(keyword).and_then(Value::as_f64) {
+        Some(bound) if !within_bound(value, bound) => {
+            Err(format!("{path} does not meet {keyword} of {bound}"))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "sku": {"type": "string", "maxLength": 5},
+                "quantity": {"type": "integer", "minimum": 1},
+                "tags": {"type": "array", "items": {"enum": ["new", "sale"]}},
+                "note": {"type": ["string", "null"]}
+            },
+            "required": ["sku"],
+            "additionalProperties": false
+        })
+    }
+
+    #[test]
+    fn valid_instance() {
+        let instance = json!({"sku": "ab-1", "quantity": 2, "tags": ["sale"], "note": null});
+        assert_eq!(validate(&schema(), &instance), Ok(()));
+    }
+
+    #[test]
+    fn invalid_instances() {
+        let cases = [
+            (json!({"quantity": 2}), "$.sku is required"),
+            (json!({"sku": 1}), "$.sku should be of type \"string\""),
+            (
+                json!({"sku": "abcdef"}),
+                "$.sku does not meet maxLength of 5",
+            ),
+            (
+                json!({"sku": "a", "quantity": 0}),
+                "$.quantity does not meet minimum of 1",
+            ),
+            (
+                json!({"sku": "a", "quantity": 1.5}),
+                "$.quantity should be of type \"integer\"",
+            ),
+            (
+                json!({"sku": "a", "tags": ["old"]}),
+                "$.tags[0] should be one of [\"new\",\"sale\"]",
+            ),
+            (
+                json!({"sku": "a", "colour": "red"}),
+                "$.colour is not allowed",
+            ),
+        ];
+
+        for (instance, reason) in cases {
+            assert_eq!(validate(&schema(), &instance), Err(reason.to_string()));
+        }
+    }
+
+    #[test]
+    fn supported_schema_is_accepted() {
+        let mut schema = schema();
+        schema["$schema"] = json!("http://iglucentral.com/schemas/com.snowplowanalytics.self-desc/schema/jsonschema/1-0-0#");
+        schema["description"] = json!("A purchase");
+        assert_eq!(check_supported(&schema), Ok(()));
+    }
+
+    #[test]
+    fn unsupported_keywords_are_rejected() {
+        let cases = [
+            (
+                json!({"type": "string", "pattern": "^[0-9]+$"}),
+                "unsupported keyword pattern at $",
+            ),
+            (
+                json!({"properties": {"id": {"type": "string", "format": "uuid"}}}),
+                "unsupported keyword format at $.properties.id",
+            ),
+            (
+                json!({"additionalProperties": {"$ref": "#/definitions/item"}}),
+                "unsupported keyword $ref at $.additionalProperties",
+            ),
+            (
+                json!({"items": {"oneOf": [{"type": "string"}]}}),
+                "unsupported keyword oneOf at $.items",
+            ),
+            (
+                json!({"items": [{"type": "string"}]}),
+                "unsupported array of items at $",
+            ),
+        ];
+
+        for (schema, reason) in cases {
+            assert_eq!(check_supported(&schema), Err(reason.to_string()));
+        }
+    }
+}
@@ -0,0 +1,77 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+mod iglu_resolver;
+mod json_schema;
+
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use crate::error::Error;
+use crate::payload::{validate_schema_uri, SelfDescribingJson};
+
+pub(crate) use iglu_resolver::IgluResolver;
+
+/// Validates self-describing JSON against its JSON Schema before an event is tracked
+///
+/// Schemas registered with the validator are used first, then schemas fetched from the Iglu registry,
+/// if one is configured. Data whose schema is in neither is not validated.
+#[derive(Default)]
+pub(crate) struct SchemaValidator {
+    schemas: HashMap<String, Value>,
+    resolver: Option<IgluResolver>,
+}
+
+impl SchemaValidator {
+    /// Fetch schemas that haven't been registered from an Iglu registry
+    pub(crate) fn set_resolver(&mut self, resolver: IgluResolver) {
+        self.resolver = Some(resolver);
+    }
+
+    /// Validate data with the Iglu URI `schema_uri` against `schema`
+    pub(crate) fn register_schema(&mut self, schema_uri: &str, schema: Value) {
+        self.schemas.insert(schema_uri.to_string(), schema);
+    }
+
+    /// Checks the Iglu URI of each registered schema, and that each only uses supported keywords
+    pub(crate) fn check_registered_schemas(&self) -> Result<(), Error> {
+        for (schema_uri, schema) in &self.schemas {
+            validate_schema_uri(schema_uri)?;
+            json_schema::check_supported(schema).map_err(|reason| {
+                Error::BuilderError(format!("Schema {schema_uri} can't be validated: {reason}"))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Checks the data of `json` against the schema it references
+    ///
+    /// Returns a [Error::BuilderError] if the schema cannot be fetched, or the data does not match it
+    pub(crate) fn validate(&self, json: &SelfDescribingJson) -> Result<(), Error> {
+        let fetched_schema;
+        let schema = match (self.schemas.get(&json.schema), &self.resolver) {
+            (Some(schema), _) => schema,
+            (None, Some(resolver)) => {
+                fetched_schema = resolver.schema(&json.schema)?;
+                &fetched_schema
+            }
+            (None, None) => return Ok(()),
+        };
+
+        json_schema::validate(schema, &json.data).map_err(|reason| {
+            Error::BuilderError(format!(
+                "Data does not match schema {}: {reason}",
+                json.schema
+            ))
+        })
+    }
+}
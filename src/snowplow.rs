@@ -9,7 +9,7 @@
 // "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
 
-use crate::emitter::BatchEmitter;
+use crate::emitter::{BatchEmitter, Emitter};
 use crate::subject::Subject;
 use crate::tracker::Tracker;
 
@@ -18,13 +18,60 @@ pub struct Snowplow;
 
 impl Snowplow {
     /// Creates a new [Tracker] instance
+    ///
+    /// The emitter's background thread is named `snowplow-emitter-{namespace}`.
     pub fn create_tracker(
         namespace: &str,
         app_id: &str,
         collector_url: &str,
         subject: Option<Subject>,
     ) -> Tracker {
-        let emitter = BatchEmitter::new(collector_url);
+        let emitter = BatchEmitter::new_named(collector_url, namespace);
         Tracker::new(namespace, app_id, emitter, subject)
     }
+
+    /// Creates a new [Tracker] instance using the provided [Emitter]
+    ///
+    /// Use this to configure the event store, retry policy or HTTP client of the emitter,
+    /// or to provide your own [Emitter] implementation.
+    pub fn create_tracker_with_emitter(
+        namespace: &str,
+        app_id: &str,
+        emitter: impl Emitter + 'static,
+        subject: Option<Subject>,
+    ) -> Tracker {
+        Tracker::new(namespace, app_id, emitter, subject)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::RecordingEmitter;
+    use crate::StructuredEvent;
+
+    #[test]
+    fn create_tracker_with_custom_emitter() {
+        let (emitter, payloads) = RecordingEmitter::new();
+
+        let mut tracker = Snowplow::create_tracker_with_emitter("ns", "app_id", emitter, None);
+
+        assert_eq!(tracker.namespace(), "ns");
+        assert_eq!(tracker.app_id(), "app_id");
+        assert_eq!(
+            tracker.emitter().collector_url(),
+            "http://recording.example.com"
+        );
+
+        let event = StructuredEvent::builder()
+            .category("shop")
+            .action("add-to-basket")
+            .build()
+            .unwrap();
+        let event_id = tracker.track(event, None).unwrap();
+
+        let payloads = payloads.lock().unwrap();
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0].eid, Some(event_id));
+    }
 }
@@ -9,10 +9,14 @@
 // "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
 
+use std::collections::HashMap;
+
 use derive_builder::Builder;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::Error;
+
 /// Subject allows you to attach additional information about your application's environment.
 ///
 /// A Subject can be attached to:
@@ -114,6 +118,190 @@ impl Subject {
     }
 }
 
+impl SubjectBuilder {
+    /// Builds the [Subject], also checking that the fields are well-formed
+    ///
+    /// The `language` must be a language tag such as `en` or `en-GB`: a 2 or 3 letter language code,
+    /// optionally followed by subtags of 2 to 8 letters or digits.
+    ///
+    /// ## Example
+    /// ```
+    /// use snowplow_tracker::Subject;
+    ///
+    /// assert!(Subject::builder().language("en-gb").build_strict().is_ok());
+    /// assert!(Subject::builder().language("english").build_strict().is_err());
+    /// ```
+    pub fn build_strict(&self) -> Result<Subject, Error> {
+        let subject = self
+            .build()
+            .map_err(|e| Error::BuilderError(e.to_string()))?;
+
+        if let Some(language) = &subject.language {
+            if !is_language_tag(language) {
+                return Err(Error::BuilderError(format!(
+                    "Invalid language {language:?}: expected a language tag such as \"en\" or \"en-GB\""
+                )));
+            }
+        }
+
+        Ok(subject)
+    }
+
+    /// Creates a builder with the fields set from a map, keyed by field name, such as `user_id` or `timezone`
+    ///
+    /// Unknown keys are ignored, as are ID fields that are not valid UUIDs.
+    ///
+    /// ## Example
+    /// ```
+    /// use std::collections::HashMap;
+    /// use snowplow_tracker::SubjectBuilder;
+    ///
+    /// let fields = HashMap::from([
+    ///     ("user_id".to_string(), "user_1".to_string()),
+    ///     ("language".to_string(), "en-gb".to_string()),
+    /// ]);
+    ///
+    /// let subject = SubjectBuilder::from_map(&fields).build().unwrap();
+    ///
+    /// assert_eq!(subject.user_id, Some("user_1".to_string()));
+    /// ```
+    pub fn from_map(fields: &HashMap<String, String>) -> Self {
+        let mut builder = Self::default();
+        for (key, value) in fields {
+            match key.as_str() {
+                "user_id" => builder.user_id(value),
+                "timezone" => builder.timezone(value),
+                "language" => builder.language(value),
+                "ip_address" => builder.ip_address(value),
+                "user_agent" => builder.user_agent(value),
+                "domain_user_id" | "network_user_id" | "session_user_id" => {
+                    let id = match Uuid::parse_str(value) {
+                        Ok(id) => id,
+                        Err(e) => {
+                            log::warn!("Ignoring subject field {key}: {e}");
+                            continue;
+                        }
+                    };
+                    match key.as_str() {
+                        "domain_user_id" => builder.domain_user_id(id),
+                        "network_user_id" => builder.network_user_id(id),
+                        _ => builder.session_user_id(id),
+                    }
+                }
+                _ => {
+                    log::debug!("Ignoring unknown subject field {key}");
+                    continue;
+                }
+            };
+        }
+        builder
+    }
+
+    /// Creates a builder with the fields set from the headers of an incoming HTTP request
+    ///
+    /// - `User-Agent` sets `user_agent`
+    /// - The first address in `X-Forwarded-For`, which is the client's, sets `ip_address`
+    /// - The most preferred language in `Accept-Language` sets `language`
+    ///
+    /// Header names are matched case-insensitively. Use [from_header_map](Self::from_header_map)
+    /// for a [HeaderMap](reqwest::header::HeaderMap).
+    ///
+    /// ## Example
+    /// ```
+    /// use std::collections::HashMap;
+    /// use snowplow_tracker::SubjectBuilder;
+    ///
+    /// let headers = HashMap::from([
+    ///     ("user-agent".to_string(), "Mozilla/5.0".to_string()),
+    ///     ("x-forwarded-for".to_string(), "203.0.113.7, 10.0.0.1".to_string()),
+    /// ]);
+    ///
+    /// let subject = SubjectBuilder::from_headers(&headers).build().unwrap();
+    ///
+    /// assert_eq!(subject.ip_address, Some("203.0.113.7".to_string()));
+    /// ```
+    #[cfg(feature = "http-headers")]
+    pub fn from_headers(headers: &HashMap<String, String>) -> Self {
+        Self::from_header_values(|name| {
+            headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        })
+    }
+
+    /// Creates a builder with the fields set from a [HeaderMap](reqwest::header::HeaderMap), as with
+    /// [from_headers](Self::from_headers)
+    ///
+    /// Header values that are not valid visible ASCII are ignored.
+    #[cfg(feature = "http-headers")]
+    pub fn from_header_map(headers: &reqwest::header::HeaderMap) -> Self {
+        Self::from_header_values(|name| headers.get(name).and_then(|value| value.to_str().ok()))
+    }
+
+    #[cfg(feature = "http-headers")]
+    fn from_header_values<'a>(header: impl Fn(&str) -> Option<&'a str>) -> Self {
+        let mut builder = Self::default();
+
+        if let Some(user_agent) = header("user-agent").map(str::trim) {
+            if !user_agent.is_empty() {
+                builder.user_agent(user_agent);
+            }
+        }
+
+        // Each proxy appends the address it received the request from, so the client's comes first
+        let client_ip = header("x-forwarded-for").and_then(|value| value.split(',').next());
+        if let Some(ip_address) = client_ip.map(str::trim) {
+            if !ip_address.is_empty() {
+                builder.ip_address(ip_address);
+            }
+        }
+
+        if let Some(language) = header("accept-language").and_then(preferred_language) {
+            builder.language(language);
+        }
+
+        builder
+    }
+}
+
+// The language with the highest quality in an `Accept-Language` header, e.g. `en-GB,en;q=0.8`,
+// ignoring the `*` wildcard. Languages of equal quality keep the order they are listed in.
+#[cfg(feature = "http-headers")]
+fn preferred_language(header: &str) -> Option<&str> {
+    let mut preferred: Option<(&str, f32)> = None;
+    for entry in header.split(',') {
+        let mut parts = entry.split(';');
+        let language = parts.next().unwrap_or_default().trim();
+        let quality = parts
+            .find_map(|param| param.trim().strip_prefix("q="))
+            .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok());
+
+        match quality {
+            Some(quality) if !language.is_empty() && language != "*" && quality > 0.0 => {
+                if preferred.map_or(true, |(_, best)| quality > best) {
+                    preferred = Some((language, quality));
+                }
+            }
+            _ => continue,
+        }
+    }
+    preferred.map(|(language, _)| language)
+}
+
+// Roughly matches a BCP 47 language tag, e.g. `en`, `en-GB` or `zh-Hant-TW`
+fn is_language_tag(tag: &str) -> bool {
+    let mut subtags = tag.split(['-', '_']);
+    let is_language = subtags.next().is_some_and(|language| {
+        (2..=3).contains(&language.len()) && language.chars().all(|c| c.is_ascii_alphabetic())
+    });
+
+    is_language
+        && subtags.all(|subtag| {
+            (2..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -177,4 +365,110 @@ mod test {
         assert_eq!(merged.user_id.unwrap(), "user_1");
         assert_eq!(merged.ip_address.unwrap(), "999.999.999.999");
     }
+
+    #[test]
+    fn test_build_subject_from_map() {
+        let domain_user_id = Uuid::new_v4();
+        let fields = HashMap::from([
+            ("user_id".to_string(), "user_1".to_string()),
+            ("timezone".to_string(), "Europe/London".to_string()),
+            ("domain_user_id".to_string(), domain_user_id.to_string()),
+            ("network_user_id".to_string(), "not-a-uuid".to_string()),
+            ("x-request-id".to_string(), "abc".to_string()),
+        ]);
+
+        let subject = SubjectBuilder::from_map(&fields).build().unwrap();
+
+        assert_eq!(subject.user_id.unwrap(), "user_1");
+        assert_eq!(subject.timezone.unwrap(), "Europe/London");
+        assert_eq!(subject.domain_user_id.unwrap(), domain_user_id);
+        assert!(subject.network_user_id.is_none());
+        assert!(subject.language.is_none());
+    }
+
+    #[cfg(feature = "http-headers")]
+    #[test]
+    fn test_build_subject_from_headers() {
+        let headers = HashMap::from([
+            (
+                "User-Agent".to_string(),
+                "Mozilla/5.0 (X11; Linux x86_64) Firefox/118.0".to_string(),
+            ),
+            (
+                "X-Forwarded-For".to_string(),
+                "203.0.113.7, 198.51.100.2, 10.0.0.1".to_string(),
+            ),
+            (
+                "accept-language".to_string(),
+                "fr;q=0.5, en-GB, en;q=0.8, *;q=0.1".to_string(),
+            ),
+            ("Host".to_string(), "example.com".to_string()),
+        ]);
+
+        let subject = SubjectBuilder::from_headers(&headers).build().unwrap();
+
+        assert_eq!(
+            subject.user_agent.unwrap(),
+            "Mozilla/5.0 (X11; Linux x86_64) Firefox/118.0"
+        );
+        assert_eq!(subject.ip_address.unwrap(), "203.0.113.7");
+        assert_eq!(subject.language.unwrap(), "en-GB");
+        assert!(subject.user_id.is_none());
+    }
+
+    #[cfg(feature = "http-headers")]
+    #[test]
+    fn test_build_subject_from_header_map() {
+        use reqwest::header::{HeaderMap, HeaderValue, ACCEPT_LANGUAGE, USER_AGENT};
+
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, HeaderValue::from_static("curl/8.0"));
+        headers.insert("x-forwarded-for", HeaderValue::from_static("2001:db8::1"));
+        headers.insert(ACCEPT_LANGUAGE, HeaderValue::from_static("*"));
+
+        let subject = SubjectBuilder::from_header_map(&headers).build().unwrap();
+
+        assert_eq!(subject.user_agent.unwrap(), "curl/8.0");
+        assert_eq!(subject.ip_address.unwrap(), "2001:db8::1");
+        assert!(subject.language.is_none());
+    }
+
+    #[test]
+    fn test_strict_build_accepts_language_tags() {
+        for language in [
+            "en",
+            "en-gb",
+            "en-GB",
+            "en_GB",
+            "haw",
+            "zh-Hant-TW",
+            "es-419",
+        ] {
+            let subject = Subject::builder().language(language).build_strict();
+            assert!(subject.is_ok(), "{language} should be valid");
+        }
+    }
+
+    #[test]
+    fn test_strict_build_rejects_invalid_language_tags() {
+        for language in [
+            "",
+            "e",
+            "english",
+            "en-",
+            "en-G",
+            "12",
+            "en gb",
+            "en-GB-toolongsubtag",
+        ] {
+            let err = Subject::builder()
+                .language(language)
+                .build_strict()
+                .unwrap_err();
+            assert!(
+                err.to_string().contains("Invalid language"),
+                "{language} should be invalid"
+            );
+        }
+    }
 }
@@ -0,0 +1,196 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+// Helpers shared between unit tests
+
+use std::sync::{Arc, Mutex};
+
+use crate::payload::{PayloadBuilder, SelfDescribingJson};
+use crate::{Emitter, Error};
+
+// An Emitter that records the payloads it is given, without sending them
+pub(crate) struct RecordingEmitter {
+    pub(crate) payloads: Arc<Mutex<Vec<PayloadBuilder>>>,
+    pub(crate) default_contexts: Vec<SelfDescribingJson>,
+}
+
+impl RecordingEmitter {
+    pub(crate) fn new() -> (Self, Arc<Mutex<Vec<PayloadBuilder>>>) {
+        let payloads = Arc::new(Mutex::new(Vec::new()));
+        (
+            Self {
+                payloads: payloads.clone(),
+                default_contexts: Vec::new(),
+            },
+            payloads,
+        )
+    }
+}
+
+impl Emitter for RecordingEmitter {
+    fn add(&mut self, payload: PayloadBuilder) -> Result<(), Error> {
+        self.payloads.lock().unwrap().push(payload);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn collector_url(&self) -> &str {
+        "http://recording.example.com"
+    }
+
+    fn default_contexts(&self) -> &[SelfDescribingJson] {
+        &self.default_contexts
+    }
+}
+
+// Starts an Iglu registry serving the given JSON Schemas, keyed by path such as `com.acme/event/jsonschema/1-0-0`
+//
+// Returns the registry URL, and the number of requests it has received
+pub(crate) fn schema_registry(
+    schemas: Vec<(&str, serde_json::Value)>,
+) -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+    use std::io::{Read, Write};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(AtomicUsize::new(0));
+    let requests_clone = requests.clone();
+    let schemas: Vec<(String, String)> = schemas
+        .into_iter()
+        .map(|(path, schema)| (format!("/schemas/{path}"), schema.to_string()))
+        .collect();
+
+    std::thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            requests_clone.fetch_add(1, Ordering::SeqCst);
+
+            // GET requests have no body, so the request line is all that is needed
+            let mut buf = [0; 4096];
+            let n = stream.read(&mut buf).unwrap_or(0);
+            let request = String::from_utf8_lossy(&buf[..n]);
+            let path = request.split_whitespace().nth(1).unwrap_or_default();
+
+            let response = match schemas.iter().find(|(schema_path, _)| schema_path == path) {
+                Some((_, schema)) => format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{schema}",
+                    schema.len()
+                ),
+                None => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_string(),
+            };
+            let _ = stream.write_all(response.as_bytes());
+        }
+    });
+
+    (url, requests)
+}
+
+// A request received by a `test_server`
+pub(crate) struct ReceivedRequest {
+    // The request line and headers
+    pub(crate) head: String,
+    pub(crate) body: Vec<u8>,
+}
+
+// Starts a server that sends each response in turn to the requests it receives, recording each request
+//
+// Returns the server URL, and the requests it has received
+pub(crate) fn test_server(responses: Vec<String>) -> (String, Arc<Mutex<Vec<ReceivedRequest>>>) {
+    use std::io::{Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let received_clone = received.clone();
+
+    std::thread::spawn(move || {
+        for response in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+
+            // Read the request headers and body before responding
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            loop {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request).to_string();
+                if let Some(headers_end) = text.find("\r\n\r\n") {
+                    let content_length = text[..headers_end]
+                        .lines()
+                        .find_map(|line| {
+                            line.to_lowercase()
+                                .strip_prefix("content-length: ")
+                                .and_then(|len| len.trim().parse::<usize>().ok())
+                        })
+                        .unwrap_or(0);
+                    if request.len() >= headers_end + 4 + content_length {
+                        received_clone.lock().unwrap().push(ReceivedRequest {
+                            head: text[..headers_end].to_string(),
+                            body: request[headers_end + 4..].to_vec(),
+                        });
+                        break;
+                    }
+                }
+                if n == 0 {
+                    break;
+                }
+            }
+
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+    });
+
+    (url, received)
+}
+
+// A logger that keeps every message, so tests can assert on what was logged
+struct CapturingLogger {
+    messages: Mutex<Vec<String>>,
+}
+
+impl log::Log for CapturingLogger {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        if let Ok(mut messages) = self.messages.lock() {
+            messages.push(format!("{} {}", record.level(), record.args()));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: CapturingLogger = CapturingLogger {
+    messages: Mutex::new(Vec::new()),
+};
+
+// Installs the capturing logger, returning the messages logged so far by all tests
+//
+// Tests run in parallel, so assertions should look for a message unique to the test
+pub(crate) fn captured_logs() -> Vec<String> {
+    static INSTALL: std::sync::Once = std::sync::Once::new();
+    INSTALL.call_once(|| {
+        log::set_logger(&LOGGER).unwrap();
+        log::set_max_level(log::LevelFilter::Debug);
+    });
+
+    LOGGER.messages.lock().unwrap().clone()
+}
@@ -0,0 +1,72 @@
+// Copyright (c) 2022 Snowplow Analytics Ltd. All rights reserved.
+//
+// This program is licensed to you under the Apache License Version 2.0,
+// and you may not use this file except in compliance with the Apache License Version 2.0.
+// You may obtain a copy of the Apache License Version 2.0 at http://www.apache.org/licenses/LICENSE-2.0.
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the Apache License Version 2.0 is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
+
+use std::time::SystemTime;
+
+use crate::Priority;
+
+/// Per-event options for [Tracker::track_with_options](crate::Tracker::track_with_options) and
+/// [Tracker::try_track_with_options](crate::Tracker::try_track_with_options)
+///
+/// The default options track an event as [Tracker::track](crate::Tracker::track) does.
+///
+/// ## Example
+/// ```
+/// use std::time::{Duration, SystemTime};
+/// use snowplow_tracker::{Priority, TrackOptions};
+///
+/// let options = TrackOptions::new()
+///     .priority(Priority::High)
+///     .expire_at(SystemTime::now() + Duration::from_secs(60))
+///     .extra("xyz", "custom");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TrackOptions {
+    pub(crate) priority: Priority,
+    pub(crate) expire_at: Option<SystemTime>,
+    pub(crate) extra: Vec<(String, String)>,
+}
+
+impl TrackOptions {
+    /// Options with normal priority, no expiry and no extra fields
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the [Priority] for sending the event, [Priority::Normal] by default
+    ///
+    /// Higher priority events are batched first by the [InMemoryEventStore](crate::InMemoryEventStore).
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Drop the event rather than send it if it hasn't been sent by `expire_at`
+    ///
+    /// This suits time-sensitive events that are worthless once late, such as after a collector outage.
+    /// Expired events are dropped as their batch is about to be sent, so an event may be dropped when
+    /// it is retried, and are passed to the emitter's [on_dead_letter](crate::BatchEmitterBuilder::on_dead_letter)
+    /// callback. Event stores that serialize events keep the deadline with
+    /// [PayloadBuilder::to_json](crate::PayloadBuilder::to_json).
+    pub fn expire_at(mut self, expire_at: SystemTime) -> Self {
+        self.expire_at = Some(expire_at);
+        self
+    }
+
+    /// Add an extra top-level payload field
+    ///
+    /// The field is set with [PayloadBuilder::raw](crate::PayloadBuilder::raw), for fields of the
+    /// tracker protocol that the crate doesn't model. Tracking fails if the tracker already sets the field.
+    pub fn extra(mut self, key: &str, value: &str) -> Self {
+        self.extra.push((key.to_string(), value.to_string()));
+        self
+    }
+}
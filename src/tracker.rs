@@ -9,20 +9,108 @@
 // "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the Apache License Version 2.0 for the specific language governing permissions and limitations there under.
 
+use std::path::Path;
 use std::time::UNIX_EPOCH;
 use std::time::{SystemTime, SystemTimeError};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use uuid::Uuid;
 
-use crate::emitter::Emitter;
+use crate::context::{Context, HostContext};
+use crate::emitter::{thread_name_for, BatchEmitterBuilder, Emitter, EmitterConfig, QueuePressure};
 use crate::error::Error;
-use crate::event::PayloadAddable;
-use crate::payload::{ContextData, Payload, SelfDescribingJson};
+use crate::event::{PayloadAddable, SelfDescribingEvent};
+use crate::event_id::{EventIdVersion, IdProvider};
+use crate::payload::{
+    validate_schema_uri, ContextData, Payload, PayloadBuilder, SelfDescribingJson,
+    DEFAULT_CONTEXTS_SCHEMA, DEFAULT_UNSTRUCT_EVENT_SCHEMA,
+};
+use crate::schema_validation::{IgluResolver, SchemaValidator};
 use crate::subject::Subject;
+use crate::track_options::TrackOptions;
 
 pub struct TrackerConfig {
     pub platform: String,
     pub version: String,
+    #[allow(dead_code)]
     pub encode_base_64: bool,
+    pub context_size_limit: Option<ContextSizeLimit>,
+    pub duplicate_contexts: DuplicateContextAction,
+    pub id_provider: Box<dyn IdProvider>,
+    pub contexts_schema: String,
+    pub unstruct_event_schema: String,
+    pub tracker_context_schema: Option<String>,
+    pub host_context: Option<HostContext>,
+    pub schema_validator: Option<SchemaValidator>,
+}
+
+impl Default for TrackerConfig {
+    fn default() -> Self {
+        Self {
+            platform: "pc".to_string(),
+            version: format!("rust-{}", env!("CARGO_PKG_VERSION")),
+            encode_base_64: false,
+            context_size_limit: None,
+            duplicate_contexts: DuplicateContextAction::default(),
+            id_provider: Box::new(EventIdVersion::V4),
+            contexts_schema: DEFAULT_CONTEXTS_SCHEMA.to_string(),
+            unstruct_event_schema: DEFAULT_UNSTRUCT_EVENT_SCHEMA.to_string(),
+            tracker_context_schema: None,
+            host_context: None,
+            schema_validator: None,
+        }
+    }
+}
+
+/// What the [Tracker] should do when the context entities of an event exceed the configured size limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContextLimitAction {
+    /// Fail to track the event, returning an error
+    Error,
+    /// Drop any context entity that would take the total size over the limit, logging a warning
+    Drop,
+}
+
+/// What the [Tracker] should do when an event has more than one context entity with the same schema
+///
+/// Entities with the same schema don't replace each other, so all of them are sent with the event.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicateContextAction {
+    /// Send the event without checking for duplicates
+    Allow,
+    /// Send the event, logging a warning for each duplicated schema
+    #[default]
+    Warn,
+    /// Fail to track the event, returning an error
+    Error,
+}
+
+/// A limit on the total serialized size of the context entities attached to an event
+#[derive(Debug, Clone, Copy)]
+pub struct ContextSizeLimit {
+    /// The maximum total size, in bytes, of the serialized context entities
+    pub max_bytes: usize,
+    /// The action to take when the limit is exceeded
+    pub on_exceed: ContextLimitAction,
+}
+
+/// Serializable configuration for a [Tracker] and its [BatchEmitter](crate::BatchEmitter)
+///
+/// The emitter settings are flattened, so a single config section can hold everything needed
+/// to create a tracker with [TrackerBuilder::from_config].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrackerSettings {
+    /// Tracker namespace that identifies the tracker within the app
+    pub namespace: String,
+    /// Application ID
+    pub app_id: String,
+    /// The platform the app runs on, defaults to `pc`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
+    /// Configuration for the [BatchEmitter](crate::BatchEmitter)
+    #[serde(flatten)]
+    pub emitter: EmitterConfig,
 }
 
 /// The Snowplow tracker, used to track events
@@ -38,6 +126,8 @@ pub struct Tracker {
     /// The [Subject] that will be applied to all events
     /// An event-level subject will take priority over this
     subject: Subject,
+    /// The number of events dropped by [try_track](Tracker::try_track)
+    dropped_events: usize,
 }
 
 impl Tracker {
@@ -48,20 +138,38 @@ impl Tracker {
         emitter: impl Emitter + 'static,
         subject: Option<Subject>,
     ) -> Tracker {
+        Tracker::create_tracker(
+            namespace,
+            app_id,
+            Box::new(emitter),
+            subject,
+            TrackerConfig::default(),
+        )
+    }
+
+    pub fn builder() -> TrackerBuilder {
+        TrackerBuilder::default()
+    }
+
+    fn create_tracker(
+        namespace: &str,
+        app_id: &str,
+        mut emitter: Box<dyn Emitter>,
+        subject: Option<Subject>,
+        config: TrackerConfig,
+    ) -> Tracker {
+        emitter.set_tracker(namespace, app_id);
         Tracker {
             namespace: namespace.to_string(),
             app_id: app_id.to_string(),
-            emitter: Box::new(emitter),
+            emitter,
             // By providing a default subject, we can avoid having to unwrap the subject
             //
             // The default for Subject provides `None` for all fields, so will be skipped
             // when serializing
-            subject: subject.unwrap_or(Subject::default()),
-            config: TrackerConfig {
-                platform: "pc".to_string(),
-                version: format!("rust-{}", env!("CARGO_PKG_VERSION")),
-                encode_base_64: false,
-            },
+            subject: subject.unwrap_or_default(),
+            config,
+            dropped_events: 0,
         }
     }
 
@@ -73,6 +181,8 @@ impl Tracker {
         &self.app_id
     }
 
+    // Returning `&Box` is part of the public API, so it is kept despite the lint
+    #[allow(clippy::borrowed_box)]
     pub fn emitter(&self) -> &Box<dyn Emitter> {
         &self.emitter
     }
@@ -86,6 +196,71 @@ impl Tracker {
         self.emitter.flush()
     }
 
+    /// Attempts to send only full batches of events to the collector, leaving any remaining events in the event store
+    ///
+    /// Useful when flushing periodically, to allow the remaining events to accumulate into a full batch
+    pub fn flush_full_batches_only(&mut self) -> Result<(), Error> {
+        self.emitter.flush_full_batches_only()
+    }
+
+    /// Attempts to send all events in the event store, calling `callback` with the number of events
+    /// finished so far and the total as each batch finishes sending
+    ///
+    /// Useful for reporting progress when flushing a large backlog, e.g. on shutdown
+    pub fn flush_with_progress(
+        &mut self,
+        callback: impl Fn(usize, usize) + Send + Sync + 'static,
+    ) -> Result<(), Error> {
+        self.emitter.flush_with_progress(Box::new(callback))
+    }
+
+    /// Discards all events waiting in the event store, without sending them
+    ///
+    /// Useful when queued events should not be sent, e.g. after a user logs out
+    pub fn clear_queue(&mut self) -> Result<(), Error> {
+        self.emitter.clear()
+    }
+
+    /// Adds events persisted by a previous run to the event store, returning the number of events added
+    ///
+    /// The file at `path` should hold one [Payload] serialized with `serde_json` per line, such as the
+    /// events returned in [Error::UndeliveredEvents]. Each event keeps its original event id and `dtm`.
+    /// Invalid lines are an error, and stop loading at that line.
+    ///
+    /// A serialized [Payload] doesn't include its [Priority](crate::Priority) or expiry time, so those
+    /// events are loaded with normal priority and never expire. Only events serialized with
+    /// [PayloadBuilder::to_json] keep them.
+    pub fn load_and_track(&mut self, path: impl AsRef<Path>) -> Result<usize, Error> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            Error::EventStoreError(format!("Failed to read {}: {e}", path.display()))
+        })?;
+
+        let mut loaded = 0;
+        for (i, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let payload = serde_json::from_str(line)
+                .map_err(|e| Error::BuilderError(e.to_string()))
+                .and_then(PayloadBuilder::from_json)
+                .map_err(|e| {
+                    Error::EventStoreError(format!(
+                        "Invalid event on line {} of {}: {e}",
+                        i + 1,
+                        path.display()
+                    ))
+                })?;
+            self.emitter.add(payload)?;
+            loaded += 1;
+        }
+
+        log::info!("Loaded {loaded} events from {}", path.display());
+
+        Ok(loaded)
+    }
+
     /// Safely shuts down the Emitter
     pub fn close_emitter(&mut self) -> Result<(), Error> {
         self.emitter.close()
@@ -131,57 +306,1613 @@ impl Tracker {
         &mut self.subject
     }
 
+    /// Merges `subject` over the tracker [Subject], so its fields replace those already set, and
+    /// fields it doesn't set are kept
+    ///
+    /// This suits setting a field without disturbing the others, such as the `user_id` on login.
+    /// Returns the tracker, so updates can be chained.
+    pub fn update_subject(&mut self, subject: Subject) -> &mut Self {
+        self.subject = subject.merge(std::mem::take(&mut self.subject));
+        self
+    }
+
     /// Tracks a Snowplow event with optional context entities and sends it to the Snowplow collector.
     pub fn track(
         &mut self,
         event: impl PayloadAddable,
         context: Option<Vec<SelfDescribingJson>>,
     ) -> Result<Uuid, Error> {
+        self.track_with_options(event, context, TrackOptions::default())
+    }
+
+    /// Tracks a Snowplow event like [track](Tracker::track), with [TrackOptions] such as a [Priority](crate::Priority)
+    /// or expiry time
+    pub fn track_with_options(
+        &mut self,
+        event: impl PayloadAddable,
+        context: Option<Vec<SelfDescribingJson>>,
+        options: TrackOptions,
+    ) -> Result<Uuid, Error> {
+        let payload_builder = self.build_payload(event, context, options)?;
+        self.add_to_emitter(payload_builder)
+    }
+
+    /// Tracks a Snowplow event like [track](Tracker::track), but never returns an error
+    ///
+    /// Returns whether the event was accepted. If the event store is full, or the event could not be
+    /// tracked for any other reason, it is dropped and counted in [dropped_events](Tracker::dropped_events).
+    pub fn try_track(
+        &mut self,
+        event: impl PayloadAddable,
+        context: Option<Vec<SelfDescribingJson>>,
+    ) -> bool {
+        self.try_track_with_options(event, context, TrackOptions::default())
+    }
+
+    /// Tracks a Snowplow event like [try_track](Tracker::try_track), with [TrackOptions]
+    pub fn try_track_with_options(
+        &mut self,
+        event: impl PayloadAddable,
+        context: Option<Vec<SelfDescribingJson>>,
+        options: TrackOptions,
+    ) -> bool {
+        match self.track_with_options(event, context, options) {
+            // The event was added, and its batch is sent later
+            Ok(_) | Err(Error::SendQueueFull) => true,
+            Err(e) => {
+                // A full queue is expected under load, so it isn't logged for every dropped event
+                if !matches!(e, Error::QueueFull) {
+                    log::warn!("Dropped event: {e}");
+                }
+                self.dropped_events += 1;
+                false
+            }
+        }
+    }
+
+    /// The number of events dropped by [try_track](Tracker::try_track)
+    pub fn dropped_events(&self) -> usize {
+        self.dropped_events
+    }
+
+    /// How full the emitter's queue is, or `None` for emitters that don't report it
+    ///
+    /// Reading this after tracking lets callers sample fewer events as the queue fills, before
+    /// [track](Tracker::track) fails with [Error::QueueFull].
+    pub fn queue_pressure(&self) -> Option<QueuePressure> {
+        self.emitter.queue_pressure()
+    }
+
+    fn add_to_emitter(&mut self, payload_builder: PayloadBuilder) -> Result<Uuid, Error> {
+        let event_id = match payload_builder.eid {
+            Some(eid) => eid,
+            None => return Err(Error::BuilderError("Event ID not set".to_string())),
+        };
+
+        self.emitter.add(payload_builder)?;
+        Ok(event_id)
+    }
+
+    /// Builds the [Payload] that [track](Tracker::track) would send for an event, without adding it to the emitter
+    ///
+    /// The payload goes through the same steps as a tracked event, including the subject merge,
+    /// contexts, including the emitter's default contexts, timestamps and schema validation.
+    /// Useful for inspecting payloads in tests, without a collector.
+    pub fn preview_payload(
+        &self,
+        event: impl PayloadAddable,
+        context: Option<Vec<SelfDescribingJson>>,
+    ) -> Result<Payload, Error> {
+        self.build_payload(event, context, TrackOptions::default())?
+            .finalise_payload()
+    }
+
+    // Builds the payload for an event, ready to be added to the emitter
+    fn build_payload(
+        &self,
+        event: impl PayloadAddable,
+        context: Option<Vec<SelfDescribingJson>>,
+        options: TrackOptions,
+    ) -> Result<PayloadBuilder, Error> {
         let since_the_epoch =
             SystemTime::now()
                 .duration_since(UNIX_EPOCH)
                 .map_err(|e: SystemTimeError| {
-                    Error::BuilderError(format!("Failed to get current time: {}", e.to_string()))
+                    Error::BuilderError(format!("Failed to get current time: {e}"))
                 })?;
 
-        let event_id = Uuid::new_v4();
+        let event_id = self.config.id_provider.next_id();
 
         let mut payload_builder = Payload::builder()
             .p(self.config.platform.clone())
             .tv(self.config.version.clone())
-            .eid(event_id.clone())
+            .eid(event_id)
             .dtm(since_the_epoch.as_millis().to_string())
-            .aid(self.app_id.clone());
+            .aid(self.app_id.clone())
+            .tna(self.namespace.clone())
+            .priority(options.priority);
+        if let Some(expire_at) = options.expire_at {
+            payload_builder = payload_builder.expire_at(expire_at);
+        }
+        for (key, value) in options.extra {
+            payload_builder = payload_builder.raw(&key, value);
+        }
+
+        // The emitter's default contexts come after the event's own, and are checked the same way
+        let mut context = context.unwrap_or_default();
+        context.extend_from_slice(self.emitter.default_contexts());
+        let mut context = self.limit_context_size(context)?;
+
+        self.check_duplicate_contexts(&context)?;
 
-        if let Some(context) = context {
-            payload_builder = payload_builder.co(ContextData::new(context));
+        if let Some(validator) = &self.config.schema_validator {
+            for entity in &context {
+                validator.validate(entity)?;
+            }
+        }
+
+        // The tracker context is added after the size limit is applied, so it is never dropped
+        if let Some(schema) = &self.config.tracker_context_schema {
+            context.push(self.tracker_context(schema));
+        }
+        if let Some(host_context) = &self.config.host_context {
+            context.push(SelfDescribingJson::from(host_context as &dyn Context));
         }
 
-        // Event Subject gets priority over Tracker Subject
-        if let Some(event_subject) = event.subject() {
-            payload_builder =
-                payload_builder.subject(event_subject.clone().merge(self.subject.clone()));
+        if !context.is_empty() {
+            payload_builder = payload_builder.co(ContextData::with_schema(
+                &self.config.contexts_schema,
+                context,
+            ));
         }
 
+        // Event Subject gets priority over Tracker Subject.
+        // The Tracker Subject is still applied when the event has no Subject of its own
+        let subject = match event.subject() {
+            Some(event_subject) => event_subject.clone().merge(self.subject.clone()),
+            None => self.subject.clone(),
+        };
+        payload_builder = payload_builder.subject(subject);
+
         payload_builder = event.add_to_payload(payload_builder);
 
-        let event_id = match payload_builder.eid {
-            Some(eid) => eid,
-            None => return Err(Error::BuilderError("Event ID not set".to_string())),
+        // Self-describing events are wrapped in the default schema, so apply any override
+        if let Some(Some(ue_pr)) = payload_builder.ue_pr.as_mut() {
+            ue_pr.schema.clone_from(&self.config.unstruct_event_schema);
+
+            if let Some(validator) = &self.config.schema_validator {
+                validator.validate(&ue_pr.data)?;
+            }
+        }
+
+        Ok(payload_builder)
+    }
+
+    // Describes the tracker that sent an event, to tell apart events from multiple trackers
+    fn tracker_context(&self, schema: &str) -> SelfDescribingJson {
+        SelfDescribingJson::new(
+            schema,
+            json!({
+                "namespace": self.namespace,
+                "version": self.config.version,
+                "platform": self.config.platform,
+            }),
+        )
+    }
+
+    // Applies the configured duplicate check to the context entities of an event
+    fn check_duplicate_contexts(&self, context: &[SelfDescribingJson]) -> Result<(), Error> {
+        if self.config.duplicate_contexts == DuplicateContextAction::Allow {
+            return Ok(());
+        }
+
+        for (i, entity) in context.iter().enumerate() {
+            // Only report each schema once, at its first duplicate
+            let earlier = context[..i]
+                .iter()
+                .filter(|other| other.schema == entity.schema)
+                .count();
+            if earlier != 1 {
+                continue;
+            }
+
+            match self.config.duplicate_contexts {
+                DuplicateContextAction::Error => {
+                    return Err(Error::BuilderError(format!(
+                        "Event has more than one context entity with schema {}",
+                        entity.schema
+                    )))
+                }
+                _ => log::warn!(
+                    "Event has more than one context entity with schema {}",
+                    entity.schema
+                ),
+            }
+        }
+
+        Ok(())
+    }
+
+    // Applies the configured context size limit, if any, to the context entities of an event
+    fn limit_context_size(
+        &self,
+        context: Vec<SelfDescribingJson>,
+    ) -> Result<Vec<SelfDescribingJson>, Error> {
+        let limit = match self.config.context_size_limit {
+            Some(limit) => limit,
+            None => return Ok(context),
         };
 
-        self.emitter.add(payload_builder)?;
-        Ok(event_id)
+        let mut total_bytes = 0;
+        let mut limited_context = Vec::with_capacity(context.len());
+        for entity in context {
+            let entity_bytes = serde_json::to_vec(&entity)
+                .map_err(|e| Error::BuilderError(format!("Failed to serialize context: {e}")))?
+                .len();
+
+            if total_bytes + entity_bytes <= limit.max_bytes {
+                total_bytes += entity_bytes;
+                limited_context.push(entity);
+                continue;
+            }
+
+            match limit.on_exceed {
+                ContextLimitAction::Error => {
+                    return Err(Error::BuilderError(format!(
+                        "Context entities exceed the size limit of {} bytes",
+                        limit.max_bytes
+                    )))
+                }
+                ContextLimitAction::Drop => log::warn!(
+                    "Dropping context entity {} of {entity_bytes} bytes, as it exceeds the size limit of {} bytes",
+                    entity.schema,
+                    limit.max_bytes
+                ),
+            }
+        }
+
+        Ok(limited_context)
+    }
+
+    /// Tracks a self-describing event with the given schema and data, without building a [SelfDescribingEvent](crate::SelfDescribingEvent).
+    ///
+    /// Returns an [Error::BuilderError] if `schema` is not a valid Iglu URI.
+    pub fn track_self_describing(
+        &mut self,
+        schema: &str,
+        data: Value,
+        context: Option<Vec<SelfDescribingJson>>,
+    ) -> Result<Uuid, Error> {
+        validate_schema_uri(schema)?;
+
+        let event = SelfDescribingEvent {
+            schema: schema.to_string(),
+            data,
+            subject: None,
+        };
+        self.track(event, context)
+    }
+
+    /// Tracks a Snowplow event with context entities defined as types implementing [Context].
+    ///
+    /// The context entities are converted into [SelfDescribingJson] before being attached to the event.
+    pub fn track_with_contexts(
+        &mut self,
+        event: impl PayloadAddable,
+        contexts: Vec<Box<dyn Context>>,
+    ) -> Result<Uuid, Error> {
+        let context = contexts.into_iter().map(SelfDescribingJson::from).collect();
+        self.track(event, Some(context))
+    }
+}
+
+// Loads a previously saved domain user id, and saves a newly generated one
+type DomainUserIdStorage = (
+    Box<dyn FnOnce() -> Option<Uuid> + Send>,
+    Box<dyn FnOnce(Uuid) + Send>,
+);
+
+/// A builder for the [Tracker] struct
+#[derive(Default)]
+pub struct TrackerBuilder {
+    namespace: Option<String>,
+    app_id: Option<String>,
+    emitter: Option<Box<dyn Emitter>>,
+    subject: Option<Subject>,
+    config: TrackerConfig,
+    domain_user_id_storage: Option<DomainUserIdStorage>,
+}
+
+impl TrackerBuilder {
+    /// Create a builder from [TrackerSettings], with a [BatchEmitter](crate::BatchEmitter) built from its emitter config
+    ///
+    /// The emitter's background thread is named `snowplow-emitter-{namespace}`.
+    pub fn from_config(settings: TrackerSettings) -> Result<Self, Error> {
+        let emitter = BatchEmitterBuilder::from(settings.emitter)
+            .thread_name(&thread_name_for(&settings.namespace))
+            .build()?;
+
+        let mut builder = Self::default()
+            .namespace(&settings.namespace)
+            .app_id(&settings.app_id)
+            .emitter(emitter);
+
+        if let Some(platform) = settings.platform {
+            builder = builder.platform(&platform);
+        }
+
+        Ok(builder)
+    }
+
+    /// Set the tracker namespace that identifies the tracker within the app
+    pub fn namespace(mut self, namespace: &str) -> Self {
+        self.namespace = Some(namespace.to_string());
+        self
+    }
+
+    /// Set the application ID
+    pub fn app_id(mut self, app_id: &str) -> Self {
+        self.app_id = Some(app_id.to_string());
+        self
+    }
+
+    /// Set the [Emitter] used to send events to the collector
+    pub fn emitter(mut self, emitter: impl Emitter + 'static) -> Self {
+        self.emitter = Some(Box::new(emitter));
+        self
+    }
+
+    /// Set the platform the app runs on
+    ///
+    /// Defaults to `pc`
+    pub fn platform(mut self, platform: &str) -> Self {
+        self.config.platform = platform.to_string();
+        self
+    }
+
+    /// Set the [Subject] that will be applied to all events
+    pub fn subject(mut self, subject: Subject) -> Self {
+        self.subject = Some(subject);
+        self
+    }
+
+    /// Generate a persistent domain user id for the tracker [Subject], if it doesn't already have one
+    ///
+    /// When the tracker is built, `load` is called to read an id saved in a previous session. If it
+    /// returns `None`, a new UUID v4 is generated and passed to `save`, so it can be stored, such as in
+    /// a file, for the next session. The id is sent with every event, unless the event's own
+    /// [Subject] sets one.
+    pub fn generate_domain_user_id(
+        mut self,
+        load: impl FnOnce() -> Option<Uuid> + Send + 'static,
+        save: impl FnOnce(Uuid) + Send + 'static,
+    ) -> Self {
+        self.domain_user_id_storage = Some((Box::new(load), Box::new(save)));
+        self
+    }
+
+    /// Limit the total serialized size of the context entities attached to an event
+    pub fn context_size_limit(mut self, max_bytes: usize, on_exceed: ContextLimitAction) -> Self {
+        self.config.context_size_limit = Some(ContextSizeLimit {
+            max_bytes,
+            on_exceed,
+        });
+        self
+    }
+
+    /// Set what happens when an event has more than one context entity with the same schema
+    ///
+    /// Defaults to [DuplicateContextAction::Warn]. The context entities passed when tracking are checked
+    /// together with the emitter's [default contexts](crate::Emitter::default_contexts).
+    pub fn duplicate_contexts(mut self, action: DuplicateContextAction) -> Self {
+        self.config.duplicate_contexts = action;
+        self
+    }
+
+    /// Set the version of UUID used for event IDs
+    ///
+    /// Defaults to [EventIdVersion::V4]. Using [EventIdVersion::V7] gives time-ordered event IDs.
+    pub fn event_id_version(mut self, version: EventIdVersion) -> Self {
+        self.config.id_provider = Box::new(version);
+        self
+    }
+
+    /// Set the [IdProvider] that generates event IDs, replacing the [event_id_version](TrackerBuilder::event_id_version)
+    ///
+    /// This is mainly useful in tests, to give events a predictable sequence of IDs.
+    pub fn id_provider(mut self, id_provider: impl IdProvider + 'static) -> Self {
+        self.config.id_provider = Box::new(id_provider);
+        self
+    }
+
+    /// Set the schema used to wrap the context entities of each event
+    ///
+    /// Defaults to `iglu:com.snowplowanalytics.snowplow/contexts/jsonschema/1-0-1`
+    pub fn contexts_schema(mut self, schema: &str) -> Self {
+        self.config.contexts_schema = schema.to_string();
+        self
+    }
+
+    /// Set the schema used to wrap the data of self-describing events
+    ///
+    /// Defaults to `iglu:com.snowplowanalytics.snowplow/unstruct_event/jsonschema/1-0-0`
+    pub fn unstruct_event_schema(mut self, schema: &str) -> Self {
+        self.config.unstruct_event_schema = schema.to_string();
+        self
+    }
+
+    /// Attach a context entity describing the tracker to every event, using your own `schema`
+    ///
+    /// This is useful to tell apart events sent by different trackers in the same app. There is no
+    /// standard schema for it, so `schema` should be one in your own Iglu registry that accepts an
+    /// object with the `namespace`, `version` and `platform` of the tracker, all strings.
+    pub fn tracker_context(mut self, schema: &str) -> Self {
+        self.config.tracker_context_schema = Some(schema.to_string());
+        self
+    }
+
+    /// Attach a [HostContext] entity describing the host (its OS, hostname and process id) to every event,
+    /// using your own `schema`
+    ///
+    /// This is off by default, as the hostname may identify a user's machine. The host details are
+    /// gathered once, when this is called. See [HostContext] for the data `schema` should accept.
+    pub fn host_context(mut self, schema: &str) -> Self {
+        self.config.host_context = Some(HostContext::current(schema));
+        self
+    }
+
+    /// Validate self-describing events and context entities against their schemas before they are tracked
+    ///
+    /// Schemas are fetched from the Iglu registry at `registry_url`, such as `http://iglucentral.com`,
+    /// or `http://my-iglu-server/api` for an Iglu Server, and cached. An event whose data doesn't match
+    /// its schema, or whose schema can't be fetched, is rejected by [Tracker::track] with an [Error::BuilderError].
+    ///
+    /// Each schema is fetched when it is first tracked, waiting up to 2 seconds for the registry. A schema
+    /// that can't be fetched is rejected without asking the registry again for a minute.
+    ///
+    /// Only the common JSON Schema keywords are supported: `type`, `enum`, `properties`, `required`,
+    /// `additionalProperties`, `items`, `minLength`, `maxLength`, `minimum`, `maximum`, `minItems` and `maxItems`.
+    /// A fetched schema using any other keyword, such as `pattern`, `format` or `$ref`, can't be fully
+    /// checked, so events with it are rejected rather than only partly validated.
+    pub fn iglu_validation(mut self, registry_url: &str) -> Self {
+        self.config
+            .schema_validator
+            .get_or_insert_with(SchemaValidator::default)
+            .set_resolver(IgluResolver::new(registry_url));
+        self
+    }
+
+    /// Validate self-describing events and context entities using `schema_uri` against a local JSON Schema
+    ///
+    /// Data with a registered schema URI that doesn't match `schema` is rejected by [Tracker::track] with an
+    /// [Error::BuilderError]. Data with any other schema URI is not validated, unless
+    /// [iglu_validation](TrackerBuilder::iglu_validation) is also set. This catches mistakes such as
+    /// misspelt fields or wrong types without any network requests.
+    ///
+    /// The same keywords are supported as for [iglu_validation](TrackerBuilder::iglu_validation).
+    /// [build](TrackerBuilder::build) fails if `schema` uses any other keyword.
+    pub fn register_schema(mut self, schema_uri: &str, schema: Value) -> Self {
+        self.config
+            .schema_validator
+            .get_or_insert_with(SchemaValidator::default)
+            .register_schema(schema_uri, schema);
+        self
+    }
+
+    /// Build the [Tracker]
+    pub fn build(self) -> Result<Tracker, Error> {
+        validate_schema_uri(&self.config.contexts_schema)?;
+        validate_schema_uri(&self.config.unstruct_event_schema)?;
+        if let Some(schema) = &self.config.tracker_context_schema {
+            validate_schema_uri(schema)?;
+        }
+        if let Some(host_context) = &self.config.host_context {
+            validate_schema_uri(&host_context.schema)?;
+        }
+        if let Some(validator) = &self.config.schema_validator {
+            validator.check_registered_schemas()?;
+        }
+
+        let namespace = self
+            .namespace
+            .ok_or_else(|| Error::BuilderError("Namespace is required".to_string()))?;
+        let app_id = self
+            .app_id
+            .ok_or_else(|| Error::BuilderError("App ID is required".to_string()))?;
+        let emitter = self
+            .emitter
+            .ok_or_else(|| Error::BuilderError("Emitter is required".to_string()))?;
+
+        let mut subject = self.subject;
+        if let Some((load, save)) = self.domain_user_id_storage {
+            let subject = subject.get_or_insert_with(Subject::default);
+            if subject.domain_user_id.is_none() {
+                subject.domain_user_id = Some(load().unwrap_or_else(|| {
+                    let domain_user_id = Uuid::new_v4();
+                    save(domain_user_id);
+                    domain_user_id
+                }));
+            }
+        }
+
+        Ok(Tracker::create_tracker(
+            &namespace,
+            &app_id,
+            emitter,
+            subject,
+            self.config,
+        ))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::BatchEmitter;
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::json;
+
+    use crate::test_utils::{schema_registry, RecordingEmitter};
+    use crate::{
+        BatchEmitter, EcommerceTransactionEvent, EventKind, InMemoryEventStore, Priority,
+        ScreenViewEvent, StructuredEvent, SyncEmitter, TimingEvent,
+    };
 
     use super::*;
 
+    fn structured_event() -> StructuredEvent {
+        StructuredEvent::builder()
+            .category("shop")
+            .action("add-to-basket")
+            .build()
+            .unwrap()
+    }
+
+    fn contexts() -> Vec<SelfDescribingJson> {
+        vec![
+            SelfDescribingJson::new("iglu:com.acme/small/jsonschema/1-0-0", json!({"a": 1})),
+            SelfDescribingJson::new(
+                "iglu:com.acme/large/jsonschema/1-0-0",
+                json!({"blob": "x".repeat(1000)}),
+            ),
+        ]
+    }
+
+    #[test]
+    fn builder_requires_fields() {
+        let err = Tracker::builder()
+            .app_id("app_id")
+            .emitter(RecordingEmitter::new().0)
+            .build()
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "Namespace is required");
+
+        let err = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .build()
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "Emitter is required");
+    }
+
+    #[test]
+    fn tracker_from_config() {
+        let settings: TrackerSettings = serde_json::from_value(json!({
+            "namespace": "ns",
+            "app_id": "app_id",
+            "platform": "srv",
+            "collector_url": "http://localhost:9090",
+            "batch_size": 5,
+            "capacity": 100,
+            "retry_policy": "NoRetry"
+        }))
+        .unwrap();
+
+        assert_eq!(settings.emitter.batch_size, 5);
+        assert_eq!(settings.emitter.retry_policy, crate::RetryPolicy::NoRetry);
+
+        let json = serde_json::to_value(&settings).unwrap();
+        assert_eq!(
+            serde_json::from_value::<TrackerSettings>(json).unwrap(),
+            settings
+        );
+
+        let mut tracker = TrackerBuilder::from_config(settings)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert_eq!(tracker.namespace(), "ns");
+        assert_eq!(tracker.config.platform, "srv");
+        assert_eq!(tracker.emitter().collector_url(), "http://localhost:9090");
+        tracker.close_emitter().unwrap();
+    }
+
+    #[test]
+    fn tracker_subject_is_flattened_into_payload() {
+        let (emitter, payloads) = RecordingEmitter::new();
+        let mut tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(emitter)
+            .subject(
+                Subject::builder()
+                    .user_id("user_1")
+                    .timezone("Europe/London")
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+
+        tracker.track(structured_event(), None).unwrap();
+
+        let payload = payloads.lock().unwrap()[0]
+            .clone()
+            .finalise_payload()
+            .unwrap();
+        let json = serde_json::to_value(payload).unwrap();
+        assert_eq!(json["uid"], "user_1");
+        assert_eq!(json["tz"], "Europe/London");
+        assert!(json.get("lang").is_none());
+    }
+
+    #[test]
+    fn payloads_report_the_kind_of_event_tracked() {
+        let (emitter, payloads) = RecordingEmitter::new();
+        let mut tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(emitter)
+            .build()
+            .unwrap();
+
+        tracker
+            .track(
+                ScreenViewEvent::builder()
+                    .id(Uuid::new_v4())
+                    .name("home")
+                    .build()
+                    .unwrap(),
+                None,
+            )
+            .unwrap();
+        tracker
+            .track(
+                TimingEvent::builder()
+                    .category("load")
+                    .variable("map")
+                    .timing(100)
+                    .build()
+                    .unwrap(),
+                None,
+            )
+            .unwrap();
+        tracker
+            .track(
+                StructuredEvent::builder()
+                    .category("shop")
+                    .action("add-to-basket")
+                    .build()
+                    .unwrap(),
+                None,
+            )
+            .unwrap();
+        tracker
+            .track(
+                SelfDescribingEvent::builder()
+                    .schema("iglu:com.acme/purchase/jsonschema/1-0-0")
+                    .data(json!({"sku": "abc"}))
+                    .build()
+                    .unwrap(),
+                None,
+            )
+            .unwrap();
+        tracker
+            .track(
+                EcommerceTransactionEvent::builder()
+                    .id("order-1")
+                    .total(10.0)
+                    .build()
+                    .unwrap(),
+                None,
+            )
+            .unwrap();
+
+        let kinds: Vec<Option<EventKind>> = payloads
+            .lock()
+            .unwrap()
+            .drain(..)
+            .map(|payload| payload.finalise_payload().unwrap().event_kind())
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                Some(EventKind::ScreenView),
+                Some(EventKind::Timing),
+                Some(EventKind::Structured),
+                Some(EventKind::SelfDescribing),
+                Some(EventKind::EcommerceTransaction),
+            ]
+        );
+    }
+
+    #[test]
+    fn self_describing_event_from_value_tracks_like_the_builder() {
+        let (emitter, _) = RecordingEmitter::new();
+        let tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(emitter)
+            .build()
+            .unwrap();
+        let schema = "iglu:com.acme/purchase/jsonschema/1-0-0";
+        let data = json!({"sku": "abc", "quantity": 2});
+
+        let from_value = SelfDescribingEvent::from_value(schema.to_string(), data.clone()).unwrap();
+        let from_builder = SelfDescribingEvent::builder()
+            .schema(schema)
+            .data(data)
+            .build()
+            .unwrap();
+
+        // Event ids and timestamps differ between payloads, so only the event fields are compared
+        let event_fields = |event| {
+            let json = serde_json::to_value(tracker.preview_payload(event, None).unwrap()).unwrap();
+            (json["e"].clone(), json["ue_pr"].clone())
+        };
+        assert_eq!(event_fields(from_value), event_fields(from_builder));
+    }
+
+    #[test]
+    fn preview_payload_is_not_tracked() {
+        let (emitter, payloads) = RecordingEmitter::new();
+        let tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(emitter)
+            .subject(
+                Subject::builder()
+                    .user_id("user_1")
+                    .timezone("Europe/London")
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+        let event = SelfDescribingEvent::builder()
+            .schema("iglu:com.acme/purchase/jsonschema/1-0-0")
+            .data(json!({"sku": "abc"}))
+            .subject(Subject::builder().user_id("user_2").build().unwrap())
+            .build()
+            .unwrap();
+
+        let payload = tracker.preview_payload(event, None).unwrap();
+
+        let json = serde_json::to_value(&payload).unwrap();
+        assert_eq!(json["e"], "ue");
+        let ue_pr: Value = serde_json::from_str(json["ue_pr"].as_str().unwrap()).unwrap();
+        assert_eq!(ue_pr["data"]["data"]["sku"], "abc");
+        assert_eq!(json["uid"], "user_2");
+        assert_eq!(json["tz"], "Europe/London");
+        assert!(payloads.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn expiry_is_set_on_payload() {
+        let (emitter, payloads) = RecordingEmitter::new();
+        let mut tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(emitter)
+            .build()
+            .unwrap();
+        let expire_at = SystemTime::now() + std::time::Duration::from_secs(60);
+
+        tracker.track(structured_event(), None).unwrap();
+        tracker
+            .track_with_options(
+                structured_event(),
+                None,
+                TrackOptions::new().expire_at(expire_at),
+            )
+            .unwrap();
+
+        let payloads = payloads.lock().unwrap();
+        assert_eq!(payloads[0].expire_at.flatten(), None);
+        assert_eq!(payloads[1].expire_at, Some(Some(expire_at)));
+    }
+
+    #[test]
+    fn priority_is_set_on_payload() {
+        let (emitter, payloads) = RecordingEmitter::new();
+        let mut tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(emitter)
+            .build()
+            .unwrap();
+
+        tracker.track(structured_event(), None).unwrap();
+        tracker
+            .track_with_options(
+                structured_event(),
+                None,
+                TrackOptions::new().priority(Priority::High),
+            )
+            .unwrap();
+
+        let payloads = payloads.lock().unwrap();
+        assert_eq!(payloads[0].tna, Some(Some("ns".to_string())));
+        assert_eq!(payloads[0].priority, Some(Priority::Normal));
+        assert_eq!(payloads[1].priority, Some(Priority::High));
+    }
+
+    #[test]
+    fn load_and_track_restores_persisted_events() {
+        let (emitter, payloads) = RecordingEmitter::new();
+        let mut tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(emitter)
+            .build()
+            .unwrap();
+        tracker.track(structured_event(), Some(contexts())).unwrap();
+        tracker.track(structured_event(), None).unwrap();
+
+        // Persist the events, as an application would on shutdown
+        let persisted: Vec<Payload> = payloads
+            .lock()
+            .unwrap()
+            .drain(..)
+            .map(|payload| payload.finalise_payload().unwrap())
+            .collect();
+        let path = std::env::temp_dir().join(format!("snowplow-events-{}.jsonl", Uuid::new_v4()));
+        let lines: Vec<String> = persisted
+            .iter()
+            .map(|payload| serde_json::to_string(payload).unwrap())
+            .collect();
+        std::fs::write(&path, lines.join("\n")).unwrap();
+
+        let (emitter, restored) = RecordingEmitter::new();
+        let mut tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(emitter)
+            .build()
+            .unwrap();
+        let loaded = tracker.load_and_track(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap(), 2);
+        let restored = restored.lock().unwrap();
+        for (original, restored) in persisted.iter().zip(restored.iter()) {
+            let restored = restored.clone().finalise_payload().unwrap();
+            assert_eq!(restored.eid, original.eid);
+
+            let mut restored_json = serde_json::to_value(&restored).unwrap();
+            let original_json = serde_json::to_value(original).unwrap();
+            restored_json["stm"] = original_json["stm"].clone();
+            assert_eq!(restored_json, original_json);
+        }
+    }
+
+    #[test]
+    fn load_and_track_keeps_priority_and_expiry_from_to_json() {
+        let expire_at = std::time::UNIX_EPOCH + std::time::Duration::from_secs(4_000_000_000);
+        let (emitter, payloads) = RecordingEmitter::new();
+        let mut tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(emitter)
+            .build()
+            .unwrap();
+        tracker
+            .track_with_options(
+                structured_event(),
+                None,
+                TrackOptions::new()
+                    .priority(Priority::High)
+                    .expire_at(expire_at),
+            )
+            .unwrap();
+
+        let line = payloads.lock().unwrap()[0].to_json().unwrap().to_string();
+        let path = std::env::temp_dir().join(format!("snowplow-events-{}.jsonl", Uuid::new_v4()));
+        std::fs::write(&path, line).unwrap();
+
+        let (emitter, restored) = RecordingEmitter::new();
+        let mut tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(emitter)
+            .build()
+            .unwrap();
+        let loaded = tracker.load_and_track(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap(), 1);
+        let restored = restored.lock().unwrap()[0]
+            .clone()
+            .finalise_payload()
+            .unwrap();
+        assert_eq!(restored.priority, Priority::High);
+        assert_eq!(restored.expire_at, Some(expire_at));
+    }
+
+    #[test]
+    fn load_and_track_reports_invalid_lines() {
+        let (emitter, _) = RecordingEmitter::new();
+        let mut tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(emitter)
+            .build()
+            .unwrap();
+        let path = std::env::temp_dir().join(format!("snowplow-events-{}.jsonl", Uuid::new_v4()));
+        std::fs::write(&path, "\nnot json\n").unwrap();
+
+        let err = tracker.load_and_track(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(err.to_string().contains("line 2"));
+    }
+
+    #[test]
+    fn generated_domain_user_id_is_stable() {
+        let saved = Arc::new(Mutex::new(None));
+        let build_tracker = |emitter: RecordingEmitter| {
+            let (load_from, save_to) = (saved.clone(), saved.clone());
+            Tracker::builder()
+                .namespace("ns")
+                .app_id("app_id")
+                .emitter(emitter)
+                .generate_domain_user_id(
+                    move || *load_from.lock().unwrap(),
+                    move |id| *save_to.lock().unwrap() = Some(id),
+                )
+                .build()
+                .unwrap()
+        };
+
+        let (emitter, payloads) = RecordingEmitter::new();
+        let mut tracker = build_tracker(emitter);
+        tracker.track(structured_event(), None).unwrap();
+        tracker.track(structured_event(), None).unwrap();
+
+        let domain_user_ids: Vec<Value> = payloads
+            .lock()
+            .unwrap()
+            .drain(..)
+            .map(|payload| {
+                serde_json::to_value(payload.finalise_payload().unwrap()).unwrap()["duid"].clone()
+            })
+            .collect();
+        let generated = saved
+            .lock()
+            .unwrap()
+            .expect("The generated id was not saved");
+        assert_eq!(domain_user_ids, vec![json!(generated), json!(generated)]);
+
+        // A tracker in the next session loads the saved id rather than generating a new one
+        let (emitter, _) = RecordingEmitter::new();
+        let tracker = build_tracker(emitter);
+        assert_eq!(tracker.subject().domain_user_id, Some(generated));
+    }
+
+    #[test]
+    fn extra_fields_are_added_to_the_payload() {
+        let (emitter, payloads) = RecordingEmitter::new();
+        let mut tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(emitter)
+            .build()
+            .unwrap();
+
+        tracker
+            .track_with_options(
+                structured_event(),
+                None,
+                TrackOptions::new().extra("xyz", "custom"),
+            )
+            .unwrap();
+
+        let payload = payloads.lock().unwrap().remove(0);
+        let json = serde_json::to_value(payload.finalise_payload().unwrap()).unwrap();
+        assert_eq!(json["xyz"], "custom");
+        assert_eq!(json["e"], "se");
+        assert_eq!(json["aid"], "app_id");
+    }
+
+    #[test]
+    fn events_use_ids_from_the_id_provider() {
+        // Gives each event the next ID in a fixed sequence
+        struct SequentialIds(std::sync::atomic::AtomicU64);
+
+        impl IdProvider for SequentialIds {
+            fn next_id(&self) -> Uuid {
+                let next = self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                Uuid::from_u64_pair(0, next)
+            }
+        }
+
+        let (emitter, payloads) = RecordingEmitter::new();
+        let mut tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(emitter)
+            .id_provider(SequentialIds(std::sync::atomic::AtomicU64::new(1)))
+            .build()
+            .unwrap();
+
+        let tracked = [
+            tracker.track(structured_event(), None).unwrap(),
+            tracker.track(structured_event(), None).unwrap(),
+        ];
+
+        let expected = [Uuid::from_u64_pair(0, 1), Uuid::from_u64_pair(0, 2)];
+        assert_eq!(tracked, expected);
+        let eids: Vec<Uuid> = payloads
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|payload| payload.eid.unwrap())
+            .collect();
+        assert_eq!(eids, expected);
+    }
+
+    #[test]
+    fn flushing_an_empty_tracker_succeeds() {
+        for ordered_flush in [false, true] {
+            let emitter = BatchEmitter::builder()
+                .collector_url("http://localhost:1")
+                .ordered_flush(ordered_flush)
+                .build()
+                .unwrap();
+            let mut tracker = Tracker::new("ns", "app_id", emitter, None);
+
+            tracker.flush().unwrap();
+
+            tracker.close_emitter().unwrap();
+        }
+    }
+
+    #[test]
+    fn try_track_with_options_applies_the_options() {
+        let (emitter, payloads) = RecordingEmitter::new();
+        let mut tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(emitter)
+            .build()
+            .unwrap();
+
+        assert!(tracker.try_track_with_options(
+            structured_event(),
+            None,
+            TrackOptions::new().priority(Priority::Low)
+        ));
+
+        assert_eq!(payloads.lock().unwrap()[0].priority, Some(Priority::Low));
+    }
+
+    #[test]
+    fn try_track_drops_events_when_full() {
+        let emitter = BatchEmitter::builder()
+            .collector_url("http://localhost:1")
+            .event_store(InMemoryEventStore::new(2, 10))
+            .build()
+            .unwrap();
+        let mut tracker = Tracker::new("ns", "app_id", emitter, None);
+
+        assert!(tracker.try_track(structured_event(), None));
+        assert!(tracker.try_track(structured_event(), None));
+        assert!(!tracker.try_track(structured_event(), None));
+        assert!(!tracker.try_track(structured_event(), None));
+        assert_eq!(tracker.dropped_events(), 2);
+
+        assert!(matches!(
+            tracker.track(structured_event(), None),
+            Err(Error::QueueFull)
+        ));
+
+        tracker.close_emitter().unwrap();
+    }
+
+    #[test]
+    fn iglu_validation_rejects_invalid_events() {
+        let (registry_url, requests) = schema_registry(vec![(
+            "com.acme/purchase/jsonschema/1-0-0",
+            json!({
+                "type": "object",
+                "properties": {"sku": {"type": "string"}, "quantity": {"type": "integer"}},
+                "required": ["sku"]
+            }),
+        )]);
+        let (emitter, payloads) = RecordingEmitter::new();
+        let mut tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(emitter)
+            .iglu_validation(&registry_url)
+            .build()
+            .unwrap();
+
+        let purchase = |data| {
+            SelfDescribingEvent::builder()
+                .schema("iglu:com.acme/purchase/jsonschema/1-0-0")
+                .data(data)
+                .build()
+                .unwrap()
+        };
+
+        tracker
+            .track(purchase(json!({"sku": "abc", "quantity": 1})), None)
+            .unwrap();
+
+        let err = tracker
+            .track(purchase(json!({"quantity": "one"})), None)
+            .unwrap_err();
+        assert!(matches!(err, Error::BuilderError(_)));
+        assert_eq!(
+            err.to_string(),
+            "Data does not match schema iglu:com.acme/purchase/jsonschema/1-0-0: $.sku is required"
+        );
+
+        let invalid_context =
+            SelfDescribingJson::new("iglu:com.acme/purchase/jsonschema/1-0-0", json!({"sku": 1}));
+        assert!(tracker
+            .track(structured_event(), Some(vec![invalid_context]))
+            .is_err());
+
+        // A schema that isn't in the registry can't be validated against
+        let unknown = SelfDescribingEvent::builder()
+            .schema("iglu:com.acme/unknown/jsonschema/1-0-0")
+            .data(json!({}))
+            .build()
+            .unwrap();
+        assert!(tracker.track(unknown, None).is_err());
+
+        assert_eq!(payloads.lock().unwrap().len(), 1);
+        // The purchase schema is cached after it is first fetched
+        assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn registered_schema_rejects_invalid_events() {
+        let (emitter, payloads) = RecordingEmitter::new();
+        let mut tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(emitter)
+            .register_schema(
+                "iglu:com.acme/purchase/jsonschema/1-0-0",
+                json!({
+                    "type": "object",
+                    "properties": {"sku": {"type": "string"}},
+                    "additionalProperties": false
+                }),
+            )
+            .build()
+            .unwrap();
+
+        let event = |schema: &str, data| {
+            SelfDescribingEvent::builder()
+                .schema(schema)
+                .data(data)
+                .build()
+                .unwrap()
+        };
+
+        tracker
+            .track(
+                event(
+                    "iglu:com.acme/purchase/jsonschema/1-0-0",
+                    json!({"sku": "abc"}),
+                ),
+                None,
+            )
+            .unwrap();
+
+        let err = tracker
+            .track(
+                event(
+                    "iglu:com.acme/purchase/jsonschema/1-0-0",
+                    json!({"skuu": "abc"}),
+                ),
+                None,
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Data does not match schema iglu:com.acme/purchase/jsonschema/1-0-0: $.skuu is not allowed"
+        );
+
+        // Events with an unregistered schema are not validated
+        tracker
+            .track(
+                event("iglu:com.acme/other/jsonschema/1-0-0", json!({"skuu": 1})),
+                None,
+            )
+            .unwrap();
+
+        assert_eq!(payloads.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn registered_schema_uri_is_validated() {
+        let result = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(RecordingEmitter::new().0)
+            .register_schema("com.acme/purchase/1-0-0", json!({}))
+            .build();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn registered_schema_with_unsupported_keywords_is_rejected() {
+        let result = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(RecordingEmitter::new().0)
+            .register_schema(
+                "iglu:com.acme/purchase/jsonschema/1-0-0",
+                json!({"properties": {"sku": {"type": "string", "pattern": "^[A-Z]+$"}}}),
+            )
+            .build();
+
+        assert!(matches!(
+            result,
+            Err(Error::BuilderError(message)) if message.contains("unsupported keyword pattern")
+        ));
+    }
+
+    #[test]
+    fn track_self_describing_matches_builder() {
+        let (emitter, payloads) = RecordingEmitter::new();
+        let mut tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(emitter)
+            .build()
+            .unwrap();
+
+        let schema = "iglu:com.acme/purchase/jsonschema/1-0-0";
+        let event = SelfDescribingEvent::builder()
+            .schema(schema)
+            .data(json!({"sku": "abc"}))
+            .build()
+            .unwrap();
+        tracker.track(event, Some(contexts())).unwrap();
+        tracker
+            .track_self_describing(schema, json!({"sku": "abc"}), Some(contexts()))
+            .unwrap();
+
+        let mut payloads: Vec<Value> = payloads
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|payload| {
+                let payload = payload.clone().finalise_payload().unwrap();
+                let mut json = serde_json::to_value(payload).unwrap();
+                // The event id and timestamps differ between events
+                for field in ["eid", "dtm", "stm"] {
+                    json.as_object_mut().unwrap().remove(field);
+                }
+                json
+            })
+            .collect();
+
+        assert_eq!(payloads.pop(), payloads.pop());
+    }
+
+    #[test]
+    fn track_self_describing_validates_schema() {
+        let (emitter, payloads) = RecordingEmitter::new();
+        let mut tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(emitter)
+            .build()
+            .unwrap();
+
+        let result = tracker.track_self_describing("com.acme/purchase/1-0-0", json!({}), None);
+
+        assert!(matches!(result, Err(Error::BuilderError(_))));
+        assert!(payloads.lock().unwrap().is_empty());
+    }
+
     #[test]
+    fn tracker_context_is_attached() {
+        let (emitter, payloads) = RecordingEmitter::new();
+        let mut tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(emitter)
+            .tracker_context("iglu:com.acme/tracker/jsonschema/1-0-0")
+            .build()
+            .unwrap();
+
+        tracker.track(structured_event(), Some(contexts())).unwrap();
+        tracker.track(structured_event(), None).unwrap();
+
+        let payloads = payloads.lock().unwrap();
+        for payload in payloads.iter() {
+            let context = payload.co.clone().unwrap().unwrap();
+            let tracker_context = context.data.last().unwrap();
+            assert_eq!(
+                tracker_context.schema,
+                "iglu:com.acme/tracker/jsonschema/1-0-0"
+            );
+            assert_eq!(tracker_context.data["namespace"], "ns");
+            assert_eq!(tracker_context.data["platform"], "pc");
+        }
+        assert_eq!(payloads[0].co.clone().unwrap().unwrap().data.len(), 3);
+
+        let result = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(RecordingEmitter::new().0)
+            .tracker_context("com.acme/tracker")
+            .build();
+        assert!(matches!(result, Err(Error::BuilderError(_))));
+    }
+
+    #[test]
+    fn host_context_is_attached() {
+        let (emitter, payloads) = RecordingEmitter::new();
+        let mut tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(emitter)
+            .host_context("iglu:com.acme/host/jsonschema/1-0-0")
+            .build()
+            .unwrap();
+
+        tracker.track(structured_event(), None).unwrap();
+
+        let payloads = payloads.lock().unwrap();
+        let context = payloads[0].co.clone().unwrap().unwrap();
+        let host_context = context.data.last().unwrap();
+        assert_eq!(host_context.schema, "iglu:com.acme/host/jsonschema/1-0-0");
+        assert_eq!(host_context.data["os"], std::env::consts::OS);
+        assert_eq!(host_context.data["pid"], std::process::id());
+
+        let result = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(RecordingEmitter::new().0)
+            .host_context("com.acme/host")
+            .build();
+        assert!(matches!(result, Err(Error::BuilderError(_))));
+    }
+
+    #[test]
+    fn custom_contexts_schema() {
+        let (emitter, payloads) = RecordingEmitter::new();
+        let mut tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(emitter)
+            .contexts_schema("iglu:com.snowplowanalytics.snowplow/contexts/jsonschema/1-0-2")
+            .build()
+            .unwrap();
+
+        tracker.track(structured_event(), Some(contexts())).unwrap();
+
+        let payloads = payloads.lock().unwrap();
+        let co = serde_json::to_value(payloads[0].co.clone().unwrap().unwrap()).unwrap();
+        let co: serde_json::Value = serde_json::from_str(co.as_str().unwrap()).unwrap();
+        assert_eq!(
+            co["schema"],
+            "iglu:com.snowplowanalytics.snowplow/contexts/jsonschema/1-0-2"
+        );
+    }
+
+    #[test]
+    fn custom_unstruct_event_schema() {
+        let (emitter, payloads) = RecordingEmitter::new();
+        let mut tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(emitter)
+            .unstruct_event_schema(
+                "iglu:com.snowplowanalytics.snowplow/unstruct_event/jsonschema/1-0-1",
+            )
+            .build()
+            .unwrap();
+
+        let event = SelfDescribingEvent::builder()
+            .schema("iglu:com.acme/event/jsonschema/1-0-0")
+            .data(json!({"a": 1}))
+            .build()
+            .unwrap();
+        tracker.track(event, None).unwrap();
+
+        let payloads = payloads.lock().unwrap();
+        let ue_pr = serde_json::to_value(payloads[0].ue_pr.clone().unwrap().unwrap()).unwrap();
+        let ue_pr: serde_json::Value = serde_json::from_str(ue_pr.as_str().unwrap()).unwrap();
+        assert_eq!(
+            ue_pr["schema"],
+            "iglu:com.snowplowanalytics.snowplow/unstruct_event/jsonschema/1-0-1"
+        );
+        assert_eq!(
+            ue_pr["data"]["schema"],
+            "iglu:com.acme/event/jsonschema/1-0-0"
+        );
+    }
+
+    #[test]
+    fn invalid_contexts_schema_is_rejected() {
+        let result = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(RecordingEmitter::new().0)
+            .contexts_schema("not a schema")
+            .build();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn v7_event_ids_are_time_ordered() {
+        let (emitter, _) = RecordingEmitter::new();
+        let mut tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(emitter)
+            .event_id_version(EventIdVersion::V7)
+            .build()
+            .unwrap();
+
+        let first = tracker.track(structured_event(), None).unwrap();
+        let second = tracker.track(structured_event(), None).unwrap();
+
+        assert_eq!(first.get_version_num(), 7);
+        assert!(first < second);
+    }
+
+    #[test]
+    fn oversized_context_returns_error() {
+        let (emitter, payloads) = RecordingEmitter::new();
+        let mut tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(emitter)
+            .context_size_limit(500, ContextLimitAction::Error)
+            .build()
+            .unwrap();
+
+        assert!(tracker.track(structured_event(), Some(contexts())).is_err());
+        assert!(payloads.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn oversized_context_is_dropped() {
+        let (emitter, payloads) = RecordingEmitter::new();
+        let mut tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(emitter)
+            .context_size_limit(500, ContextLimitAction::Drop)
+            .build()
+            .unwrap();
+
+        tracker.track(structured_event(), Some(contexts())).unwrap();
+
+        let payloads = payloads.lock().unwrap();
+        let context = payloads[0].co.clone().unwrap().unwrap();
+        assert_eq!(context.data.len(), 1);
+        assert_eq!(
+            context.data[0].schema,
+            "iglu:com.acme/small/jsonschema/1-0-0"
+        );
+    }
+
+    #[test]
+    fn emitter_default_contexts_count_towards_the_size_limit() {
+        let (mut emitter, payloads) = RecordingEmitter::new();
+        emitter.default_contexts = vec![SelfDescribingJson::new(
+            "iglu:com.acme/deployment/jsonschema/1-0-0",
+            json!({"region": "x".repeat(500)}),
+        )];
+        let mut tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(emitter)
+            .context_size_limit(500, ContextLimitAction::Error)
+            .build()
+            .unwrap();
+
+        assert!(tracker.track(structured_event(), None).is_err());
+        assert!(payloads.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn duplicate_context_schemas_are_checked() {
+        let duplicated = || {
+            vec![
+                SelfDescribingJson::new("iglu:com.acme/user/jsonschema/1-0-0", json!({"id": 1})),
+                SelfDescribingJson::new("iglu:com.acme/user/jsonschema/1-0-0", json!({"id": 2})),
+            ]
+        };
+        let tracker_with = |action| {
+            let (emitter, payloads) = RecordingEmitter::new();
+            let tracker = Tracker::builder()
+                .namespace("ns")
+                .app_id("app_id")
+                .emitter(emitter)
+                .duplicate_contexts(action)
+                .build()
+                .unwrap();
+            (tracker, payloads)
+        };
+
+        // Warning is the default, and still sends both entities
+        let (mut tracker, payloads) = tracker_with(DuplicateContextAction::default());
+        tracker
+            .track(structured_event(), Some(duplicated()))
+            .unwrap();
+        let context = payloads.lock().unwrap()[0].co.clone().unwrap().unwrap();
+        assert_eq!(context.data.len(), 2);
+
+        let (mut tracker, payloads) = tracker_with(DuplicateContextAction::Error);
+        let err = tracker
+            .track(structured_event(), Some(duplicated()))
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("iglu:com.acme/user/jsonschema/1-0-0"));
+        assert!(payloads.lock().unwrap().is_empty());
+        tracker.track(structured_event(), Some(contexts())).unwrap();
+
+        let (mut tracker, payloads) = tracker_with(DuplicateContextAction::Allow);
+        tracker
+            .track(structured_event(), Some(duplicated()))
+            .unwrap();
+        assert_eq!(payloads.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn duplicates_of_emitter_default_contexts_are_checked() {
+        let (mut emitter, payloads) = RecordingEmitter::new();
+        emitter.default_contexts = vec![SelfDescribingJson::new(
+            "iglu:com.acme/user/jsonschema/1-0-0",
+            json!({"id": 1}),
+        )];
+        let mut tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(emitter)
+            .duplicate_contexts(DuplicateContextAction::Error)
+            .build()
+            .unwrap();
+
+        let err = tracker
+            .track(
+                structured_event(),
+                Some(vec![SelfDescribingJson::new(
+                    "iglu:com.acme/user/jsonschema/1-0-0",
+                    json!({"id": 2}),
+                )]),
+            )
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("iglu:com.acme/user/jsonschema/1-0-0"));
+        assert!(payloads.lock().unwrap().is_empty());
+
+        tracker.track(structured_event(), Some(contexts())).unwrap();
+        let context = payloads.lock().unwrap()[0].co.clone().unwrap().unwrap();
+        assert_eq!(context.data.len(), contexts().len() + 1);
+    }
+
+    #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn create_new_tracker() {
         let mut tracker = Tracker::new(
             "test namespace",
@@ -198,7 +1929,7 @@ mod tests {
 
         assert_eq!(tracker.namespace, "test namespace");
         assert_eq!(tracker.app_id, "test app id");
-        assert_eq!(tracker.emitter.collector_url(), "http://example.com/");
+        assert_eq!(tracker.emitter.collector_url(), "http://example.com");
         assert_eq!(tracker.subject.user_id, Some("user_1".to_string()));
         assert_eq!(tracker.config.platform, "pc".to_string());
         assert_eq!(
@@ -265,4 +1996,59 @@ mod tests {
 
         tracker.close_emitter().unwrap();
     }
+
+    #[test]
+    fn reported_queue_pressure_rises_as_the_queue_fills() {
+        // Batches are as large as the queue, so no events are sent
+        let emitter =
+            SyncEmitter::with_event_store("http://localhost:9090", InMemoryEventStore::new(4, 4));
+        let mut tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(emitter)
+            .build()
+            .unwrap();
+
+        let percents: Vec<u8> = (0..3)
+            .map(|_| {
+                tracker.track(structured_event(), None).unwrap();
+                tracker.queue_pressure().unwrap().percent()
+            })
+            .collect();
+
+        assert_eq!(percents, vec![25, 50, 75]);
+    }
+
+    #[test]
+    fn update_subject_keeps_fields_it_does_not_set() {
+        let mut tracker = Tracker::builder()
+            .namespace("ns")
+            .app_id("app_id")
+            .emitter(RecordingEmitter::new().0)
+            .subject(
+                Subject::builder()
+                    .user_id("anonymous")
+                    .language("en-GB")
+                    .ip_address("203.0.113.7")
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+
+        tracker
+            .update_subject(Subject::builder().user_id("user_1").build().unwrap())
+            .update_subject(
+                Subject::builder()
+                    .timezone("Europe/London")
+                    .build()
+                    .unwrap(),
+            );
+
+        let subject = tracker.subject();
+        assert_eq!(subject.user_id.as_deref(), Some("user_1"));
+        assert_eq!(subject.timezone.as_deref(), Some("Europe/London"));
+        assert_eq!(subject.language.as_deref(), Some("en-GB"));
+        assert_eq!(subject.ip_address.as_deref(), Some("203.0.113.7"));
+    }
 }
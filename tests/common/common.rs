@@ -1,17 +1,26 @@
+use snowplow_tracker::{MicroClient, MicroEvents};
 use testcontainers::{clients::Cli, Container, RunnableImage};
 
 use super::micro::Micro;
 
+fn micro_events(page: &str) -> MicroEvents {
+    match page {
+        "good" => MicroEvents::Good,
+        "bad" => MicroEvents::Bad,
+        _ => panic!("Unknown Micro endpoint: {page}"),
+    }
+}
+
 pub async fn micro_endpoint(micro_url: &str, page: &str) -> serde_json::Value {
-    let resp = reqwest::get(micro_url.to_string() + "/micro/" + page)
+    let events = MicroClient::new(micro_url)
+        .events(micro_events(page))
         .await
         .unwrap();
-    let text = resp.text().await.unwrap();
-    serde_json::from_str(&text).unwrap()
+    serde_json::Value::Array(events)
 }
 
-pub fn setup(docker: &Cli) -> (Container<Micro>, String) {
-    let micro_image = Micro::default();
+pub fn setup(docker: &Cli) -> (Container<'_, Micro>, String) {
+    let micro_image = Micro;
     // We cannot call `$(pwd)` as usual in a path for a docker volume, so we need to get the current working directory
     let pwd = std::env::current_dir()
         .unwrap()
@@ -29,22 +38,12 @@ pub fn setup(docker: &Cli) -> (Container<Micro>, String) {
 }
 
 pub async fn wait_for_events(micro_url: &str, page: &str, number: usize) {
-    let timeout = std::time::Instant::now() + std::time::Duration::from_secs(30);
-    loop {
-        if timeout < std::time::Instant::now() {
-            panic!("Timeout waiting for events");
-        }
-
-        let response = micro_endpoint(micro_url, page).await;
-        match response.as_array() {
-            Some(events) => {
-                if events.len() >= number {
-                    break;
-                } else {
-                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-                }
-            }
-            _ => tokio::time::sleep(std::time::Duration::from_millis(100)).await,
-        }
-    }
+    MicroClient::new(micro_url)
+        .wait_for(
+            micro_events(page),
+            number,
+            std::time::Duration::from_secs(30),
+        )
+        .await
+        .unwrap();
 }
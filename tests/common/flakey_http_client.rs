@@ -3,7 +3,7 @@ use std::sync::{
     Arc,
 };
 
-use snowplow_tracker::{HttpClient, SelfDescribingJson};
+use snowplow_tracker::{HttpClient, RequestContext, SelfDescribingJson};
 use testcontainers::clients::Cli;
 
 use crate::common::setup;
@@ -16,7 +16,11 @@ pub struct FlakeyHttpClient {
 
 #[async_trait::async_trait]
 impl HttpClient for FlakeyHttpClient {
-    async fn post(&self, payload: SelfDescribingJson) -> Result<u16, snowplow_tracker::Error> {
+    async fn post(
+        &self,
+        payload: SelfDescribingJson,
+        _context: RequestContext,
+    ) -> Result<u16, snowplow_tracker::Error> {
         if self.count.load(Ordering::SeqCst) < self.number_of_events_to_block {
             self.count.fetch_add(1, Ordering::SeqCst);
             return Ok(500);
@@ -57,10 +61,14 @@ async fn flaky_http_client_returns_500_n_times() {
         schema: String::new(),
         data: serde_json::json!({}),
     };
+    let context = RequestContext {
+        batch_id: uuid::Uuid::new_v4(),
+        attempt: 1,
+    };
 
     for _ in 0..5 {
-        assert_eq!(client.post(sdj.clone()).await.unwrap(), 500);
+        assert_eq!(client.post(sdj.clone(), context).await.unwrap(), 500);
     }
 
-    assert_eq!(client.post(sdj.clone()).await.unwrap(), 200);
+    assert_eq!(client.post(sdj.clone(), context).await.unwrap(), 200);
 }
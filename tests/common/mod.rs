@@ -1,7 +1,10 @@
+// Shared helpers are compiled into each test binary, which won't all use every item
+#![allow(dead_code, unused_imports)]
+
+#[allow(clippy::module_inception)]
 mod common;
 mod flakey_http_client;
 mod micro;
 
 pub use common::{micro_endpoint, setup, wait_for_events};
 pub use flakey_http_client::FlakeyHttpClient;
-pub use micro::Micro;
@@ -1,6 +1,13 @@
+#![cfg(feature = "micro")]
+
 use std::sync::{atomic::AtomicUsize, Arc};
+use std::time::Duration;
 
-use snowplow_tracker::{BatchEmitter, InMemoryEventStore, ScreenViewEvent, Tracker};
+use serde_json::json;
+use snowplow_tracker::{
+    BatchEmitter, Emitter, InMemoryEventStore, Payload, ScreenViewEvent, SelfDescribingJson,
+    Tracker,
+};
 use testcontainers::clients::Cli;
 use uuid::Uuid;
 
@@ -123,3 +130,104 @@ async fn successful_send_after_retry() {
     assert!(counter.load(std::sync::atomic::Ordering::SeqCst) == 2);
     assert_eq!(1, all_events["good"]);
 }
+
+#[tokio::test]
+async fn flush_on_interval() {
+    let docker = Cli::default();
+    let (_container, micro_url) = setup(&docker);
+
+    let event_store = InMemoryEventStore::new(100, 50);
+
+    let emitter = BatchEmitter::builder()
+        .collector_url(&micro_url)
+        .event_store(event_store)
+        .flush_interval(Duration::from_millis(200))
+        .build()
+        .unwrap();
+
+    let mut tracker = Tracker::new("ns", "app_id", emitter, None);
+
+    let screenview_event = ScreenViewEvent::builder()
+        .id(Uuid::new_v4())
+        .name("a screen view")
+        .build()
+        .unwrap();
+
+    tracker.track(screenview_event, None).unwrap();
+
+    // The event should be sent without an explicit flush
+    wait_for_events(&micro_url, "good", 1).await;
+    tracker.close_emitter().unwrap();
+
+    let all_events = micro_endpoint(&micro_url, "all").await;
+
+    assert_eq!(1, all_events["good"]);
+}
+
+#[tokio::test]
+async fn close_async_sends_all_events() {
+    let docker = Cli::default();
+    let (_container, micro_url) = setup(&docker);
+
+    let mut emitter = BatchEmitter::builder()
+        .collector_url(&micro_url)
+        .event_store(InMemoryEventStore::new(100, 10))
+        .build()
+        .unwrap();
+
+    for _ in 0..25 {
+        let payload = Payload::builder()
+            .p("srv".to_string())
+            .tv("rust-test".to_string())
+            .eid(Uuid::new_v4())
+            .dtm("1".to_string())
+            .aid("app_id".to_string());
+        emitter.add(payload).unwrap();
+    }
+
+    emitter.close_async().await.unwrap();
+
+    // Every event reached the collector, whether or not it passed validation
+    let all_events = micro_endpoint(&micro_url, "all").await;
+    assert_eq!(25, all_events["total"]);
+}
+
+#[tokio::test]
+async fn send_heartbeats() {
+    let docker = Cli::default();
+    let (_container, micro_url) = setup(&docker);
+
+    // Heartbeats use a schema from Iglu Central, so Micro can validate them
+    let emitter = BatchEmitter::builder()
+        .collector_url(&micro_url)
+        .event_store(InMemoryEventStore::new(100, 10))
+        .heartbeat(
+            Duration::from_millis(200),
+            SelfDescribingJson::new(
+                "iglu:com.snowplowanalytics.snowplow/timing/jsonschema/1-0-0",
+                json!({"category": "emitter", "variable": "heartbeat", "timing": 0}),
+            ),
+        )
+        .build()
+        .unwrap();
+    let mut tracker = Tracker::new("ns", "app_id", emitter, None);
+
+    let timeout = std::time::Instant::now() + Duration::from_secs(5);
+    loop {
+        let all_events = micro_endpoint(&micro_url, "all").await;
+        if all_events["good"].as_u64().unwrap_or_default() >= 1 {
+            break;
+        }
+        assert!(
+            std::time::Instant::now() < timeout,
+            "No valid heartbeat reached the collector"
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    tracker.close_emitter().unwrap();
+
+    let good_events = micro_endpoint(&micro_url, "good").await;
+    let heartbeat = &good_events.as_array().unwrap()[0]["event"];
+    assert_eq!(heartbeat["app_id"], "app_id");
+    assert_eq!(heartbeat["name_tracker"], "ns");
+}
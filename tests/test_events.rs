@@ -1,10 +1,13 @@
+#![cfg(feature = "micro")]
+
 use serde_json::json;
 use testcontainers::clients::Cli;
 use uuid::Uuid;
 
 use snowplow_tracker::{
-    BatchEmitter, InMemoryEventStore, ScreenViewEvent, SelfDescribingEvent, SelfDescribingJson,
-    StructuredEvent, Subject, TimingEvent, Tracker,
+    BatchEmitter, Context, InMemoryEventStore, ScreenViewEvent, SelfDescribingEvent,
+    SelfDescribingJson, StructuredEvent, Subject, TimingEvent, Tracker, TrackerBuilder,
+    TrackerSettings,
 };
 
 mod common;
@@ -51,6 +54,29 @@ async fn track_valid_event_to_good() {
     assert_eq!(1, good_events.len());
 }
 
+#[tokio::test]
+async fn track_event_with_tracker_namespace() {
+    let docker = Cli::default();
+    let (_container, micro_url) = setup(&docker);
+
+    let mut tracker = test_tracker(&micro_url, None, None, None);
+
+    let structured_event = StructuredEvent::builder()
+        .category("shop")
+        .action("add-to-basket")
+        .build()
+        .unwrap();
+
+    tracker.track(structured_event, None).unwrap();
+    wait_for_events(&micro_url, "good", 1).await;
+    tracker.close_emitter().unwrap();
+
+    let good_events = micro_endpoint(&micro_url, "good").await;
+    let event = &good_events.as_array().unwrap().last().unwrap()["event"];
+
+    assert_eq!("test-namespace", event["name_tracker"]);
+}
+
 #[tokio::test]
 async fn track_event_with_subject() {
     let docker = Cli::default();
@@ -290,6 +316,105 @@ async fn track_self_describing_event() {
     );
 }
 
+#[derive(serde::Serialize)]
+struct WebPageContext {
+    keywords: Vec<String>,
+}
+
+impl Context for WebPageContext {
+    fn schema(&self) -> &str {
+        "iglu:org.schema/WebPage/jsonschema/1-0-0"
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!(self)
+    }
+}
+
+#[tokio::test]
+async fn track_event_with_typed_context() {
+    let docker = Cli::default();
+    let (_container, micro_url) = setup(&docker);
+
+    let mut tracker = test_tracker(&micro_url, None, None, None);
+    tracker
+        .track_with_contexts(
+            StructuredEvent::builder()
+                .category("shop")
+                .action("add-to-basket")
+                .build()
+                .unwrap(),
+            vec![Box::new(WebPageContext {
+                keywords: vec!["tester".to_string()],
+            })],
+        )
+        .unwrap();
+
+    wait_for_events(&micro_url, "good", 1).await;
+    tracker.close_emitter().unwrap();
+
+    let good_events = micro_endpoint(&micro_url, "good").await;
+    let received_event = good_events.as_array().unwrap().last().unwrap();
+
+    let expected_context = json!({
+        "data": {
+            "keywords": [
+                "tester"
+            ]
+        },
+        "schema": "iglu:org.schema/WebPage/jsonschema/1-0-0",
+    });
+
+    assert_eq!(
+        received_event["event"]["contexts"]["data"]
+            .as_array()
+            .unwrap()
+            .first()
+            .unwrap(),
+        &expected_context
+    );
+}
+
+#[tokio::test]
+async fn track_with_tracker_from_config() {
+    let docker = Cli::default();
+    let (_container, micro_url) = setup(&docker);
+
+    let settings: TrackerSettings = serde_json::from_value(json!({
+        "namespace": "test-namespace",
+        "app_id": "test-app-id",
+        "platform": "srv",
+        "collector_url": micro_url,
+        "batch_size": 1,
+        "capacity": 10,
+        "retry_policy": { "MaxRetries": 3 }
+    }))
+    .unwrap();
+    let settings: TrackerSettings =
+        serde_json::from_str(&serde_json::to_string(&settings).unwrap()).unwrap();
+
+    let mut tracker = TrackerBuilder::from_config(settings)
+        .unwrap()
+        .build()
+        .unwrap();
+
+    let event = StructuredEvent::builder()
+        .category("shop")
+        .action("add-to-basket")
+        .build()
+        .unwrap();
+    tracker.track(event, None).unwrap();
+
+    wait_for_events(&micro_url, "good", 1).await;
+    tracker.close_emitter().unwrap();
+
+    let good_events = micro_endpoint(&micro_url, "good").await;
+    let received_event = good_events.as_array().unwrap().last().unwrap();
+
+    assert_eq!(received_event["event"]["app_id"], "test-app-id");
+    assert_eq!(received_event["event"]["platform"], "srv");
+}
+
 #[tokio::test]
 async fn track_timing_event() {
     let docker = Cli::default();
@@ -0,0 +1,129 @@
+#![cfg(feature = "micro")]
+
+use std::time::Duration;
+
+use testcontainers::clients::Cli;
+
+use snowplow_tracker::{
+    BatchEmitter, EmitterConfig, InMemoryEventStore, MicroClient, MicroEvents, StructuredEvent,
+    Tracker, TrackerBuilder, TrackerSettings,
+};
+
+mod common;
+use common::setup;
+
+fn test_tracker(micro_url: &str) -> Tracker {
+    let emitter = BatchEmitter::builder()
+        .collector_url(micro_url)
+        .event_store(InMemoryEventStore::new(10, 1))
+        .build()
+        .unwrap();
+
+    Tracker::new("test-namespace", "test-app-id", emitter, None)
+}
+
+#[tokio::test]
+async fn counts_good_events() {
+    let docker = Cli::default();
+    let (_container, micro_url) = setup(&docker);
+    let micro = MicroClient::new(&micro_url);
+    let mut tracker = test_tracker(&micro_url);
+
+    for _ in 0..2 {
+        let event = StructuredEvent::builder()
+            .category("shop")
+            .action("add-to-basket")
+            .build()
+            .unwrap();
+        tracker.track(event, None).unwrap();
+    }
+
+    micro
+        .wait_for_good(2, Duration::from_secs(30))
+        .await
+        .unwrap();
+    tracker.close_emitter().unwrap();
+
+    assert_eq!(micro.good_count().await.unwrap(), 2);
+    assert_eq!(micro.bad_count().await.unwrap(), 0);
+
+    let events = micro.events(MicroEvents::Good).await.unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0]["event"]["se_category"], "shop");
+}
+
+#[tokio::test]
+async fn tracker_from_config_sends_good_events() {
+    let docker = Cli::default();
+    let (_container, micro_url) = setup(&docker);
+    let micro = MicroClient::new(&micro_url);
+
+    let mut emitter = EmitterConfig::new(&micro_url);
+    emitter.batch_size = 1;
+    let settings = TrackerSettings {
+        namespace: "config-namespace".to_string(),
+        app_id: "config-app-id".to_string(),
+        platform: None,
+        emitter,
+    };
+    let mut tracker = TrackerBuilder::from_config(settings)
+        .unwrap()
+        .build()
+        .unwrap();
+
+    let event = StructuredEvent::builder()
+        .category("shop")
+        .action("add-to-basket")
+        .build()
+        .unwrap();
+    tracker.track(event, None).unwrap();
+
+    micro
+        .wait_for_good(1, Duration::from_secs(30))
+        .await
+        .unwrap();
+    tracker.close_emitter().unwrap();
+
+    let events = micro.events(MicroEvents::Good).await.unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["event"]["app_id"], "config-app-id");
+    assert_eq!(events[0]["event"]["name_tracker"], "config-namespace");
+    assert_eq!(micro.bad_count().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn reset_discards_events() {
+    let docker = Cli::default();
+    let (_container, micro_url) = setup(&docker);
+    let micro = MicroClient::new(&micro_url);
+    let mut tracker = test_tracker(&micro_url);
+
+    let event = StructuredEvent::builder()
+        .category("shop")
+        .action("add-to-basket")
+        .build()
+        .unwrap();
+    tracker.track(event, None).unwrap();
+    micro
+        .wait_for_good(1, Duration::from_secs(30))
+        .await
+        .unwrap();
+    tracker.close_emitter().unwrap();
+
+    micro.reset().await.unwrap();
+
+    assert_eq!(micro.good_count().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn wait_for_times_out() {
+    let docker = Cli::default();
+    let (_container, micro_url) = setup(&docker);
+    let micro = MicroClient::new(&micro_url);
+
+    let result = micro
+        .wait_for(MicroEvents::Bad, 1, Duration::from_millis(200))
+        .await;
+
+    assert!(result.is_err());
+}
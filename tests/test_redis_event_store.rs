@@ -0,0 +1,92 @@
+#![cfg(feature = "redis")]
+
+use std::time::Duration;
+
+use testcontainers::{clients::Cli, images::redis::Redis};
+
+use snowplow_tracker::{AsyncEventStore, Payload, PayloadBuilder, RedisEventStore};
+use uuid::Uuid;
+
+fn payload(eid: Uuid) -> PayloadBuilder {
+    Payload::builder()
+        .p("srv".to_string())
+        .tv("rust-test".to_string())
+        .eid(eid)
+        .dtm("1667218811000".to_string())
+        .aid("test-app-id".to_string())
+}
+
+fn event_ids(payloads: &[Payload]) -> Vec<String> {
+    payloads
+        .iter()
+        .map(|payload| serde_json::to_value(payload).unwrap()["eid"].to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn events_survive_across_store_instances() {
+    let docker = Cli::default();
+    let container = docker.run(Redis);
+    let redis_url = format!("redis://127.0.0.1:{}", container.get_host_port_ipv4(6379));
+
+    let eids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+    {
+        let mut first = RedisEventStore::new(&redis_url, "events", 10, 2).unwrap();
+        for eid in &eids {
+            first.add(payload(*eid)).await.unwrap();
+        }
+    }
+
+    let mut second = RedisEventStore::new(&redis_url, "events", 10, 2).unwrap();
+    assert_eq!(second.len().await, 3);
+
+    let batch = second.full_batch().await.unwrap();
+    let remaining = second.batch_of(2).await.unwrap();
+    second.cleanup_after_send_attempt(batch.id).await.unwrap();
+    second
+        .cleanup_after_send_attempt(remaining.id)
+        .await
+        .unwrap();
+
+    let sent = [batch.events, remaining.events].concat();
+    let expected: Vec<String> = eids.iter().map(|eid| format!("\"{eid}\"")).collect();
+    assert_eq!(event_ids(&sent), expected);
+    assert!(second.is_empty().await);
+}
+
+#[tokio::test]
+async fn full_store_rejects_events() {
+    let docker = Cli::default();
+    let container = docker.run(Redis);
+    let redis_url = format!("redis://127.0.0.1:{}", container.get_host_port_ipv4(6379));
+
+    let mut store = RedisEventStore::new(&redis_url, "events", 1, 1).unwrap();
+    store.add(payload(Uuid::new_v4())).await.unwrap();
+
+    assert!(store.add(payload(Uuid::new_v4())).await.is_err());
+}
+
+#[tokio::test]
+async fn batches_never_cleaned_up_are_requeued() {
+    let docker = Cli::default();
+    let container = docker.run(Redis);
+    let redis_url = format!("redis://127.0.0.1:{}", container.get_host_port_ipv4(6379));
+
+    let eids: Vec<Uuid> = (0..2).map(|_| Uuid::new_v4()).collect();
+    {
+        // The instance stops before the batch it took is cleaned up
+        let mut stopped = RedisEventStore::new(&redis_url, "events", 10, 2).unwrap();
+        for eid in &eids {
+            stopped.add(payload(*eid)).await.unwrap();
+        }
+        stopped.full_batch().await.unwrap();
+    }
+
+    let mut store = RedisEventStore::new(&redis_url, "events", 10, 2)
+        .unwrap()
+        .in_flight_timeout(Duration::ZERO);
+    let batch = store.batch_of(2).await.unwrap();
+
+    let expected: Vec<String> = eids.iter().map(|eid| format!("\"{eid}\"")).collect();
+    assert_eq!(event_ids(&batch.events), expected);
+}
@@ -0,0 +1,37 @@
+#![cfg(feature = "micro")]
+
+use snowplow_tracker::{InMemoryEventStore, StructuredEvent, SyncEmitter, Tracker};
+use testcontainers::clients::Cli;
+
+mod common;
+use common::{micro_endpoint, setup, wait_for_events};
+
+// The blocking HTTP client can't be used within an async runtime,
+// so these tests only start a runtime to query Micro
+#[test]
+fn track_and_flush_with_sync_emitter() {
+    let docker = Cli::default();
+    let (_container, micro_url) = setup(&docker);
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let emitter = SyncEmitter::with_event_store(&micro_url, InMemoryEventStore::new(10, 2));
+    let mut tracker = Tracker::new("ns", "app_id", emitter, None);
+
+    for _ in 0..3 {
+        let event = StructuredEvent::builder()
+            .category("shop")
+            .action("add-to-basket")
+            .build()
+            .unwrap();
+        tracker.track(event, None).unwrap();
+    }
+
+    // The first full batch is sent as soon as it fills, the remaining event is sent on flush
+    runtime.block_on(wait_for_events(&micro_url, "good", 2));
+    tracker.flush().unwrap();
+    runtime.block_on(wait_for_events(&micro_url, "good", 3));
+    tracker.close_emitter().unwrap();
+
+    let all_events = runtime.block_on(micro_endpoint(&micro_url, "all"));
+    assert_eq!(3, all_events["good"]);
+}